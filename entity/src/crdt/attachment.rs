@@ -1,11 +1,13 @@
 use super::{CrdtAddOnly, CrdtInstance, CrdtTransaction};
 use crate::{
-    entity::{attachment, conversation},
-    patch::{Attachment, Conversation},
+    entity::{attachment, blob, conversation},
+    patch::{attachment::BlobHash, Attachment, AttachmentChunk, Conversation},
     uuid::SplitUuid,
 };
 use futures::{future::LocalBoxFuture, FutureExt};
-use sea_orm::{ActiveModelTrait, ActiveValue, DatabaseTransaction, EntityTrait, QueryFilter};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter,
+};
 use uuid::Uuid;
 
 impl CrdtInstance for Attachment {
@@ -47,12 +49,16 @@ impl CrdtTransaction<Attachment> for DatabaseTransaction {
                 uuid2: ActiveValue::Set(uuid.2),
                 uuid3: ActiveValue::Set(uuid.3),
                 conversation: ActiveValue::Set(conversation.id),
-                payload: ActiveValue::Set(value.payload.clone()),
                 crdt_author: ActiveValue::Set(value.crdt.0 .0),
+                hash: ActiveValue::Set(value.hash.map(|hash| hash.to_vec())),
             };
 
             model.save(self).await.unwrap();
 
+            if let Some(hash) = value.hash {
+                adopt_parked_chunks(self, value.id, hash).await;
+            }
+
             value
         }
         .boxed_local()
@@ -83,3 +89,102 @@ impl CrdtTransaction<Attachment> for DatabaseTransaction {
         .boxed_local()
     }
 }
+
+/// Moves the chunks of a download that did not finish before payloads were
+/// stored under their hash, kept under the bytes of the uuid of the attachment,
+/// to the hash of the payload, so that the download resumes. Chunks already
+/// stored under the hash are kept.
+async fn adopt_parked_chunks(trans: &DatabaseTransaction, id: Uuid, hash: BlobHash) {
+    let placeholder = id.as_bytes().to_vec();
+    while let Some(chunk) = blob::Entity::find()
+        .filter(blob::Column::Hash.eq(placeholder.clone()))
+        .one(trans)
+        .await
+        .unwrap()
+    {
+        let stored = blob::Entity::find()
+            .filter(blob::Column::Hash.eq(hash.to_vec()))
+            .filter(blob::Column::Index.eq(chunk.index))
+            .one(trans)
+            .await
+            .unwrap();
+        if stored.is_some() {
+            blob::Entity::delete_by_id(chunk.id)
+                .exec(trans)
+                .await
+                .unwrap();
+            continue;
+        }
+
+        let mut chunk: blob::ActiveModel = chunk.into();
+        chunk.hash = ActiveValue::Set(hash.to_vec());
+        chunk.update(trans).await.unwrap();
+    }
+}
+
+impl CrdtInstance for AttachmentChunk {
+    type Id = (BlobHash, i32);
+    type Crdt = CrdtAddOnly;
+
+    fn id(&self) -> Self::Id {
+        (self.hash, self.index)
+    }
+
+    fn crdt(&self) -> Self::Crdt {
+        self.crdt
+    }
+
+    fn set_crdt(&mut self, crdt: Self::Crdt) {
+        self.crdt = crdt
+    }
+}
+
+impl CrdtTransaction<AttachmentChunk> for DatabaseTransaction {
+    type RowId = i32;
+
+    fn save(
+        &mut self,
+        value: AttachmentChunk,
+        existent: Option<(Self::RowId, AttachmentChunk)>,
+    ) -> LocalBoxFuture<'_, AttachmentChunk> {
+        async move {
+            blob::ActiveModel {
+                id: match existent {
+                    Some((id, _)) => ActiveValue::Unchanged(id),
+                    None => ActiveValue::NotSet,
+                },
+                hash: ActiveValue::Set(value.hash.to_vec()),
+                index: ActiveValue::Set(value.index),
+                data: ActiveValue::Set(value.data.clone()),
+                total: ActiveValue::Set(value.total as i64),
+                crdt_author: ActiveValue::Set(value.crdt.0 .0),
+            }
+            .save(self)
+            .await
+            .unwrap();
+
+            value
+        }
+        .boxed_local()
+    }
+
+    fn existent(
+        &mut self,
+        (hash, index): <AttachmentChunk as CrdtInstance>::Id,
+    ) -> LocalBoxFuture<'_, Option<(Self::RowId, AttachmentChunk)>> {
+        async move {
+            let chunk = blob::Entity::find()
+                .filter(blob::Column::Hash.eq(hash.to_vec()))
+                .filter(blob::Column::Index.eq(index))
+                .one(self)
+                .await
+                .unwrap()?;
+            let id = chunk.id;
+
+            // The stored chunk belongs to no conversation in particular, only
+            // its crdt is compared.
+            Some((id, (Uuid::nil(), chunk).into()))
+        }
+        .boxed_local()
+    }
+}
@@ -1,6 +1,8 @@
-use super::{writable::CrdtWritable, CrdtInstance, CrdtTransaction};
+use super::{
+    log_conflict, writable::CrdtWritable, CrdtInstance, CrdtTransaction, CONFLICT_ON_CONTACT,
+};
 use crate::{
-    entity::contact,
+    entity::{conflict_log, contact},
     patch::{Contact, Key},
 };
 use futures::{future::LocalBoxFuture, FutureExt};
@@ -42,6 +44,8 @@ impl CrdtTransaction<Contact> for DatabaseTransaction {
                 name: ActiveValue::Set(contact.name.clone()),
                 crdt_generation: ActiveValue::Set(contact.crdt.generation),
                 crdt_author: ActiveValue::Set(contact.crdt.author.0),
+                verified: ActiveValue::NotSet,
+                alias: ActiveValue::NotSet,
             };
 
             match existent {
@@ -54,6 +58,21 @@ impl CrdtTransaction<Contact> for DatabaseTransaction {
         .boxed_local()
     }
 
+    fn conflict(&mut self, kept: &Contact, discarded: &Contact) -> LocalBoxFuture<'_, ()> {
+        let log = conflict_log::ActiveModel {
+            id: ActiveValue::NotSet,
+            kind: ActiveValue::Set(CONFLICT_ON_CONTACT.to_string()),
+            target: ActiveValue::Set(kept.key.to_vec()),
+            generation: ActiveValue::Set(kept.crdt.generation),
+            kept_author: ActiveValue::Set(kept.crdt.author.0),
+            kept_value: ActiveValue::Set(Some(kept.name.clone())),
+            discarded_author: ActiveValue::Set(discarded.crdt.author.0),
+            discarded_value: ActiveValue::Set(Some(discarded.name.clone())),
+        };
+
+        async move { log_conflict(self, log).await }.boxed_local()
+    }
+
     fn existent(&mut self, key: Key) -> LocalBoxFuture<'_, Option<(i32, Contact)>> {
         async move {
             let key = key.get_or_create(self).await;
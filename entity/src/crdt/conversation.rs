@@ -1,5 +1,11 @@
-use super::{writable::CrdtWritable, CrdtInstance, CrdtTransaction};
-use crate::{entity::conversation, patch::Conversation, uuid::SplitUuid};
+use super::{
+    log_conflict, writable::CrdtWritable, CrdtInstance, CrdtTransaction, CONFLICT_ON_CONVERSATION,
+};
+use crate::{
+    entity::{conflict_log, conversation},
+    patch::Conversation,
+    uuid::SplitUuid,
+};
 use futures::{future::LocalBoxFuture, FutureExt};
 use sea_orm::{ActiveModelTrait, ActiveValue, DatabaseTransaction, EntityTrait, QueryFilter};
 use uuid::Uuid;
@@ -39,6 +45,7 @@ impl CrdtTransaction<Conversation> for DatabaseTransaction {
                 title: ActiveValue::Set(conversation.title.clone()),
                 crdt_generation: ActiveValue::Set(conversation.crdt.generation),
                 crdt_author: ActiveValue::Set(conversation.crdt.author.0),
+                clock: ActiveValue::NotSet,
             };
 
             match existent {
@@ -62,6 +69,25 @@ impl CrdtTransaction<Conversation> for DatabaseTransaction {
         .boxed_local()
     }
 
+    fn conflict(
+        &mut self,
+        kept: &Conversation,
+        discarded: &Conversation,
+    ) -> LocalBoxFuture<'_, ()> {
+        let log = conflict_log::ActiveModel {
+            id: ActiveValue::NotSet,
+            kind: ActiveValue::Set(CONFLICT_ON_CONVERSATION.to_string()),
+            target: ActiveValue::Set(kept.id.as_bytes().to_vec()),
+            generation: ActiveValue::Set(kept.crdt.generation),
+            kept_author: ActiveValue::Set(kept.crdt.author.0),
+            kept_value: ActiveValue::Set(kept.title.clone()),
+            discarded_author: ActiveValue::Set(discarded.crdt.author.0),
+            discarded_value: ActiveValue::Set(discarded.title.clone()),
+        };
+
+        async move { log_conflict(self, log).await }.boxed_local()
+    }
+
     fn existent(
         &mut self,
         id: <Conversation as CrdtInstance>::Id,
@@ -0,0 +1,207 @@
+use super::{sequence::CrdtPosition, CrdtInstance, CrdtTransaction};
+use crate::{
+    entity::{attachment, blob, cleared_message, conversation, history_clear, message, receipt},
+    patch::{Conversation, HistoryClear},
+    uuid::{SplitUuid, UuidValue},
+};
+use futures::{future::LocalBoxFuture, FutureExt};
+use sea_orm::{
+    sea_query::OnConflict, ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction,
+    EntityTrait, QueryFilter,
+};
+use uuid::Uuid;
+
+impl CrdtInstance for HistoryClear {
+    type Id = Uuid;
+    type Crdt = CrdtPosition;
+
+    fn id(&self) -> Self::Id {
+        self.conversation
+    }
+
+    fn crdt(&self) -> Self::Crdt {
+        self.before
+    }
+
+    fn set_crdt(&mut self, crdt: Self::Crdt) {
+        self.before = crdt;
+    }
+}
+
+impl CrdtTransaction<HistoryClear> for DatabaseTransaction {
+    type RowId = i32;
+
+    fn save(
+        &mut self,
+        clear: HistoryClear,
+        existent: Option<(i32, HistoryClear)>,
+    ) -> LocalBoxFuture<'_, HistoryClear> {
+        async move {
+            let conversation = Conversation::get_or_create(clear.conversation, self).await;
+
+            let active = history_clear::ActiveModel {
+                conversation: ActiveValue::Set(conversation.id),
+                before_sequence: ActiveValue::Set(clear.before.sequence),
+                before_author: ActiveValue::Set(clear.before.author.0),
+                crdt_author: ActiveValue::Set(clear.author.0),
+                before_id: ActiveValue::Set(clear.before.id.as_bytes().to_vec()),
+            };
+            match existent {
+                Some(_) => active.update(self).await.unwrap(),
+                None => active.insert(self).await.unwrap(),
+            };
+            Conversation::advance_clock(conversation.id, clear.before.sequence, self).await;
+
+            let cleared = message::Entity::find()
+                .filter(message::Column::Conversation.eq(conversation.id))
+                .filter(clear.before.preceding())
+                .all(self)
+                .await
+                .unwrap();
+            for message in cleared {
+                forget(self, conversation.id, message.get_uuid().into()).await;
+            }
+
+            clear
+        }
+        .boxed_local()
+    }
+
+    fn existent(
+        &mut self,
+        id: <HistoryClear as CrdtInstance>::Id,
+    ) -> LocalBoxFuture<'_, Option<(i32, HistoryClear)>> {
+        async move {
+            let uuid_filter = SplitUuid::from(id).to_filter::<conversation::Column>();
+
+            let (conversation, clear) = conversation::Entity::find()
+                .find_also_related(history_clear::Entity)
+                .filter(uuid_filter.0)
+                .filter(uuid_filter.1)
+                .filter(uuid_filter.2)
+                .filter(uuid_filter.3)
+                .one(self)
+                .await
+                .unwrap()?;
+
+            Some((conversation.id, (clear?, id).into()))
+        }
+        .boxed_local()
+    }
+}
+
+/// Whether patches about the message `id` of `conversation` are dropped,
+/// because the message was cleared or, given its `position`, falls within
+/// the cleared history. In the latter case whatever arrived about it before
+/// is cleared as well.
+pub async fn drop_cleared(
+    trans: &mut DatabaseTransaction,
+    conversation: Uuid,
+    id: Uuid,
+    position: Option<CrdtPosition>,
+) -> bool {
+    let cleared = cleared_message::Entity::find_by_id(id.as_bytes().to_vec())
+        .one(&*trans)
+        .await
+        .unwrap();
+    if cleared.is_some() {
+        return true;
+    }
+
+    let Some(position) = position else { return false; };
+    let existent: Option<(i32, HistoryClear)> = trans.existent(conversation).await;
+    let Some((conversation, clear)) = existent else { return false; };
+    if position >= clear.before {
+        return false;
+    }
+
+    forget(trans, conversation, id).await;
+    true
+}
+
+/// Deletes the message `id`, its receipts and its file, remembering its uuid
+/// so that patches about it arriving later are dropped.
+async fn forget(trans: &DatabaseTransaction, conversation: i32, id: Uuid) {
+    let uuid = SplitUuid::from(id);
+
+    let uuid_filter = uuid.to_filter::<message::Column>();
+    let message = message::Entity::find()
+        .filter(uuid_filter.0)
+        .filter(uuid_filter.1)
+        .filter(uuid_filter.2)
+        .filter(uuid_filter.3)
+        .one(trans)
+        .await
+        .unwrap();
+    if let Some(message) = message {
+        message::Entity::delete_by_id(message.id)
+            .exec(trans)
+            .await
+            .unwrap();
+        if let Some(attachment) = message.attachment {
+            forget_attachment(trans, attachment).await;
+        }
+    }
+
+    let uuid_filter = uuid.to_filter::<receipt::Column>();
+    receipt::Entity::delete_many()
+        .filter(uuid_filter.0)
+        .filter(uuid_filter.1)
+        .filter(uuid_filter.2)
+        .filter(uuid_filter.3)
+        .exec(trans)
+        .await
+        .unwrap();
+
+    cleared_message::Entity::insert(cleared_message::ActiveModel {
+        uuid: ActiveValue::Set(id.as_bytes().to_vec()),
+        conversation: ActiveValue::Set(conversation),
+    })
+    .on_conflict(
+        OnConflict::column(cleared_message::Column::Uuid)
+            .do_nothing()
+            .to_owned(),
+    )
+    .exec_without_returning(trans)
+    .await
+    .unwrap();
+}
+
+/// Deletes the attachment `id` unless a message still refers to it, along
+/// with its payload unless another attachment has the same.
+async fn forget_attachment(trans: &DatabaseTransaction, id: i32) {
+    let referenced = message::Entity::find()
+        .filter(message::Column::Attachment.eq(id))
+        .one(trans)
+        .await
+        .unwrap();
+    if referenced.is_some() {
+        return;
+    }
+
+    let attachment = attachment::Entity::find_by_id(id).one(trans).await.unwrap();
+    let Some(attachment) = attachment else { return; };
+    attachment::Entity::delete_by_id(id)
+        .exec(trans)
+        .await
+        .unwrap();
+
+    // Chunks of a download that did not finish are parked under the uuid.
+    let uuid: Uuid = attachment.get_uuid().into();
+    let mut payloads = vec![uuid.as_bytes().to_vec()];
+    if let Some(hash) = attachment.hash {
+        let shared = attachment::Entity::find()
+            .filter(attachment::Column::Hash.eq(hash.clone()))
+            .one(trans)
+            .await
+            .unwrap();
+        if shared.is_none() {
+            payloads.push(hash);
+        }
+    }
+    blob::Entity::delete_many()
+        .filter(blob::Column::Hash.is_in(payloads))
+        .exec(trans)
+        .await
+        .unwrap();
+}
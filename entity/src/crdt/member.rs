@@ -1,7 +1,7 @@
-use super::{CrdtAddOnly, CrdtInstance, CrdtTransaction};
+use super::{writable::CrdtWritable, CrdtAddOnly, CrdtInstance, CrdtTransaction};
 use crate::{
-    entity::member,
-    patch::{Contact, Conversation, Key, Member},
+    entity::{key, key_supersede, member},
+    patch::{Contact, Conversation, Key, KeySupersede, Member, MemberRemoval},
 };
 use futures::{future::LocalBoxFuture, FutureExt};
 use sea_orm::{
@@ -43,6 +43,9 @@ impl CrdtTransaction<Member> for DatabaseTransaction {
                     contact: ActiveValue::Set(contact.key),
                     conversation: ActiveValue::Set(conversation.id),
                     crdt_author: ActiveValue::Set(value.crdt.0 .0),
+                    removed: ActiveValue::Set(false),
+                    removed_crdt_generation: ActiveValue::Set(0),
+                    removed_crdt_author: ActiveValue::Set(0),
                 }
                 .insert(self)
                 .await
@@ -76,3 +79,157 @@ impl CrdtTransaction<Member> for DatabaseTransaction {
         .boxed_local()
     }
 }
+
+impl CrdtInstance for MemberRemoval {
+    type Id = (Key, Uuid);
+    type Crdt = CrdtWritable;
+
+    fn id(&self) -> Self::Id {
+        (self.key.clone(), self.conversation)
+    }
+
+    fn crdt(&self) -> Self::Crdt {
+        self.crdt
+    }
+
+    fn set_crdt(&mut self, crdt: Self::Crdt) {
+        self.crdt = crdt;
+    }
+}
+
+impl CrdtTransaction<MemberRemoval> for DatabaseTransaction {
+    type RowId = (i32, i32);
+
+    fn save(
+        &mut self,
+        value: MemberRemoval,
+        existent: Option<(Self::RowId, MemberRemoval)>,
+    ) -> LocalBoxFuture<'_, MemberRemoval> {
+        async move {
+            let (_, contact) = Contact::get_or_create(value.key.clone(), self).await;
+            let conversation = Conversation::get_or_create(value.conversation, self).await;
+
+            let active = member::ActiveModel {
+                contact: ActiveValue::Unchanged(contact.key),
+                conversation: ActiveValue::Unchanged(conversation.id),
+                crdt_author: ActiveValue::NotSet,
+                removed: ActiveValue::Set(value.removed),
+                removed_crdt_generation: ActiveValue::Set(value.crdt.generation),
+                removed_crdt_author: ActiveValue::Set(value.crdt.author.0),
+            };
+
+            match existent {
+                Some(_) => active.update(self).await.unwrap(),
+                None => member::ActiveModel {
+                    contact: ActiveValue::Set(contact.key),
+                    conversation: ActiveValue::Set(conversation.id),
+                    crdt_author: ActiveValue::Set(0),
+                    ..active
+                }
+                .insert(self)
+                .await
+                .unwrap(),
+            };
+
+            value
+        }
+        .boxed_local()
+    }
+
+    fn existent(
+        &mut self,
+        id: <MemberRemoval as CrdtInstance>::Id,
+    ) -> LocalBoxFuture<'_, Option<(Self::RowId, MemberRemoval)>> {
+        async move {
+            let (key, contact) = Contact::get_or_create(id.0, self).await;
+            let conversation = Conversation::get_or_create(id.1, self).await;
+
+            member::Entity::find()
+                .filter(member::Column::Contact.eq(contact.key))
+                .filter(member::Column::Conversation.eq(conversation.id))
+                .one(self)
+                .await
+                .unwrap()
+                .map(move |model| {
+                    let id = (model.contact, model.conversation);
+                    (id, (key, model, conversation).into())
+                })
+        }
+        .boxed_local()
+    }
+}
+
+impl CrdtInstance for KeySupersede {
+    type Id = (Key, Uuid);
+    type Crdt = CrdtAddOnly;
+
+    fn id(&self) -> Self::Id {
+        (self.old.clone(), self.conversation)
+    }
+
+    fn crdt(&self) -> Self::Crdt {
+        self.crdt
+    }
+
+    fn set_crdt(&mut self, crdt: Self::Crdt) {
+        self.crdt = crdt;
+    }
+}
+
+impl CrdtTransaction<KeySupersede> for DatabaseTransaction {
+    type RowId = i32;
+
+    fn save(
+        &mut self,
+        value: KeySupersede,
+        existent: Option<(Self::RowId, KeySupersede)>,
+    ) -> LocalBoxFuture<'_, KeySupersede> {
+        async move {
+            if existent.is_none() {
+                let (old, _) = Contact::get_or_create(value.old.clone(), self).await;
+                let (new, _) = Contact::get_or_create(value.new.clone(), self).await;
+                let conversation = Conversation::get_or_create(value.conversation, self).await;
+
+                key_supersede::ActiveModel {
+                    id: ActiveValue::NotSet,
+                    conversation: ActiveValue::Set(conversation.id),
+                    old: ActiveValue::Set(old.id),
+                    new: ActiveValue::Set(new.id),
+                    signature: ActiveValue::Set(value.signature.clone()),
+                    crdt_author: ActiveValue::Set(value.crdt.0 .0),
+                }
+                .insert(self)
+                .await
+                .unwrap();
+            }
+
+            value
+        }
+        .boxed_local()
+    }
+
+    fn existent(
+        &mut self,
+        id: <KeySupersede as CrdtInstance>::Id,
+    ) -> LocalBoxFuture<'_, Option<(Self::RowId, KeySupersede)>> {
+        async move {
+            let (old, _) = Contact::get_or_create(id.0, self).await;
+            let conversation = Conversation::get_or_create(id.1, self).await;
+
+            let supersede = key_supersede::Entity::find()
+                .filter(key_supersede::Column::Conversation.eq(conversation.id))
+                .filter(key_supersede::Column::Old.eq(old.id))
+                .one(self)
+                .await
+                .unwrap()?;
+            let new = key::Entity::find_by_id(supersede.new)
+                .one(self)
+                .await
+                .unwrap()
+                .expect("Inconsistent database");
+
+            Some((supersede.id, (old, new, supersede, id.1).into()))
+        }
+        .boxed_local()
+    }
+}
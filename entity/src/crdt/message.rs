@@ -6,8 +6,8 @@ use super::{
 use crate::{
     entity::{conversation, key, message},
     patch::{
-        attachment::AttachmentMetaModel, Attachment, Contact, Conversation, Key, MessageStatus,
-        NewMessage,
+        attachment::AttachmentMetaModel, Attachment, Contact, Conversation, Key, MessageEdit,
+        MessageStatus, MessageTombstone, NewMessage,
     },
     uuid::SplitUuid,
 };
@@ -67,11 +67,34 @@ impl CrdtTransaction<NewMessage> for DatabaseTransaction {
                 crdt_sequence: ActiveValue::Set(message.crdt.sequence),
                 status_crdt_generation: ActiveValue::NotSet,
                 status_crdt_author: ActiveValue::NotSet,
+                starred: ActiveValue::NotSet,
+                deleted: ActiveValue::NotSet,
+                deleted_crdt_generation: ActiveValue::NotSet,
+                deleted_crdt_author: ActiveValue::NotSet,
+                text_crdt_generation: ActiveValue::NotSet,
+                text_crdt_author: ActiveValue::NotSet,
+                reply_to: ActiveValue::Set(message.reply_to.map(|uuid| uuid.as_bytes().to_vec())),
+                created_at: ActiveValue::Set(message.created_at),
+                mime: ActiveValue::Set(message.mime.clone()),
+                size: ActiveValue::Set(message.size.map(|size| size as i64)),
+                thumbnail: ActiveValue::Set(message.thumbnail.clone()),
+                sync_index: ActiveValue::NotSet,
             };
 
             match existent {
                 Some((id, _)) => {
                     active.id = ActiveValue::Unchanged(id);
+
+                    // An edit, or a deletion, merged before the message itself
+                    // already decided its text.
+                    let row = message::Entity::find_by_id(id)
+                        .one(self)
+                        .await
+                        .unwrap()
+                        .unwrap();
+                    if row.text_crdt_generation > 0 || row.deleted {
+                        active.text = ActiveValue::NotSet;
+                    }
                 }
                 None => {
                     let uuid = SplitUuid::from(message.id);
@@ -86,6 +109,7 @@ impl CrdtTransaction<NewMessage> for DatabaseTransaction {
             }
 
             active.save(self).await.unwrap();
+            Conversation::advance_clock(conversation.id, message.crdt.sequence, self).await;
 
             message
         }
@@ -165,6 +189,15 @@ impl CrdtWritableSequenceTransaction<NewMessage> for DatabaseTransaction {
         }
         .boxed_local()
     }
+
+    fn clock<'a>(&'a mut self, group: &'a NewMessage) -> LocalBoxFuture<'a, Option<i32>> {
+        async move {
+            Conversation::get_or_create(group.conversation, self)
+                .await
+                .clock
+        }
+        .boxed_local()
+    }
 }
 
 impl CrdtInstance for MessageStatus {
@@ -224,6 +257,18 @@ impl CrdtTransaction<MessageStatus> for DatabaseTransaction {
                         crdt_generation: ActiveValue::Set(0),
                         crdt_author: ActiveValue::Set(0),
                         crdt_sequence: ActiveValue::Set(0),
+                        starred: ActiveValue::Set(false),
+                        deleted: ActiveValue::Set(false),
+                        deleted_crdt_generation: ActiveValue::Set(0),
+                        deleted_crdt_author: ActiveValue::Set(0),
+                        text_crdt_generation: ActiveValue::Set(0),
+                        text_crdt_author: ActiveValue::Set(0),
+                        reply_to: ActiveValue::Set(None),
+                        created_at: ActiveValue::Set(0),
+                        mime: ActiveValue::Set(None),
+                        size: ActiveValue::Set(None),
+                        thumbnail: ActiveValue::Set(None),
+                        sync_index: ActiveValue::Set(None),
                     }
                 }
             };
@@ -260,3 +305,421 @@ impl CrdtTransaction<MessageStatus> for DatabaseTransaction {
         .boxed_local()
     }
 }
+
+impl CrdtInstance for MessageTombstone {
+    type Id = Uuid;
+    type Crdt = CrdtWritable;
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn crdt(&self) -> Self::Crdt {
+        self.crdt
+    }
+
+    fn set_crdt(&mut self, crdt: Self::Crdt) {
+        self.crdt = crdt;
+    }
+}
+
+impl CrdtTransaction<MessageTombstone> for DatabaseTransaction {
+    type RowId = i32;
+
+    fn save(
+        &mut self,
+        tombstone: MessageTombstone,
+        existent: Option<(Self::RowId, MessageTombstone)>,
+    ) -> LocalBoxFuture<'_, MessageTombstone> {
+        async move {
+            let conversation = Conversation::get_or_create(tombstone.conversation, self).await;
+
+            let active = match existent {
+                Some((id, _)) => message::ActiveModel {
+                    id: ActiveValue::Unchanged(id),
+                    conversation: ActiveValue::Set(conversation.id),
+                    text: ActiveValue::Set(Default::default()),
+                    deleted: ActiveValue::Set(true),
+                    deleted_crdt_generation: ActiveValue::Set(tombstone.crdt.generation),
+                    deleted_crdt_author: ActiveValue::Set(tombstone.crdt.author.0),
+                    ..Default::default()
+                },
+                None => {
+                    let (from, _) = Contact::get_or_create(Key::default(), self).await;
+                    let uuid = SplitUuid::from(tombstone.id);
+
+                    message::ActiveModel {
+                        id: ActiveValue::NotSet,
+                        uuid0: ActiveValue::Set(uuid.0),
+                        uuid1: ActiveValue::Set(uuid.1),
+                        uuid2: ActiveValue::Set(uuid.2),
+                        uuid3: ActiveValue::Set(uuid.3),
+                        status: ActiveValue::Set(0),
+                        from: ActiveValue::Set(from.id),
+                        conversation: ActiveValue::Set(conversation.id),
+                        text: ActiveValue::Set(Default::default()),
+                        attachment: ActiveValue::Set(None),
+                        status_crdt_generation: ActiveValue::Set(0),
+                        status_crdt_author: ActiveValue::Set(0),
+                        crdt_generation: ActiveValue::Set(0),
+                        crdt_author: ActiveValue::Set(0),
+                        crdt_sequence: ActiveValue::Set(0),
+                        starred: ActiveValue::Set(false),
+                        deleted: ActiveValue::Set(true),
+                        deleted_crdt_generation: ActiveValue::Set(tombstone.crdt.generation),
+                        deleted_crdt_author: ActiveValue::Set(tombstone.crdt.author.0),
+                        text_crdt_generation: ActiveValue::Set(0),
+                        text_crdt_author: ActiveValue::Set(0),
+                        reply_to: ActiveValue::Set(None),
+                        created_at: ActiveValue::Set(0),
+                        mime: ActiveValue::Set(None),
+                        size: ActiveValue::Set(None),
+                        thumbnail: ActiveValue::Set(None),
+                        sync_index: ActiveValue::Set(None),
+                    }
+                }
+            };
+
+            active.save(self).await.unwrap();
+
+            tombstone
+        }
+        .boxed_local()
+    }
+
+    fn existent(
+        &mut self,
+        id: <MessageTombstone as CrdtInstance>::Id,
+    ) -> LocalBoxFuture<'_, Option<(Self::RowId, MessageTombstone)>> {
+        async move {
+            let uuid_filter = SplitUuid::from(id).to_filter::<message::Column>();
+
+            let (message, conversation) = message::Entity::find()
+                .find_also_related(conversation::Entity)
+                .filter(uuid_filter.0)
+                .filter(uuid_filter.1)
+                .filter(uuid_filter.2)
+                .filter(uuid_filter.3)
+                .one(self)
+                .await
+                .unwrap()?;
+
+            let conversation = conversation.unwrap();
+            let id = message.id;
+
+            Some((id, (message, conversation).into()))
+        }
+        .boxed_local()
+    }
+}
+
+impl CrdtInstance for MessageEdit {
+    type Id = Uuid;
+    type Crdt = CrdtWritable;
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn crdt(&self) -> Self::Crdt {
+        self.crdt
+    }
+
+    fn set_crdt(&mut self, crdt: Self::Crdt) {
+        self.crdt = crdt;
+    }
+}
+
+impl CrdtTransaction<MessageEdit> for DatabaseTransaction {
+    type RowId = i32;
+
+    fn save(
+        &mut self,
+        edit: MessageEdit,
+        existent: Option<(Self::RowId, MessageEdit)>,
+    ) -> LocalBoxFuture<'_, MessageEdit> {
+        async move {
+            let conversation = Conversation::get_or_create(edit.conversation, self).await;
+
+            let active = match existent {
+                Some((id, _)) => {
+                    // A deleted message keeps no text, whatever edit is merged
+                    // after its tombstone.
+                    let row = message::Entity::find_by_id(id)
+                        .one(self)
+                        .await
+                        .unwrap()
+                        .unwrap();
+                    let text = match row.deleted {
+                        true => ActiveValue::NotSet,
+                        false => ActiveValue::Set(edit.text.clone()),
+                    };
+
+                    message::ActiveModel {
+                        id: ActiveValue::Unchanged(id),
+                        conversation: ActiveValue::Set(conversation.id),
+                        text,
+                        text_crdt_generation: ActiveValue::Set(edit.crdt.generation),
+                        text_crdt_author: ActiveValue::Set(edit.crdt.author.0),
+                        ..Default::default()
+                    }
+                }
+                None => {
+                    let (from, _) = Contact::get_or_create(Key::default(), self).await;
+                    let uuid = SplitUuid::from(edit.id);
+
+                    message::ActiveModel {
+                        id: ActiveValue::NotSet,
+                        uuid0: ActiveValue::Set(uuid.0),
+                        uuid1: ActiveValue::Set(uuid.1),
+                        uuid2: ActiveValue::Set(uuid.2),
+                        uuid3: ActiveValue::Set(uuid.3),
+                        status: ActiveValue::Set(0),
+                        from: ActiveValue::Set(from.id),
+                        conversation: ActiveValue::Set(conversation.id),
+                        text: ActiveValue::Set(edit.text.clone()),
+                        attachment: ActiveValue::Set(None),
+                        status_crdt_generation: ActiveValue::Set(0),
+                        status_crdt_author: ActiveValue::Set(0),
+                        crdt_generation: ActiveValue::Set(0),
+                        crdt_author: ActiveValue::Set(0),
+                        crdt_sequence: ActiveValue::Set(0),
+                        starred: ActiveValue::Set(false),
+                        deleted: ActiveValue::Set(false),
+                        deleted_crdt_generation: ActiveValue::Set(0),
+                        deleted_crdt_author: ActiveValue::Set(0),
+                        text_crdt_generation: ActiveValue::Set(edit.crdt.generation),
+                        text_crdt_author: ActiveValue::Set(edit.crdt.author.0),
+                        reply_to: ActiveValue::Set(None),
+                        created_at: ActiveValue::Set(0),
+                        mime: ActiveValue::Set(None),
+                        size: ActiveValue::Set(None),
+                        thumbnail: ActiveValue::Set(None),
+                        sync_index: ActiveValue::Set(None),
+                    }
+                }
+            };
+
+            active.save(self).await.unwrap();
+
+            edit
+        }
+        .boxed_local()
+    }
+
+    fn existent(
+        &mut self,
+        id: <MessageEdit as CrdtInstance>::Id,
+    ) -> LocalBoxFuture<'_, Option<(Self::RowId, MessageEdit)>> {
+        async move {
+            let uuid_filter = SplitUuid::from(id).to_filter::<message::Column>();
+
+            let (message, conversation) = message::Entity::find()
+                .find_also_related(conversation::Entity)
+                .filter(uuid_filter.0)
+                .filter(uuid_filter.1)
+                .filter(uuid_filter.2)
+                .filter(uuid_filter.3)
+                .one(self)
+                .await
+                .unwrap()?;
+
+            let conversation = conversation.unwrap();
+            let id = message.id;
+
+            Some((id, (message, conversation).into()))
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::writable::CrdtWritableTransaction;
+    use migration::MigratorTrait;
+    use sea_orm::{ConnectOptions, Database, DatabaseConnection, TransactionTrait};
+
+    async fn a_peer() -> DatabaseConnection {
+        let mut options = ConnectOptions::new("sqlite::memory:".to_string());
+        options.max_connections(1);
+        let connection = Database::connect(options).await.unwrap();
+        migration::Migrator::up(&connection, None).await.unwrap();
+
+        connection
+    }
+
+    async fn merge<P: CrdtInstance + 'static>(peer: &DatabaseConnection, patch: P) -> Option<P>
+    where
+        DatabaseTransaction: CrdtTransaction<P>,
+    {
+        let mut trans = peer.begin().await.unwrap();
+        let merged = trans.merge(patch).await;
+        trans.commit().await.unwrap();
+
+        merged
+    }
+
+    async fn edit(peer: &DatabaseConnection, author: Author, id: Uuid, text: &str) -> MessageEdit {
+        let mut trans = peer.begin().await.unwrap();
+        let edit = trans
+            .set(
+                author,
+                MessageEdit {
+                    id,
+                    conversation: CONVERSATION,
+                    text: text.to_string(),
+                    crdt: Default::default(),
+                },
+            )
+            .await;
+        trans.commit().await.unwrap();
+
+        edit
+    }
+
+    async fn text(peer: &DatabaseConnection) -> String {
+        message::Entity::find()
+            .one(peer)
+            .await
+            .unwrap()
+            .unwrap()
+            .text
+    }
+
+    const CONVERSATION: Uuid = Uuid::from_u128(7);
+    const ALICE: Author = Author(1);
+    const BOB: Author = Author(2);
+
+    fn a_message() -> NewMessage {
+        NewMessage {
+            id: Uuid::from_u128(11),
+            from: Key::default(),
+            conversation: CONVERSATION,
+            text: "hello".to_string(),
+            attachment: None,
+            mime: None,
+            size: None,
+            thumbnail: None,
+            reply_to: None,
+            created_at: 0,
+            crdt: CrdtWritableSequence {
+                writable: CrdtWritable {
+                    generation: 1,
+                    author: ALICE,
+                },
+                sequence: 1,
+            },
+        }
+    }
+
+    mod given_a_message_on_two_peers {
+        use super::*;
+
+        type Given = (DatabaseConnection, DatabaseConnection, NewMessage);
+        async fn given() -> Given {
+            let (alice, bob) = (a_peer().await, a_peer().await);
+            let message = a_message();
+            for peer in [&alice, &bob] {
+                merge(peer, message.clone()).await;
+            }
+
+            (alice, bob, message)
+        }
+
+        #[tokio::test]
+        async fn then_the_edit_with_the_highest_generation_wins() {
+            let (alice, bob, message) = given().await;
+
+            edit(&alice, ALICE, message.id, "first").await;
+            let from_alice = edit(&alice, ALICE, message.id, "second").await;
+            let from_bob = edit(&bob, BOB, message.id, "bob").await;
+            merge(&alice, from_bob).await;
+            merge(&bob, from_alice).await;
+
+            assert_eq!(text(&alice).await, "second");
+            assert_eq!(text(&bob).await, "second");
+        }
+
+        #[tokio::test]
+        async fn then_edits_of_the_same_generation_resolve_by_author() {
+            let (alice, bob, message) = given().await;
+
+            let from_alice = edit(&alice, ALICE, message.id, "alice").await;
+            let from_bob = edit(&bob, BOB, message.id, "bob").await;
+            merge(&alice, from_bob).await;
+            merge(&bob, from_alice).await;
+
+            assert_eq!(text(&alice).await, "bob");
+            assert_eq!(text(&bob).await, "bob");
+        }
+    }
+
+    mod given_a_deleted_message {
+        use super::*;
+
+        type Given = (DatabaseConnection, NewMessage);
+        async fn given() -> Given {
+            let (alice, bob) = (a_peer().await, a_peer().await);
+            let message = a_message();
+            merge(&alice, message.clone()).await;
+            let late_edit = edit(&alice, ALICE, message.id, "edited").await;
+
+            let mut trans = bob.begin().await.unwrap();
+            trans
+                .set(
+                    BOB,
+                    MessageTombstone {
+                        id: message.id,
+                        conversation: CONVERSATION,
+                        crdt: Default::default(),
+                    },
+                )
+                .await;
+            trans.commit().await.unwrap();
+            merge(&bob, late_edit).await;
+
+            (bob, message)
+        }
+
+        #[tokio::test]
+        async fn then_an_edit_arriving_after_the_tombstone_writes_no_text() {
+            let (bob, ..) = given().await;
+
+            assert_eq!(text(&bob).await, "");
+        }
+
+        #[tokio::test]
+        async fn then_the_message_arriving_after_the_tombstone_writes_no_text() {
+            let (bob, message) = given().await;
+
+            merge(&bob, message).await;
+
+            assert_eq!(text(&bob).await, "");
+        }
+    }
+
+    mod given_an_edit_received_before_its_message {
+        use super::*;
+
+        type Given = DatabaseConnection;
+        async fn given() -> Given {
+            let (alice, bob) = (a_peer().await, a_peer().await);
+            let message = a_message();
+            merge(&alice, message.clone()).await;
+            let edited = edit(&alice, ALICE, message.id, "edited").await;
+
+            merge(&bob, edited).await;
+            merge(&bob, message).await;
+
+            bob
+        }
+
+        #[tokio::test]
+        async fn then_the_edited_text_is_kept() {
+            let bob = given().await;
+
+            assert_eq!(text(&bob).await, "edited");
+        }
+    }
+}
@@ -1,16 +1,29 @@
 pub mod attachment;
 pub mod contact;
 pub mod conversation;
+pub mod history;
 pub mod member;
 pub mod message;
+pub mod receipt;
 pub mod sequence;
 pub mod writable;
 
+use crate::entity::conflict_log;
 use futures::{future::LocalBoxFuture, FutureExt};
+use sea_orm::{sea_query::OnConflict, DatabaseTransaction, EntityTrait};
 use serde::{Deserialize, Serialize};
 
+/// Who wrote a CRDT value, and so which of two concurrent writes wins. Derived
+/// from the writer's key, wide enough that distinct keys do not tie.
 #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
-pub struct Author(pub i32);
+pub struct Author(pub i64);
+
+/// Kind of a conflict on a conversation in the `conflict_log` table, its target
+/// is the uuid of the conversation.
+pub const CONFLICT_ON_CONVERSATION: &str = "conversation";
+/// Kind of a conflict on a contact in the `conflict_log` table, its target is
+/// the key of the contact.
+pub const CONFLICT_ON_CONTACT: &str = "contact";
 
 pub trait CrdtInstance: Sized {
     type Id;
@@ -23,6 +36,12 @@ pub trait CrdtInstance: Sized {
 
 pub trait CrdtOrd: Ord + Default + Sized {
     fn next(&self, author: Author) -> Self;
+
+    /// Whether `self` and `other` were written without either knowing of the
+    /// other, so that merging them discards a write nobody saw.
+    fn concurrent(&self, _other: &Self) -> bool {
+        false
+    }
 }
 
 #[derive(Clone, Copy, Default, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -42,9 +61,17 @@ pub trait CrdtTransaction<V: CrdtInstance + 'static> {
             let existent = existent_rowid.as_ref().map(|(_, existent)| existent);
 
             if let Some(existent) = existent {
+                let concurrent = existent.crdt().concurrent(&value.crdt());
                 if existent.crdt() >= value.crdt() {
+                    if concurrent {
+                        self.conflict(existent, &value).await;
+                    }
                     return None;
                 }
+
+                if concurrent {
+                    self.conflict(&value, existent).await;
+                }
             }
 
             Some(self.save(value, existent_rowid).await)
@@ -52,9 +79,34 @@ pub trait CrdtTransaction<V: CrdtInstance + 'static> {
         .boxed_local()
     }
 
+    /// Called by [`CrdtTransaction::merge`] when `discarded` loses to the
+    /// concurrent write `kept`. Does nothing unless overridden.
+    fn conflict(&mut self, _kept: &V, _discarded: &V) -> LocalBoxFuture<'_, ()> {
+        async {}.boxed_local()
+    }
+
     fn save(&mut self, value: V, existent: Option<(Self::RowId, V)>) -> LocalBoxFuture<'_, V>;
     fn existent(
         &mut self,
         id: <V as CrdtInstance>::Id,
     ) -> LocalBoxFuture<'_, Option<(Self::RowId, V)>>;
 }
+
+/// Records a write lost to a concurrent one, once. The same discarded write
+/// arrives again over every channel relaying it.
+async fn log_conflict(trans: &DatabaseTransaction, log: conflict_log::ActiveModel) {
+    conflict_log::Entity::insert(log)
+        .on_conflict(
+            OnConflict::columns([
+                conflict_log::Column::Kind,
+                conflict_log::Column::Target,
+                conflict_log::Column::Generation,
+                conflict_log::Column::DiscardedAuthor,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(trans)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,96 @@
+use super::{writable::CrdtWritable, CrdtInstance, CrdtTransaction};
+use crate::{
+    entity::{conversation, receipt},
+    patch::{Contact, Conversation, Key, Receipt},
+    uuid::SplitUuid,
+};
+use futures::{future::LocalBoxFuture, FutureExt};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter,
+};
+use uuid::Uuid;
+
+impl CrdtInstance for Receipt {
+    type Id = (Uuid, Key);
+    type Crdt = CrdtWritable;
+
+    fn id(&self) -> Self::Id {
+        (self.message, self.member.clone())
+    }
+
+    fn crdt(&self) -> Self::Crdt {
+        self.crdt
+    }
+
+    fn set_crdt(&mut self, crdt: Self::Crdt) {
+        self.crdt = crdt;
+    }
+}
+
+impl CrdtTransaction<Receipt> for DatabaseTransaction {
+    type RowId = i32;
+
+    fn save(
+        &mut self,
+        value: Receipt,
+        existent: Option<(i32, Receipt)>,
+    ) -> LocalBoxFuture<'_, Receipt> {
+        async move {
+            let (member, _) = Contact::get_or_create(value.member.clone(), self).await;
+            let conversation = Conversation::get_or_create(value.conversation, self).await;
+            let uuid = SplitUuid::from(value.message);
+
+            receipt::ActiveModel {
+                id: match existent {
+                    Some((id, _)) => ActiveValue::Unchanged(id),
+                    None => ActiveValue::NotSet,
+                },
+                uuid0: ActiveValue::Set(uuid.0),
+                uuid1: ActiveValue::Set(uuid.1),
+                uuid2: ActiveValue::Set(uuid.2),
+                uuid3: ActiveValue::Set(uuid.3),
+                conversation: ActiveValue::Set(conversation.id),
+                contact: ActiveValue::Set(member.id),
+                status: ActiveValue::Set(value.status),
+                crdt_generation: ActiveValue::Set(value.crdt.generation),
+                crdt_author: ActiveValue::Set(value.crdt.author.0),
+            }
+            .save(self)
+            .await
+            .unwrap();
+
+            value
+        }
+        .boxed_local()
+    }
+
+    fn existent(
+        &mut self,
+        (message, member): <Receipt as CrdtInstance>::Id,
+    ) -> LocalBoxFuture<'_, Option<(i32, Receipt)>> {
+        async move {
+            let uuid_filter = SplitUuid::from(message).to_filter::<receipt::Column>();
+            let (member, _) = Contact::get_or_create(member, self).await;
+
+            let receipt = receipt::Entity::find()
+                .filter(uuid_filter.0)
+                .filter(uuid_filter.1)
+                .filter(uuid_filter.2)
+                .filter(uuid_filter.3)
+                .filter(receipt::Column::Contact.eq(member.id))
+                .one(self)
+                .await
+                .unwrap()?;
+
+            let conversation = conversation::Entity::find_by_id(receipt.conversation)
+                .one(self)
+                .await
+                .unwrap()
+                .unwrap();
+            let id = receipt.id;
+
+            Some((id, (receipt, member, conversation).into()))
+        }
+        .boxed_local()
+    }
+}
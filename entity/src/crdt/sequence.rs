@@ -1,6 +1,10 @@
 use super::{writable::CrdtWritable, Author, CrdtInstance, CrdtOrd, CrdtTransaction};
+use crate::{entity::message, uuid::SplitUuid};
 use futures::{future::LocalBoxFuture, FutureExt};
+use sea_orm::{sea_query::SimpleExpr, ColumnTrait, Condition};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use uuid::Uuid;
 
 #[derive(Clone, Copy, Default, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct CrdtWritableSequence {
@@ -16,6 +20,113 @@ impl CrdtOrd for CrdtWritableSequence {
     }
 }
 
+/// Where a message sits in its conversation, ordered the way messages are
+/// listed: by sequence, then by author, then by uuid as split in the message
+/// table. The uuid tells apart messages pushed at once by devices sharing an
+/// author.
+#[derive(Clone, Copy, Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CrdtPosition {
+    pub sequence: i32,
+    pub author: Author,
+    pub id: Uuid,
+}
+impl CrdtPosition {
+    pub fn new(crdt: CrdtWritableSequence, id: Uuid) -> Self {
+        CrdtPosition {
+            sequence: crdt.sequence,
+            author: crdt.writable.author,
+            id,
+        }
+    }
+
+    /// Before every message of `sequence`.
+    pub fn start_of(sequence: i32) -> Self {
+        CrdtPosition {
+            sequence,
+            author: Author(i64::MIN),
+            id: SplitUuid(i32::MIN, i32::MIN, i32::MIN, i32::MIN).into(),
+        }
+    }
+
+    /// Messages positioned before this.
+    pub fn preceding(&self) -> Condition {
+        before_cursor(&self.cursor())
+    }
+
+    /// Messages positioned after this.
+    pub fn following(&self) -> Condition {
+        after_cursor(&self.cursor())
+    }
+
+    /// Values of the message columns messages are positioned by.
+    pub fn cursor(&self) -> [(message::Column, i64); 6] {
+        let id = SplitUuid::from(self.id);
+
+        [
+            (message::Column::CrdtSequence, self.sequence.into()),
+            (message::Column::CrdtAuthor, self.author.0),
+            (message::Column::Uuid0, id.0.into()),
+            (message::Column::Uuid1, id.1.into()),
+            (message::Column::Uuid2, id.2.into()),
+            (message::Column::Uuid3, id.3.into()),
+        ]
+    }
+
+    fn key(&self) -> (i32, Author, SplitUuid) {
+        (self.sequence, self.author, self.id.into())
+    }
+}
+impl PartialOrd for CrdtPosition {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for CrdtPosition {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+impl CrdtOrd for CrdtPosition {
+    fn next(&self, author: Author) -> Self {
+        CrdtPosition {
+            sequence: self.sequence + 1,
+            author,
+            id: self.id,
+        }
+    }
+}
+
+/// Messages sorting before `cursor`, the value of each column to sort by, the
+/// first one first.
+pub fn before_cursor(cursor: &[(message::Column, i64)]) -> Condition {
+    past_cursor(cursor, message::Column::lt)
+}
+
+/// Messages sorting after `cursor`, see [`before_cursor`].
+pub fn after_cursor(cursor: &[(message::Column, i64)]) -> Condition {
+    past_cursor(cursor, message::Column::gt)
+}
+
+fn past_cursor(
+    cursor: &[(message::Column, i64)],
+    past: fn(&message::Column, i64) -> SimpleExpr,
+) -> Condition {
+    let ((column, value), rest) = cursor.split_first().expect("Empty cursor");
+    let beyond = Condition::any().add(past(column, *value));
+    if rest.is_empty() {
+        return beyond;
+    }
+
+    beyond.add(
+        Condition::all()
+            .add(column.eq(*value))
+            .add(past_cursor(rest, past)),
+    )
+}
+
+/// Sequences are Lamport timestamps: a pushed value goes after everything its
+/// author has seen in the group, so that a reply always follows what it
+/// answers. Concurrent pushes share a sequence and are ordered by author.
 pub trait CrdtWritableSequenceTransaction<V: CrdtInstance<Crdt = CrdtWritableSequence> + 'static>:
     CrdtTransaction<V>
 {
@@ -27,7 +138,9 @@ pub trait CrdtWritableSequenceTransaction<V: CrdtInstance<Crdt = CrdtWritableSeq
                 .map(|(_, existent)| existent.crdt())
                 .unwrap_or_default()
                 .next(author);
-            crdt.sequence = self.last(&value).await.unwrap_or_default().sequence + 1;
+            let last = self.last(&value).await.unwrap_or_default().sequence;
+            let clock = self.clock(&value).await.unwrap_or_default();
+            crdt.sequence = last.max(clock) + 1;
             value.set_crdt(crdt);
 
             self.save(value, existent).await
@@ -36,6 +149,13 @@ pub trait CrdtWritableSequenceTransaction<V: CrdtInstance<Crdt = CrdtWritableSeq
     }
 
     fn last<'a>(&'a mut self, group: &'a V) -> LocalBoxFuture<'a, Option<CrdtWritableSequence>>;
+
+    /// Highest sequence seen in the group, kept by groups whose values can go
+    /// away. `None` when only the values stored count, see
+    /// [`CrdtWritableSequenceTransaction::last`].
+    fn clock<'a>(&'a mut self, _group: &'a V) -> LocalBoxFuture<'a, Option<i32>> {
+        async move { None }.boxed_local()
+    }
 }
 
 #[cfg(test)]
@@ -43,7 +163,7 @@ pub mod tests {
     use super::*;
 
     #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
-    struct CrdtSequenceMock(char, i32, i32, i32);
+    struct CrdtSequenceMock(char, i32, i64, i32);
     impl CrdtInstance for CrdtSequenceMock {
         type Id = char;
         type Crdt = CrdtWritableSequence;
@@ -70,7 +190,7 @@ pub mod tests {
     }
 
     #[derive(Default)]
-    struct CrdtWritableSequenceTransactionMock(Vec<CrdtSequenceMock>);
+    struct CrdtWritableSequenceTransactionMock(Vec<CrdtSequenceMock>, Option<i32>);
     impl CrdtTransaction<CrdtSequenceMock> for CrdtWritableSequenceTransactionMock {
         type RowId = usize;
 
@@ -84,6 +204,7 @@ pub mod tests {
                     Some((row, _)) => self.0[row] = value,
                     None => self.0.push(value),
                 }
+                self.1 = Some(self.1.unwrap_or_default().max(value.3));
 
                 value
             }
@@ -116,6 +237,10 @@ pub mod tests {
             }
             .boxed_local()
         }
+
+        fn clock(&mut self, _group: &CrdtSequenceMock) -> LocalBoxFuture<'_, Option<i32>> {
+            async move { self.1 }.boxed_local()
+        }
     }
 
     mod when_pushing_a_new_value {
@@ -137,7 +262,7 @@ pub mod tests {
         #[tokio::test]
         async fn on_list_with_elements_will_be_one_plus_the_last_element() {
             let mut list =
-                CrdtWritableSequenceTransactionMock(vec![CrdtSequenceMock('b', 1, 7, 1)]);
+                CrdtWritableSequenceTransactionMock(vec![CrdtSequenceMock('b', 1, 7, 1)], None);
 
             let item = CrdtSequenceMock('a', 0, 0, 0);
 
@@ -158,7 +283,7 @@ pub mod tests {
         #[tokio::test]
         async fn if_pushing_an_already_existent_it_goes_to_end_of_list() {
             let mut list =
-                CrdtWritableSequenceTransactionMock(vec![CrdtSequenceMock('a', 3, 7, 1)]);
+                CrdtWritableSequenceTransactionMock(vec![CrdtSequenceMock('a', 3, 7, 1)], None);
 
             let item = CrdtSequenceMock('a', 0, 0, 0);
 
@@ -170,4 +295,63 @@ pub mod tests {
             assert_eq!(list.0, vec![CrdtSequenceMock('a', 4, 5, 2),])
         }
     }
+
+    mod when_two_authors_push_concurrently {
+        use super::*;
+
+        const ALICE: Author = Author(1);
+        const BOB: Author = Author(2);
+
+        type Given = (
+            CrdtWritableSequenceTransactionMock,
+            CrdtWritableSequenceTransactionMock,
+        );
+        async fn given() -> Given {
+            let shared = CrdtSequenceMock('a', 1, ALICE.0, 1);
+            let mut alice = CrdtWritableSequenceTransactionMock(vec![shared], None);
+            let mut bob = CrdtWritableSequenceTransactionMock(vec![shared], None);
+
+            let from_alice = alice.push(ALICE, CrdtSequenceMock('b', 0, 0, 0)).await;
+            let from_bob = bob.push(BOB, CrdtSequenceMock('c', 0, 0, 0)).await;
+            alice.merge(from_bob).await;
+            bob.merge(from_alice).await;
+
+            (alice, bob)
+        }
+
+        fn listed(list: &CrdtWritableSequenceTransactionMock) -> Vec<char> {
+            let mut values = list.0.clone();
+            values.sort_by_key(|value| CrdtPosition::new(value.crdt(), Uuid::nil()));
+            values.iter().map(|value| value.0).collect()
+        }
+
+        #[tokio::test]
+        async fn then_both_are_listed_in_the_same_order_by_author() {
+            let (alice, bob) = given().await;
+
+            assert_eq!(listed(&alice), ['a', 'b', 'c']);
+            assert_eq!(listed(&bob), listed(&alice));
+            assert_eq!(alice.0[1].3, alice.0[2].3);
+        }
+
+        #[tokio::test]
+        async fn then_the_next_push_follows_both() {
+            let (mut alice, _) = given().await;
+
+            let reply = alice.push(ALICE, CrdtSequenceMock('d', 0, 0, 0)).await;
+
+            assert_eq!(reply, CrdtSequenceMock('d', 1, ALICE.0, 3));
+            assert_eq!(listed(&alice), ['a', 'b', 'c', 'd']);
+        }
+
+        #[tokio::test]
+        async fn then_the_next_push_follows_them_even_once_they_are_gone() {
+            let (_, mut bob) = given().await;
+            bob.0.retain(|value| value.0 == 'a');
+
+            let reply = bob.push(BOB, CrdtSequenceMock('d', 0, 0, 0)).await;
+
+            assert_eq!(reply, CrdtSequenceMock('d', 1, BOB.0, 3));
+        }
+    }
 }
@@ -14,6 +14,10 @@ impl CrdtOrd for CrdtWritable {
             generation: self.generation + 1,
         }
     }
+
+    fn concurrent(&self, other: &Self) -> bool {
+        self.generation == other.generation && self.author != other.author
+    }
 }
 
 impl<V: CrdtInstance<Crdt = CrdtWritable> + 'static, S: CrdtTransaction<V>>
@@ -44,7 +48,7 @@ pub mod tests {
     use super::*;
 
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-    struct CrdtValueMock(usize, i32, i32);
+    struct CrdtValueMock(usize, i32, i64);
     impl CrdtInstance for CrdtValueMock {
         type Id = usize;
         type Crdt = CrdtWritable;
@@ -128,6 +132,28 @@ pub mod tests {
         }
     }
 
+    mod when_comparing_two_writes {
+        use super::*;
+
+        fn write(generation: i32, author: i64) -> CrdtWritable {
+            CrdtWritable {
+                generation,
+                author: Author(author),
+            }
+        }
+
+        #[test]
+        fn same_generation_from_different_authors_are_concurrent() {
+            assert!(write(2, 3).concurrent(&write(2, 5)));
+        }
+
+        #[test]
+        fn the_same_write_or_another_generation_is_not_concurrent() {
+            assert!(!write(2, 3).concurrent(&write(2, 3)));
+            assert!(!write(2, 3).concurrent(&write(3, 5)));
+        }
+    }
+
     mod when_merging_a_value {
         use super::*;
 
@@ -12,8 +12,8 @@ pub struct Model {
     pub uuid2: i32,
     pub uuid3: i32,
     pub conversation: i32,
-    pub payload: Option<Vec<u8>>,
-    pub crdt_author: i32,
+    pub crdt_author: i64,
+    pub hash: Option<Vec<u8>>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
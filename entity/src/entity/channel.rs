@@ -10,6 +10,9 @@ pub struct Model {
     pub conversation: i32,
     pub peer: i32,
     pub sync_index: i32,
+    pub snapshot: Option<i32>,
+    pub snapshot_index: i32,
+    pub local_key: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -22,8 +25,6 @@ pub enum Relation {
         on_delete = "Cascade"
     )]
     Conversation,
-    #[sea_orm(has_many = "super::initial_sync::Entity")]
-    InitialSync,
     #[sea_orm(
         belongs_to = "super::key::Entity",
         from = "Column::Peer",
@@ -32,6 +33,14 @@ pub enum Relation {
         on_delete = "Restrict"
     )]
     Key,
+    #[sea_orm(
+        belongs_to = "super::snapshot::Entity",
+        from = "Column::Snapshot",
+        to = "super::snapshot::Column::Id",
+        on_update = "NoAction",
+        on_delete = "NoAction"
+    )]
+    Snapshot,
 }
 
 impl Related<super::conversation::Entity> for Entity {
@@ -40,15 +49,15 @@ impl Related<super::conversation::Entity> for Entity {
     }
 }
 
-impl Related<super::initial_sync::Entity> for Entity {
+impl Related<super::key::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::InitialSync.def()
+        Relation::Key.def()
     }
 }
 
-impl Related<super::key::Entity> for Entity {
+impl Related<super::snapshot::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::Key.def()
+        Relation::Snapshot.def()
     }
 }
 
@@ -9,7 +9,9 @@ pub struct Model {
     pub key: i32,
     pub name: String,
     pub crdt_generation: i32,
-    pub crdt_author: i32,
+    pub crdt_author: i64,
+    pub verified: bool,
+    pub alias: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -26,6 +28,8 @@ pub enum Relation {
     Member,
     #[sea_orm(has_many = "super::message::Entity")]
     Message,
+    #[sea_orm(has_many = "super::receipt::Entity")]
+    Receipt,
 }
 
 impl Related<super::key::Entity> for Entity {
@@ -46,4 +50,10 @@ impl Related<super::message::Entity> for Entity {
     }
 }
 
+impl Related<super::receipt::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Receipt.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -13,7 +13,8 @@ pub struct Model {
     pub uuid3: i32,
     pub title: Option<String>,
     pub crdt_generation: i32,
-    pub crdt_author: i32,
+    pub crdt_author: i64,
+    pub clock: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -22,10 +23,24 @@ pub enum Relation {
     Attachment,
     #[sea_orm(has_many = "super::channel::Entity")]
     Channel,
+    #[sea_orm(has_many = "super::cleared_message::Entity")]
+    ClearedMessage,
+    #[sea_orm(has_one = "super::history_clear::Entity")]
+    HistoryClear,
+    #[sea_orm(has_many = "super::invite::Entity")]
+    Invite,
+    #[sea_orm(has_many = "super::key_supersede::Entity")]
+    KeySupersede,
+    #[sea_orm(has_one = "super::local_conversation_settings::Entity")]
+    LocalConversationSettings,
     #[sea_orm(has_many = "super::member::Entity")]
     Member,
     #[sea_orm(has_many = "super::message::Entity")]
     Message,
+    #[sea_orm(has_many = "super::receipt::Entity")]
+    Receipt,
+    #[sea_orm(has_many = "super::snapshot::Entity")]
+    Snapshot,
 }
 
 impl Related<super::attachment::Entity> for Entity {
@@ -40,6 +55,36 @@ impl Related<super::channel::Entity> for Entity {
     }
 }
 
+impl Related<super::cleared_message::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ClearedMessage.def()
+    }
+}
+
+impl Related<super::history_clear::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::HistoryClear.def()
+    }
+}
+
+impl Related<super::invite::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Invite.def()
+    }
+}
+
+impl Related<super::key_supersede::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::KeySupersede.def()
+    }
+}
+
+impl Related<super::local_conversation_settings::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::LocalConversationSettings.def()
+    }
+}
+
 impl Related<super::member::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Member.def()
@@ -52,4 +97,16 @@ impl Related<super::message::Entity> for Entity {
     }
 }
 
+impl Related<super::receipt::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Receipt.def()
+    }
+}
+
+impl Related<super::snapshot::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Snapshot.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -7,25 +7,25 @@ use sea_orm::entity::prelude::*;
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
-    pub channel: i32,
+    pub snapshot: i32,
     pub payload: Vec<u8>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(
-        belongs_to = "super::channel::Entity",
-        from = "Column::Channel",
-        to = "super::channel::Column::Id",
+        belongs_to = "super::snapshot::Entity",
+        from = "Column::Snapshot",
+        to = "super::snapshot::Column::Id",
         on_update = "NoAction",
         on_delete = "Cascade"
     )]
-    Channel,
+    Snapshot,
 }
 
-impl Related<super::channel::Entity> for Entity {
+impl Related<super::snapshot::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::Channel.def()
+        Relation::Snapshot.def()
     }
 }
 
@@ -22,6 +22,10 @@ pub enum Relation {
     Member,
     #[sea_orm(has_many = "super::message::Entity")]
     Message,
+    #[sea_orm(has_many = "super::message_delivery::Entity")]
+    MessageDelivery,
+    #[sea_orm(has_many = "super::receipt::Entity")]
+    Receipt,
 }
 
 impl Related<super::channel::Entity> for Entity {
@@ -54,4 +58,16 @@ impl Related<super::message::Entity> for Entity {
     }
 }
 
+impl Related<super::message_delivery::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MessageDelivery.def()
+    }
+}
+
+impl Related<super::receipt::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Receipt.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
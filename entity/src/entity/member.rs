@@ -9,7 +9,10 @@ pub struct Model {
     pub contact: i32,
     #[sea_orm(primary_key, auto_increment = false)]
     pub conversation: i32,
-    pub crdt_author: i32,
+    pub crdt_author: i64,
+    pub removed: bool,
+    pub removed_crdt_generation: i32,
+    pub removed_crdt_author: i64,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -17,10 +17,22 @@ pub struct Model {
     pub text: String,
     pub attachment: Option<i32>,
     pub crdt_generation: i32,
-    pub crdt_author: i32,
+    pub crdt_author: i64,
     pub status_crdt_generation: i32,
-    pub status_crdt_author: i32,
+    pub status_crdt_author: i64,
     pub crdt_sequence: i32,
+    pub starred: bool,
+    pub deleted: bool,
+    pub deleted_crdt_generation: i32,
+    pub deleted_crdt_author: i64,
+    pub text_crdt_generation: i32,
+    pub text_crdt_author: i64,
+    pub reply_to: Option<Vec<u8>>,
+    pub created_at: i64,
+    pub mime: Option<String>,
+    pub size: Option<i64>,
+    pub thumbnail: Option<Vec<u8>>,
+    pub sync_index: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -57,6 +69,8 @@ pub enum Relation {
         on_delete = "Restrict"
     )]
     Key,
+    #[sea_orm(has_one = "super::message_delivery::Entity")]
+    MessageDelivery,
 }
 
 impl Related<super::attachment::Entity> for Entity {
@@ -83,4 +97,10 @@ impl Related<super::key::Entity> for Entity {
     }
 }
 
+impl Related<super::message_delivery::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MessageDelivery.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
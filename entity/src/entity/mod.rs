@@ -3,12 +3,25 @@
 pub mod prelude;
 
 pub mod attachment;
+pub mod blob;
+pub mod blocked;
 pub mod channel;
+pub mod cleared_message;
+pub mod conflict_log;
 pub mod contact;
 pub mod conversation;
+pub mod device;
+pub mod history_clear;
 pub mod initial_sync;
+pub mod invite;
 pub mod key;
+pub mod key_supersede;
 pub mod local;
+pub mod local_conversation_settings;
 pub mod member;
 pub mod message;
+pub mod message_delivery;
+pub mod preference;
+pub mod receipt;
+pub mod snapshot;
 pub mod sync;
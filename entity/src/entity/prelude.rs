@@ -1,12 +1,25 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.0
 
 pub use super::attachment::Entity as Attachment;
+pub use super::blob::Entity as Blob;
+pub use super::blocked::Entity as Blocked;
 pub use super::channel::Entity as Channel;
+pub use super::cleared_message::Entity as ClearedMessage;
+pub use super::conflict_log::Entity as ConflictLog;
 pub use super::contact::Entity as Contact;
 pub use super::conversation::Entity as Conversation;
+pub use super::device::Entity as Device;
+pub use super::history_clear::Entity as HistoryClear;
 pub use super::initial_sync::Entity as InitialSync;
+pub use super::invite::Entity as Invite;
 pub use super::key::Entity as Key;
+pub use super::key_supersede::Entity as KeySupersede;
 pub use super::local::Entity as Local;
+pub use super::local_conversation_settings::Entity as LocalConversationSettings;
 pub use super::member::Entity as Member;
 pub use super::message::Entity as Message;
+pub use super::message_delivery::Entity as MessageDelivery;
+pub use super::preference::Entity as Preference;
+pub use super::receipt::Entity as Receipt;
+pub use super::snapshot::Entity as Snapshot;
 pub use super::sync::Entity as Sync;
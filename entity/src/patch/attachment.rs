@@ -1,21 +1,27 @@
 use super::Conversation;
 use crate::{
     crdt::{Author, CrdtAddOnly},
-    entity::{attachment, conversation},
+    entity::{attachment, blob, conversation},
     uuid::{SplitUuid, UuidValue},
 };
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, DatabaseTransaction, EntityTrait, FromQueryResult, Iterable,
-    QueryFilter, QuerySelect, SelectModel, Selector, TryIntoModel,
+    ActiveModelTrait, ActiveValue, DatabaseTransaction, EntityTrait, FromQueryResult, QueryFilter,
+    SelectModel, Selector, TryIntoModel,
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// BLAKE3 hash of the payload of an attachment. Payloads are stored and
+/// synced under their hash, so attachments of the same file share its bytes.
+pub type BlobHash = [u8; 32];
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Attachment {
     pub id: Uuid,
+    #[serde(with = "crate::uuid::interned")]
     pub conversation: Uuid,
-    pub payload: Option<Vec<u8>>,
+    /// `None` while only a message referencing the attachment is known.
+    pub hash: Option<BlobHash>,
     pub crdt: CrdtAddOnly,
 }
 impl From<(Uuid, attachment::Model)> for Attachment {
@@ -23,7 +29,7 @@ impl From<(Uuid, attachment::Model)> for Attachment {
         Attachment {
             id: attachment.get_uuid().into(),
             conversation,
-            payload: attachment.payload,
+            hash: attachment.hash.map(|hash| hash.try_into().unwrap()),
             crdt: CrdtAddOnly(Author(attachment.crdt_author)),
         }
     }
@@ -35,6 +41,35 @@ impl From<(conversation::Model, attachment::Model)> for Attachment {
         (conversation, attachment).into()
     }
 }
+/// Piece of the payload of an attachment, so that large files are neither
+/// held in memory nor sent as a single patch.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AttachmentChunk {
+    pub hash: BlobHash,
+    /// Conversation the chunk is synced to. The payload is stored once for
+    /// every conversation.
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub index: i32,
+    pub data: Vec<u8>,
+    /// Declared length of the whole payload, to report progress while the
+    /// chunks arrive.
+    pub total: u64,
+    pub crdt: CrdtAddOnly,
+}
+impl From<(Uuid, blob::Model)> for AttachmentChunk {
+    fn from((conversation, chunk): (Uuid, blob::Model)) -> Self {
+        AttachmentChunk {
+            hash: chunk.hash.try_into().unwrap(),
+            conversation,
+            index: chunk.index,
+            data: chunk.data,
+            total: chunk.total as u64,
+            crdt: CrdtAddOnly(Author(chunk.crdt_author)),
+        }
+    }
+}
+
 impl Attachment {
     pub async fn get_or_create(uuid: Uuid, trans: &DatabaseTransaction) -> AttachmentMetaModel {
         let uuid = SplitUuid::from(uuid);
@@ -45,10 +80,6 @@ impl Attachment {
             .filter(uuid_filter.1)
             .filter(uuid_filter.2)
             .filter(uuid_filter.3)
-            .columns(
-                attachment::Column::iter()
-                    .filter(|col| !matches!(col, attachment::Column::Payload)),
-            )
             .into_model::<AttachmentMetaModel>()
             .one(trans)
             .await
@@ -66,8 +97,8 @@ impl Attachment {
                     uuid2: ActiveValue::Set(uuid.2),
                     uuid3: ActiveValue::Set(uuid.3),
                     conversation: ActiveValue::Set(conversation.id),
-                    payload: ActiveValue::Set(None),
                     crdt_author: ActiveValue::Set(0),
+                    hash: ActiveValue::Set(None),
                 }
                 .save(trans)
                 .await
@@ -88,7 +119,7 @@ pub struct AttachmentMetaModel {
     pub uuid2: i32,
     pub uuid3: i32,
     pub conversation: i32,
-    pub crdt_author: i32,
+    pub crdt_author: i64,
 }
 impl From<attachment::Model> for AttachmentMetaModel {
     fn from(value: attachment::Model) -> Self {
@@ -105,11 +136,6 @@ impl From<attachment::Model> for AttachmentMetaModel {
 }
 impl AttachmentMetaModel {
     pub fn find_by_id(id: i32) -> Selector<SelectModel<AttachmentMetaModel>> {
-        attachment::Entity::find_by_id(id)
-            .columns(
-                attachment::Column::iter()
-                    .filter(|col| !matches!(col, attachment::Column::Payload)),
-            )
-            .into_model::<AttachmentMetaModel>()
+        attachment::Entity::find_by_id(id).into_model::<AttachmentMetaModel>()
     }
 }
@@ -43,6 +43,8 @@ impl Contact {
                 name: ActiveValue::Set(Default::default()),
                 crdt_generation: ActiveValue::Set(0),
                 crdt_author: ActiveValue::Set(0),
+                verified: ActiveValue::Set(false),
+                alias: ActiveValue::Set(None),
             }
             .insert(trans)
             .await
@@ -1,14 +1,18 @@
 use crate::{
-    crdt::{writable::CrdtWritable, Author},
-    entity::conversation,
+    crdt::{sequence::CrdtPosition, writable::CrdtWritable, Author},
+    entity::{conversation, history_clear},
     uuid::{SplitUuid, UuidValue},
 };
-use sea_orm::{ActiveModelTrait, ActiveValue, DatabaseTransaction, EntityTrait, QueryFilter};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction, EntityTrait,
+    QueryFilter,
+};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Conversation {
+    #[serde(with = "crate::uuid::interned")]
     pub id: Uuid,
     pub title: Option<String>,
     pub crdt: CrdtWritable,
@@ -51,10 +55,51 @@ impl Conversation {
                 title: ActiveValue::Set(Default::default()),
                 crdt_generation: ActiveValue::Set(0),
                 crdt_author: ActiveValue::Set(0),
+                clock: ActiveValue::Set(None),
             }
             .insert(trans)
             .await
             .unwrap(),
         }
     }
+
+    /// Moves the Lamport clock of the conversation `id` up to `sequence`, a
+    /// position seen in it. Positions seen stay covered by the clock even once
+    /// whatever held them is gone.
+    pub async fn advance_clock(id: i32, sequence: i32, trans: &DatabaseTransaction) {
+        conversation::Entity::update_many()
+            .col_expr(
+                conversation::Column::Clock,
+                Expr::cust_with_values("max(coalesce(clock, 0), ?)", [sequence]),
+            )
+            .filter(conversation::Column::Id.eq(id))
+            .exec(trans)
+            .await
+            .unwrap();
+    }
+}
+
+/// Clears the messages of a conversation positioned before `before`. Only
+/// moves forward, so that a peer still holding cleared messages cannot bring
+/// them back.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HistoryClear {
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub before: CrdtPosition,
+    /// Who cleared it, `before` holds the author of a message.
+    pub author: Author,
+}
+impl From<(history_clear::Model, Uuid)> for HistoryClear {
+    fn from((clear, conversation): (history_clear::Model, Uuid)) -> Self {
+        HistoryClear {
+            conversation,
+            before: CrdtPosition {
+                sequence: clear.before_sequence,
+                author: Author(clear.before_author),
+                id: Uuid::from_slice(&clear.before_id).expect("Corrupted database"),
+            },
+            author: Author(clear.crdt_author),
+        }
+    }
 }
@@ -1,7 +1,7 @@
 use super::Key;
 use crate::{
-    crdt::{Author, CrdtAddOnly},
-    entity::{conversation, key, member},
+    crdt::{writable::CrdtWritable, Author, CrdtAddOnly},
+    entity::{conversation, key, key_supersede, member},
     uuid::UuidValue,
 };
 use serde::{Deserialize, Serialize};
@@ -10,6 +10,7 @@ use uuid::Uuid;
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Member {
     pub key: Key,
+    #[serde(with = "crate::uuid::interned")]
     pub conversation: Uuid,
     pub crdt: CrdtAddOnly,
 }
@@ -27,3 +28,61 @@ impl From<(key::Model, member::Model, conversation::Model)> for Member {
         (key, member, Uuid::from(conversation.get_uuid())).into()
     }
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MemberRemoval {
+    pub key: Key,
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub removed: bool,
+    pub crdt: CrdtWritable,
+}
+impl From<(key::Model, member::Model, Uuid)> for MemberRemoval {
+    fn from((key, member, conversation): (key::Model, member::Model, Uuid)) -> Self {
+        MemberRemoval {
+            key: Key::new(key.public).expect("Inconsistent database"),
+            conversation,
+            removed: member.removed,
+            crdt: CrdtWritable {
+                generation: member.removed_crdt_generation,
+                author: Author(member.removed_crdt_author),
+            },
+        }
+    }
+}
+impl From<(key::Model, member::Model, conversation::Model)> for MemberRemoval {
+    fn from((key, member, conversation): (key::Model, member::Model, conversation::Model)) -> Self {
+        (key, member, Uuid::from(conversation.get_uuid())).into()
+    }
+}
+
+/// Statement, signed by `old`, that its owner now uses `new` in
+/// `conversation`. See [`KeySupersede::signed_payload`].
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KeySupersede {
+    pub old: Key,
+    pub new: Key,
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub signature: Vec<u8>,
+    pub crdt: CrdtAddOnly,
+}
+impl KeySupersede {
+    /// Bytes signed by the old key.
+    pub fn signed_payload(old: &Key, new: &Key) -> Vec<u8> {
+        [b"icechat-supersede".as_slice(), old, new].concat()
+    }
+}
+impl From<(key::Model, key::Model, key_supersede::Model, Uuid)> for KeySupersede {
+    fn from(
+        (old, new, supersede, conversation): (key::Model, key::Model, key_supersede::Model, Uuid),
+    ) -> Self {
+        KeySupersede {
+            old: Key::new(old.public).expect("Inconsistent database"),
+            new: Key::new(new.public).expect("Inconsistent database"),
+            conversation,
+            signature: supersede.signature,
+            crdt: CrdtAddOnly(Author(supersede.crdt_author)),
+        }
+    }
+}
@@ -12,9 +12,11 @@ use uuid::Uuid;
 pub struct NewAttachmentMessage {
     pub id: Uuid,
     pub from: Key,
+    #[serde(with = "crate::uuid::interned")]
     pub conversation: Uuid,
     pub filename: String,
     pub attachment: Uuid,
+    pub created_at: i64,
     pub crdt: CrdtWritableSequence,
 }
 impl NewAttachmentMessage {
@@ -25,6 +27,49 @@ impl NewAttachmentMessage {
             conversation: self.conversation,
             text: self.filename,
             attachment: Some(self.attachment),
+            mime: None,
+            size: None,
+            thumbnail: None,
+            reply_to: None,
+            created_at: self.created_at,
+            crdt: self.crdt,
+        }
+    }
+}
+
+/// Same as [`NewAttachmentMessage`], declaring the type and size of the
+/// file. A variant of its own, so that attachment messages sent before keep
+/// their encoding.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NewTypedAttachmentMessage {
+    pub id: Uuid,
+    pub from: Key,
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub filename: String,
+    pub attachment: Uuid,
+    /// `None` when the sender did not recognize the type.
+    pub mime: Option<String>,
+    pub size: u64,
+    /// Downscaled preview of images, so that receivers see them without
+    /// downloading the file.
+    pub thumbnail: Option<Vec<u8>>,
+    pub created_at: i64,
+    pub crdt: CrdtWritableSequence,
+}
+impl NewTypedAttachmentMessage {
+    pub fn into_crdt(self) -> NewMessage {
+        NewMessage {
+            id: self.id,
+            from: self.from,
+            conversation: self.conversation,
+            text: self.filename,
+            attachment: Some(self.attachment),
+            mime: self.mime,
+            size: Some(self.size),
+            thumbnail: self.thumbnail,
+            reply_to: None,
+            created_at: self.created_at,
             crdt: self.crdt,
         }
     }
@@ -34,8 +79,10 @@ impl NewAttachmentMessage {
 pub struct NewTextMessage {
     pub id: Uuid,
     pub from: Key,
+    #[serde(with = "crate::uuid::interned")]
     pub conversation: Uuid,
     pub text: String,
+    pub created_at: i64,
     pub crdt: CrdtWritableSequence,
 }
 impl NewTextMessage {
@@ -46,6 +93,43 @@ impl NewTextMessage {
             conversation: self.conversation,
             text: self.text,
             attachment: None,
+            mime: None,
+            size: None,
+            thumbnail: None,
+            reply_to: None,
+            created_at: self.created_at,
+            crdt: self.crdt,
+        }
+    }
+}
+
+/// Same as [`NewTextMessage`], quoting an earlier message of the
+/// conversation. A variant of its own, so that text messages keep their
+/// encoding.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NewReplyMessage {
+    pub id: Uuid,
+    pub from: Key,
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub text: String,
+    pub reply_to: Uuid,
+    pub created_at: i64,
+    pub crdt: CrdtWritableSequence,
+}
+impl NewReplyMessage {
+    pub fn into_crdt(self) -> NewMessage {
+        NewMessage {
+            id: self.id,
+            from: self.from,
+            conversation: self.conversation,
+            text: self.text,
+            attachment: None,
+            mime: None,
+            size: None,
+            thumbnail: None,
+            reply_to: Some(self.reply_to),
+            created_at: self.created_at,
             crdt: self.crdt,
         }
     }
@@ -58,6 +142,18 @@ pub struct NewMessage {
     pub conversation: Uuid,
     pub text: String,
     pub attachment: Option<Uuid>,
+    /// MIME type of the attachment, as declared by the sender.
+    pub mime: Option<String>,
+    /// Length of the attachment, `None` for attachments sent before it was
+    /// declared.
+    pub size: Option<u64>,
+    /// JPEG preview of image attachments.
+    pub thumbnail: Option<Vec<u8>>,
+    /// Message being replied to. Only kept for text messages.
+    pub reply_to: Option<Uuid>,
+    /// Unix milliseconds when the sender wrote it, by the sender's clock. Only
+    /// shown, ordering is left to `crdt`.
+    pub created_at: i64,
     pub crdt: CrdtWritableSequence,
 }
 impl
@@ -79,6 +175,9 @@ impl
         let id = message.get_uuid();
         let from = Key::new(from.public).expect("Inconsistent database");
         let attachment = attachment.map(|attachment| attachment.get_uuid().into());
+        let reply_to = message
+            .reply_to
+            .map(|uuid| Uuid::from_slice(&uuid).expect("Inconsistent database"));
 
         NewMessage {
             id: id.into(),
@@ -86,6 +185,11 @@ impl
             conversation,
             text: message.text,
             attachment,
+            mime: message.mime,
+            size: message.size.map(|size| size as u64),
+            thumbnail: message.thumbnail,
+            reply_to,
+            created_at: message.created_at,
             crdt: CrdtWritableSequence {
                 writable: CrdtWritable {
                     author: Author(message.crdt_author),
@@ -118,37 +222,81 @@ impl
     }
 }
 impl NewMessage {
-    pub fn into_serializable(self) -> Either<NewTextMessage, NewAttachmentMessage> {
-        match self.attachment {
-            Some(attachment) => Either::Right(NewAttachmentMessage {
+    pub fn into_serializable(
+        self,
+    ) -> Either<
+        Either<NewTextMessage, NewReplyMessage>,
+        Either<NewAttachmentMessage, NewTypedAttachmentMessage>,
+    > {
+        match (self.attachment, self.reply_to) {
+            (Some(attachment), _) => Either::Right(match self.size {
+                Some(size) => Either::Right(NewTypedAttachmentMessage {
+                    id: self.id,
+                    from: self.from,
+                    conversation: self.conversation,
+                    filename: self.text,
+                    attachment,
+                    mime: self.mime,
+                    size,
+                    thumbnail: self.thumbnail,
+                    created_at: self.created_at,
+                    crdt: self.crdt,
+                }),
+                None => Either::Left(NewAttachmentMessage {
+                    id: self.id,
+                    from: self.from,
+                    conversation: self.conversation,
+                    filename: self.text,
+                    attachment,
+                    created_at: self.created_at,
+                    crdt: self.crdt,
+                }),
+            }),
+            (None, Some(reply_to)) => Either::Left(Either::Right(NewReplyMessage {
                 id: self.id,
                 from: self.from,
                 conversation: self.conversation,
-                filename: self.text,
-                attachment,
+                text: self.text,
+                reply_to,
+                created_at: self.created_at,
                 crdt: self.crdt,
-            }),
-            None => Either::Left(NewTextMessage {
+            })),
+            (None, None) => Either::Left(Either::Left(NewTextMessage {
                 id: self.id,
                 from: self.from,
                 conversation: self.conversation,
                 text: self.text,
+                created_at: self.created_at,
                 crdt: self.crdt,
-            }),
+            })),
         }
     }
 
     pub fn into_text(self) -> NewTextMessage {
         match self.into_serializable() {
-            Either::Left(text) => text,
-            Either::Right(_) => panic!(),
+            Either::Left(Either::Left(text)) => text,
+            _ => panic!(),
+        }
+    }
+
+    pub fn into_reply(self) -> NewReplyMessage {
+        match self.into_serializable() {
+            Either::Left(Either::Right(reply)) => reply,
+            _ => panic!(),
         }
     }
 
     pub fn into_attachment(self) -> NewAttachmentMessage {
         match self.into_serializable() {
-            Either::Left(_) => panic!(),
-            Either::Right(attachment) => attachment,
+            Either::Right(Either::Left(attachment)) => attachment,
+            _ => panic!(),
+        }
+    }
+
+    pub fn into_typed_attachment(self) -> NewTypedAttachmentMessage {
+        match self.into_serializable() {
+            Either::Right(Either::Right(attachment)) => attachment,
+            _ => panic!(),
         }
     }
 }
@@ -156,6 +304,7 @@ impl NewMessage {
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MessageStatus {
     pub id: Uuid,
+    #[serde(with = "crate::uuid::interned")]
     pub conversation: Uuid,
     pub status: i32,
     pub crdt: CrdtWritable,
@@ -182,3 +331,66 @@ impl From<(message::Model, conversation::Model)> for MessageStatus {
         (message, conversation).into()
     }
 }
+
+/// Marks a message as deleted. The message row is kept, so that the
+/// tombstone converges like any writable, but its text is dropped.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MessageTombstone {
+    pub id: Uuid,
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub crdt: CrdtWritable,
+}
+impl From<(message::Model, Uuid)> for MessageTombstone {
+    fn from((message, conversation): (message::Model, Uuid)) -> Self {
+        let id = message.get_uuid();
+
+        MessageTombstone {
+            id: id.into(),
+            conversation,
+            crdt: CrdtWritable {
+                author: Author(message.deleted_crdt_author),
+                generation: message.deleted_crdt_generation,
+            },
+        }
+    }
+}
+impl From<(message::Model, conversation::Model)> for MessageTombstone {
+    fn from((message, conversation): (message::Model, conversation::Model)) -> Self {
+        let conversation = Uuid::from(conversation.get_uuid());
+
+        (message, conversation).into()
+    }
+}
+
+/// New text of an already sent message.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MessageEdit {
+    pub id: Uuid,
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub text: String,
+    pub crdt: CrdtWritable,
+}
+impl From<(message::Model, Uuid)> for MessageEdit {
+    fn from((message, conversation): (message::Model, Uuid)) -> Self {
+        let id = message.get_uuid();
+
+        MessageEdit {
+            id: id.into(),
+            conversation,
+            text: message.text,
+            crdt: CrdtWritable {
+                author: Author(message.text_crdt_author),
+                generation: message.text_crdt_generation,
+            },
+        }
+    }
+}
+impl From<(message::Model, conversation::Model)> for MessageEdit {
+    fn from((message, conversation): (message::Model, conversation::Model)) -> Self {
+        let conversation = Uuid::from(conversation.get_uuid());
+
+        (message, conversation).into()
+    }
+}
@@ -3,21 +3,30 @@ pub mod contact;
 pub mod conversation;
 pub mod member;
 pub mod message;
+pub mod receipt;
 
 pub use self::{
-    attachment::Attachment,
+    attachment::{Attachment, AttachmentChunk},
     contact::Contact,
-    conversation::Conversation,
-    member::Member,
-    message::{MessageStatus, NewAttachmentMessage, NewMessage, NewTextMessage},
+    conversation::{Conversation, HistoryClear},
+    member::{KeySupersede, Member, MemberRemoval},
+    message::{
+        MessageEdit, MessageStatus, MessageTombstone, NewAttachmentMessage, NewMessage,
+        NewReplyMessage, NewTextMessage, NewTypedAttachmentMessage,
+    },
+    receipt::Receipt,
+};
+use crate::{
+    crdt::{history, sequence::CrdtPosition, CrdtTransaction},
+    entity::key,
 };
-use crate::{crdt::CrdtTransaction, entity::key};
 use either::Either;
 use sea_orm::{
     ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter,
 };
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
+use uuid::Uuid;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Patch {
@@ -28,9 +37,24 @@ pub enum Patch {
     MessageStatus(MessageStatus),
     Attachment(Attachment),
     NewAttachmentMessage(NewAttachmentMessage),
+    MemberRemoval(MemberRemoval),
+    Receipt(Receipt),
+    MessageTombstone(MessageTombstone),
+    MessageEdit(MessageEdit),
+    AttachmentChunk(AttachmentChunk),
+    KeySupersede(KeySupersede),
+    NewReplyMessage(NewReplyMessage),
+    NewTypedAttachmentMessage(NewTypedAttachmentMessage),
+    HistoryClear(HistoryClear),
 }
 impl Patch {
     pub async fn merge(self, trans: &mut DatabaseTransaction) -> Option<Self> {
+        if let Some((conversation, message, position)) = self.message() {
+            if history::drop_cleared(trans, conversation, message, position).await {
+                return None;
+            }
+        }
+
         match self {
             Patch::Contact(crdt) => trans.merge(crdt).await.map(Patch::Contact),
             Patch::Conversation(crdt) => trans.merge(crdt).await.map(Patch::Conversation),
@@ -45,6 +69,55 @@ impl Patch {
                 .merge(crdt.into_crdt())
                 .await
                 .map(|crdt| Patch::NewAttachmentMessage(crdt.into_attachment())),
+            Patch::MemberRemoval(crdt) => trans.merge(crdt).await.map(Patch::MemberRemoval),
+            Patch::Receipt(crdt) => trans.merge(crdt).await.map(Patch::Receipt),
+            Patch::MessageTombstone(crdt) => trans.merge(crdt).await.map(Patch::MessageTombstone),
+            Patch::MessageEdit(crdt) => trans.merge(crdt).await.map(Patch::MessageEdit),
+            Patch::AttachmentChunk(crdt) => trans.merge(crdt).await.map(Patch::AttachmentChunk),
+            Patch::KeySupersede(crdt) => trans.merge(crdt).await.map(Patch::KeySupersede),
+            Patch::NewReplyMessage(crdt) => trans
+                .merge(crdt.into_crdt())
+                .await
+                .map(|crdt| Patch::NewReplyMessage(crdt.into_reply())),
+            Patch::NewTypedAttachmentMessage(crdt) => trans
+                .merge(crdt.into_crdt())
+                .await
+                .map(|crdt| Patch::NewTypedAttachmentMessage(crdt.into_typed_attachment())),
+            Patch::HistoryClear(crdt) => trans.merge(crdt).await.map(Patch::HistoryClear),
+        }
+    }
+
+    /// Conversation and uuid of the message the patch is about, along with
+    /// its position when the patch creates it.
+    fn message(&self) -> Option<(Uuid, Uuid, Option<CrdtPosition>)> {
+        match self {
+            Patch::NewTextMessage(message) => Some((
+                message.conversation,
+                message.id,
+                Some(CrdtPosition::new(message.crdt, message.id)),
+            )),
+            Patch::NewAttachmentMessage(message) => Some((
+                message.conversation,
+                message.id,
+                Some(CrdtPosition::new(message.crdt, message.id)),
+            )),
+            Patch::NewReplyMessage(message) => Some((
+                message.conversation,
+                message.id,
+                Some(CrdtPosition::new(message.crdt, message.id)),
+            )),
+            Patch::NewTypedAttachmentMessage(message) => Some((
+                message.conversation,
+                message.id,
+                Some(CrdtPosition::new(message.crdt, message.id)),
+            )),
+            Patch::MessageStatus(status) => Some((status.conversation, status.id, None)),
+            Patch::Receipt(receipt) => Some((receipt.conversation, receipt.message, None)),
+            Patch::MessageTombstone(tombstone) => {
+                Some((tombstone.conversation, tombstone.id, None))
+            }
+            Patch::MessageEdit(edit) => Some((edit.conversation, edit.id, None)),
+            _ => None,
         }
     }
 }
@@ -68,6 +141,16 @@ impl From<NewTextMessage> for Patch {
         Patch::NewTextMessage(value)
     }
 }
+impl From<NewReplyMessage> for Patch {
+    fn from(value: NewReplyMessage) -> Self {
+        Patch::NewReplyMessage(value)
+    }
+}
+impl From<NewTypedAttachmentMessage> for Patch {
+    fn from(value: NewTypedAttachmentMessage) -> Self {
+        Patch::NewTypedAttachmentMessage(value)
+    }
+}
 impl From<MessageStatus> for Patch {
     fn from(value: MessageStatus) -> Patch {
         Patch::MessageStatus(value)
@@ -83,11 +166,50 @@ impl From<NewAttachmentMessage> for Patch {
         Patch::NewAttachmentMessage(value)
     }
 }
+impl From<MemberRemoval> for Patch {
+    fn from(value: MemberRemoval) -> Self {
+        Patch::MemberRemoval(value)
+    }
+}
+impl From<Receipt> for Patch {
+    fn from(value: Receipt) -> Self {
+        Patch::Receipt(value)
+    }
+}
+impl From<MessageTombstone> for Patch {
+    fn from(value: MessageTombstone) -> Self {
+        Patch::MessageTombstone(value)
+    }
+}
+impl From<MessageEdit> for Patch {
+    fn from(value: MessageEdit) -> Self {
+        Patch::MessageEdit(value)
+    }
+}
+impl From<HistoryClear> for Patch {
+    fn from(value: HistoryClear) -> Self {
+        Patch::HistoryClear(value)
+    }
+}
+impl From<AttachmentChunk> for Patch {
+    fn from(value: AttachmentChunk) -> Self {
+        Patch::AttachmentChunk(value)
+    }
+}
+impl From<KeySupersede> for Patch {
+    fn from(value: KeySupersede) -> Self {
+        Patch::KeySupersede(value)
+    }
+}
 impl From<NewMessage> for Patch {
     fn from(value: NewMessage) -> Self {
         match value.into_serializable() {
-            Either::Left(text) => Patch::NewTextMessage(text),
-            Either::Right(attachment) => Patch::NewAttachmentMessage(attachment),
+            Either::Left(Either::Left(text)) => Patch::NewTextMessage(text),
+            Either::Left(Either::Right(reply)) => Patch::NewReplyMessage(reply),
+            Either::Right(Either::Left(attachment)) => Patch::NewAttachmentMessage(attachment),
+            Either::Right(Either::Right(attachment)) => {
+                Patch::NewTypedAttachmentMessage(attachment)
+            }
         }
     }
 }
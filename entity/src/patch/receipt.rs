@@ -0,0 +1,40 @@
+use super::Key;
+use crate::{
+    crdt::{writable::CrdtWritable, Author},
+    entity::{conversation, key, receipt},
+    uuid::UuidValue,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Status of a message as seen by one member of the conversation.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Receipt {
+    pub message: Uuid,
+    #[serde(with = "crate::uuid::interned")]
+    pub conversation: Uuid,
+    pub member: Key,
+    pub status: i32,
+    pub crdt: CrdtWritable,
+}
+impl From<(receipt::Model, key::Model, Uuid)> for Receipt {
+    fn from((receipt, member, conversation): (receipt::Model, key::Model, Uuid)) -> Self {
+        Receipt {
+            message: receipt.get_uuid().into(),
+            conversation,
+            member: Key::new(member.public).expect("Inconsistent database"),
+            status: receipt.status,
+            crdt: CrdtWritable {
+                generation: receipt.crdt_generation,
+                author: Author(receipt.crdt_author),
+            },
+        }
+    }
+}
+impl From<(receipt::Model, key::Model, conversation::Model)> for Receipt {
+    fn from(
+        (receipt, member, conversation): (receipt::Model, key::Model, conversation::Model),
+    ) -> Self {
+        (receipt, member, Uuid::from(conversation.get_uuid())).into()
+    }
+}
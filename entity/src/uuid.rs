@@ -1,5 +1,5 @@
 use crate::{
-    entity::{attachment, conversation, message},
+    entity::{attachment, conversation, invite, message, receipt},
     patch::attachment::AttachmentMetaModel,
 };
 use sea_orm::{sea_query::IntoCondition, ColumnTrait};
@@ -132,3 +132,79 @@ impl UuidValue for AttachmentMetaModel {
         SplitUuid(self.uuid0, self.uuid1, self.uuid2, self.uuid3)
     }
 }
+
+impl UuidColumn for receipt::Column {
+    fn get_column() -> [Self; 4] {
+        [
+            receipt::Column::Uuid0,
+            receipt::Column::Uuid1,
+            receipt::Column::Uuid2,
+            receipt::Column::Uuid3,
+        ]
+    }
+}
+impl UuidValue for receipt::Model {
+    fn get_uuid(&self) -> SplitUuid {
+        SplitUuid(self.uuid0, self.uuid1, self.uuid2, self.uuid3)
+    }
+}
+
+impl UuidColumn for invite::Column {
+    fn get_column() -> [Self; 4] {
+        [
+            invite::Column::Uuid0,
+            invite::Column::Uuid1,
+            invite::Column::Uuid2,
+            invite::Column::Uuid3,
+        ]
+    }
+}
+impl UuidValue for invite::Model {
+    fn get_uuid(&self) -> SplitUuid {
+        SplitUuid(self.uuid0, self.uuid1, self.uuid2, self.uuid3)
+    }
+}
+
+/// Serialization of the conversation a patch belongs to. The nil uuid, which
+/// no conversation has, stands for the conversation a channel interned and
+/// is written as an empty byte string. Any other uuid is written as usual, so
+/// the format at rest is unaffected.
+pub mod interned {
+    use serde::{
+        de::{self, Visitor},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+    use std::fmt;
+    use uuid::Uuid;
+
+    pub fn serialize<S: Serializer>(uuid: &Uuid, serializer: S) -> Result<S::Ok, S::Error> {
+        match uuid.is_nil() && !serializer.is_human_readable() {
+            true => serializer.serialize_bytes(&[]),
+            false => uuid.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+        if deserializer.is_human_readable() {
+            return Uuid::deserialize(deserializer);
+        }
+
+        struct InternedVisitor;
+        impl<'de> Visitor<'de> for InternedVisitor {
+            type Value = Uuid;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "16 bytes, or none for the interned conversation")
+            }
+
+            fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Uuid, E> {
+                match value {
+                    [] => Ok(Uuid::nil()),
+                    value => Uuid::from_slice(value).map_err(E::custom),
+                }
+            }
+        }
+
+        deserializer.deserialize_bytes(InternedVisitor)
+    }
+}
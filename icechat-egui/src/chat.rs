@@ -1,45 +1,108 @@
-use futures_util::{future::select_all, FutureExt};
+use futures_util::TryStreamExt;
 use icechat::{
-    channel::{Channel, ChannelStateLabel, ChannelValue, Ed25519Cert},
-    database::{ChannelData, Contact, Conversation, Database, Message, MessageStatus},
-    SqliteChannel,
+    channel::{
+        ChannelStateChange, ChannelStateLabel, ChannelValue, ConnectConfig, Ed25519Cert,
+        OfflineReason,
+    },
+    client::ChannelSet,
+    database::{
+        error::DatabaseResult, ChannelData, Contact, Conversation, Database, DoNotDisturb, Message,
+        MessageStatus,
+    },
+    poll_runtime::LocalRuntime,
 };
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
+    io::{Read, Write},
     path::Path,
+    rc::Rc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::runtime::Runtime;
 use uuid::Uuid;
 
+/// Largest file the client lets the user send.
+pub const MAX_ATTACHMENT_BYTES: usize = 64 * 1024 * 1024;
+
+/// How often the traffic of a channel is sampled to estimate its throughput.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Least time between two typing notifications for the same conversation.
+const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+
 pub struct Chat {
-    runtime: Runtime,
+    runtime: Rc<LocalRuntime>,
     database: Database,
-    sync: Vec<SqliteChannel>,
+    sync: ChannelSet,
+    /// Sent messages not yet acked by every channel of their conversation,
+    /// with the sync bookmark taken when they were sent.
+    undelivered: Vec<(Conversation, i32, Uuid)>,
+    on_fully_delivered: Option<Box<dyn Fn(Uuid)>>,
+    /// Bytes moved by each channel, keyed by its name, when last sampled, and
+    /// the throughput measured over the window before that.
+    traffic: HashMap<String, (Instant, u64, f64)>,
+    /// When the user was last told to be typing in each conversation.
+    typing: HashMap<Uuid, Instant>,
+    /// Sync bookmark taken when each conversation was last marked read.
+    read_at: HashMap<Uuid, i32>,
 }
 impl Chat {
     pub fn load<P: AsRef<Path>>(path: P) -> Chat {
-        let runtime = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap();
-        let database = runtime
-            .block_on(Database::connect(&path.as_ref().to_string_lossy()))
-            .unwrap();
+        Self::load_with(path, None).unwrap()
+    }
+
+    /// See [`Database::connect_encrypted`].
+    pub fn load_encrypted<P: AsRef<Path>>(path: P, passphrase: &str) -> DatabaseResult<Chat> {
+        Self::load_with(path, Some(passphrase))
+    }
+
+    /// Whether `path` is an existing database sealed with a passphrase.
+    pub fn is_encrypted<P: AsRef<Path>>(path: P) -> bool {
+        if !path.as_ref().exists() {
+            return false;
+        }
+
+        LocalRuntime::new()
+            .unwrap()
+            .block_on(Database::is_encrypted(&path.as_ref().to_string_lossy()))
+            .unwrap()
+    }
+
+    fn load_with<P: AsRef<Path>>(path: P, passphrase: Option<&str>) -> DatabaseResult<Chat> {
+        let runtime = Rc::new(LocalRuntime::new().unwrap());
+        let path = path.as_ref().to_string_lossy();
+        let mut database = runtime.block_on(async {
+            match passphrase {
+                Some(passphrase) => Database::connect_encrypted(&path, passphrase).await,
+                None => Database::connect(&path).await,
+            }
+        })?;
+        database.set_max_attachment_bytes(Some(MAX_ATTACHMENT_BYTES));
+        database.set_hide_blocked(true);
+        database.set_connect_config(ConnectConfig::from_env().unwrap_or_else(|e| {
+            log::error!("Ignoring bad ICECHAT_SIGNALING: {e}");
+            Default::default()
+        }));
 
         let mut r = Chat {
             runtime,
             database,
             sync: Default::default(),
+            undelivered: Default::default(),
+            on_fully_delivered: None,
+            traffic: Default::default(),
+            typing: Default::default(),
+            read_at: Default::default(),
         };
 
-        let runtime = r.runtime.handle().clone();
+        let runtime = r.runtime.clone();
         runtime.block_on(r.sync_channels());
 
-        r
+        Ok(r)
     }
 
-    pub fn runtime(&self) -> &Runtime {
-        &self.runtime
+    /// Sync futures are `!Send`, see [`LocalRuntime`].
+    pub fn runtime(&self) -> Rc<LocalRuntime> {
+        self.runtime.clone()
     }
 
     pub fn database(&self) -> &Database {
@@ -47,35 +110,7 @@ impl Chat {
     }
 
     async fn sync_channels(&mut self) {
-        let mut database = Vec::new();
-
-        for conversation in self.database.list_conversation().await.unwrap() {
-            for channel in self.database.list_channels(&conversation).await.unwrap() {
-                database.push(channel);
-            }
-        }
-
-        let database = database
-            .into_iter()
-            .map(|channel| (channel.channel.clone(), channel))
-            .collect::<HashMap<_, _>>();
-
-        let instance = self
-            .sync
-            .iter()
-            .map(|channel| channel.channel().channel.to_string())
-            .collect::<HashSet<_>>();
-
-        self.sync.extend(
-            database
-                .values()
-                .filter(|new_channel| !instance.contains(&new_channel.channel))
-                .cloned()
-                .map(|channel| Channel::new(channel, self.database.private_key().clone())),
-        );
-
-        self.sync
-            .retain_mut(|channel| database.contains_key(&channel.channel().channel))
+        self.sync.sync_channels(&self.database).await.unwrap();
     }
 
     pub fn profile(&self) -> Contact {
@@ -91,24 +126,145 @@ impl Chat {
             .unwrap()
     }
 
-    pub fn send_message(&mut self, conversation: Conversation, content: String) {
+    pub fn send_message(
+        &mut self,
+        conversation: Conversation,
+        content: String,
+        reply_to: Option<Uuid>,
+    ) -> Message {
+        self.runtime.block_on(async {
+            let message = self
+                .database
+                .send_message(conversation.clone(), content, reply_to)
+                .await
+                .unwrap();
+            let bookmark = self.database.sync_bookmark().await.unwrap();
+            self.undelivered
+                .push((conversation, bookmark, message.uuid));
+
+            message
+        })
+    }
+
+    /// Tells the peers of `conversation` that the user is typing, at most once
+    /// every [`TYPING_INTERVAL`].
+    pub fn send_typing(&mut self, conversation: &Conversation) {
+        let now = Instant::now();
+        if let Some(last) = self.typing.get(&conversation.uuid) {
+            if now.duration_since(*last) < TYPING_INTERVAL {
+                return;
+            }
+        }
+        self.typing.insert(conversation.uuid, now);
+
+        for sync in self.sync.iter_mut() {
+            if sync.channel().conversation == conversation.uuid {
+                sync.send_typing();
+            }
+        }
+    }
+
+    /// Peers currently typing in `conversation`.
+    pub fn typing(&self, conversation: &Conversation) -> Vec<Ed25519Cert> {
+        let mut typers: Vec<Ed25519Cert> = vec![];
+        for sync in self.sync.iter() {
+            let peer = sync.channel().peer_cert;
+            if sync.channel().conversation == conversation.uuid
+                && sync.peer_typing()
+                && !typers.contains(&peer)
+            {
+                typers.push(peer);
+            }
+        }
+
+        typers
+    }
+
+    /// Calls `callback` with the uuid of each sent message once every channel
+    /// of its conversation acked it.
+    pub fn on_fully_delivered(&mut self, callback: impl Fn(Uuid) + 'static) {
+        self.on_fully_delivered = Some(Box::new(callback));
+    }
+
+    /// Calls `callback` whenever a channel connects or disconnects, see
+    /// [`Channel::on_state_change`].
+    pub fn on_channel_state_change(&mut self, callback: impl Fn(ChannelStateChange) + 'static) {
+        self.sync.on_state_change(callback);
+    }
+
+    async fn check_deliveries(&mut self) {
+        let mut undelivered = Vec::new();
+
+        for (conversation, bookmark, uuid) in std::mem::take(&mut self.undelivered) {
+            let delivered = self
+                .database
+                .delivered_to_all(&conversation, bookmark)
+                .await
+                .unwrap();
+
+            match (delivered, &self.on_fully_delivered) {
+                (true, Some(callback)) => callback(uuid),
+                (true, None) => {}
+                (false, _) => undelivered.push((conversation, bookmark, uuid)),
+            }
+        }
+
+        self.undelivered = undelivered;
+    }
+
+    pub fn send_file<R: Read>(
+        &mut self,
+        conversation: Conversation,
+        filename: String,
+        reader: R,
+        len: u64,
+    ) -> DatabaseResult<()> {
+        self.runtime.block_on(
+            self.database
+                .send_file_stream(conversation, filename, reader, len),
+        )
+    }
+
+    pub fn attachment_progress(&self, id: i32) -> Option<(u64, u64)> {
+        self.runtime
+            .block_on(self.database.attachment_progress(id))
+            .unwrap()
+    }
+
+    pub fn edit_message(&self, message: &Message, new_text: String) {
         self.runtime
-            .block_on(self.database.send_message(conversation, content))
+            .block_on(self.database.edit_message(message, new_text))
             .unwrap()
     }
 
-    pub fn send_file(&mut self, conversation: Conversation, filename: String, payload: Vec<u8>) {
+    pub fn delete_message(&self, message: &Message) {
         self.runtime
-            .block_on(self.database.send_file(conversation, filename, payload))
+            .block_on(self.database.delete_message(message))
             .unwrap()
     }
 
-    pub fn fetch_file_payload(&self, id: i32) -> Option<Vec<u8>> {
+    pub fn set_starred(&self, message: &Message, starred: bool) {
         self.runtime
-            .block_on(self.database.fetch_file_payload(id))
+            .block_on(self.database.set_starred(message, starred))
             .unwrap()
     }
 
+    pub fn list_starred(&self) -> Vec<(Conversation, Message)> {
+        self.runtime.block_on(self.database.list_starred()).unwrap()
+    }
+
+    /// Writes the payload of the attachment to `writer` one chunk at a time.
+    pub fn save_file_payload<W: Write>(&self, id: i32, mut writer: W) -> DatabaseResult<()> {
+        self.runtime.block_on(async {
+            let mut chunks = self.database.fetch_file_stream(id);
+            while let Some(chunk) = chunks.try_next().await? {
+                writer.write_all(&chunk)?;
+            }
+
+            Ok(())
+        })
+    }
+
     pub fn create_conversation(&self) -> Conversation {
         self.runtime
             .block_on(self.database.create_conversation(None))
@@ -116,13 +272,16 @@ impl Chat {
     }
 
     pub fn join_conversation(&mut self, conversation: Uuid, peer: Ed25519Cert) -> Conversation {
-        let runtime = self.runtime.handle().clone();
+        let runtime = self.runtime.clone();
         runtime.block_on(async {
             let conversation = self.database.join_conversation(conversation).await.unwrap();
-            self.database
+            if let Err(e) = self
+                .database
                 .create_channel(conversation.clone(), peer)
                 .await
-                .unwrap();
+            {
+                log::error!("Cannot add channel to {}: {e}", peer.hex());
+            }
             self.sync_channels().await;
             conversation
         })
@@ -139,6 +298,52 @@ impl Chat {
         });
     }
 
+    pub fn is_muted(&self, conversation: &Conversation) -> bool {
+        self.runtime
+            .block_on(self.database.is_muted(conversation))
+            .unwrap()
+    }
+
+    pub fn set_muted(&self, conversation: &Conversation, muted: bool) {
+        self.runtime
+            .block_on(self.database.set_muted(conversation, muted))
+            .unwrap()
+    }
+
+    pub fn is_archived(&self, conversation: &Conversation) -> bool {
+        self.runtime
+            .block_on(self.database.is_archived(conversation))
+            .unwrap()
+    }
+
+    pub fn set_archived(&self, conversation: &Conversation, archived: bool) {
+        self.runtime
+            .block_on(self.database.set_archived(conversation, archived))
+            .unwrap()
+    }
+
+    pub fn unread_count(&self, conversation: &Conversation) -> usize {
+        self.runtime
+            .block_on(self.database.unread_count(conversation))
+            .unwrap()
+    }
+
+    /// Called while the conversation is being shown, marks it as read when it
+    /// was not shown before or when patches arrived since it was last marked.
+    pub fn on_conversation_focused(&mut self, conversation: &Conversation) {
+        let runtime = self.runtime.clone();
+        runtime.block_on(async {
+            let bookmark = self.database.sync_bookmark().await.unwrap();
+            if self.read_at.get(&conversation.uuid) == Some(&bookmark) {
+                return;
+            }
+
+            self.database.mark_read(conversation).await.unwrap();
+            let bookmark = self.database.sync_bookmark().await.unwrap();
+            self.read_at.insert(conversation.uuid, bookmark);
+        })
+    }
+
     pub fn save_conversation(&self, conversation: Conversation) {
         self.runtime
             .block_on(self.database.save_conversation(conversation))
@@ -151,6 +356,18 @@ impl Chat {
             .unwrap()
     }
 
+    pub fn list_unarchived(&self) -> Vec<Conversation> {
+        self.runtime
+            .block_on(self.database.list_unarchived())
+            .unwrap()
+    }
+
+    pub fn list_archived(&self) -> Vec<Conversation> {
+        self.runtime
+            .block_on(self.database.list_archived())
+            .unwrap()
+    }
+
     pub fn new_messages(&mut self) -> Vec<Message> {
         self.runtime.block_on(async {
             let messages = self.database.new_messages(None).await.unwrap();
@@ -166,20 +383,51 @@ impl Chat {
         })
     }
 
-    pub fn add_channel(&mut self, conversation: Conversation, peer: Ed25519Cert) {
-        let runtime = self.runtime.handle().clone();
+    pub fn should_notify(&self) -> bool {
+        self.runtime
+            .block_on(self.database.should_notify(now()))
+            .unwrap()
+    }
+
+    pub fn do_not_disturb(&self) -> bool {
+        self.runtime
+            .block_on(self.database.do_not_disturb())
+            .unwrap()
+            .active(now())
+    }
+
+    /// Pauses notifications for `duration` seconds, or until turned off when
+    /// `None`.
+    pub fn set_do_not_disturb(&self, enabled: bool, duration: Option<i64>) {
+        let value = match enabled {
+            true => DoNotDisturb::On(duration.map(|duration| now() + duration)),
+            false => DoNotDisturb::Off,
+        };
+
+        self.runtime
+            .block_on(self.database.set_do_not_disturb(value))
+            .unwrap()
+    }
+
+    pub fn add_channel(
+        &mut self,
+        conversation: Conversation,
+        peer: Ed25519Cert,
+    ) -> DatabaseResult<()> {
+        let runtime = self.runtime.clone();
 
         runtime.block_on(async {
-            self.database
-                .create_channel(conversation, peer)
-                .await
-                .unwrap();
+            if let Err(e) = self.database.create_channel(conversation, peer).await {
+                log::error!("Cannot add channel to {}: {e}", peer.hex());
+                return Err(e);
+            }
             self.sync_channels().await;
-        });
+            Ok(())
+        })
     }
 
     pub fn remove_channel(&mut self, conversation: Conversation, peer: Ed25519Cert) {
-        let runtime = self.runtime.handle().clone();
+        let runtime = self.runtime.clone();
 
         runtime.block_on(async {
             self.database
@@ -190,37 +438,136 @@ impl Chat {
         });
     }
 
-    pub fn channels(&self) -> impl Iterator<Item = (&ChannelData, ChannelStateLabel)> {
-        self.sync
-            .iter()
-            .map(|channel| (channel.channel(), channel.state()))
+    /// Patches still to be sent across all channels.
+    pub fn sync_backlog(&self) -> usize {
+        self.runtime.block_on(self.database.sync_backlog()).unwrap()
+    }
+
+    /// Removes `peer` from every conversation and drops all channels to it.
+    pub fn revoke_peer(&mut self, peer: Ed25519Cert) {
+        let runtime = self.runtime.clone();
+
+        runtime.block_on(async {
+            self.database.revoke_peer(&peer, true).await.unwrap();
+            self.sync_channels().await;
+        });
+    }
+
+    /// Removes `peer` from every conversation, like [`Chat::revoke_peer`], and
+    /// then blocks it, so that it can not come back.
+    pub fn block_and_revoke_peer(&mut self, peer: Ed25519Cert) {
+        let runtime = self.runtime.clone();
+
+        runtime.block_on(async {
+            // Revoking first, the conversations to remove it from are found
+            // through the channels that blocking drops.
+            self.database.revoke_peer(&peer, true).await.unwrap();
+            self.database.block_contact(&peer).await.unwrap();
+            self.sync_channels().await;
+        });
+    }
+
+    /// See [`Database::set_verified`].
+    pub fn set_verified(&self, peer: Ed25519Cert, verified: bool) {
+        self.runtime
+            .block_on(self.database.set_verified(&peer, verified))
+            .unwrap()
+    }
+
+    /// See [`Database::set_alias`].
+    pub fn set_alias(&self, peer: Ed25519Cert, alias: Option<String>) {
+        self.runtime
+            .block_on(self.database.set_alias(&peer, alias))
+            .unwrap()
+    }
+
+    /// Drops the channels to `peer` and ignores it from now on, see
+    /// [`Database::block_contact`].
+    pub fn block_contact(&mut self, peer: Ed25519Cert) {
+        let runtime = self.runtime.clone();
+
+        runtime.block_on(async {
+            self.database.block_contact(&peer).await.unwrap();
+            self.sync_channels().await;
+        });
+    }
+
+    /// Channels with their state, why they went offline if they did and,
+    /// while connected, an estimate of their throughput in bytes per second.
+    pub fn channels(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            &ChannelData,
+            ChannelStateLabel,
+            Option<OfflineReason>,
+            Option<f64>,
+        ),
+    > {
+        let now = Instant::now();
+
+        self.sync.iter().map(move |channel| {
+            let throughput = channel.traffic().map(|traffic| {
+                let bytes = traffic.sent + traffic.received;
+                match self.traffic.get(&channel.channel().channel) {
+                    Some((since, _, rate))
+                        if now.duration_since(*since) < THROUGHPUT_WINDOW * 2 =>
+                    {
+                        *rate
+                    }
+                    Some((since, sampled, _)) => {
+                        let elapsed = now.duration_since(*since).as_secs_f64();
+                        bytes.saturating_sub(*sampled) as f64 / elapsed
+                    }
+                    None => 0.0,
+                }
+            });
+
+            (
+                channel.channel(),
+                channel.state(),
+                channel.offline_reason(),
+                throughput,
+            )
+        })
+    }
+
+    fn sample_traffic(&mut self, index: usize) {
+        let channel = self.sync.get(index);
+        let Some(traffic) = channel.traffic() else { return; };
+        let now = Instant::now();
+
+        let bytes = traffic.sent + traffic.received;
+
+        let (since, sampled, rate) = self
+            .traffic
+            .entry(channel.channel().channel.clone())
+            .or_insert((now, 0, 0.0));
+        let elapsed = now.duration_since(*since);
+        if elapsed >= THROUGHPUT_WINDOW {
+            *rate = bytes.saturating_sub(*sampled) as f64 / elapsed.as_secs_f64();
+            *since = now;
+            *sampled = bytes;
+        }
     }
 
     pub async fn pre_wait(&mut self) {
         let mut trans = self.database.begin().await.unwrap();
-        for sync in self.sync.iter_mut() {
-            if sync.state() == ChannelStateLabel::Offline {
-                sync.connect(self.database.start_sync(sync.channel().clone()));
-            }
-            sync.pre_wait(&mut trans).await;
-        }
+        self.sync.pre_wait(&self.database, &mut trans).await;
         trans.commit().await.unwrap();
     }
 
     pub async fn wait(&mut self) -> ChatValue {
-        if self.sync.is_empty() {
-            std::future::pending::<()>().await;
-            unreachable!()
-        }
-
-        let (value, index, _) =
-            select_all(self.sync.iter_mut().map(|sync| sync.wait().boxed_local())).await;
-
-        (value, index)
+        self.sync.wait().await
     }
 
     pub async fn then(&mut self, (value, index): ChatValue) {
-        self.sync[index].then(value).await;
+        self.sync.then((value, index)).await;
+        self.sample_traffic(index);
+        self.check_deliveries().await;
+        if self.database.migrate_superseded_channels().await.unwrap() > 0 {
+            self.sync_channels().await;
+        }
     }
 
     pub fn connected(&self) -> bool {
@@ -230,10 +577,177 @@ impl Chat {
     }
 
     pub async fn close(self) {
-        for mut sync in self.sync {
-            sync.close().await
-        }
+        self.sync.close().await
     }
 }
 
 pub type ChatValue = (ChannelValue, usize);
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icechat::testing::sync_until_idle;
+
+    fn temp_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("icechat-{}.sqlite", Uuid::new_v4()))
+    }
+
+    mod given_a_conversation_with_a_peer {
+        use super::*;
+
+        type Given = (Chat, Database, Conversation, Conversation);
+        fn given() -> Given {
+            let chat = Chat::load(temp_path());
+            let conversation = chat.create_conversation();
+            let (peer, joined) = chat.runtime().block_on(async {
+                let peer = Database::connect(":memory:").await.unwrap();
+                let joined = peer.join_conversation(conversation.uuid).await.unwrap();
+                let database = chat.database();
+                database
+                    .create_channel(conversation.clone(), *peer.cert())
+                    .await
+                    .unwrap();
+                peer.create_channel(joined.clone(), *database.cert())
+                    .await
+                    .unwrap();
+                sync_until_idle(database, &peer).await.unwrap();
+
+                (peer, joined)
+            });
+
+            (chat, peer, conversation, joined)
+        }
+
+        fn receive(chat: &Chat, peer: &Database, joined: &Conversation, text: &str) {
+            chat.runtime().block_on(async {
+                peer.send_message(joined.clone(), text.to_string(), None)
+                    .await
+                    .unwrap();
+                sync_until_idle(chat.database(), peer).await.unwrap();
+            });
+        }
+
+        mod when_a_message_is_sent {
+            use super::*;
+            use std::cell::RefCell;
+
+            type Given = (Chat, Database, Uuid, Rc<RefCell<Vec<Uuid>>>);
+            fn given() -> Given {
+                let (mut chat, peer, conversation, _) = super::given();
+                let delivered = Rc::new(RefCell::new(Vec::new()));
+                let on_delivered = delivered.clone();
+                chat.on_fully_delivered(move |uuid| on_delivered.borrow_mut().push(uuid));
+                let message = chat.send_message(conversation, "sent".to_string(), None);
+
+                (chat, peer, message.uuid, delivered)
+            }
+
+            #[test]
+            fn then_it_is_not_delivered_before_the_peer_acks_it() {
+                let (mut chat, _, _, delivered) = given();
+
+                chat.runtime().block_on(chat.check_deliveries());
+
+                assert!(delivered.borrow().is_empty());
+            }
+
+            #[test]
+            fn then_the_callback_is_called_once_the_peer_acks_it() {
+                let (mut chat, peer, uuid, delivered) = given();
+                let runtime = chat.runtime();
+
+                runtime
+                    .block_on(sync_until_idle(chat.database(), &peer))
+                    .unwrap();
+                runtime.block_on(chat.check_deliveries());
+                runtime.block_on(chat.check_deliveries());
+
+                assert_eq!(*delivered.borrow(), [uuid]);
+            }
+        }
+
+        mod when_the_peer_is_blocked_and_revoked {
+            use super::*;
+
+            fn given() -> Given {
+                let (mut chat, peer, conversation, joined) = super::given();
+                chat.block_and_revoke_peer(*peer.cert());
+
+                (chat, peer, conversation, joined)
+            }
+
+            #[test]
+            fn then_it_is_no_longer_a_member() {
+                let (chat, peer, mut conversation, _) = given();
+
+                chat.refresh_conversation(&mut conversation);
+
+                let members = conversation.members.iter();
+                assert!(!members
+                    .map(|member| member.key)
+                    .any(|key| key == *peer.cert()));
+            }
+
+            #[test]
+            fn then_it_is_blocked_without_channels() {
+                let (chat, peer, conversation, _) = given();
+                let database = chat.database();
+
+                let runtime = chat.runtime();
+                let channels = runtime.block_on(database.list_channels(&conversation));
+                let blocked = runtime.block_on(database.list_blocked());
+
+                assert!(channels.unwrap().is_empty());
+                assert_eq!(blocked.unwrap(), [*peer.cert()]);
+            }
+        }
+
+        mod when_it_is_focused {
+            use super::*;
+
+            fn given() -> Given {
+                let (mut chat, peer, conversation, joined) = super::given();
+                receive(&chat, &peer, &joined, "first");
+                chat.on_conversation_focused(&conversation);
+
+                (chat, peer, conversation, joined)
+            }
+
+            #[test]
+            fn then_it_is_read() {
+                let (chat, _, conversation, _) = given();
+
+                assert_eq!(chat.unread_count(&conversation), 0);
+            }
+
+            #[test]
+            fn then_focusing_it_again_changes_nothing() {
+                let (mut chat, _, conversation, _) = given();
+                let runtime = chat.runtime();
+                let before = runtime.block_on(chat.database().sync_bookmark()).unwrap();
+
+                chat.on_conversation_focused(&conversation);
+
+                let after = runtime.block_on(chat.database().sync_bookmark()).unwrap();
+                assert_eq!(after, before);
+            }
+
+            #[test]
+            fn then_a_message_arriving_meanwhile_is_read() {
+                let (mut chat, peer, conversation, joined) = given();
+
+                receive(&chat, &peer, &joined, "second");
+                chat.on_conversation_focused(&conversation);
+
+                assert_eq!(chat.unread_count(&conversation), 0);
+            }
+        }
+    }
+}
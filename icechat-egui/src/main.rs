@@ -5,12 +5,19 @@ use eframe::egui;
 use egui_dock::Tree;
 use icechat::{
     channel::Ed25519Cert,
-    database::{Contact, Content, Conversation},
-    notification::NotificationManager,
+    database::{
+        error::DatabaseError, AttachmentInfo, Contact, Content, Conversation, DeliveryState,
+        Message, MessageOrder, MessageStatus, MAX_MESSAGE_TEXT_BYTES,
+    },
+    invite::Invite,
+    notification::{Notification, NotificationManager},
     poll_runtime::PollRuntime,
 };
 use rfd::FileDialog;
-use std::{borrow::Cow, cell::RefCell, time::Duration};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, time::Duration};
+
+/// Messages fetched at a time, see [`Conversation::messages_page`].
+const PAGE: usize = 10;
 
 fn main() {
     env_logger::init();
@@ -24,26 +31,90 @@ fn main() {
         path.to_string_lossy().into_owned()
     });
 
-    let chat = Chat::load(path);
+    let screen = match Chat::is_encrypted(&path) {
+        true => Screen::Unlock {
+            path,
+            passphrase: Default::default(),
+            error: None,
+        },
+        false => Screen::Chat(Box::new(App::new(Chat::load(path)))),
+    };
 
     eframe::run_native(
         "Icechat",
         Default::default(),
-        Box::new(move |_| Box::new(App::new(chat))),
+        Box::new(move |_| Box::new(screen)),
     )
 }
 
+/// Asks for the passphrase of an encrypted database before showing the chat.
+enum Screen {
+    Unlock {
+        path: String,
+        passphrase: String,
+        error: Option<String>,
+    },
+    Chat(Box<App>),
+}
+impl eframe::App for Screen {
+    fn update(&mut self, ctx: &eframe::egui::Context, frame: &mut eframe::Frame) {
+        let (path, passphrase, error) = match self {
+            Screen::Unlock {
+                path,
+                passphrase,
+                error,
+            } => (path, passphrase, error),
+            Screen::Chat(app) => return app.update(ctx, frame),
+        };
+
+        let mut unlocked = None;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.label(format!("Passphrase for {path}"));
+            let text_edit = ui.add(egui::TextEdit::singleline(passphrase).password(true));
+            text_edit.request_focus();
+
+            let submit = ui
+                .input_mut()
+                .consume_key(egui::Modifiers::default(), egui::Key::Enter);
+            if ui.button("Unlock").clicked() || submit {
+                match Chat::load_encrypted(&path, passphrase) {
+                    Ok(chat) => unlocked = Some(chat),
+                    Err(e) => *error = Some(e.to_string()),
+                }
+            }
+
+            if let Some(error) = error {
+                ui.colored_label(egui::Color32::RED, error.as_str());
+            }
+        });
+
+        if let Some(chat) = unlocked {
+            *self = Screen::Chat(Box::new(App::new(chat)));
+        }
+    }
+}
+
 struct App {
     chat: Chat,
     conversations: Tree<RefCell<ConversationTab>>,
     runtime: PollRuntime,
     join: String,
+    notifications: NotificationManager,
 }
 impl App {
-    pub fn new(chat: Chat) -> App {
+    pub fn new(mut chat: Chat) -> App {
+        chat.on_fully_delivered(|uuid| log::info!("Message {uuid} delivered to every peer"));
+        chat.on_channel_state_change(|change| {
+            log::info!(
+                "Channel with {} went from {:?} to {:?}",
+                change.peer.hex(),
+                change.from,
+                change.to
+            )
+        });
         let user = chat.profile();
         let mut conversations = Tree::<RefCell<ConversationTab>>::default();
-        for conversation in chat.list_conversation() {
+        for conversation in chat.list_unarchived() {
             conversations
                 .push_to_first_leaf(RefCell::new(ConversationTab::new(conversation, &user)));
         }
@@ -53,6 +124,7 @@ impl App {
             conversations,
             runtime: Default::default(),
             join: Default::default(),
+            notifications: Default::default(),
         }
     }
 }
@@ -61,8 +133,8 @@ impl eframe::App for App {
         #![allow(clippy::await_holding_refcell_ref)]
         ctx.request_repaint_after(Duration::from_millis(5));
 
-        let runtime = self.chat.runtime().handle().clone();
-        let changed = self.runtime.poll(runtime, async {
+        let runtime = self.chat.runtime();
+        let changed = self.runtime.poll(&runtime, async {
             self.chat.pre_wait().await;
             let wait = self.chat.wait();
             let Ok(value) = tokio::time::timeout(Duration::from_millis(1), wait).await else {
@@ -74,8 +146,23 @@ impl eframe::App for App {
         });
 
         if changed == Some(true) {
-            for message in self.chat.new_messages() {
-                NotificationManager::show(message)
+            self.notifications
+                .set_do_not_disturb(!self.chat.should_notify());
+            let messages = self.chat.new_messages();
+            let conversations = match messages.is_empty() {
+                true => Vec::new(),
+                false => self.chat.list_conversation(),
+            };
+            for message in messages {
+                let conversation = conversations
+                    .iter()
+                    .find(|conversation| conversation.uuid == message.conversation);
+                if let Some(conversation) =
+                    conversation.filter(|conversation| !self.chat.is_muted(conversation))
+                {
+                    self.notifications
+                        .show(Notification::from_message(&message, conversation));
+                }
             }
         }
 
@@ -96,41 +183,71 @@ impl eframe::App for App {
                         frame.close();
                     }
                 });
+                ui.menu_button("Notifications", |ui| {
+                    let mut do_not_disturb = self.chat.do_not_disturb();
+                    if ui.checkbox(&mut do_not_disturb, "Do not disturb").changed() {
+                        self.chat.set_do_not_disturb(do_not_disturb, None);
+                    }
+
+                    if ui.button("Pause for 1 hour").clicked() {
+                        self.chat.set_do_not_disturb(true, Some(60 * 60));
+                    }
+                });
+                ui.menu_button("Saved messages", |ui| {
+                    let starred = self.chat.list_starred();
+                    if starred.is_empty() {
+                        ui.label("No saved messages");
+                    }
+                    for (conversation, message) in starred {
+                        ui.horizontal(|ui| {
+                            if ui.button("⭐").on_hover_text("Unsave").clicked() {
+                                self.chat.set_starred(&message, false);
+                            }
+                            let text = match &message.content {
+                                Content::Text(text) => text,
+                                Content::Attachment(name, ..) => name,
+                                Content::Deleted => "<deleted>",
+                                Content::Blocked => "<blocked>",
+                            };
+                            ui.label(format!(
+                                "{title}: {name}: {text}",
+                                title = conversation.title.as_deref().unwrap_or("<untitled>"),
+                                name = message.from.display_name()
+                            ));
+                        });
+                    }
+                });
+                ui.menu_button("Archived", |ui| {
+                    let archived = self.chat.list_archived();
+                    if archived.is_empty() {
+                        ui.label("No archived conversations");
+                    }
+                    for conversation in archived {
+                        let tab = ConversationTab::new(conversation, &self.chat.profile());
+                        ui.horizontal(|ui| {
+                            ui.label(tab.title());
+                            if ui.button("Unarchive").clicked() {
+                                self.chat.set_archived(&tab.conversation, false);
+                                self.conversations.push_to_first_leaf(RefCell::new(tab));
+                            }
+                        });
+                    }
+                });
                 if ui.button("Join:").clicked() {
                     let join = std::mem::take(&mut self.join);
-                    let join = join.split_once(':');
-                    let join = join.and_then(|(conversation, peer)| {
-                        let conversation = conversation
-                            .parse()
-                            .map_err(|e| {
-                                log::error!("Bad uuid {conversation:?}, {e}");
-                                log::debug!("{e:?}");
-                                e
-                            })
-                            .ok()?;
-
-                        let peer = (2..=peer.len())
-                            .step_by(2)
-                            .map(|idx| {
-                                u8::from_str_radix(&peer[(idx - 2)..][..2], 16).unwrap_or_default()
-                            })
-                            .collect::<Vec<u8>>();
-                        let peer = peer
-                            .as_slice()
-                            .try_into()
-                            .map_err(|e| {
-                                log::error!("Bad key {peer:?}, {e}");
-                                log::debug!("{e:?}");
-                                e
-                            })
-                            .ok()?;
-                        let peer = Ed25519Cert(peer);
-
-                        Some((conversation, peer))
-                    });
-
-                    if let Some((conversation, peer)) = join {
-                        let conversation = self.chat.join_conversation(conversation, peer);
+                    let join = join
+                        .parse::<Invite>()
+                        .map_err(|e| {
+                            log::error!("Bad invite {join:?}, {e}");
+                            log::debug!("{e:?}");
+                            e
+                        })
+                        .ok();
+
+                    if let Some(invite) = join {
+                        let conversation = self
+                            .chat
+                            .join_conversation(invite.conversation, invite.peer);
                         self.conversations
                             .push_to_first_leaf(RefCell::new(ConversationTab::new(
                                 conversation,
@@ -166,7 +283,11 @@ impl<'a> egui_dock::TabViewer for TabViewer<'a> {
     }
 
     fn title(&mut self, tab: &mut Self::Tab) -> egui::WidgetText {
-        tab.borrow_mut().title().into()
+        let tab = tab.borrow();
+        match self.0.unread_count(&tab.conversation) {
+            0 => tab.title().into(),
+            unread => format!("{} ({unread})", tab.title()).into(),
+        }
     }
 }
 
@@ -176,8 +297,23 @@ struct ConversationTab {
     new_name: String,
     new_title: String,
     new_channel: String,
+    /// Why the key in `new_channel` could not be added.
+    channel_error: Option<String>,
     message: String,
-    max: usize,
+    /// Message whose text is being edited, sending replaces its text.
+    editing: Option<Message>,
+    /// Message the next one sent replies to.
+    replying: Option<Message>,
+    /// Member whose safety number is shown.
+    comparing: Option<Ed25519Cert>,
+    /// Member being renamed, with the alias typed so far.
+    aliasing: Option<(Ed25519Cert, String)>,
+    send_error: Option<String>,
+    /// How many pages of [`PAGE`] messages are shown.
+    pages: usize,
+    /// Previews of the attachments shown, `None` for the ones that did not
+    /// decode.
+    thumbnails: HashMap<i32, Option<egui::TextureHandle>>,
 }
 impl ConversationTab {
     pub fn new(conversation: Conversation, user: &Contact) -> ConversationTab {
@@ -189,8 +325,15 @@ impl ConversationTab {
             new_name: user.name.to_string(),
             new_title,
             new_channel: Default::default(),
+            channel_error: None,
             message: Default::default(),
-            max: 10,
+            editing: None,
+            replying: None,
+            comparing: None,
+            aliasing: None,
+            send_error: None,
+            pages: 1,
+            thumbnails: Default::default(),
         }
     }
 
@@ -206,8 +349,10 @@ impl ConversationTab {
                     .iter()
                     .filter(|member| member.key != self.user)
                     .fold(None, |list, member| match list {
-                        Some(list) => Some(format!("{list}, {member}", member = member.name)),
-                        None => Some(member.name.clone()),
+                        Some(list) => {
+                            Some(format!("{list}, {member}", member = member.display_name()))
+                        }
+                        None => Some(member.display_name().to_string()),
                     });
 
                 other
@@ -217,12 +362,16 @@ impl ConversationTab {
     }
 
     fn ui(&mut self, ui: &mut egui::Ui, chat: &mut Chat) {
-        let runtime = chat.runtime().handle().clone();
+        let runtime = chat.runtime();
         chat.refresh_conversation(&mut self.conversation);
+        chat.on_conversation_focused(&self.conversation);
 
         egui::CentralPanel::default().show_inside(ui, |ui| {
             ui.horizontal(|ui| {
                 let text_edit = ui.text_edit_multiline(&mut self.message);
+                if text_edit.changed() && !self.message.is_empty() {
+                    chat.send_typing(&self.conversation);
+                }
 
                 if ui.button("Send").clicked() && !self.message.is_empty() {
                     self.send_message(chat);
@@ -232,6 +381,15 @@ impl ConversationTab {
                     self.send_file(chat);
                 }
 
+                if self.editing.is_some() && ui.button("Cancel edit").clicked() {
+                    self.editing = None;
+                    self.message.clear();
+                }
+
+                if self.replying.is_some() && ui.button("Cancel reply").clicked() {
+                    self.replying = None;
+                }
+
                 if ui
                     .input_mut()
                     .consume_key(egui::Modifiers::default(), egui::Key::Enter)
@@ -240,52 +398,154 @@ impl ConversationTab {
                     text_edit.request_focus();
                 }
             });
+            if let Some(replying) = &self.replying {
+                ui.weak(format!("Replying to {}", Self::quote(replying)));
+            }
+            let typers = chat.typing(&self.conversation);
+            if !typers.is_empty() {
+                ui.weak(self.conversation.typing_label(&typers));
+            }
+            if let Some(error) = &self.send_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
             egui::containers::ScrollArea::vertical().show(ui, |ui| {
                 ui.vertical(|ui| {
-                    let length = runtime
-                        .block_on(self.conversation.length(chat.database()))
-                        .unwrap();
-                    let mut n = 0;
-                    for index in (0..length).rev().take(self.max) {
-                        let message = runtime
-                            .block_on(self.conversation.get_message(chat.database(), index))
-                            .unwrap();
-                        let Some(message) = message else { break; };
-                        n += 1;
-
-                        ui.label(format!(
-                            "({state:?}) {name}",
-                            state = message.status,
-                            name = message.from.name
-                        ));
+                    let (messages, more) = runtime.block_on(async {
+                        let mut messages = vec![];
+                        let mut before = None;
+                        for _ in 0..self.pages {
+                            let page = self
+                                .conversation
+                                .messages_page(
+                                    chat.database(),
+                                    before.as_ref(),
+                                    PAGE,
+                                    MessageOrder::CausalSequence,
+                                )
+                                .await
+                                .unwrap();
+                            let more = page.len() == PAGE;
+                            before = page.first().cloned();
+                            messages.extend(page.into_iter().rev());
+                            if !more {
+                                return (messages, false);
+                            }
+                        }
+                        (messages, true)
+                    });
+
+                    for message in messages {
+                        ui.horizontal(|ui| {
+                            let star = match message.starred {
+                                true => "⭐",
+                                false => "☆",
+                            };
+                            if ui.button(star).on_hover_text("Saved messages").clicked() {
+                                chat.set_starred(&message, !message.starred);
+                            }
+                            if message.from.key == self.user
+                                && message.content != Content::Deleted
+                                && ui
+                                    .button("🗑")
+                                    .on_hover_text("Delete for everyone")
+                                    .clicked()
+                            {
+                                chat.delete_message(&message);
+                            }
+                            // Receipts already tell what happened to messages
+                            // that reached a peer.
+                            let delivery = match message.status {
+                                MessageStatus::Sent if message.from.key == self.user => runtime
+                                    .block_on(chat.database().delivery_state(&message))
+                                    .unwrap(),
+                                _ => DeliveryState::Delivered,
+                            };
+                            let state = match delivery {
+                                DeliveryState::Delivered => format!("{:?}", message.status),
+                                delivery => format!("{delivery:?}"),
+                            };
+                            ui.label(format!(
+                                "{sent_at}({state}) {name}{edited}",
+                                sent_at = Self::sent_at(&message),
+                                name = message.from.display_name(),
+                                edited = if message.meta.edited { " (edited)" } else { "" },
+                            ));
+                        });
+                        if let Some(reply_to) = message.meta.reply_to {
+                            let quoted = runtime
+                                .block_on(
+                                    self.conversation
+                                        .get_message_by_uuid(chat.database(), reply_to),
+                                )
+                                .unwrap();
+                            ui.weak(match quoted {
+                                Some(quoted) => format!("↪ {}", Self::quote(&quoted)),
+                                None => "↪ Message not received yet".to_string(),
+                            });
+                        }
+                        let original = message.clone();
+                        let preview = match &message.content {
+                            Content::Attachment(
+                                _,
+                                id,
+                                AttachmentInfo {
+                                    thumbnail: Some(thumbnail),
+                                    ..
+                                },
+                            ) => self.thumbnail(ui.ctx(), *id, thumbnail),
+                            _ => None,
+                        };
                         ui.horizontal(|ui| match message.content {
                             Content::Text(text) => {
-                                if ui.button("⬅").clicked() {
-                                    self.message = format!(
-                                        "{sender} said:\n{message}\n\n",
-                                        sender = message.from.name,
-                                        message = text,
-                                    );
+                                if ui.button("⬅").on_hover_text("Reply").clicked() {
+                                    self.replying = Some(original.clone());
                                 }
                                 if ui.button("📋").clicked() {
                                     ui.output().copied_text = text.to_string();
                                 }
+                                if message.from.key == self.user && ui.button("✏").clicked() {
+                                    self.message = text.clone();
+                                    self.editing = Some(original);
+                                }
 
                                 ui.label(text);
                             }
-                            Content::Attachment(name, id) => {
-                                if ui.button("💾").clicked() {
-                                    Self::save_file(chat, &name, id);
+                            Content::Attachment(name, id, info) => match chat
+                                .attachment_progress(id)
+                            {
+                                Some((received, total)) if received < total => {
+                                    ui.label(format!("{name} ({}%)", received * 100 / total));
                                 }
-
-                                ui.label(name);
+                                _ => {
+                                    if ui.button("💾").clicked() {
+                                        Self::save_file(chat, &name, id);
+                                    }
+
+                                    match info.mime {
+                                        Some(mime) if mime.starts_with("image/") => ui.label("🖼"),
+                                        _ => ui.label("📄"),
+                                    };
+                                    ui.label(name);
+                                    if let Some(size) = info.size {
+                                        ui.weak(format_size(size));
+                                    }
+                                }
+                            },
+                            Content::Deleted => {
+                                ui.weak("Message deleted");
+                            }
+                            Content::Blocked => {
+                                ui.weak("Message from a blocked contact");
                             }
                         });
+                        if let Some(preview) = preview {
+                            ui.image(&preview, preview.size_vec2());
+                        }
                         ui.separator();
                     }
 
-                    if n == self.max && ui.button("Load more").clicked() {
-                        self.max += 10;
+                    if more && ui.button("Load more").clicked() {
+                        self.pages += 1;
                     }
                 });
             });
@@ -299,16 +559,22 @@ impl ConversationTab {
                     egui::containers::ScrollArea::horizontal().show(ui, |ui| {
                         let channels = chat
                             .channels()
-                            .filter(|(channel, _)| channel.conversation == self.conversation.uuid);
+                            .filter(|(channel, ..)| channel.conversation == self.conversation.uuid);
                         let mut remove = None;
 
-                        for (channel, state) in channels {
+                        for (channel, state, offline_reason, throughput) in channels {
                             ui.horizontal(|ui| {
                                 if ui.button("X").clicked() {
                                     remove = Some(channel.peer_cert);
                                 }
                                 let fp = channel.peer_cert.hex();
-                                ui.label(format!("({state:?}) {fp}"));
+                                match offline_reason {
+                                    Some(reason) => ui.label(format!("({state:?}, {reason}) {fp}")),
+                                    None => ui.label(format!("({state:?}) {fp}")),
+                                };
+                                if let Some(throughput) = throughput {
+                                    ui.weak(format!("{:.1} KiB/s", throughput / 1024.0));
+                                }
                             });
                         }
 
@@ -317,9 +583,11 @@ impl ConversationTab {
                         }
 
                         ui.horizontal(|ui| {
-                            let id = self.conversation.uuid;
-                            let key = chat.profile().key.hex();
-                            let invite = format!("{id}:{key}");
+                            let invite = Invite {
+                                conversation: self.conversation.uuid,
+                                peer: chat.profile().key,
+                            }
+                            .to_string();
 
                             if ui.button("📋").clicked() {
                                 ui.output().copied_text = invite.clone();
@@ -333,25 +601,129 @@ impl ConversationTab {
                         ui.label("Peer pub key:");
                         ui.text_edit_singleline(&mut self.new_channel);
                         if ui.button("Add").clicked() {
-                            let peer = std::mem::take(&mut self.new_channel);
-                            let mut peer = (0..peer.len())
-                                .step_by(2)
-                                .map(|idx| {
-                                    u8::from_str_radix(&peer[idx..][..2], 16).unwrap_or_default()
-                                })
-                                .collect::<Vec<u8>>();
-                            peer.resize(32, 0);
-                            let peer = Ed25519Cert(peer.try_into().unwrap());
-
-                            chat.add_channel(self.conversation.clone(), peer);
+                            self.channel_error =
+                                match self.new_channel.trim().parse::<Ed25519Cert>() {
+                                    Ok(peer) => chat
+                                        .add_channel(self.conversation.clone(), peer)
+                                        .err()
+                                        .map(|e| e.to_string()),
+                                    Err(e) => Some(e.to_string()),
+                                };
+                            if self.channel_error.is_none() {
+                                self.new_channel.clear();
+                            }
+                        }
+                        if let Some(error) = &self.channel_error {
+                            ui.colored_label(egui::Color32::RED, error);
                         }
                     });
 
                     ui.heading("Members");
+                    let mut revoke = None;
+                    let mut block = None;
+                    let mut cut_off = None;
                     for member in self.conversation.members.iter() {
-                        let name = member.name.as_str();
-                        let fp = member.key.hex();
-                        ui.label(format!("{name} ({fp})"));
+                        ui.horizontal(|ui| {
+                            if member.key != self.user
+                                && ui
+                                    .button("Remove everywhere")
+                                    .on_hover_text("Drops this peer from all conversations")
+                                    .clicked()
+                            {
+                                revoke = Some(member.key);
+                            }
+                            if member.key != self.user
+                                && ui
+                                    .button("Block")
+                                    .on_hover_text("Ignores everything from this peer")
+                                    .clicked()
+                            {
+                                block = Some(member.key);
+                            }
+                            if member.key != self.user
+                                && ui
+                                    .button("Block & remove everywhere")
+                                    .on_hover_text(
+                                        "Drops this peer from all conversations and ignores \
+                                        everything from them",
+                                    )
+                                    .clicked()
+                            {
+                                cut_off = Some(member.key);
+                            }
+                            if member.key != self.user
+                                && ui
+                                    .button("Safety number")
+                                    .on_hover_text("Compare it with this peer to verify them")
+                                    .clicked()
+                            {
+                                self.comparing = Some(member.key);
+                            }
+                            if member.key != self.user
+                                && ui
+                                    .button("Rename")
+                                    .on_hover_text("Name this peer for yourself only")
+                                    .clicked()
+                            {
+                                self.aliasing =
+                                    Some((member.key, member.alias.clone().unwrap_or_default()));
+                            }
+                            let name = member.display_name();
+                            let fp = member.key.hex();
+                            ui.label(format!("{name} ({fp})"));
+                            if member.verified {
+                                ui.label("✔").on_hover_text("Verified");
+                            }
+                        });
+                    }
+                    let mut done = false;
+                    if let Some((peer, alias)) = &mut self.aliasing {
+                        ui.horizontal(|ui| {
+                            ui.label("Alias:");
+                            ui.text_edit_singleline(alias);
+                            if ui.button("Save").clicked() {
+                                let alias = Some(alias.clone()).filter(|alias| !alias.is_empty());
+                                chat.set_alias(*peer, alias);
+                                done = true;
+                            }
+                            if ui.button("Cancel").clicked() {
+                                done = true;
+                            }
+                        });
+                    }
+                    if done {
+                        self.aliasing = None;
+                    }
+                    let compared = self.comparing.and_then(|peer| {
+                        self.conversation
+                            .members
+                            .iter()
+                            .find(|member| member.key == peer)
+                    });
+                    if let Some(member) = compared {
+                        ui.label(format!("Safety number with {}:", member.display_name()));
+                        ui.monospace(self.user.safety_number(&member.key));
+                        ui.horizontal(|ui| {
+                            let toggle = match member.verified {
+                                true => "Unverify",
+                                false => "Mark verified",
+                            };
+                            if ui.button(toggle).clicked() {
+                                chat.set_verified(member.key, !member.verified);
+                            }
+                            if ui.button("Close").clicked() {
+                                self.comparing = None;
+                            }
+                        });
+                    }
+                    if let Some(revoke) = revoke {
+                        chat.revoke_peer(revoke);
+                    }
+                    if let Some(block) = block {
+                        chat.block_contact(block);
+                    }
+                    if let Some(cut_off) = cut_off {
+                        chat.block_and_revoke_peer(cut_off);
                     }
 
                     ui.heading("Profile");
@@ -376,37 +748,128 @@ impl ConversationTab {
                             };
                             chat.save_conversation(self.conversation.clone());
                         }
-                    })
+                    });
+                    let mut muted = chat.is_muted(&self.conversation);
+                    if ui.checkbox(&mut muted, "Mute notifications").changed() {
+                        chat.set_muted(&self.conversation, muted);
+                    }
+                    let mut archived = chat.is_archived(&self.conversation);
+                    if ui
+                        .checkbox(&mut archived, "Archive")
+                        .on_hover_text("Left out of the conversations opened on start")
+                        .changed()
+                    {
+                        chat.set_archived(&self.conversation, archived);
+                    }
                 });
             });
     }
 
     fn send_message(&mut self, chat: &mut Chat) {
+        self.send_error = None;
+        let (len, max) = (self.message.len(), MAX_MESSAGE_TEXT_BYTES);
+        if len > max {
+            self.send_error = Some(DatabaseError::MessageTooLarge { len, max }.to_string());
+            return;
+        }
+
         let content = std::mem::take(&mut self.message);
-        chat.send_message(self.conversation.clone(), content);
+        match self.editing.take() {
+            Some(message) => chat.edit_message(&message, content),
+            None => {
+                let reply_to = self.replying.take().map(|message| message.uuid);
+                chat.send_message(self.conversation.clone(), content, reply_to);
+            }
+        }
+    }
+
+    /// Sender and beginning of the text of `message`, to be shown above its
+    /// replies.
+    fn quote(message: &Message) -> String {
+        const QUOTE_CHARS: usize = 40;
+
+        let text = message.text().lines().next().unwrap_or_default();
+        let mut quote = text.chars().take(QUOTE_CHARS).collect::<String>();
+        if quote.len() < message.text().len() {
+            quote.push('…');
+        }
+
+        format!("{}: {quote}", message.from.display_name())
+    }
+
+    /// Time of day `message` was sent at, in UTC as there is no time zone
+    /// database at hand. Empty for messages sent before timestamps were kept.
+    fn sent_at(message: &Message) -> String {
+        if message.created_at == 0 {
+            return String::new();
+        }
+
+        let minutes = message.created_at / 1000 / 60;
+        format!("{:02}:{:02} UTC ", minutes / 60 % 24, minutes % 60)
     }
 
     fn send_file(&mut self, chat: &mut Chat) {
-        let path = FileDialog::new().set_title("Send file").pick_file();
+        let max = chat.database().max_attachment_bytes();
+        let title = match max {
+            Some(max) => format!("Send file (up to {} MiB)", max / 1024 / 1024),
+            None => "Send file".to_string(),
+        };
+        let path = FileDialog::new().set_title(&title).pick_file();
 
         let Some(path) = path else { return; };
+        self.send_error = None;
+
+        let len = std::fs::metadata(&path).unwrap().len() as usize;
+        if let Some(max) = max.filter(|max| len > *max) {
+            self.send_error = Some(DatabaseError::AttachmentTooLarge { len, max }.to_string());
+            return;
+        }
 
         let name = path
             .file_name()
             .map(|x| x.to_string_lossy().into_owned())
             .unwrap_or_else(|| "unnamed file".to_string());
-        let blob = std::fs::read(path).unwrap();
+        let file = std::fs::File::open(path).unwrap();
 
-        chat.send_file(self.conversation.clone(), name, blob);
+        if let Err(e) = chat.send_file(self.conversation.clone(), name, file, len as u64) {
+            self.send_error = Some(e.to_string());
+        }
+    }
+
+    fn thumbnail(
+        &mut self,
+        ctx: &egui::Context,
+        id: i32,
+        jpeg: &[u8],
+    ) -> Option<egui::TextureHandle> {
+        self.thumbnails
+            .entry(id)
+            .or_insert_with(|| {
+                let image =
+                    image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg).ok()?;
+                let image = image.into_rgb8();
+                let size = [image.width() as usize, image.height() as usize];
+                let image = egui::ColorImage::from_rgb(size, image.as_raw());
+
+                Some(ctx.load_texture(format!("thumbnail-{id}"), image, Default::default()))
+            })
+            .clone()
     }
 
     fn save_file(chat: &Chat, name: &str, id: i32) {
-        let blob = chat.fetch_file_payload(id);
         let path = FileDialog::new().set_file_name(name).save_file();
 
-        let Some(blob) = blob else { return; };
         let Some(path) = path else { return; };
 
-        std::fs::write(path, blob).unwrap();
+        let file = std::fs::File::create(path).unwrap();
+        chat.save_file_payload(id, file).unwrap();
+    }
+}
+
+fn format_size(size: u64) -> String {
+    match size {
+        size if size < 1024 => format!("{size} B"),
+        size if size < 1024 * 1024 => format!("{:.1} KiB", size as f64 / 1024.0),
+        size => format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0)),
     }
 }
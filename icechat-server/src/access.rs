@@ -0,0 +1,276 @@
+//! Who may run control commands, and how often.
+//!
+//! Anyone with a channel to the control conversation can post to it, so
+//! commands are only run for the control users, each up to its permission and
+//! a number of commands per window. Control users are kept in the database, so
+//! the ones added once are still allowed after a restart, until revoked.
+
+use crate::{Command, CommandError, CommandResult};
+use icechat::{
+    channel::Ed25519Cert,
+    database::{error::DatabaseResult, Database},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// What a control user may run. A level allows whatever the ones below it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    /// Commands that only report on the server.
+    Read,
+    /// Every command.
+    Admin,
+}
+impl Permission {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Admin => "admin",
+        }
+    }
+}
+impl FromStr for Permission {
+    type Err = BadPermission;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Permission::Read),
+            "admin" => Ok(Permission::Admin),
+            _ => Err(BadPermission(s.to_string())),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Unknown permission {0:?}, expected read or admin")]
+pub struct BadPermission(String);
+
+/// Preference the control users are kept under, each as its cert and the name
+/// of its permission.
+const CONTROL_USERS: &str = "icechat-server.control_users";
+
+pub struct Access {
+    allowed: HashMap<Ed25519Cert, Permission>,
+    limit: usize,
+    window: Duration,
+    recent: HashMap<Ed25519Cert, VecDeque<Instant>>,
+}
+impl Access {
+    /// Nobody allowed yet, and at most `limit` commands per `window` from
+    /// each control user once they are.
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Access {
+            allowed: Default::default(),
+            limit,
+            window,
+            recent: Default::default(),
+        }
+    }
+
+    pub fn allow(&mut self, cert: Ed25519Cert, permission: Permission) {
+        self.allowed.insert(cert, permission);
+    }
+
+    /// Takes the permission of `cert` away. Returns whether it had one.
+    pub fn revoke(&mut self, cert: &Ed25519Cert) -> bool {
+        self.recent.remove(cert);
+        self.allowed.remove(cert).is_some()
+    }
+
+    /// Allows the control users kept in `database` by [`Access::save`].
+    pub async fn load(&mut self, database: &Database) -> DatabaseResult<()> {
+        let users: Vec<([u8; 32], String)> = database.preference(CONTROL_USERS).await?;
+        for (cert, permission) in users {
+            match permission.parse() {
+                Ok(permission) => self.allow(Ed25519Cert(cert), permission),
+                Err(e) => log::error!("Ignoring control user {}: {e}", Ed25519Cert(cert).hex()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the control users in `database`.
+    pub async fn save(&self, database: &Database) -> DatabaseResult<()> {
+        let users = self
+            .allowed
+            .iter()
+            .map(|(cert, permission)| (cert.0, permission.as_str().to_string()))
+            .collect::<Vec<_>>();
+
+        database.set_preference(CONTROL_USERS, &users).await
+    }
+
+    /// Counts a command sent by `sender` at `now`. Senders that are not
+    /// control users, or that went over the limit, are refused.
+    pub fn admit(&mut self, sender: &Ed25519Cert, now: Instant) -> CommandResult<Permission> {
+        let permission = *self
+            .allowed
+            .get(sender)
+            .ok_or_else(|| CommandError::Unauthorized(sender.hex()))?;
+
+        let recent = self.recent.entry(*sender).or_default();
+        while recent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= self.window)
+        {
+            recent.pop_front();
+        }
+        if recent.len() >= self.limit {
+            return Err(CommandError::RateLimited(self.limit, self.window));
+        }
+        recent.push_back(now);
+
+        Ok(permission)
+    }
+
+    /// Refuses `command` if `permission` does not cover it.
+    pub fn authorize(permission: Permission, command: &Command) -> CommandResult<()> {
+        let required = command.permission();
+        if permission < required {
+            return Err(CommandError::Forbidden(required));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icechat::channel::Ed25519Seed;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    mod given_a_reader_and_an_admin {
+        use super::*;
+
+        type Given = (Access, Ed25519Cert, Ed25519Cert, Instant);
+        fn given() -> Given {
+            let [reader, admin] = [(); 2].map(|_| Ed25519Seed::generate().public_key());
+            let mut access = Access::new(2, WINDOW);
+            access.allow(reader, Permission::Read);
+            access.allow(admin, Permission::Admin);
+
+            (access, reader, admin, Instant::now())
+        }
+
+        #[test]
+        fn then_an_unknown_sender_is_denied() {
+            let (mut access, ..) = given();
+            let stranger = Ed25519Seed::generate().public_key();
+
+            let r = access.admit(&stranger, Instant::now());
+
+            assert!(matches!(r, Err(CommandError::Unauthorized(cert)) if cert == stranger.hex()));
+        }
+
+        #[test]
+        fn then_the_reader_cannot_run_admin_commands() {
+            let (mut access, reader, ..) = given();
+
+            let permission = access.admit(&reader, Instant::now()).unwrap();
+
+            assert!(Access::authorize(permission, &Command::List).is_ok());
+            assert!(matches!(
+                Access::authorize(permission, &Command::Compact),
+                Err(CommandError::Forbidden(Permission::Admin))
+            ));
+        }
+
+        #[test]
+        fn then_the_admin_can_run_anything() {
+            let (mut access, _, admin, now) = given();
+
+            let permission = access.admit(&admin, now).unwrap();
+
+            assert!(Access::authorize(permission, &Command::List).is_ok());
+            assert!(Access::authorize(permission, &Command::Compact).is_ok());
+        }
+
+        mod when_a_sender_goes_over_the_limit {
+            use super::*;
+
+            fn given() -> super::Given {
+                let (mut access, reader, admin, now) = super::given();
+                for _ in 0..2 {
+                    access.admit(&reader, now).unwrap();
+                }
+
+                (access, reader, admin, now)
+            }
+
+            #[test]
+            fn then_it_is_refused() {
+                let (mut access, reader, _, now) = given();
+
+                let r = access.admit(&reader, now + WINDOW / 2);
+
+                assert!(matches!(r, Err(CommandError::RateLimited(2, WINDOW))));
+            }
+
+            #[test]
+            fn then_the_others_are_not() {
+                let (mut access, _, admin, now) = given();
+
+                assert!(access.admit(&admin, now).is_ok());
+            }
+
+            #[test]
+            fn then_it_is_admitted_again_after_the_window() {
+                let (mut access, reader, _, now) = given();
+
+                assert!(access.admit(&reader, now + WINDOW).is_ok());
+            }
+        }
+
+        mod when_the_reader_is_revoked {
+            use super::*;
+
+            fn given() -> super::Given {
+                let (mut access, reader, admin, now) = super::given();
+                assert!(access.revoke(&reader));
+
+                (access, reader, admin, now)
+            }
+
+            #[test]
+            fn then_it_is_denied() {
+                let (mut access, reader, _, now) = given();
+
+                let r = access.admit(&reader, now);
+
+                assert!(matches!(r, Err(CommandError::Unauthorized(cert)) if cert == reader.hex()));
+            }
+
+            #[tokio::test]
+            async fn then_it_is_still_denied_once_loaded_again() {
+                let (access, reader, admin, now) = given();
+                let database = Database::connect(":memory:").await.unwrap();
+
+                access.save(&database).await.unwrap();
+                let mut loaded = Access::new(2, WINDOW);
+                loaded.load(&database).await.unwrap();
+
+                assert!(loaded.admit(&reader, now).is_err());
+                assert_eq!(loaded.admit(&admin, now).unwrap(), Permission::Admin);
+            }
+        }
+
+        #[tokio::test]
+        async fn then_they_are_still_allowed_once_loaded_again() {
+            let (access, reader, admin, now) = given();
+            let database = Database::connect(":memory:").await.unwrap();
+
+            access.save(&database).await.unwrap();
+            let mut loaded = Access::new(2, WINDOW);
+            loaded.load(&database).await.unwrap();
+
+            assert_eq!(loaded.admit(&reader, now).unwrap(), Permission::Read);
+            assert_eq!(loaded.admit(&admin, now).unwrap(), Permission::Admin);
+        }
+    }
+}
@@ -1,33 +1,76 @@
+mod access;
+#[cfg(feature = "metrics")]
+mod metrics;
+
+use access::{Access, Permission};
 use clap::Parser;
-use futures_util::{
-    future::{pending, select_all},
-    FutureExt,
-};
 use icechat::{
-    channel::{BadEd25519CertStr, ChannelStateLabel, ChannelValue, Ed25519Cert},
+    channel::{BadEd25519CertStr, ChannelStateLabel, ConnectConfig, Ed25519Cert},
+    client::{Client, ClientEvent},
     database::{
         error::{DatabaseError, DatabaseResult},
-        Conversation, Database, Message,
+        sync::SyncDataId,
+        Conversation, Database, InviteToken, Message,
     },
-    SqliteChannel,
+    invite::{BadInvite, Invite},
 };
-use std::{collections::HashSet, time::Duration};
-use tokio::task::LocalSet;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc::UnboundedReceiver, task::LocalSet};
 use uuid::Uuid;
 
+#[derive(clap::Parser, Debug)]
+struct ServerArgs {
+    /// Path to the database.
+    path: String,
+    /// Cert of the control user given the admin permission. Given again on
+    /// each start, even if revoked meanwhile.
+    control_user: Option<String>,
+    /// Only forward patches between peers, without seeding new channels or
+    /// merging what is received.
+    #[arg(long)]
+    relay: bool,
+    /// Address to serve Prometheus metrics on.
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics: Option<std::net::SocketAddr>,
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let path = std::env::args()
-        .nth(1)
-        .expect("Must provide path to database");
-    let control_user = std::env::args().nth(2);
+    let ServerArgs {
+        path,
+        control_user,
+        relay,
+        #[cfg(feature = "metrics")]
+            metrics: metrics_address,
+    } = ServerArgs::parse();
     let local_set = LocalSet::new();
 
+    #[cfg(feature = "metrics")]
+    let metrics = metrics::Metrics::default();
+    #[cfg(feature = "metrics")]
+    if let Some(address) = metrics_address {
+        let listener = tokio::net::TcpListener::bind(address)
+            .await
+            .expect("Could not bind the metrics address");
+        local_set.spawn_local(metrics.clone().serve(listener));
+    }
+
     local_set.spawn_local(async move {
         loop {
-            let r = tokio::task::spawn_local(main2(path.clone(), control_user.clone())).await;
+            let r = tokio::task::spawn_local(main2(
+                path.clone(),
+                control_user.clone(),
+                relay,
+                #[cfg(feature = "metrics")]
+                metrics.clone(),
+            ))
+            .await;
             if let Err(e) = r {
                 log::error!("{e}");
                 log::debug!("{e:?}");
@@ -43,167 +86,262 @@ async fn main() {
     local_set.await;
 }
 
-async fn main2(path: String, control_user: Option<String>) {
-    println!("main2");
-    let mut server = Server::new(&path).await.unwrap();
+async fn main2(
+    path: String,
+    control_user: Option<String>,
+    relay: bool,
+    #[cfg(feature = "metrics")] metrics: metrics::Metrics,
+) {
+    log::info!("Starting on {path}");
+    let mut server = Server::new(&path, relay).await.unwrap();
+    #[cfg(feature = "metrics")]
+    {
+        server.metrics = metrics;
+    }
     if let Some(control) = control_user {
-        server.add_control(control.parse().unwrap()).await.unwrap();
+        let control = control.parse().unwrap();
+        server
+            .add_control(control, Permission::Admin)
+            .await
+            .unwrap();
+    }
+    for (control, permission) in control_users_from_env() {
+        server.add_control(control, permission).await.unwrap();
     }
-    println!(
-        "Control invite: {}:{}",
-        server.control.uuid,
-        server.database.cert().hex()
-    );
-    log::info!("teste");
+    let invite = Invite {
+        conversation: server.control.uuid,
+        peer: *server.client.database().cert(),
+    };
+    println!("Control invite: {invite}");
 
     loop {
-        server.sync_channels().await.unwrap();
-        for channel in server.channels.iter() {
-            println!(
-                "{state:?} {key}",
-                state = channel.state(),
-                key = channel.channel().peer_cert.hex()
-            );
-        }
+        server.client.sync_channels().await.unwrap();
+        server.flush_outbox().await;
 
         for message in server.control_messages().await.unwrap() {
-            let text = message.text();
-            let response = server.handle_message(text).await;
-
-            match response {
-                Ok(response) => {
-                    server.send_control_message(response).await.unwrap();
-                }
-                Err(e) => {
-                    server
-                        .send_control_message(format!("{e}\nOn message {text}\n{e:?}"))
-                        .await
-                        .unwrap();
-                }
-            }
-            server.set_message_handled(&message).await.unwrap();
+            server.respond(&message).await.unwrap();
         }
 
-        let value = server.wait().await.unwrap();
-        server.then(value).await.unwrap();
+        server.poll().await.unwrap();
+    }
+}
+
+/// Commands a control user may send per minute, unless `ICECHAT_CONTROL_RATE`
+/// says otherwise.
+const DEFAULT_CONTROL_RATE: usize = 30;
+
+/// Control users besides the one given as argument, from
+/// `ICECHAT_CONTROL_USERS`: comma separated certs, each optionally followed by
+/// `=read` or `=admin`. Without it a cert only gets [`Permission::Read`]. Like
+/// the argument, they are given their permission again on each start.
+fn control_users_from_env() -> Vec<(Ed25519Cert, Permission)> {
+    let Ok(users) = std::env::var("ICECHAT_CONTROL_USERS") else { return vec![]; };
+
+    let mut r = vec![];
+    for user in users
+        .split(',')
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+    {
+        let (cert, permission) = user.split_once('=').unwrap_or((user, "read"));
+        match (cert.parse(), permission.parse()) {
+            (Ok(cert), Ok(permission)) => r.push((cert, permission)),
+            (Err(e), _) => log::error!("Ignoring bad control user {user:?}: {e}"),
+            (_, Err(e)) => log::error!("Ignoring bad control user {user:?}: {e}"),
+        }
     }
+
+    r
 }
 
+/// Preference the responses waiting in the outbox are kept under.
+const OUTBOX: &str = "icechat-server.outbox";
+
 struct Server {
-    database: Database,
-    channels: Vec<SqliteChannel>,
+    client: Client,
+    events: UnboundedReceiver<ClientEvent>,
     control: Conversation,
+    /// Responses that could not be saved yet, retried on each iteration and
+    /// kept in the database, so that they survive a restart. Once saved a
+    /// response is a patch in the sync log and reaches the control user
+    /// whenever its channel reconnects.
+    outbox: VecDeque<String>,
+    access: Access,
+    #[cfg(feature = "metrics")]
+    metrics: metrics::Metrics,
 }
 impl Server {
-    async fn new(path: &str) -> DatabaseResult<Server> {
-        let database = Database::connect(path).await?;
-        let control = database
-            .join_conversation(Self::control_id(database.cert()))
-            .await?;
+    async fn new(path: &str, relay: bool) -> DatabaseResult<Server> {
+        let mut database = match std::env::var("ICECHAT_PASSPHRASE") {
+            Ok(passphrase) => Database::connect_encrypted(path, &passphrase).await?,
+            Err(_) => Database::connect(path).await?,
+        };
+        database.set_relay(relay);
+        database.set_connect_config(ConnectConfig::from_env().unwrap_or_else(|e| {
+            log::error!("Ignoring bad ICECHAT_SIGNALING: {e}");
+            Default::default()
+        }));
+        let control = database.control_conversation(database.cert()).await?;
+
+        let (mut client, events) = Client::new(database);
+        match std::env::var("ICECHAT_RATE_LIMIT").map(|limit| limit.parse()) {
+            Ok(Ok(limit)) => client.channels_mut().set_rate_limit(Some(limit)),
+            Ok(Err(e)) => log::error!("Ignoring bad ICECHAT_RATE_LIMIT: {e}"),
+            Err(_) => {}
+        }
+        let control_rate = match std::env::var("ICECHAT_CONTROL_RATE").map(|rate| rate.parse()) {
+            Ok(Ok(rate)) => rate,
+            Ok(Err(e)) => {
+                log::error!("Ignoring bad ICECHAT_CONTROL_RATE: {e}");
+                DEFAULT_CONTROL_RATE
+            }
+            Err(_) => DEFAULT_CONTROL_RATE,
+        };
+
+        let mut access = Access::new(control_rate, Duration::from_secs(60));
+        access.load(client.database()).await?;
+        let outbox = client.database().preference(OUTBOX).await?;
 
         Ok(Server {
-            database,
-            channels: Default::default(),
+            client,
+            events,
             control,
+            outbox,
+            access,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
         })
     }
 
-    fn control_id(cert: &Ed25519Cert) -> Uuid {
-        let mut r = [0; 16];
-        r.iter_mut().enumerate().for_each(|(i, r)| {
-            *r = cert.0[i] ^ cert.0[i * 2];
-        });
-        Uuid::from_bytes(r)
+    async fn add_control(
+        &mut self,
+        peer: Ed25519Cert,
+        permission: Permission,
+    ) -> DatabaseResult<()> {
+        self.client
+            .database()
+            .create_channel(self.control.clone(), peer)
+            .await?;
+        self.access.allow(peer, permission);
+        self.access.save(self.client.database()).await?;
+
+        Ok(())
+    }
+
+    async fn control_messages(&mut self) -> DatabaseResult<Vec<Message>> {
+        self.client
+            .database()
+            .new_messages(Some(&self.control))
+            .await
     }
 
-    async fn sync_channels(&mut self) -> DatabaseResult<()> {
-        #![allow(clippy::mutable_key_type)]
-        let mut channels = HashSet::new();
-        for conversation in self.database.list_conversation().await? {
-            for data in self.database.list_channels(&conversation).await? {
-                channels.insert(data);
-            }
-        }
+    /// Runs the command in `message`, as the peer of the channel that
+    /// delivered it. The author a message declares is not checked by anyone,
+    /// so a peer could otherwise pass its commands off as another's.
+    async fn handle_message(&mut self, message: &Message) -> CommandResult<String> {
+        let sender = self.client.database().delivered_by(message).await?;
+        let sender = sender.ok_or_else(|| CommandError::Unauthorized(message.from.key.hex()))?;
+        let permission = self.access.admit(&sender, Instant::now())?;
 
-        for i in (0..self.channels.len()).rev() {
-            if channels.take(self.channels[i].channel()).is_none() {
-                log::info!("Removing {:?}", self.channels[i].channel());
-                self.channels.remove(i);
-            }
-        }
+        let mut args = message.text().trim().split(' ').collect::<Vec<_>>();
+        args.insert(0, "");
+        let args = CommandArgs::try_parse_from(args)?;
+        Access::authorize(permission, &args.subcommand)?;
 
-        for channel in channels {
-            log::info!("Adding {channel:?}");
-            let sync = SqliteChannel::new(channel, self.database.private_key().clone());
-            self.channels.push(sync);
+        args.subcommand
+            .run(&mut self.client, &mut self.access)
+            .await
+    }
+
+    /// Queues the response to the command in `message`. Commands of senders
+    /// that are not control users, or that went over the limit, are dropped
+    /// without one, lest answering them floods the control conversation.
+    async fn respond(&mut self, message: &Message) -> DatabaseResult<()> {
+        let text = message.text();
+        match self.handle_message(message).await {
+            Ok(response) => self.queue_control_message(response).await,
+            Err(e @ (CommandError::Unauthorized(_) | CommandError::RateLimited(..))) => {
+                log::warn!("Dropping message {text:?}: {e}")
+            }
+            Err(e) => {
+                let response = format!("{e}\nOn message {text}\n{e:?}");
+                self.queue_control_message(response).await;
+            }
         }
 
-        Ok(())
+        self.set_message_handled(message).await
     }
 
-    async fn add_control(&mut self, peer: Ed25519Cert) -> DatabaseResult<()> {
-        self.database
-            .create_channel(self.control.clone(), peer)
+    async fn send_control_message(&mut self, text: String) -> DatabaseResult<()> {
+        log::info!("conversation={}: Response {text:?}", self.control.uuid);
+        self.client
+            .database()
+            .send_message(self.control.clone(), text, None)
             .await?;
 
         Ok(())
     }
 
-    async fn control_messages(&mut self) -> DatabaseResult<Vec<Message>> {
-        self.database.new_messages(Some(&self.control)).await
+    async fn queue_control_message(&mut self, text: String) {
+        self.outbox.push_back(text);
+        self.flush_outbox().await;
     }
 
-    async fn handle_message(&mut self, message: &str) -> CommandResult<String> {
-        let mut args = message.trim().split(' ').collect::<Vec<_>>();
-        args.insert(0, "");
-        let args = CommandArgs::try_parse_from(args)?;
-
-        args.subcommand.run(&mut self.database).await
-    }
+    async fn flush_outbox(&mut self) {
+        let pending = self.outbox.len();
+        while let Some(text) = self.outbox.front() {
+            if let Err(e) = self.send_control_message(text.clone()).await {
+                log::warn!("Keeping {} responses in the outbox: {e}", self.outbox.len());
+                break;
+            }
+            self.outbox.pop_front();
+        }
+        if pending == 0 {
+            return;
+        }
 
-    async fn send_control_message(&mut self, text: String) -> DatabaseResult<()> {
-        log::info!("Response {text:?}");
-        self.database.send_message(self.control.clone(), text).await
+        let database = self.client.database();
+        if let Err(e) = database.set_preference(OUTBOX, &self.outbox).await {
+            log::warn!("Could not keep the outbox: {e}");
+        }
     }
 
     async fn set_message_handled(&mut self, message: &Message) -> DatabaseResult<()> {
-        self.database
+        self.client
+            .database()
             .set_message_status(message, icechat::database::MessageStatus::Delivered)
             .await
     }
 
-    async fn wait(&mut self) -> DatabaseResult<(ChannelValue, usize)> {
-        if self.channels.is_empty() {
+    async fn poll(&mut self) -> DatabaseResult<()> {
+        if self.client.channels().is_empty() {
             log::error!(
                 "List of channels is empty. Server will not do anything. Add a control user"
             );
-            return pending().await;
         }
 
-        let mut trans = self.database.begin().await?;
+        self.client.poll().await?;
+        #[cfg(feature = "metrics")]
+        self.metrics.publish(self.client.channels());
 
-        for channel in self.channels.iter_mut() {
-            if channel.state() == ChannelStateLabel::Offline {
-                channel.connect(self.database.start_sync(channel.channel().clone()));
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                ClientEvent::ChannelAdded(channel) => {
+                    log::info!("{}: Adding channel", channel.log_context())
+                }
+                ClientEvent::ChannelRemoved(channel) => {
+                    log::info!("{}: Removing channel", channel.log_context())
+                }
+                ClientEvent::StateChange(change) => log::info!(
+                    "peer={}: {:?} -> {:?}",
+                    change.peer.hex(),
+                    change.from,
+                    change.to
+                ),
+                ClientEvent::Activity(_) => {}
             }
-            channel.pre_wait(&mut trans).await;
         }
 
-        let waits = self
-            .channels
-            .iter_mut()
-            .map(|channel| channel.wait().boxed_local())
-            .collect::<Vec<_>>();
-
-        let (value, index, _) = select_all(waits).await;
-
-        trans.commit().await?;
-        Ok((value, index))
-    }
-
-    async fn then(&mut self, (value, index): (ChannelValue, usize)) -> DatabaseResult<()> {
-        self.channels[index].then(value).await;
-
         Ok(())
     }
 }
@@ -216,17 +354,87 @@ pub struct CommandArgs {
 
 #[derive(clap::Subcommand, Debug, Clone)]
 pub enum Command {
-    Echo { echos: Vec<String> },
-    SetName { name: String },
+    Echo {
+        echos: Vec<String>,
+    },
+    SetName {
+        name: String,
+    },
     Cert,
-    Join { invite: String },
-    CreateConversation { title: Option<String> },
+    Join {
+        invite: String,
+    },
+    CreateConversation {
+        title: Option<String>,
+    },
+    /// Retitles `conversation`. Commands are split on spaces, so the words
+    /// of the title are joined back.
+    SetTitle {
+        conversation: String,
+        #[arg(required = true)]
+        title: Vec<String>,
+    },
     List,
-    AddMember { conversation: String, cert: String },
+    AddMember {
+        conversation: String,
+        cert: String,
+    },
+    RemoveMember {
+        conversation: String,
+        cert: String,
+    },
+    Invite {
+        conversation: String,
+        #[arg(long)]
+        single_use: bool,
+        #[arg(long)]
+        expires: Option<i64>,
+    },
+    Redeem {
+        token: String,
+        cert: String,
+    },
+    /// One line per channel: peer cert, state, sync index and conversation.
+    Status,
+    /// Drops the channels to `cert` in every conversation.
+    Prune {
+        cert: String,
+    },
+    /// Takes the permission of the control user `cert` away. Its channel to
+    /// the control conversation is kept, see [`Command::Prune`].
+    RevokeControl {
+        cert: String,
+    },
+    Backlog,
+    Compact,
+    /// Writes a backup to `path` on the server, wrapped with
+    /// `ICECHAT_PASSPHRASE` if it is set.
+    Backup {
+        path: String,
+    },
+    Export {
+        conversation: String,
+    },
+    DumpLog {
+        conversation: String,
+        #[arg(default_value_t = 20)]
+        limit: usize,
+    },
 }
 impl Command {
-    async fn run(self, database: &mut Database) -> CommandResult<String> {
-        let _ = database;
+    fn permission(&self) -> Permission {
+        match self {
+            Command::Echo { .. }
+            | Command::Cert
+            | Command::List
+            | Command::Status
+            | Command::Backlog => Permission::Read,
+            _ => Permission::Admin,
+        }
+    }
+
+    async fn run(self, client: &mut Client, access: &mut Access) -> CommandResult<String> {
+        let database = client.database_mut();
 
         match self {
             Command::Echo { echos } => Ok(format!("{echos:?}")),
@@ -239,29 +447,49 @@ impl Command {
             }
             Command::Cert => Ok(database.cert().hex()),
             Command::Join { invite } => {
-                let (conversation, cert) = invite.split_once(':').ok_or(CommandError::BadInvite)?;
-                let conversation = conversation.parse()?;
-                let cert = cert.parse()?;
+                let invite: Invite = invite.parse()?;
 
-                let conversation = database.join_conversation(conversation).await?;
-                database.create_channel(conversation.clone(), cert).await?;
+                let conversation = database.join_conversation(invite.conversation).await?;
+                database
+                    .create_channel(conversation.clone(), invite.peer)
+                    .await?;
 
                 Ok(format!("Joined conversation {}", conversation.uuid))
             }
             Command::CreateConversation { title } => {
                 let conversation = database.create_conversation(title).await?;
+                let invite = Invite {
+                    conversation: conversation.uuid,
+                    peer: *database.cert(),
+                };
 
-                Ok(format!("{}:{}", conversation.uuid, database.cert().hex()))
+                Ok(invite.to_string())
+            }
+            Command::SetTitle {
+                conversation,
+                title,
+            } => {
+                let conversation = conversation.parse()?;
+
+                let mut conversation = database
+                    .get_conversation(conversation)
+                    .await?
+                    .ok_or(CommandError::InexistentConversation(conversation))?;
+
+                let title = title.join(" ");
+                conversation.title = Some(title.clone());
+                database.save_conversation(conversation).await?;
+
+                Ok(format!("Title changed to {title:?}"))
             }
             Command::List => {
                 let mut r = vec![];
                 for conversation in database.list_conversation().await? {
-                    r.push(format!(
-                        "Conversation {:?} {}:{}",
-                        conversation.title,
-                        conversation.uuid,
-                        database.cert().hex()
-                    ));
+                    let invite = Invite {
+                        conversation: conversation.uuid,
+                        peer: *database.cert(),
+                    };
+                    r.push(format!("Conversation {:?} {invite}", conversation.title));
                     for member in conversation.members.iter() {
                         r.push(format!("  Member {} ({})", member.name, member.key.hex()));
                     }
@@ -274,7 +502,7 @@ impl Command {
             }
             Command::AddMember { conversation, cert } => {
                 let conversation = conversation.parse()?;
-                let cert = cert.parse()?;
+                let cert = Ed25519Cert::from_hex(&cert)?;
 
                 let conversation = database
                     .get_conversation(conversation)
@@ -289,6 +517,158 @@ impl Command {
                     conversation = conversation.uuid
                 ))
             }
+            Command::RemoveMember { conversation, cert } => {
+                let conversation = conversation.parse()?;
+                let cert = Ed25519Cert::from_hex(&cert)?;
+
+                let conversation = database
+                    .get_conversation(conversation)
+                    .await?
+                    .ok_or(CommandError::InexistentConversation(conversation))?;
+
+                database.remove_member(&conversation, cert).await?;
+
+                Ok(format!(
+                    "{cert} removed from {conversation}",
+                    cert = cert.hex(),
+                    conversation = conversation.uuid
+                ))
+            }
+            Command::Invite {
+                conversation,
+                single_use,
+                expires,
+            } => {
+                let conversation = conversation.parse()?;
+
+                let conversation = database
+                    .get_conversation(conversation)
+                    .await?
+                    .ok_or(CommandError::InexistentConversation(conversation))?;
+
+                let token = database
+                    .create_invite(&conversation, single_use, expires)
+                    .await?;
+
+                Ok(format!("{}", token.0))
+            }
+            Command::Redeem { token, cert } => {
+                let token = InviteToken(token.parse()?);
+                let cert = Ed25519Cert::from_hex(&cert)?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as i64;
+
+                let conversation = database.redeem_invite(token, cert, now).await?;
+
+                Ok(format!(
+                    "{cert} joined {conversation}",
+                    cert = cert.hex(),
+                    conversation = conversation.uuid
+                ))
+            }
+            Command::Status => {
+                let database = client.database();
+                let mut r = vec![];
+                for conversation in database.list_conversation().await? {
+                    for channel in database.list_channels(&conversation).await? {
+                        let state = client
+                            .channels()
+                            .iter()
+                            .find(|connected| *connected.channel() == channel)
+                            .map_or(ChannelStateLabel::Offline, |connected| connected.state());
+                        let sync_index = database.channel_sync_index(&channel).await?;
+                        let Some(sync_index) = sync_index else { continue; };
+
+                        r.push(format!(
+                            "{cert} {state:?} {sync_index} {conversation}",
+                            cert = channel.peer_cert.hex(),
+                            conversation = conversation.uuid
+                        ));
+                    }
+                }
+
+                Ok(r.join("\n"))
+            }
+            Command::Prune { cert } => {
+                let cert = Ed25519Cert::from_hex(&cert)?;
+
+                let pruned = database.revoke_peer(&cert, false).await?;
+
+                Ok(format!(
+                    "Pruned {pruned} channels to {cert}",
+                    cert = cert.hex()
+                ))
+            }
+            Command::RevokeControl { cert } => {
+                let cert = Ed25519Cert::from_hex(&cert)?;
+
+                if !access.revoke(&cert) {
+                    return Ok(format!("{cert} is not a control user", cert = cert.hex()));
+                }
+                access.save(database).await?;
+
+                Ok(format!(
+                    "{cert} is no longer a control user",
+                    cert = cert.hex()
+                ))
+            }
+            Command::Backlog => {
+                let backlog = database.sync_backlog().await?;
+
+                Ok(format!("{backlog} patches left to sync"))
+            }
+            Command::Compact => {
+                let stats = database.compact().await?;
+
+                Ok(format!(
+                    "Removed {initial} snapshot patches and {sync} patches, reclaimed {bytes} bytes",
+                    initial = stats.initial_sync_removed,
+                    sync = stats.sync_removed,
+                    bytes = stats.bytes_reclaimed
+                ))
+            }
+            Command::Backup { path } => {
+                let passphrase = std::env::var("ICECHAT_PASSPHRASE").ok();
+                let file = std::fs::File::create(&path).map_err(DatabaseError::from)?;
+                database.backup(file, passphrase.as_deref()).await?;
+
+                Ok(format!("Backup written to {path}"))
+            }
+            Command::Export { conversation } => {
+                let conversation = conversation.parse()?;
+
+                let conversation = database
+                    .get_conversation(conversation)
+                    .await?
+                    .ok_or(CommandError::InexistentConversation(conversation))?;
+
+                Ok(database.export_conversation_json(&conversation).await?)
+            }
+            Command::DumpLog {
+                conversation,
+                limit,
+            } => {
+                let conversation = conversation.parse()?;
+
+                let conversation = database
+                    .get_conversation(conversation)
+                    .await?
+                    .ok_or(CommandError::InexistentConversation(conversation))?;
+
+                let mut r = vec![];
+                for data in database.patch_log(&conversation, limit).await? {
+                    let (SyncDataId::Global(id) | SyncDataId::InitialSync(id)) = data.id;
+                    r.push(format!(
+                        "#{id} {kind} by author {author}",
+                        kind = data.kind(),
+                        author = data.author().0
+                    ));
+                }
+
+                Ok(r.join("\n"))
+            }
         }
     }
 }
@@ -303,13 +683,222 @@ pub enum CommandError {
     UnknownCommand(String),
     #[error("Provided empty command")]
     EmptyCommand,
-    #[error("Bad invite to join conversation, missing : separator")]
-    BadInvite,
+    #[error(transparent)]
+    BadInvite(#[from] BadInvite),
     #[error(transparent)]
     Uuid(#[from] uuid::Error),
     #[error(transparent)]
     Ed25519Cert(#[from] BadEd25519CertStr),
     #[error("Conversation {0} does not exist")]
     InexistentConversation(Uuid),
+    #[error("{0} is not a control user")]
+    Unauthorized(String),
+    #[error("Command requires the {0:?} permission")]
+    Forbidden(Permission),
+    #[error("Over the limit of {0} commands per {1:?}")]
+    RateLimited(usize, Duration),
 }
 pub type CommandResult<T> = Result<T, CommandError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icechat::{
+        database::{DeliveryState, MessageOrder},
+        testing,
+    };
+
+    /// Messages the server sent to the control conversation.
+    async fn responses(server: &Server) -> Vec<Message> {
+        let database = server.client.database();
+        let mut responses = server
+            .control
+            .messages_page(database, None, 10, MessageOrder::CausalSequence)
+            .await
+            .unwrap();
+        responses.retain(|message| message.from.key == *database.cert());
+
+        responses
+    }
+
+    mod given_a_control_user_that_is_offline {
+        use super::*;
+
+        type Given = (Server, Database, Conversation);
+        async fn given() -> Given {
+            let mut server = Server::new(":memory:", false).await.unwrap();
+            let peer = Database::connect(":memory:").await.unwrap();
+            let joined = peer.join_conversation(server.control.uuid).await.unwrap();
+            peer.create_channel(joined.clone(), *server.client.database().cert())
+                .await
+                .unwrap();
+            server
+                .add_control(*peer.cert(), Permission::Admin)
+                .await
+                .unwrap();
+
+            (server, peer, joined)
+        }
+
+        mod when_a_response_is_queued {
+            use super::*;
+
+            async fn given() -> Given {
+                let (mut server, peer, joined) = super::given().await;
+                server.queue_control_message("response".to_string()).await;
+
+                (server, peer, joined)
+            }
+
+            #[tokio::test]
+            async fn then_it_waits_for_the_channel_to_reconnect() {
+                let (server, ..) = given().await;
+
+                let responses = responses(&server).await;
+                let state = server.client.database().delivery_state(&responses[0]).await;
+
+                assert_eq!(responses.len(), 1);
+                assert_eq!(responses[0].text(), "response");
+                assert_eq!(state.unwrap(), DeliveryState::Pending);
+                assert!(server.outbox.is_empty());
+            }
+
+            #[tokio::test]
+            async fn then_it_is_delivered_after_reconnecting() {
+                let (server, peer, joined) = given().await;
+
+                let database = server.client.database();
+                testing::sync_until_idle(database, &peer).await.unwrap();
+
+                let received = peer.new_messages(Some(&joined)).await.unwrap();
+                let responses = responses(&server).await;
+                let state = database.delivery_state(&responses[0]).await;
+                assert_eq!(received.len(), 1);
+                assert_eq!(received[0].text(), "response");
+                assert_eq!(state.unwrap(), DeliveryState::Delivered);
+            }
+        }
+    }
+
+    /// Has `peer` send `command` to the control conversation, and the
+    /// server respond to it.
+    async fn send_command(
+        server: &mut Server,
+        peer: &Database,
+        joined: &Conversation,
+        command: &str,
+    ) {
+        peer.send_message(joined.clone(), command.to_string(), None)
+            .await
+            .unwrap();
+        testing::sync_until_idle(server.client.database(), peer)
+            .await
+            .unwrap();
+        for message in server.control_messages().await.unwrap() {
+            server.respond(&message).await.unwrap();
+        }
+    }
+
+    mod given_a_peer_in_the_control_conversation {
+        use super::*;
+
+        type Given = (Server, Database, Conversation);
+        async fn given() -> Given {
+            let server = Server::new(":memory:", false).await.unwrap();
+            let peer = Database::connect(":memory:").await.unwrap();
+            let joined = peer.join_conversation(server.control.uuid).await.unwrap();
+            peer.create_channel(joined.clone(), *server.client.database().cert())
+                .await
+                .unwrap();
+            server
+                .client
+                .database()
+                .create_channel(server.control.clone(), *peer.cert())
+                .await
+                .unwrap();
+
+            (server, peer, joined)
+        }
+
+        #[tokio::test]
+        async fn then_its_commands_are_dropped_without_a_response() {
+            let (mut server, peer, joined) = given().await;
+
+            send_command(&mut server, &peer, &joined, "echo hi").await;
+
+            assert!(responses(&server).await.is_empty());
+            assert!(server.control_messages().await.unwrap().is_empty());
+        }
+
+        mod when_it_is_revoked {
+            use super::*;
+
+            async fn given() -> (Server, Database, Conversation, String) {
+                let (mut server, peer, joined) = super::given().await;
+                server
+                    .add_control(*peer.cert(), Permission::Read)
+                    .await
+                    .unwrap();
+
+                let revoke = Command::RevokeControl {
+                    cert: peer.cert().hex(),
+                };
+                let response = revoke
+                    .run(&mut server.client, &mut server.access)
+                    .await
+                    .unwrap();
+
+                (server, peer, joined, response)
+            }
+
+            #[tokio::test]
+            async fn then_its_commands_are_dropped() {
+                let (mut server, peer, joined, response) = given().await;
+
+                send_command(&mut server, &peer, &joined, "echo hi").await;
+
+                assert!(response.ends_with("is no longer a control user"));
+                assert!(responses(&server).await.is_empty());
+            }
+
+            #[tokio::test]
+            async fn then_it_stays_revoked_once_loaded_again() {
+                let (server, peer, ..) = given().await;
+
+                let mut loaded = Access::new(DEFAULT_CONTROL_RATE, Duration::from_secs(60));
+                loaded.load(server.client.database()).await.unwrap();
+
+                assert!(loaded.admit(peer.cert(), Instant::now()).is_err());
+            }
+        }
+    }
+
+    mod given_a_response_left_in_the_outbox {
+        use super::*;
+
+        async fn given() -> String {
+            let path = std::env::temp_dir().join(format!("icechat-{}.sqlite", Uuid::new_v4()));
+            let path = path.to_str().unwrap().to_string();
+            let database = Database::connect(&path).await.unwrap();
+            let outbox = VecDeque::from(["response".to_string()]);
+            database.set_preference(OUTBOX, &outbox).await.unwrap();
+
+            path
+        }
+
+        #[tokio::test]
+        async fn then_it_is_sent_after_a_restart() {
+            let path = given().await;
+
+            let mut server = Server::new(&path, false).await.unwrap();
+            server.flush_outbox().await;
+
+            let responses = responses(&server).await;
+            assert_eq!(responses.len(), 1);
+            assert_eq!(responses[0].text(), "response");
+            let outbox: VecDeque<String> =
+                server.client.database().preference(OUTBOX).await.unwrap();
+            assert!(outbox.is_empty());
+        }
+    }
+}
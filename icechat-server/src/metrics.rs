@@ -0,0 +1,216 @@
+//! Prometheus metrics of the channels, served over plain HTTP.
+//!
+//! The channels live in the server loop, which publishes them here after each
+//! poll. Their counters are shared with the syncs, so a scrape sees them as
+//! they are, while the state of a channel is as of the last poll.
+
+use icechat::{
+    channel::{ChannelStateLabel, Ed25519Cert},
+    client::ChannelSet,
+    pipe_sync::SyncCounters,
+};
+use std::{cell::RefCell, fmt::Write, io, rc::Rc, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use uuid::Uuid;
+
+const STATES: [ChannelStateLabel; 4] = [
+    ChannelStateLabel::Offline,
+    ChannelStateLabel::PreConnecting,
+    ChannelStateLabel::Connecting,
+    ChannelStateLabel::Connected,
+];
+
+/// Reads one of the [`SyncCounters`].
+type ReadCounter = fn(&SyncCounters) -> u64;
+
+struct ChannelMetrics {
+    peer: Ed25519Cert,
+    conversation: Uuid,
+    state: ChannelStateLabel,
+    counters: Arc<SyncCounters>,
+}
+
+/// Cheap to clone, every clone sees what was last published.
+#[derive(Clone, Default)]
+pub struct Metrics(Rc<RefCell<Vec<ChannelMetrics>>>);
+impl Metrics {
+    pub fn publish(&self, channels: &ChannelSet) {
+        *self.0.borrow_mut() = channels
+            .iter()
+            .map(|channel| ChannelMetrics {
+                peer: channel.channel().peer_cert,
+                conversation: channel.channel().conversation,
+                state: channel.state(),
+                counters: channel.counters(),
+            })
+            .collect();
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let channels = self.0.borrow();
+        let mut r = String::new();
+
+        writeln!(r, "# HELP icechat_channels Channels in each state.").unwrap();
+        writeln!(r, "# TYPE icechat_channels gauge").unwrap();
+        for state in STATES {
+            let count = channels
+                .iter()
+                .filter(|channel| channel.state == state)
+                .count();
+            writeln!(r, "icechat_channels{{state=\"{state:?}\"}} {count}").unwrap();
+        }
+
+        let counters: [(&str, &str, ReadCounter); 5] = [
+            ("patches_sent", "Patches sent to the peer.", |c| {
+                SyncCounters::get(&c.patches_sent)
+            }),
+            ("patches_received", "Patches received from the peer.", |c| {
+                SyncCounters::get(&c.patches_received)
+            }),
+            ("bytes_sent", "Bytes sent to the peer.", |c| {
+                SyncCounters::get(&c.bytes_sent)
+            }),
+            ("bytes_received", "Bytes received from the peer.", |c| {
+                SyncCounters::get(&c.bytes_received)
+            }),
+            (
+                "merge_conflicts",
+                "Patches received that lost to what was stored.",
+                |c| SyncCounters::get(&c.conflicts),
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(r, "# HELP icechat_{name}_total {help}").unwrap();
+            writeln!(r, "# TYPE icechat_{name}_total counter").unwrap();
+            for channel in channels.iter() {
+                writeln!(
+                    r,
+                    "icechat_{name}_total{{peer=\"{peer}\",conversation=\"{conversation}\"}} {value}",
+                    peer = channel.peer.hex(),
+                    conversation = channel.conversation,
+                    value = value(&channel.counters),
+                )
+                .unwrap();
+            }
+        }
+
+        r
+    }
+
+    /// Answers every connection to `listener` with the metrics, whatever it
+    /// asked for. Must run inside a [`tokio::task::LocalSet`].
+    pub async fn serve(self, listener: TcpListener) {
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::warn!("Could not accept a metrics connection: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+
+            let body = self.render();
+            tokio::task::spawn_local(async move {
+                if let Err(e) = respond(&mut stream, &body).await {
+                    log::debug!("Could not send the metrics: {e}");
+                }
+            });
+        }
+    }
+}
+
+async fn respond(stream: &mut TcpStream, body: &str) -> io::Result<()> {
+    // Read before answering, so that the client does not see the connection
+    // reset on a request it is still sending.
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+
+    let head = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icechat::{channel::Ed25519Seed, client::Client, database::Database};
+
+    mod given_a_server_with_a_channel {
+        use super::*;
+
+        type Given = (Client, Metrics, Ed25519Cert);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let peer = Ed25519Seed::generate().public_key();
+            database.create_channel(conversation, peer).await.unwrap();
+            let (mut client, _) = Client::new(database);
+            client.sync_channels().await.unwrap();
+
+            let metrics = Metrics::default();
+            metrics.publish(client.channels());
+
+            (client, metrics, peer)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_counted_by_state() {
+            let (_, metrics, ..) = given().await;
+
+            let rendered = metrics.render();
+
+            assert!(rendered.contains("icechat_channels{state=\"Offline\"} 1\n"));
+            assert!(rendered.contains("icechat_channels{state=\"Connected\"} 0\n"));
+        }
+
+        #[tokio::test]
+        async fn then_its_counters_are_read_on_render() {
+            let (client, metrics, peer) = given().await;
+            let counters = client.channels().get(0).counters();
+
+            SyncCounters::add(&counters.conflicts, 3);
+
+            let line = format!("icechat_merge_conflicts_total{{peer=\"{}\",", peer.hex());
+            let rendered = metrics.render();
+            let conflicts = rendered.lines().find(|l| l.starts_with(&line)).unwrap();
+            assert!(conflicts.ends_with(" 3"));
+        }
+
+        #[tokio::test]
+        async fn then_they_are_served_over_http() {
+            let (_, metrics, ..) = given().await;
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let local_set = tokio::task::LocalSet::new();
+            local_set.spawn_local(metrics.clone().serve(listener));
+
+            let response = local_set
+                .run_until(async {
+                    let mut stream = TcpStream::connect(address).await.unwrap();
+                    stream
+                        .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+                        .await
+                        .unwrap();
+                    let mut response = String::new();
+                    stream.read_to_string(&mut response).await.unwrap();
+                    response
+                })
+                .await;
+
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.ends_with(&metrics.render()));
+        }
+    }
+}
@@ -3,6 +3,35 @@ pub use sea_orm_migration::prelude::*;
 pub mod id;
 mod m20230326_000001_add_attachment;
 mod m20230326_000001_create_table;
+mod m20230410_000001_member_removal;
+mod m20230412_000001_add_receipt;
+mod m20230415_000001_add_invite;
+mod m20230416_000001_add_preference;
+mod m20230417_000001_share_initial_sync;
+mod m20230418_000001_star_message;
+mod m20230419_000001_message_tombstone;
+mod m20230420_000001_message_edit;
+mod m20230421_000001_attachment_chunk;
+mod m20230422_000001_attachment_total;
+mod m20230423_000001_key_rotation;
+mod m20230424_000001_message_reply;
+mod m20230425_000001_blocked_contact;
+mod m20230426_000001_contact_verified;
+mod m20230427_000001_contact_alias;
+mod m20230428_000001_message_created_at;
+mod m20230429_000001_conversation_settings;
+mod m20230430_000001_attachment_blob;
+mod m20230501_000001_attachment_metadata;
+mod m20230502_000001_attachment_thumbnail;
+mod m20230503_000001_message_sync_index;
+mod m20230504_000001_paired_device;
+mod m20230505_000001_history_clear;
+mod m20230506_000001_wide_author;
+mod m20230507_000001_conversation_clock;
+mod m20230508_000001_conversation_archive;
+mod m20230509_000001_conflict_log;
+mod m20230510_000001_message_delivery;
+mod m20230511_000001_history_clear_id;
 
 pub struct Migrator;
 
@@ -12,6 +41,35 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20230326_000001_create_table::Migration),
             Box::new(m20230326_000001_add_attachment::Migration),
+            Box::new(m20230410_000001_member_removal::Migration),
+            Box::new(m20230412_000001_add_receipt::Migration),
+            Box::new(m20230415_000001_add_invite::Migration),
+            Box::new(m20230416_000001_add_preference::Migration),
+            Box::new(m20230417_000001_share_initial_sync::Migration),
+            Box::new(m20230418_000001_star_message::Migration),
+            Box::new(m20230419_000001_message_tombstone::Migration),
+            Box::new(m20230420_000001_message_edit::Migration),
+            Box::new(m20230421_000001_attachment_chunk::Migration),
+            Box::new(m20230422_000001_attachment_total::Migration),
+            Box::new(m20230423_000001_key_rotation::Migration),
+            Box::new(m20230424_000001_message_reply::Migration),
+            Box::new(m20230425_000001_blocked_contact::Migration),
+            Box::new(m20230426_000001_contact_verified::Migration),
+            Box::new(m20230427_000001_contact_alias::Migration),
+            Box::new(m20230428_000001_message_created_at::Migration),
+            Box::new(m20230429_000001_conversation_settings::Migration),
+            Box::new(m20230430_000001_attachment_blob::Migration),
+            Box::new(m20230501_000001_attachment_metadata::Migration),
+            Box::new(m20230502_000001_attachment_thumbnail::Migration),
+            Box::new(m20230503_000001_message_sync_index::Migration),
+            Box::new(m20230504_000001_paired_device::Migration),
+            Box::new(m20230505_000001_history_clear::Migration),
+            Box::new(m20230506_000001_wide_author::Migration),
+            Box::new(m20230507_000001_conversation_clock::Migration),
+            Box::new(m20230508_000001_conversation_archive::Migration),
+            Box::new(m20230509_000001_conflict_log::Migration),
+            Box::new(m20230510_000001_message_delivery::Migration),
+            Box::new(m20230511_000001_history_clear_id::Migration),
         ]
     }
 }
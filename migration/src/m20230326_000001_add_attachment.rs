@@ -97,7 +97,7 @@ impl MigrationTrait for Migration {
         let conn = manager.get_connection();
         let old_messages = old_message::Entity::find().all(conn).await?;
         for message in old_messages {
-            entity::entity::message::ActiveModel {
+            new_message::ActiveModel {
                 id: ActiveValue::Set(message.id),
                 uuid0: ActiveValue::Set(message.uuid0),
                 uuid1: ActiveValue::Set(message.uuid1),
@@ -184,7 +184,7 @@ impl MigrationTrait for Migration {
             .await?;
 
         let conn = manager.get_connection();
-        let messages = entity::entity::message::Entity::find().all(conn).await?;
+        let messages = new_message::Entity::find().all(conn).await?;
         for message in messages {
             old_message::ActiveModel {
                 id: ActiveValue::Set(message.id),
@@ -277,3 +277,35 @@ mod old_message {
 
     impl ActiveModelBehavior for ActiveModel {}
 }
+
+/// The message table as this migration leaves it, later migrations change the
+/// one of the entity crate.
+mod new_message {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+    #[sea_orm(table_name = "message")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub uuid0: i32,
+        pub uuid1: i32,
+        pub uuid2: i32,
+        pub uuid3: i32,
+        pub status: i32,
+        pub from: i32,
+        pub conversation: i32,
+        pub text: String,
+        pub attachment: Option<i32>,
+        pub crdt_generation: i32,
+        pub crdt_author: i32,
+        pub status_crdt_generation: i32,
+        pub status_crdt_author: i32,
+        pub crdt_sequence: i32,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
@@ -0,0 +1,73 @@
+use crate::{
+    id::{Id, TableConcepts, Uuid},
+    m20230326_000001_create_table::{Contact, Conversation, Key},
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Receipt::Table)
+                    .col_id()
+                    .col(ColumnDef::new(Uuid::Uuid0).integer().not_null())
+                    .col(ColumnDef::new(Uuid::Uuid1).integer().not_null())
+                    .col(ColumnDef::new(Uuid::Uuid2).integer().not_null())
+                    .col(ColumnDef::new(Uuid::Uuid3).integer().not_null())
+                    .col(ColumnDef::new(Receipt::Conversation).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Receipt::Table, Receipt::Conversation)
+                            .to(Conversation::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Receipt::Contact).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Receipt::Table, Receipt::Contact)
+                            .to(Contact::Table, Contact::Key)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Receipt::Table, Receipt::Contact)
+                            .to(Key::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("receipt_uuid_contact")
+                            .col(Uuid::Uuid0)
+                            .col(Uuid::Uuid1)
+                            .col(Uuid::Uuid2)
+                            .col(Uuid::Uuid3)
+                            .col(Receipt::Contact),
+                    )
+                    .col(ColumnDef::new(Receipt::Status).integer().not_null())
+                    .crdt_writable()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Receipt::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Receipt {
+    Table,
+    Conversation,
+    Contact,
+    Status,
+}
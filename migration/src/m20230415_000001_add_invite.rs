@@ -0,0 +1,54 @@
+use crate::{
+    id::{Id, TableConcepts},
+    m20230326_000001_create_table::Conversation,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Invite::Table)
+                    .col_id()
+                    .col_uuid()
+                    .col(ColumnDef::new(Invite::Conversation).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Invite::Table, Invite::Conversation)
+                            .to(Conversation::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Invite::SingleUse).boolean().not_null())
+                    .col(ColumnDef::new(Invite::Expires).big_integer())
+                    .col(
+                        ColumnDef::new(Invite::Used)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Invite::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Invite {
+    Table,
+    Conversation,
+    SingleUse,
+    Expires,
+    Used,
+}
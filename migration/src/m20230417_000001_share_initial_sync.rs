@@ -0,0 +1,179 @@
+use crate::{
+    id::{Id, TableConcepts},
+    m20230326_000001_create_table::Conversation,
+};
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Snapshot::Table)
+                    .col_id()
+                    .col(ColumnDef::new(Snapshot::Conversation).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Snapshot::Table, Snapshot::Conversation)
+                            .to(Conversation::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Snapshot::Options).binary().not_null())
+                    .col(ColumnDef::new(Snapshot::SyncIndex).integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        for mut column in [
+            ColumnDef::new(Channel::Snapshot).integer().to_owned(),
+            ColumnDef::new(Channel::SnapshotIndex)
+                .integer()
+                .not_null()
+                .default(0)
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Channel::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // Pending seeds become one snapshot per channel, with empty options so
+        // that they are never shared.
+        db.execute_unprepared(
+            "INSERT INTO snapshot (id, conversation, options, sync_index) \
+             SELECT id, conversation, x'', sync_index FROM channel \
+             WHERE id IN (SELECT channel FROM initial_sync);",
+        )
+        .await?;
+        db.execute_unprepared(
+            "UPDATE channel SET snapshot = id WHERE id IN (SELECT id FROM snapshot);",
+        )
+        .await?;
+
+        manager
+            .rename_table(
+                Table::rename()
+                    .table(InitialSync::Table, InitialSyncOld::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(InitialSync::Table)
+                    .col_id()
+                    .col(ColumnDef::new(InitialSync::Snapshot).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(InitialSync::Table, InitialSync::Snapshot)
+                            .to(Snapshot::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(InitialSync::Payload).binary().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        db.execute_unprepared(
+            "INSERT INTO initial_sync (id, snapshot, payload) \
+             SELECT id, channel, payload FROM initial_sync_old;",
+        )
+        .await?;
+        manager
+            .drop_table(Table::drop().table(InitialSyncOld::Table).to_owned())
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        manager
+            .rename_table(
+                Table::rename()
+                    .table(InitialSync::Table, InitialSyncOld::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(InitialSync::Table)
+                    .col_id()
+                    .col(ColumnDef::new(InitialSync::Channel).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(InitialSync::Table, InitialSync::Channel)
+                            .to(Channel::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(InitialSync::Payload).binary().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        db.execute_unprepared(
+            "INSERT INTO initial_sync (channel, payload) \
+             SELECT channel.id, initial_sync_old.payload FROM initial_sync_old \
+             JOIN channel ON channel.snapshot = initial_sync_old.snapshot \
+             WHERE initial_sync_old.id > channel.snapshot_index \
+             ORDER BY channel.id, initial_sync_old.id;",
+        )
+        .await?;
+        manager
+            .drop_table(Table::drop().table(InitialSyncOld::Table).to_owned())
+            .await?;
+
+        for column in [Channel::Snapshot, Channel::SnapshotIndex] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Channel::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .drop_table(Table::drop().table(Snapshot::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Snapshot {
+    Table,
+    Conversation,
+    Options,
+    SyncIndex,
+}
+
+#[derive(Iden)]
+enum Channel {
+    Table,
+    Snapshot,
+    SnapshotIndex,
+}
+
+#[derive(Iden)]
+enum InitialSync {
+    Table,
+    Snapshot,
+    Channel,
+    Payload,
+}
+
+#[derive(Iden)]
+enum InitialSyncOld {
+    Table,
+}
@@ -0,0 +1,60 @@
+use crate::id::{Id, TableConcepts};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AttachmentChunk::Table)
+                    .col_id()
+                    .col(
+                        ColumnDef::new(AttachmentChunk::Attachment)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(AttachmentChunk::Table, AttachmentChunk::Attachment)
+                            .to(Attachment::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(AttachmentChunk::Index).integer().not_null())
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("attachment_chunk_attachment_index")
+                            .col(AttachmentChunk::Attachment)
+                            .col(AttachmentChunk::Index),
+                    )
+                    .col(ColumnDef::new(AttachmentChunk::Data).binary().not_null())
+                    .crdt_add_only()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AttachmentChunk::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum AttachmentChunk {
+    Table,
+    Attachment,
+    Index,
+    Data,
+}
+
+#[derive(Iden)]
+enum Attachment {
+    Table,
+}
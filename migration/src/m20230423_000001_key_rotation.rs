@@ -0,0 +1,125 @@
+use crate::{
+    id::{Id, TableConcepts},
+    m20230326_000001_create_table::{Conversation, Key},
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(KeySupersede::Table)
+                    .col_id()
+                    .col(
+                        ColumnDef::new(KeySupersede::Conversation)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(KeySupersede::Table, KeySupersede::Conversation)
+                            .to(Conversation::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(KeySupersede::Old).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(KeySupersede::Table, KeySupersede::Old)
+                            .to(Key::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(KeySupersede::New).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(KeySupersede::Table, KeySupersede::New)
+                            .to(Key::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("key_supersede_conversation_old")
+                            .col(KeySupersede::Conversation)
+                            .col(KeySupersede::Old),
+                    )
+                    .col(ColumnDef::new(KeySupersede::Signature).binary().not_null())
+                    .crdt_add_only()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Local::Table)
+                    .add_column(
+                        ColumnDef::new(Local::Retired)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Channel::Table)
+                    .add_column(ColumnDef::new(Channel::LocalKey).integer().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Channel::Table)
+                    .drop_column(Channel::LocalKey)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Local::Table)
+                    .drop_column(Local::Retired)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(KeySupersede::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum KeySupersede {
+    Table,
+    Conversation,
+    Old,
+    New,
+    Signature,
+}
+
+#[derive(Iden)]
+enum Local {
+    Table,
+    Retired,
+}
+
+#[derive(Iden)]
+enum Channel {
+    Table,
+    LocalKey,
+}
@@ -0,0 +1,74 @@
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// The message patches carry when they were sent, which changes their
+/// encoding, so the stored patches are dropped and the channels that had not
+/// received all of them are seeded again from the conversation, keeping the
+/// options of the snapshot they drained. The snapshots are left empty for
+/// the database to fill on open.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .add_column(
+                        ColumnDef::new(Message::CreatedAt)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        reseed(manager).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(Message::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        reseed(manager).await
+    }
+}
+
+async fn reseed(manager: &SchemaManager<'_>) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    for sql in [
+        "CREATE TEMP TABLE reseed AS \
+         SELECT channel.id, channel.conversation, coalesce(snapshot.options, x'') AS options \
+         FROM channel LEFT JOIN snapshot ON snapshot.id = channel.snapshot \
+         WHERE channel.snapshot IS NOT NULL \
+         OR channel.sync_index < (SELECT coalesce(max(id), 0) FROM sync);",
+        "DELETE FROM initial_sync;",
+        "UPDATE channel SET snapshot = NULL, snapshot_index = 0, sync_index = 0;",
+        "DELETE FROM snapshot;",
+        "DELETE FROM sync;",
+        "INSERT INTO snapshot (id, conversation, options, sync_index) \
+         SELECT id, conversation, options, 0 FROM reseed;",
+        "UPDATE channel SET snapshot = id WHERE id IN (SELECT id FROM reseed);",
+        "DROP TABLE reseed;",
+    ] {
+        db.execute_unprepared(sql).await?;
+    }
+
+    Ok(())
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Message {
+    Table,
+    CreatedAt,
+}
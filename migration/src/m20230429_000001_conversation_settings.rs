@@ -0,0 +1,57 @@
+use crate::{id::Id, m20230326_000001_create_table::Conversation};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(LocalConversationSettings::Table)
+                    .col(
+                        ColumnDef::new(LocalConversationSettings::Conversation)
+                            .integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                LocalConversationSettings::Table,
+                                LocalConversationSettings::Conversation,
+                            )
+                            .to(Conversation::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(LocalConversationSettings::Muted)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(LocalConversationSettings::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum LocalConversationSettings {
+    Table,
+    Conversation,
+    Muted,
+}
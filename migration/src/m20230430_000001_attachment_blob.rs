@@ -0,0 +1,314 @@
+use crate::id::{Id, TableConcepts};
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, DatabaseBackend, Statement},
+};
+
+/// Size of the chunks payloads are stored in, the `ATTACHMENT_CHUNK_BYTES` of
+/// the database, so that the chunks of equal payloads are equal.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Blob::Table)
+                    .col_id()
+                    .col(ColumnDef::new(Blob::Hash).binary().not_null())
+                    .col(ColumnDef::new(Blob::Index).integer().not_null())
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("blob_hash_index")
+                            .col(Blob::Hash)
+                            .col(Blob::Index),
+                    )
+                    .col(ColumnDef::new(Blob::Data).binary().not_null())
+                    .col(ColumnDef::new(Blob::Total).big_integer().not_null())
+                    .crdt_add_only()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Attachment::Table)
+                    .add_column(ColumnDef::new(Attachment::Hash).binary())
+                    .to_owned(),
+            )
+            .await?;
+
+        // One attachment at a time, so that no more than one payload is held in
+        // memory.
+        let mut after = 0;
+        while let Some(attachment) = db
+            .query_one(Statement::from_sql_and_values(
+                DatabaseBackend::Sqlite,
+                "SELECT id, payload, crdt_author FROM attachment \
+                WHERE id > ? ORDER BY id LIMIT 1;",
+                [after.into()],
+            ))
+            .await?
+        {
+            let id = attachment.try_get::<i32>("", "id")?;
+            after = id;
+            let payload = attachment.try_get::<Option<Vec<u8>>>("", "payload")?;
+            let author = attachment.try_get::<i32>("", "crdt_author")?;
+
+            let hash = match payload {
+                Some(payload) if !payload.is_empty() => {
+                    let hash = blake3::hash(&payload).as_bytes().to_vec();
+                    if !is_stored(db, &hash).await? {
+                        let total = payload.len() as i64;
+                        for (index, data) in payload.chunks(CHUNK_BYTES).enumerate() {
+                            db.execute(Statement::from_sql_and_values(
+                                DatabaseBackend::Sqlite,
+                                "INSERT INTO blob (hash, \"index\", data, total, crdt_author) \
+                                VALUES (?, ?, ?, ?, ?);",
+                                [
+                                    hash.clone().into(),
+                                    (index as i32).into(),
+                                    data.to_vec().into(),
+                                    total.into(),
+                                    author.into(),
+                                ],
+                            ))
+                            .await?;
+                        }
+                    }
+                    hash
+                }
+                _ => {
+                    let Some(hash) = hash_chunks(db, id).await? else {
+                        park_chunks(db, id).await?;
+                        continue;
+                    };
+                    if !is_stored(db, &hash).await? {
+                        db.execute(Statement::from_sql_and_values(
+                            DatabaseBackend::Sqlite,
+                            "INSERT INTO blob (hash, \"index\", data, total, crdt_author) \
+                            SELECT ?, \"index\", data, total, crdt_author FROM attachment_chunk \
+                            WHERE attachment = ?;",
+                            [hash.clone().into(), id.into()],
+                        ))
+                        .await?;
+                    }
+                    hash
+                }
+            };
+
+            db.execute(Statement::from_sql_and_values(
+                DatabaseBackend::Sqlite,
+                "UPDATE attachment SET hash = ? WHERE id = ?;",
+                [hash.into(), id.into()],
+            ))
+            .await?;
+        }
+
+        manager
+            .drop_table(Table::drop().table(AttachmentChunk::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Attachment::Table)
+                    .drop_column(Attachment::Payload)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Attachment::Table)
+                    .add_column(ColumnDef::new(Attachment::Payload).binary())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(AttachmentChunk::Table)
+                    .col_id()
+                    .col(
+                        ColumnDef::new(AttachmentChunk::Attachment)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(AttachmentChunk::Table, AttachmentChunk::Attachment)
+                            .to(Attachment::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(AttachmentChunk::Index).integer().not_null())
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("attachment_chunk_attachment_index")
+                            .col(AttachmentChunk::Attachment)
+                            .col(AttachmentChunk::Index),
+                    )
+                    .col(ColumnDef::new(AttachmentChunk::Data).binary().not_null())
+                    .crdt_add_only()
+                    .col(
+                        ColumnDef::new(AttachmentChunk::Total)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        db.execute_unprepared(
+            "INSERT INTO attachment_chunk (attachment, \"index\", data, crdt_author, total) \
+            SELECT attachment.id, blob.\"index\", blob.data, blob.crdt_author, blob.total \
+            FROM attachment JOIN blob ON blob.hash = attachment.hash;",
+        )
+        .await?;
+        db.execute_unprepared("UPDATE attachment SET payload = x'' WHERE hash IS NOT NULL;")
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Attachment::Table)
+                    .drop_column(Attachment::Hash)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(Blob::Table).to_owned())
+            .await
+    }
+}
+
+/// Hash of the chunks of the attachment `id`, `None` when not all of them
+/// arrived.
+async fn hash_chunks<C: ConnectionTrait>(db: &C, id: i32) -> Result<Option<Vec<u8>>, DbErr> {
+    let received = db
+        .query_one(Statement::from_sql_and_values(
+            DatabaseBackend::Sqlite,
+            "SELECT COUNT(*) AS chunks, SUM(LENGTH(data)) AS received, MAX(total) AS total \
+            FROM attachment_chunk WHERE attachment = ?;",
+            [id.into()],
+        ))
+        .await?;
+    let Some(received) = received else { return Ok(None); };
+    if received.try_get::<i64>("", "chunks")? == 0
+        || received.try_get::<i64>("", "received")? < received.try_get::<i64>("", "total")?
+    {
+        return Ok(None);
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    let mut after = -1;
+    while let Some(chunk) = db
+        .query_one(Statement::from_sql_and_values(
+            DatabaseBackend::Sqlite,
+            "SELECT \"index\", data FROM attachment_chunk \
+            WHERE attachment = ? AND \"index\" > ? ORDER BY \"index\" LIMIT 1;",
+            [id.into(), after.into()],
+        ))
+        .await?
+    {
+        after = chunk.try_get::<i32>("", "index")?;
+        hasher.update(&chunk.try_get::<Vec<u8>>("", "data")?);
+    }
+
+    Ok(Some(hasher.finalize().as_bytes().to_vec()))
+}
+
+/// Keeps the chunks of a download of the attachment `id` that did not finish
+/// under a placeholder, the bytes of the uuid of the attachment, since the hash
+/// of the whole payload is not known. The attachment is left as if only a
+/// message referencing it was known, so that the attachment synced again by
+/// its sender, now with the hash, is taken and adopts the chunks.
+async fn park_chunks<C: ConnectionTrait>(db: &C, id: i32) -> Result<(), DbErr> {
+    let uuid = db
+        .query_one(Statement::from_sql_and_values(
+            DatabaseBackend::Sqlite,
+            "SELECT uuid0, uuid1, uuid2, uuid3 FROM attachment WHERE id = ?;",
+            [id.into()],
+        ))
+        .await?;
+    let Some(uuid) = uuid else { return Ok(()); };
+    let mut placeholder = Vec::with_capacity(16);
+    for column in ["uuid0", "uuid1", "uuid2", "uuid3"] {
+        placeholder.extend(uuid.try_get::<i32>("", column)?.to_be_bytes());
+    }
+
+    db.execute(Statement::from_sql_and_values(
+        DatabaseBackend::Sqlite,
+        "INSERT OR IGNORE INTO blob (hash, \"index\", data, total, crdt_author) \
+        SELECT ?, \"index\", data, total, crdt_author FROM attachment_chunk \
+        WHERE attachment = ?;",
+        [placeholder.into(), id.into()],
+    ))
+    .await?;
+    db.execute(Statement::from_sql_and_values(
+        DatabaseBackend::Sqlite,
+        "UPDATE attachment SET crdt_author = 0 WHERE id = ?;",
+        [id.into()],
+    ))
+    .await?;
+
+    Ok(())
+}
+
+async fn is_stored<C: ConnectionTrait>(db: &C, hash: &[u8]) -> Result<bool, DbErr> {
+    let stored = db
+        .query_one(Statement::from_sql_and_values(
+            DatabaseBackend::Sqlite,
+            "SELECT COUNT(*) AS chunks FROM blob WHERE hash = ?;",
+            [hash.to_vec().into()],
+        ))
+        .await?;
+
+    Ok(match stored {
+        Some(stored) => stored.try_get::<i64>("", "chunks")? > 0,
+        None => false,
+    })
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Blob {
+    Table,
+    Hash,
+    Index,
+    Data,
+    Total,
+}
+
+#[derive(Iden)]
+enum Attachment {
+    Table,
+    Hash,
+    Payload,
+}
+
+#[derive(Iden)]
+enum AttachmentChunk {
+    Table,
+    Attachment,
+    Index,
+    Data,
+    Total,
+}
@@ -0,0 +1,97 @@
+use crate::{id::Id, m20230326_000001_create_table::Conversation};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(HistoryClear::Table)
+                    .col(
+                        ColumnDef::new(HistoryClear::Conversation)
+                            .integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(HistoryClear::Table, HistoryClear::Conversation)
+                            .to(Conversation::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(HistoryClear::BeforeSequence)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(HistoryClear::BeforeAuthor)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(HistoryClear::CrdtAuthor)
+                            .integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ClearedMessage::Table)
+                    .col(
+                        ColumnDef::new(ClearedMessage::Uuid)
+                            .binary()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ClearedMessage::Conversation)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ClearedMessage::Table, ClearedMessage::Conversation)
+                            .to(Conversation::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ClearedMessage::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(HistoryClear::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum HistoryClear {
+    Table,
+    Conversation,
+    BeforeSequence,
+    BeforeAuthor,
+    CrdtAuthor,
+}
+
+#[derive(Iden)]
+enum ClearedMessage {
+    Table,
+    Uuid,
+    Conversation,
+}
@@ -0,0 +1,149 @@
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, DatabaseBackend, Statement},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Authors went from a 32 bit fold of the key to a 64 bit hash of it. The
+/// author columns already hold 64 bit integers, they are rewritten from the
+/// known keys. The stored patches encode the author with its old width, so
+/// they are dropped and the channels that had not received all of them are
+/// seeded again from the conversation. A channel still draining its snapshot
+/// keeps the options of the snapshot, the options of a channel that was done
+/// with it are no longer known and it is seeded with the defaults. The
+/// snapshots are left empty for the database to fill on open.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        reauthor(manager, fold, hash).await?;
+        reseed(manager).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        reauthor(manager, hash, fold).await?;
+        reseed(manager).await
+    }
+}
+
+/// Author columns as of this migration, by table.
+const AUTHORS: [(&str, &[&str]); 9] = [
+    (
+        "message",
+        &[
+            "crdt_author",
+            "status_crdt_author",
+            "deleted_crdt_author",
+            "text_crdt_author",
+        ],
+    ),
+    ("contact", &["crdt_author"]),
+    ("conversation", &["crdt_author"]),
+    ("member", &["crdt_author", "removed_crdt_author"]),
+    ("receipt", &["crdt_author"]),
+    ("key_supersede", &["crdt_author"]),
+    ("attachment", &["crdt_author"]),
+    ("blob", &["crdt_author"]),
+    ("history_clear", &["before_author", "crdt_author"]),
+];
+
+fn fold(public: &[u8]) -> i64 {
+    public[..16]
+        .chunks(4)
+        .map(|chunk| i32::from_le_bytes(chunk.try_into().unwrap()))
+        .fold(0, |a, b| a ^ b)
+        .into()
+}
+
+fn hash(public: &[u8]) -> i64 {
+    let hash = blake3::hash(public);
+    i64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+}
+
+async fn reauthor(
+    manager: &SchemaManager<'_>,
+    from: fn(&[u8]) -> i64,
+    to: fn(&[u8]) -> i64,
+) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    let blocked = db
+        .query_all(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            "SELECT public FROM blocked;".to_string(),
+        ))
+        .await?;
+    for blocked in blocked {
+        let public = blocked.try_get::<Vec<u8>>("", "public")?;
+        db.execute(Statement::from_sql_and_values(
+            DatabaseBackend::Sqlite,
+            "UPDATE blocked SET author = ? WHERE public = ?;",
+            [to(&public).into(), public.into()],
+        ))
+        .await?;
+    }
+
+    // Every author is rewritten at once through the table of known keys, so
+    // that a new author is never taken for an old one.
+    db.execute_unprepared(
+        "CREATE TEMP TABLE reauthor (old INTEGER NOT NULL, new INTEGER NOT NULL);",
+    )
+    .await?;
+    let keys = db
+        .query_all(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            "SELECT public FROM key WHERE length(public) = 32;".to_string(),
+        ))
+        .await?;
+    for key in keys {
+        let public = key.try_get::<Vec<u8>>("", "public")?;
+        db.execute(Statement::from_sql_and_values(
+            DatabaseBackend::Sqlite,
+            "INSERT INTO reauthor (old, new) VALUES (?, ?);",
+            [from(&public).into(), to(&public).into()],
+        ))
+        .await?;
+    }
+    for (table, columns) in AUTHORS {
+        let set = columns
+            .iter()
+            .map(|column| {
+                format!(
+                    "{column} = coalesce((SELECT new FROM reauthor WHERE old = {table}.{column}), {column})"
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        db.execute_unprepared(&format!("UPDATE {table} SET {set};"))
+            .await?;
+    }
+    db.execute_unprepared("DROP TABLE reauthor;").await?;
+
+    Ok(())
+}
+
+async fn reseed(manager: &SchemaManager<'_>) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    for sql in [
+        "CREATE TEMP TABLE reseed AS \
+         SELECT channel.id, channel.conversation, coalesce(snapshot.options, x'') AS options \
+         FROM channel LEFT JOIN snapshot ON snapshot.id = channel.snapshot \
+         WHERE channel.snapshot IS NOT NULL \
+         OR channel.sync_index < (SELECT coalesce(max(id), 0) FROM sync);",
+        "DELETE FROM initial_sync;",
+        "UPDATE channel SET snapshot = NULL, snapshot_index = 0, sync_index = 0;",
+        "DELETE FROM snapshot;",
+        "DELETE FROM sync;",
+        "UPDATE message SET sync_index = NULL;",
+        "INSERT INTO snapshot (id, conversation, options, sync_index) \
+         SELECT id, conversation, options, 0 FROM reseed;",
+        "UPDATE channel SET snapshot = id WHERE id IN (SELECT id FROM reseed);",
+        "DROP TABLE reseed;",
+    ] {
+        db.execute_unprepared(sql).await?;
+    }
+
+    Ok(())
+}
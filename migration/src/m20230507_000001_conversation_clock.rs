@@ -0,0 +1,48 @@
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Conversation::Table)
+                    .add_column(ColumnDef::new(Conversation::Clock).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Cleared messages are gone, but their sequences were seen.
+        db.execute_unprepared(
+            "UPDATE conversation SET clock = \
+             (SELECT before_sequence FROM history_clear \
+             WHERE history_clear.conversation = conversation.id);",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Conversation::Table)
+                    .drop_column(Conversation::Clock)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Conversation {
+    Table,
+    Clock,
+}
@@ -0,0 +1,58 @@
+use crate::id::TableConcepts;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ConflictLog::Table)
+                    .col_id()
+                    .col(ColumnDef::new(ConflictLog::Kind).string().not_null())
+                    .col(ColumnDef::new(ConflictLog::Target).binary().not_null())
+                    .col(ColumnDef::new(ConflictLog::Generation).integer().not_null())
+                    .col(ColumnDef::new(ConflictLog::KeptAuthor).integer().not_null())
+                    .col(ColumnDef::new(ConflictLog::KeptValue).string().null())
+                    .col(
+                        ColumnDef::new(ConflictLog::DiscardedAuthor)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ConflictLog::DiscardedValue).string().null())
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("conflict_log_write")
+                            .col(ConflictLog::Kind)
+                            .col(ConflictLog::Target)
+                            .col(ConflictLog::Generation)
+                            .col(ConflictLog::DiscardedAuthor),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ConflictLog::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum ConflictLog {
+    Table,
+    Kind,
+    Target,
+    Generation,
+    KeptAuthor,
+    KeptValue,
+    DiscardedAuthor,
+    DiscardedValue,
+}
@@ -0,0 +1,54 @@
+use crate::{
+    id::Id,
+    m20230326_000001_create_table::{Key, Message},
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MessageDelivery::Table)
+                    .col(
+                        ColumnDef::new(MessageDelivery::Message)
+                            .integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(MessageDelivery::Table, MessageDelivery::Message)
+                            .to(Message::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(MessageDelivery::Peer).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(MessageDelivery::Table, MessageDelivery::Peer)
+                            .to(Key::Table, Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MessageDelivery::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum MessageDelivery {
+    Table,
+    Message,
+    Peer,
+}
@@ -0,0 +1,78 @@
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// A clear also holds the uuid of the message it is positioned at, which
+/// tells apart messages sent at once by devices sharing an author. Clears
+/// already stored get the lowest uuid, so they keep every message at their
+/// sequence and author. The uuid changes the encoding of the clear patches,
+/// so the stored patches are dropped and the channels that had not received
+/// all of them are seeded again from the conversation, keeping the options of
+/// the snapshot they drained. The snapshots are left empty for the database
+/// to fill on open.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(HistoryClear::Table)
+                    .add_column(
+                        ColumnDef::new(HistoryClear::BeforeId)
+                            .binary()
+                            .not_null()
+                            .default(Expr::cust("x'80000000800000008000000080000000'")),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        reseed(manager).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(HistoryClear::Table)
+                    .drop_column(HistoryClear::BeforeId)
+                    .to_owned(),
+            )
+            .await?;
+
+        reseed(manager).await
+    }
+}
+
+async fn reseed(manager: &SchemaManager<'_>) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    for sql in [
+        "CREATE TEMP TABLE reseed AS \
+         SELECT channel.id, channel.conversation, coalesce(snapshot.options, x'') AS options \
+         FROM channel LEFT JOIN snapshot ON snapshot.id = channel.snapshot \
+         WHERE channel.snapshot IS NOT NULL \
+         OR channel.sync_index < (SELECT coalesce(max(id), 0) FROM sync);",
+        "DELETE FROM initial_sync;",
+        "UPDATE channel SET snapshot = NULL, snapshot_index = 0, sync_index = 0;",
+        "DELETE FROM snapshot;",
+        "DELETE FROM sync;",
+        "UPDATE message SET sync_index = NULL;",
+        "INSERT INTO snapshot (id, conversation, options, sync_index) \
+         SELECT id, conversation, options, 0 FROM reseed;",
+        "UPDATE channel SET snapshot = id WHERE id IN (SELECT id FROM reseed);",
+        "DROP TABLE reseed;",
+    ] {
+        db.execute_unprepared(sql).await?;
+    }
+
+    Ok(())
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum HistoryClear {
+    Table,
+    BeforeId,
+}
@@ -1,34 +1,120 @@
 use crate::{
     database::{ChannelData, DbSync},
-    fragmentable::Fragmentable,
-    pipe_sync::{PipeSync, PipeSyncResult, PipeSyncValue},
+    fragmentable::DEFAULT_MAX_PACKET_LEN,
+    pipe_sync::{
+        Keepalive, PipeSync, PipeSyncError, PipeSyncResult, PipeSyncTraffic, PipeSyncValue,
+        SyncCounters,
+    },
+    transport::{IcepipeTransport, Transport},
 };
 use entity::crdt::Author;
-use futures_util::{future::LocalBoxFuture, FutureExt};
+use futures_util::future::LocalBoxFuture;
 use icepipe::{
-    agreement::Ed25519PairAndPeer,
-    connect::{ConnectResult, Connection},
-    pipe_stream::StreamError,
+    agreement::{AgreementError, Ed25519PairAndPeer},
+    connect::ConnectError,
+    pipe_stream::{StreamError, StreamResult},
 };
-use ring::signature::{Ed25519KeyPair, KeyPair};
-use std::{ops::Deref, str::FromStr};
+use ring::{
+    digest::{digest, SHA512},
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use std::{fmt, ops::Deref, str::FromStr, sync::Arc};
+use url::Url;
 
-pub struct Channel<S: DbSync> {
+pub struct Channel<S: DbSync, T: Transport = IcepipeTransport> {
     channel: ChannelData,
     key: Ed25519Seed,
-    state: ChannelState<S>,
+    state: ChannelState<S, T>,
+    transport: T,
+    rate_limit: Option<u32>,
+    keepalive: Option<Keepalive>,
+    max_packet_len: usize,
+    /// Label of `state` last reported to `on_state_change`.
+    reported_state: ChannelStateLabel,
+    offline_reason: Option<OfflineReason>,
+    on_state_change: Option<Box<dyn Fn(ChannelStateChange)>>,
+    counters: Arc<SyncCounters>,
 }
 impl<S: DbSync> Channel<S> {
-    pub fn new(channel: ChannelData, key: Ed25519Seed) -> Self {
+    /// Servers used by connections started afterwards.
+    pub fn set_connect_config(&mut self, config: ConnectConfig) {
+        self.transport.config = config;
+    }
+}
+impl<S: DbSync, T: Transport> Channel<S, T> {
+    pub fn new(channel: ChannelData, key: Ed25519Seed) -> Self
+    where
+        T: Default,
+    {
+        Self::with_transport(channel, key, Default::default())
+    }
+
+    /// Same as [`Channel::new`], connecting through `transport`.
+    pub fn with_transport(channel: ChannelData, key: Ed25519Seed, transport: T) -> Self {
         Self {
             channel,
             key,
             state: Default::default(),
+            transport,
+            rate_limit: None,
+            keepalive: Some(Default::default()),
+            max_packet_len: DEFAULT_MAX_PACKET_LEN,
+            reported_state: ChannelStateLabel::Offline,
+            offline_reason: None,
+            on_state_change: None,
+            counters: Default::default(),
+        }
+    }
+
+    /// See [`PipeSync::set_rate_limit`], applies to the current connection and
+    /// to later ones.
+    pub fn set_rate_limit(&mut self, bytes_per_second: Option<u32>) {
+        self.rate_limit = bytes_per_second;
+        if let ChannelState::Connected(pipe_sync) = &mut self.state {
+            pipe_sync.set_rate_limit(bytes_per_second);
+        }
+    }
+
+    /// See [`PipeSync::set_keepalive`], applies to the current connection and
+    /// to later ones. On by default, with the intervals of [`Keepalive`].
+    pub fn set_keepalive(&mut self, keepalive: Option<Keepalive>) {
+        self.keepalive = keepalive;
+        if let ChannelState::Connected(pipe_sync) = &mut self.state {
+            pipe_sync.set_keepalive(keepalive);
+        }
+    }
+
+    /// Longest packet the peer may announce, see [`Transport::connect`].
+    /// Applies to later connections, [`DEFAULT_MAX_PACKET_LEN`] by default.
+    pub fn set_max_packet_len(&mut self, max_packet_len: usize) {
+        self.max_packet_len = max_packet_len;
+    }
+
+    /// Calls `callback` on each transition of [`Channel::state`], so that
+    /// there is no need to poll it.
+    pub fn on_state_change(&mut self, callback: impl Fn(ChannelStateChange) + 'static) {
+        self.on_state_change = Some(Box::new(callback));
+    }
+
+    fn report_state_change(&mut self) {
+        let to = self.state.label();
+        let from = std::mem::replace(&mut self.reported_state, to);
+        if from == to {
+            return;
+        }
+
+        if let Some(callback) = &self.on_state_change {
+            callback(ChannelStateChange {
+                peer: self.channel.peer_cert,
+                from,
+                to,
+            });
         }
     }
 
     pub fn connect(&mut self, state: S) {
         self.state = ChannelState::PreConnecting(state);
+        self.report_state_change();
     }
 
     pub fn channel(&self) -> &ChannelData {
@@ -39,6 +125,48 @@ impl<S: DbSync> Channel<S> {
         self.state.label()
     }
 
+    /// Why the last connection ended, kept until the channel connects again.
+    pub fn offline_reason(&self) -> Option<OfflineReason> {
+        self.offline_reason
+    }
+
+    /// See [`DbSync::peer_clock_skew`], only known while connected.
+    pub fn peer_clock_skew(&self) -> Option<i64> {
+        match &self.state {
+            ChannelState::Connected(pipe_sync) => pipe_sync.sync().peer_clock_skew(),
+            _ => None,
+        }
+    }
+
+    /// See [`DbSync::send_typing`], dropped unless connected.
+    pub fn send_typing(&mut self) {
+        let author = self.key.public_key().as_author();
+        if let ChannelState::Connected(pipe_sync) = &mut self.state {
+            pipe_sync.sync_mut().send_typing(author);
+        }
+    }
+
+    /// See [`DbSync::peer_typing`], only known while connected.
+    pub fn peer_typing(&self) -> bool {
+        match &self.state {
+            ChannelState::Connected(pipe_sync) => pipe_sync.sync().peer_typing(),
+            _ => false,
+        }
+    }
+
+    /// See [`PipeSyncTraffic`], only known while connected.
+    pub fn traffic(&self) -> Option<PipeSyncTraffic> {
+        match &self.state {
+            ChannelState::Connected(pipe_sync) => Some(pipe_sync.traffic()),
+            _ => None,
+        }
+    }
+
+    /// See [`SyncCounters`], summed over every connection of the channel.
+    pub fn counters(&self) -> Arc<SyncCounters> {
+        self.counters.clone()
+    }
+
     pub async fn pre_wait(&mut self, database: &mut S::Database) {
         let state = std::mem::take(&mut self.state);
 
@@ -49,16 +177,19 @@ impl<S: DbSync> Channel<S> {
                 self.state = state;
             }
             Err(e) => {
-                log::warn!("{e}");
-                log::debug!("{e:?}");
+                let context = self.channel.log_context();
+                log::warn!("{context}: {e}");
+                log::debug!("{context}: {e:?}");
+                self.offline_reason = Some(OfflineReason::of(&e));
             }
         };
+        self.report_state_change();
     }
 
     async fn pre_wait_impl(
-        state: ChannelState<S>,
+        state: ChannelState<S, T>,
         database: &mut S::Database,
-    ) -> PipeSyncResult<ChannelState<S>> {
+    ) -> PipeSyncResult<ChannelState<S, T>> {
         match state {
             ChannelState::Connected(mut sync) => {
                 if sync.rx_closed() {
@@ -72,20 +203,24 @@ impl<S: DbSync> Channel<S> {
         }
     }
 
-    pub async fn wait(&mut self) -> ChannelValue {
+    pub async fn wait(&mut self) -> ChannelValue<T> {
         let r = self.wait_impl().await;
-        match r {
+        let value = match r {
             Ok(value) => value,
             Err(e) => {
                 self.state = ChannelState::Offline;
-                log::warn!("{e}");
-                log::debug!("{e:?}");
-                ChannelValue::Error
+                let context = self.channel.log_context();
+                log::warn!("{context}: {e}");
+                log::debug!("{context}: {e:?}");
+                ChannelValue::Error(e)
             }
-        }
+        };
+        self.report_state_change();
+
+        value
     }
 
-    async fn wait_impl(&mut self) -> PipeSyncResult<ChannelValue> {
+    async fn wait_impl(&mut self) -> PipeSyncResult<ChannelValue<T>> {
         match &mut self.state {
             ChannelState::Offline => {
                 std::future::pending::<()>().await;
@@ -100,14 +235,20 @@ impl<S: DbSync> Channel<S> {
                 ))
             }
             ChannelState::Connecting(_, connecting) => {
-                let pipe = connecting.await.map_err(StreamError::from)?;
+                let pipe = connecting.await?;
                 let state = std::mem::take(&mut self.state);
                 let sync = match state {
                     ChannelState::Connecting(sync_state, _) => sync_state,
                     _ => unreachable!(),
                 };
-                let pipe_sync = PipeSync::new(sync, Fragmentable::new(pipe));
-                self.state = ChannelState::Connected(pipe_sync);
+                let mut pipe_sync = PipeSync::new(sync, pipe);
+                pipe_sync.set_rate_limit(self.rate_limit);
+                pipe_sync.set_keepalive(self.keepalive);
+                pipe_sync.set_compression(true);
+                pipe_sync.set_counters(self.counters.clone());
+                pipe_sync.set_log_context(self.channel.log_context());
+                self.state = ChannelState::Connected(Box::new(pipe_sync));
+                self.offline_reason = None;
 
                 Ok(ChannelValue::Connected)
             }
@@ -118,20 +259,23 @@ impl<S: DbSync> Channel<S> {
         }
     }
 
-    pub async fn then(&mut self, value: ChannelValue) {
+    pub async fn then(&mut self, value: ChannelValue<T>) {
         let r = self.then_impl(value).await;
 
         match r {
             Ok(()) => {}
             Err(e) => {
                 self.state = ChannelState::Offline;
-                log::warn!("{e}");
-                log::debug!("{e:?}");
+                let context = self.channel.log_context();
+                log::warn!("{context}: {e}");
+                log::debug!("{context}: {e:?}");
+                self.offline_reason = Some(OfflineReason::of(&e));
             }
         }
+        self.report_state_change();
     }
 
-    pub async fn then_impl(&mut self, value: ChannelValue) -> PipeSyncResult<()> {
+    pub async fn then_impl(&mut self, value: ChannelValue<T>) -> PipeSyncResult<()> {
         match (&mut self.state, value) {
             (ChannelState::PreConnecting(_), ChannelValue::StartConnection(channel, auth)) => {
                 let state = std::mem::take(&mut self.state);
@@ -140,16 +284,7 @@ impl<S: DbSync> Channel<S> {
                     _ => unreachable!(),
                 };
 
-                let connecting = async move {
-                    icepipe::ConnectOptions {
-                        channel,
-                        signaling: Default::default(),
-                        ice: Default::default(),
-                    }
-                    .connect(auth)
-                    .await
-                }
-                .boxed_local();
+                let connecting = self.transport.connect(channel, auth, self.max_packet_len);
                 self.state = ChannelState::Connecting(sync, connecting);
 
                 Ok(())
@@ -157,6 +292,10 @@ impl<S: DbSync> Channel<S> {
             (ChannelState::Connected(pipe_sync), ChannelValue::PipeSyncValue(value)) => {
                 pipe_sync.then(value).await
             }
+            (_, ChannelValue::Error(e)) => {
+                self.offline_reason = Some(OfflineReason::of(&e));
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
@@ -165,12 +304,14 @@ impl<S: DbSync> Channel<S> {
         let r = Self::close_impl(std::mem::take(&mut self.state)).await;
 
         if let Err(e) = r {
-            log::warn!("{e}");
-            log::debug!("{e:?}");
+            let context = self.channel.log_context();
+            log::warn!("{context}: {e}");
+            log::debug!("{context}: {e:?}");
         }
+        self.report_state_change();
     }
 
-    async fn close_impl(state: ChannelState<S>) -> PipeSyncResult<()> {
+    async fn close_impl(state: ChannelState<S, T>) -> PipeSyncResult<()> {
         match state {
             ChannelState::Offline => Ok(()),
             ChannelState::PreConnecting(_) => Ok(()),
@@ -187,6 +328,13 @@ impl Ed25519Seed {
         Self(seed)
     }
 
+    /// The key derived from `seed`, always the same for the same bytes. Meant
+    /// for tests that pin who the authors are, a real key comes from
+    /// [`Ed25519Seed::generate`].
+    pub fn from_entropy(seed: [u8; 32]) -> Ed25519Seed {
+        Self::new(seed)
+    }
+
     pub fn generate() -> Ed25519Seed {
         let seed = ring::rand::generate(&ring::rand::SystemRandom::new())
             .unwrap()
@@ -230,13 +378,40 @@ impl Deref for Ed25519Seed {
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Ed25519Cert(pub [u8; 32]);
 impl Ed25519Cert {
+    /// The author CRDTs tie-break on. It is a 64 bit hash of the whole key,
+    /// so distinct keys only share an author by chance, with odds that do not
+    /// matter at the size of a conversation.
     pub fn as_author(&self) -> Author {
-        Author(
-            i32::from_le_bytes((&self.0[0..][..4]).try_into().unwrap())
-                ^ i32::from_le_bytes((&self.0[4..][..4]).try_into().unwrap())
-                ^ i32::from_le_bytes((&self.0[8..][..4]).try_into().unwrap())
-                ^ i32::from_le_bytes((&self.0[12..][..4]).try_into().unwrap()),
-        )
+        let hash = blake3::hash(&self.0);
+        Author(i64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap()))
+    }
+
+    /// Whether the key is a point of the curve, and so can be used to derive
+    /// a channel with [`Ed25519Seed::x25519_agree`].
+    pub fn is_valid(&self) -> bool {
+        icepipe::curve25519_conversion::ed25519_public_key_to_x25519(self.0.as_slice()).is_some()
+    }
+
+    /// Parses the 64 hex digits written by [`Ed25519Cert::hex`]. Input of
+    /// any other length, or that is not a point of the curve, is rejected.
+    pub fn from_hex(s: &str) -> Result<Ed25519Cert, BadEd25519CertStr> {
+        let s = s.as_bytes();
+        if s.len() != 64 {
+            return Err(BadEd25519CertStr);
+        }
+
+        let mut r = [0; 32];
+        for (byte, hex) in r.iter_mut().zip(s.chunks(2)) {
+            let hex = std::str::from_utf8(hex).map_err(|_| BadEd25519CertStr)?;
+            *byte = u8::from_str_radix(hex, 16).map_err(|_| BadEd25519CertStr)?;
+        }
+
+        let cert = Ed25519Cert(r);
+        if !cert.is_valid() {
+            return Err(BadEd25519CertStr);
+        }
+
+        Ok(cert)
     }
 
     pub fn hex(&self) -> String {
@@ -245,40 +420,67 @@ impl Ed25519Cert {
             .map(|c| format!("{c:02x}"))
             .collect::<String>()
     }
+
+    /// Sixty digits, in groups of five, that two users compare out of band to
+    /// make sure each one holds the other's key. Both sides get the same
+    /// number.
+    pub fn safety_number(&self, other: &Ed25519Cert) -> String {
+        let mut halves = [self, other].map(Ed25519Cert::fingerprint);
+        halves.sort();
+
+        halves
+            .concat()
+            .chunks(5)
+            .map(|group| group.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Thirty digits derived from the key, half of a safety number.
+    fn fingerprint(&self) -> Vec<char> {
+        let hash = digest(&SHA512, &[b"icechat-safety-number", &self.0[..]].concat());
+
+        hash.as_ref()[..30]
+            .chunks(5)
+            .flat_map(|chunk| {
+                let value = chunk
+                    .iter()
+                    .fold(0, |value, byte| value << 8 | *byte as u64);
+                format!("{:05}", value % 100_000)
+                    .chars()
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+impl fmt::Display for Ed25519Cert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.hex())
+    }
 }
 impl FromStr for Ed25519Cert {
     type Err = BadEd25519CertStr;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() != 64 {
-            return Err(BadEd25519CertStr);
-        }
-
-        let mut r = [0; 32];
-        for i in 0..32 {
-            let hex = &s[i * 2..][..2];
-            r[i] = u8::from_str_radix(hex, 16).map_err(|_| BadEd25519CertStr)?;
-        }
-
-        Ok(Ed25519Cert(r))
+        Ed25519Cert::from_hex(s)
     }
 }
 #[derive(thiserror::Error, Debug)]
-#[error("String is not a valid Ed25519 cert")]
+#[error("Invalid key, not a valid Ed25519 cert")]
 pub struct BadEd25519CertStr;
 
-pub enum ChannelState<S: DbSync> {
+pub enum ChannelState<S: DbSync, T: Transport> {
     Offline,
     PreConnecting(S),
-    Connecting(S, LocalBoxFuture<'static, ConnectResult<Connection>>),
-    Connected(PipeSync<S, Fragmentable<Connection>>),
+    Connecting(S, LocalBoxFuture<'static, StreamResult<T::Pipe>>),
+    Connected(Box<PipeSync<S, T::Pipe>>),
 }
-impl<S: DbSync> Default for ChannelState<S> {
+impl<S: DbSync, T: Transport> Default for ChannelState<S, T> {
     fn default() -> Self {
         Self::Offline
     }
 }
-impl<S: DbSync> ChannelState<S> {
+impl<S: DbSync, T: Transport> ChannelState<S, T> {
     fn label(&self) -> ChannelStateLabel {
         match self {
             ChannelState::Offline => ChannelStateLabel::Offline,
@@ -289,11 +491,13 @@ impl<S: DbSync> ChannelState<S> {
     }
 }
 
-pub enum ChannelValue {
+pub enum ChannelValue<T: Transport = IcepipeTransport> {
     Connected,
     StartConnection(String, Ed25519PairAndPeer),
-    PipeSyncValue(PipeSyncValue<Fragmentable<Connection>>),
-    Error,
+    PipeSyncValue(PipeSyncValue<T::Pipe>),
+    /// The channel went [`ChannelStateLabel::Offline`] because of this error,
+    /// see [`Channel::offline_reason`].
+    Error(PipeSyncError),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -303,3 +507,249 @@ pub enum ChannelStateLabel {
     Connecting,
     Connected,
 }
+
+/// Why a channel went offline, coarse enough to show to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfflineReason {
+    /// The peer did not prove it holds the key of the channel, or refused
+    /// ours.
+    PeerRejected,
+    /// The peer stopped answering, see [`Keepalive`].
+    PeerTimeout,
+    ConnectionLost,
+    /// Saving or reading what was synced failed on this side.
+    DatabaseError,
+}
+impl OfflineReason {
+    pub fn of(error: &PipeSyncError) -> Self {
+        match error {
+            PipeSyncError::PeerTimeout => OfflineReason::PeerTimeout,
+            PipeSyncError::DatabaseError(_) => OfflineReason::DatabaseError,
+            PipeSyncError::StreamError(StreamError::Other(e)) => match e.downcast_ref() {
+                Some(ConnectError::AgreementError(
+                    AgreementError::BadAuth(_) | AgreementError::CryptoError(_),
+                )) => OfflineReason::PeerRejected,
+                _ => OfflineReason::ConnectionLost,
+            },
+            PipeSyncError::IoError(_) | PipeSyncError::StreamError(_) => {
+                OfflineReason::ConnectionLost
+            }
+        }
+    }
+}
+impl fmt::Display for OfflineReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OfflineReason::PeerRejected => "peer rejected",
+            OfflineReason::PeerTimeout => "peer timed out",
+            OfflineReason::ConnectionLost => "connection lost",
+            OfflineReason::DatabaseError => "database error",
+        })
+    }
+}
+
+/// Signaling and ICE servers to connect through, the defaults of icepipe
+/// when not set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectConfig {
+    pub signaling: Option<Url>,
+    pub ice: Vec<String>,
+}
+impl ConnectConfig {
+    /// Reads `ICECHAT_SIGNALING`, a URL, and `ICECHAT_ICE`, a comma separated
+    /// list of ICE server URLs.
+    pub fn from_env() -> Result<Self, url::ParseError> {
+        let signaling = std::env::var("ICECHAT_SIGNALING")
+            .ok()
+            .map(|signaling| signaling.parse())
+            .transpose()?;
+        let ice = std::env::var("ICECHAT_ICE").unwrap_or_default();
+
+        Ok(Self::new(signaling, &ice))
+    }
+
+    fn new(signaling: Option<Url>, ice: &str) -> Self {
+        let ice = ice
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+
+        ConnectConfig { signaling, ice }
+    }
+}
+
+/// See [`Channel::on_state_change`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStateChange {
+    pub peer: Ed25519Cert,
+    pub from: ChannelStateLabel,
+    pub to: ChannelStateLabel,
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{database::Database, SqliteChannel};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn ice_servers_are_comma_separated() {
+        let config = ConnectConfig::new(None, "stun:a.example:3478, turn:b.example:3478,");
+
+        assert_eq!(
+            config.ice,
+            ["stun:a.example:3478", "turn:b.example:3478"].map(ToOwned::to_owned)
+        );
+        assert_eq!(ConnectConfig::new(None, ""), ConnectConfig::default());
+    }
+
+    #[test]
+    fn safety_number_is_the_same_on_both_sides() {
+        let [alice, bob, carol] = [(); 3].map(|_| Ed25519Seed::generate().public_key());
+
+        let number = alice.safety_number(&bob);
+
+        assert_eq!(number, bob.safety_number(&alice));
+        assert_ne!(number, alice.safety_number(&carol));
+        assert_eq!(number.split(' ').count(), 12);
+        assert!(number
+            .split(' ')
+            .all(|group| group.len() == 5 && group.chars().all(|c| c.is_ascii_digit())));
+    }
+
+    #[test]
+    fn keys_off_the_curve_are_invalid() {
+        let invalid = Ed25519Cert([2; 32]);
+
+        assert!(!invalid.is_valid());
+        assert!(invalid.hex().parse::<Ed25519Cert>().is_err());
+        assert!(Ed25519Seed::generate().public_key().is_valid());
+    }
+
+    #[test]
+    fn hex_keys_are_not_padded() {
+        let key = Ed25519Seed::generate().public_key();
+        let hex = key.hex();
+
+        assert_eq!(Ed25519Cert::from_hex(&hex).unwrap(), key);
+        assert!(Ed25519Cert::from_hex(&hex[..62]).is_err());
+        assert!(Ed25519Cert::from_hex(&format!("{hex}00")).is_err());
+        assert!(Ed25519Cert::from_hex(&format!("é{}", &hex[2..])).is_err());
+        assert!(Ed25519Cert::from_hex(&format!("0é{}", &hex[3..])).is_err());
+    }
+
+    #[test]
+    fn keys_round_trip_through_a_string() {
+        let key = Ed25519Seed::generate().public_key();
+
+        assert_eq!(key.to_string(), key.hex());
+        assert_eq!(key.to_string().parse::<Ed25519Cert>().unwrap(), key);
+    }
+
+    #[test]
+    fn keys_folding_to_the_same_32_bits_are_distinct_authors() {
+        let key = Ed25519Seed::generate().public_key();
+        let mut swapped = key;
+        swapped.0[..8].rotate_left(4);
+        let folded = |key: &Ed25519Cert| {
+            key.0[..16]
+                .chunks(4)
+                .map(|chunk| i32::from_le_bytes(chunk.try_into().unwrap()))
+                .fold(0, |a, b| a ^ b)
+        };
+
+        assert_ne!(key, swapped);
+        assert_eq!(folded(&key), folded(&swapped));
+        assert_ne!(key.as_author(), swapped.as_author());
+        assert_eq!(key.as_author(), key.as_author());
+    }
+
+    #[test]
+    fn a_failed_key_agreement_is_a_rejection() {
+        let rejected = ConnectError::AgreementError(AgreementError::BadAuth(Box::new(
+            AgreementError::CryptoError(ring::error::Unspecified),
+        )));
+        let lost = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+
+        assert_eq!(
+            OfflineReason::of(&StreamError::from(rejected).into()),
+            OfflineReason::PeerRejected
+        );
+        assert_eq!(
+            OfflineReason::of(&StreamError::from(lost).into()),
+            OfflineReason::ConnectionLost
+        );
+        assert_eq!(
+            OfflineReason::of(&PipeSyncError::PeerTimeout),
+            OfflineReason::PeerTimeout
+        );
+    }
+
+    #[tokio::test]
+    async fn the_error_that_took_a_channel_offline_is_kept() {
+        let database = Database::connect(":memory:").await.unwrap();
+        let conversation = database.create_conversation(None).await.unwrap();
+        let peer = Ed25519Seed::generate().public_key();
+        database
+            .create_channel(conversation.clone(), peer)
+            .await
+            .unwrap();
+        let data = database
+            .list_channels(&conversation)
+            .await
+            .unwrap()
+            .remove(0);
+        let mut channel = SqliteChannel::new(data, database.private_key().clone());
+        assert_eq!(channel.offline_reason(), None);
+
+        channel
+            .then(ChannelValue::Error(PipeSyncError::PeerTimeout))
+            .await;
+
+        assert_eq!(channel.state(), ChannelStateLabel::Offline);
+        assert_eq!(channel.offline_reason(), Some(OfflineReason::PeerTimeout));
+    }
+
+    #[tokio::test]
+    async fn state_changes_are_reported_with_the_peer() {
+        let database = Database::connect(":memory:").await.unwrap();
+        let conversation = database.create_conversation(None).await.unwrap();
+        let peer = Ed25519Seed::generate().public_key();
+        database
+            .create_channel(conversation.clone(), peer)
+            .await
+            .unwrap();
+        let data = database
+            .list_channels(&conversation)
+            .await
+            .unwrap()
+            .remove(0);
+
+        let mut channel = SqliteChannel::new(data.clone(), database.private_key().clone());
+        let changes = Rc::new(RefCell::new(vec![]));
+        let pushed = changes.clone();
+        channel.on_state_change(move |change| pushed.borrow_mut().push(change));
+
+        channel.connect(database.start_sync(data));
+        channel.close().await;
+        channel.close().await;
+
+        assert_eq!(
+            *changes.borrow(),
+            [
+                ChannelStateChange {
+                    peer,
+                    from: ChannelStateLabel::Offline,
+                    to: ChannelStateLabel::PreConnecting,
+                },
+                ChannelStateChange {
+                    peer,
+                    from: ChannelStateLabel::PreConnecting,
+                    to: ChannelStateLabel::Offline,
+                },
+            ]
+        );
+    }
+}
@@ -9,8 +9,14 @@ pub struct ChannelPipe {
 }
 impl ChannelPipe {
     pub fn channel() -> (ChannelPipe, ChannelPipe) {
-        let a = tokio::sync::mpsc::channel(1);
-        let b = tokio::sync::mpsc::channel(1);
+        Self::with_capacity(16)
+    }
+
+    /// Same as [`ChannelPipe::channel`], each end holding up to `capacity`
+    /// unread messages before sending waits for the other end to read.
+    pub fn with_capacity(capacity: usize) -> (ChannelPipe, ChannelPipe) {
+        let a = tokio::sync::mpsc::channel(capacity);
+        let b = tokio::sync::mpsc::channel(capacity);
 
         let a_pipe = ChannelPipe {
             send: Some(b.0),
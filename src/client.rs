@@ -0,0 +1,284 @@
+//! Headless driver of the channels of a [`Database`], for applications that
+//! already run inside an async runtime.
+//!
+//! Every method of [`Database`] is async, so such an application calls them
+//! directly. What remains is keeping one [`SqliteChannel`] per channel of the
+//! database and feeding them, which is what [`Client::poll`] does. Sync
+//! futures are `!Send`, so the client must be polled from a
+//! [`tokio::task::LocalSet`].
+
+use crate::{
+    channel::{ChannelStateChange, ChannelStateLabel, ChannelValue},
+    database::{error::DatabaseResult, ChannelData, Database},
+    SqliteChannel,
+};
+use futures_util::{
+    future::{pending, select_all},
+    FutureExt,
+};
+use sea_orm::DatabaseTransaction;
+use std::{collections::HashSet, rc::Rc};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// One [`SqliteChannel`] for each channel of a [`Database`], fed together.
+///
+/// A round is [`ChannelSet::pre_wait`], [`ChannelSet::wait`] and
+/// [`ChannelSet::then`]. The transaction given to `pre_wait` is owned by the
+/// caller, which decides whether to commit it before waiting or after.
+#[derive(Default)]
+pub struct ChannelSet {
+    channels: Vec<SqliteChannel>,
+    on_state_change: Option<Rc<dyn Fn(ChannelStateChange)>>,
+    rate_limit: Option<u32>,
+}
+impl ChannelSet {
+    /// See [`Channel::set_rate_limit`](crate::channel::Channel::set_rate_limit),
+    /// each channel of the set has its own limit.
+    pub fn set_rate_limit(&mut self, bytes_per_second: Option<u32>) {
+        for channel in self.channels.iter_mut() {
+            channel.set_rate_limit(bytes_per_second);
+        }
+        self.rate_limit = bytes_per_second;
+    }
+
+    /// Calls `callback` whenever a channel of the set connects or
+    /// disconnects, see [`Channel::on_state_change`](crate::channel::Channel::on_state_change).
+    pub fn on_state_change(&mut self, callback: impl Fn(ChannelStateChange) + 'static) {
+        let callback: Rc<dyn Fn(ChannelStateChange)> = Rc::new(callback);
+        for channel in self.channels.iter_mut() {
+            let callback = callback.clone();
+            channel.on_state_change(move |change| callback(change));
+        }
+        self.on_state_change = Some(callback);
+    }
+
+    /// Adds and drops channels so that there is one for each channel the
+    /// database lists.
+    pub async fn sync_channels(&mut self, database: &Database) -> DatabaseResult<SyncedChannels> {
+        #![allow(clippy::mutable_key_type)]
+        let mut channels = HashSet::new();
+        for conversation in database.list_conversation().await? {
+            for data in database.list_channels(&conversation).await? {
+                channels.insert(data);
+            }
+        }
+
+        let mut synced = SyncedChannels::default();
+        for i in (0..self.channels.len()).rev() {
+            if channels.take(self.channels[i].channel()).is_none() {
+                let removed = self.channels.remove(i);
+                synced.removed.push(removed.channel().clone());
+            }
+        }
+
+        for channel in channels {
+            let mut sync = database.channel(channel.clone());
+            if let Some(callback) = self.on_state_change.clone() {
+                sync.on_state_change(move |change| callback(change));
+            }
+            sync.set_rate_limit(self.rate_limit);
+            self.channels.push(sync);
+            synced.added.push(channel);
+        }
+
+        Ok(synced)
+    }
+
+    /// Connects the offline channels and lets every channel save what it
+    /// received into `trans`.
+    pub async fn pre_wait(&mut self, database: &Database, trans: &mut DatabaseTransaction) {
+        for channel in self.channels.iter_mut() {
+            if channel.state() == ChannelStateLabel::Offline {
+                channel.connect(database.start_sync(channel.channel().clone()));
+            }
+            channel.pre_wait(trans).await;
+        }
+    }
+
+    /// Waits for any channel to make progress, returning what it made and its
+    /// index. Never returns while the set is empty.
+    pub async fn wait(&mut self) -> (ChannelValue, usize) {
+        if self.channels.is_empty() {
+            return pending().await;
+        }
+
+        let waits = self
+            .channels
+            .iter_mut()
+            .map(|channel| channel.wait().boxed_local())
+            .collect::<Vec<_>>();
+
+        let (value, index, _) = select_all(waits).await;
+        (value, index)
+    }
+
+    pub async fn then(&mut self, (value, index): (ChannelValue, usize)) {
+        self.channels[index].then(value).await;
+    }
+
+    pub fn get(&self, index: usize) -> &SqliteChannel {
+        &self.channels[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SqliteChannel> {
+        self.channels.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut SqliteChannel> {
+        self.channels.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    pub async fn close(self) {
+        for mut channel in self.channels {
+            channel.close().await
+        }
+    }
+}
+
+/// Channels added and dropped by [`ChannelSet::sync_channels`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncedChannels {
+    pub added: Vec<ChannelData>,
+    pub removed: Vec<ChannelData>,
+}
+
+/// Something that happened while [`Client::poll`] ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    /// A channel was added because the database now lists it.
+    ChannelAdded(ChannelData),
+    /// A channel was dropped because the database no longer lists it.
+    ChannelRemoved(ChannelData),
+    StateChange(ChannelStateChange),
+    /// The channel exchanged data with its peer, new messages may have been
+    /// merged into the database.
+    Activity(ChannelData),
+}
+
+pub struct Client {
+    database: Database,
+    channels: ChannelSet,
+    events: UnboundedSender<ClientEvent>,
+}
+impl Client {
+    /// The client, and the receiving end of its events.
+    pub fn new(database: Database) -> (Client, UnboundedReceiver<ClientEvent>) {
+        let (events, receiver) = unbounded_channel();
+
+        let mut channels = ChannelSet::default();
+        let sender = events.clone();
+        channels.on_state_change(move |change| {
+            let _ = sender.send(ClientEvent::StateChange(change));
+        });
+
+        let client = Client {
+            database,
+            channels,
+            events,
+        };
+
+        (client, receiver)
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    pub fn database_mut(&mut self) -> &mut Database {
+        &mut self.database
+    }
+
+    pub fn channels(&self) -> &ChannelSet {
+        &self.channels
+    }
+
+    pub fn channels_mut(&mut self) -> &mut ChannelSet {
+        &mut self.channels
+    }
+
+    /// See [`ChannelSet::sync_channels`].
+    pub async fn sync_channels(&mut self) -> DatabaseResult<()> {
+        let synced = self.channels.sync_channels(&self.database).await?;
+
+        for channel in synced.removed {
+            self.emit(ClientEvent::ChannelRemoved(channel));
+        }
+        for channel in synced.added {
+            self.emit(ClientEvent::ChannelAdded(channel));
+        }
+
+        Ok(())
+    }
+
+    /// Syncs the list of channels, then waits for any channel to make progress
+    /// and handles it. Meant to be called in a loop. Never returns while the
+    /// database has no channels.
+    pub async fn poll(&mut self) -> DatabaseResult<()> {
+        self.sync_channels().await?;
+
+        let mut trans = self.database.begin().await?;
+        self.channels.pre_wait(&self.database, &mut trans).await;
+        let (value, index) = self.channels.wait().await;
+        trans.commit().await?;
+
+        self.channels.then((value, index)).await;
+        self.emit(ClientEvent::Activity(
+            self.channels.get(index).channel().clone(),
+        ));
+
+        let migrated = self.database.migrate_superseded_channels().await?;
+        if migrated > 0 {
+            log::info!("Migrated {migrated} channels to rotated keys");
+        }
+
+        Ok(())
+    }
+
+    fn emit(&self, event: ClientEvent) {
+        // Nobody listening is fine, events are only informative.
+        let _ = self.events.send(event);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::channel::Ed25519Seed;
+
+    #[tokio::test]
+    async fn given_a_channel_then_it_is_added_and_removed_with_the_database() {
+        let database = Database::connect(":memory:").await.unwrap();
+        let conversation = database.create_conversation(None).await.unwrap();
+        let peer = Ed25519Seed::generate().public_key();
+        let (mut client, mut events) = Client::new(database);
+
+        client
+            .database()
+            .create_channel(conversation.clone(), peer)
+            .await
+            .unwrap();
+        client.sync_channels().await.unwrap();
+
+        let Ok(ClientEvent::ChannelAdded(added)) = events.try_recv() else { panic!() };
+        assert_eq!(added.peer_cert, peer);
+        assert_eq!(client.channels().len(), 1);
+
+        client
+            .database()
+            .remove_channel(conversation, peer)
+            .await
+            .unwrap();
+        client.sync_channels().await.unwrap();
+
+        assert_eq!(events.try_recv(), Ok(ClientEvent::ChannelRemoved(added)));
+        assert!(client.channels().is_empty());
+    }
+}
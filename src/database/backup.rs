@@ -0,0 +1,94 @@
+//! Portable archive of a whole database, see
+//! [`Database::backup`](super::Database::backup).
+//!
+//! A backup is `MAGIC || version || wrapped || payload`, where the payload is
+//! the bincode of an [`Archive`], sealed with a passphrase as in the `local`
+//! table when `wrapped` is 1. The archive holds patches rather than rows, so
+//! it is restored by replaying them into whatever schema is current.
+
+use super::{
+    error::{DatabaseError, DatabaseResult},
+    passphrase,
+};
+use entity::patch::Patch;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const MAGIC: &[u8] = b"ICECHATBAK";
+// Version 2 widened the authors the patches carry.
+const VERSION: u8 = 2;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Archive {
+    pub seed: [u8; 32],
+    /// Every conversation as a snapshot with its whole history, in the order
+    /// they must be merged.
+    pub patches: Vec<Patch>,
+    /// Conversation and peer of each channel.
+    pub channels: Vec<(Uuid, [u8; 32])>,
+}
+
+pub(crate) fn encode(archive: &Archive, passphrase: Option<&str>) -> DatabaseResult<Vec<u8>> {
+    let payload = bincode::serialize(archive)?;
+    let (wrapped, payload) = match passphrase {
+        Some(passphrase) => (1, passphrase::seal(passphrase, &payload)),
+        None => (0, payload),
+    };
+
+    Ok([MAGIC, &[VERSION, wrapped], &payload].concat())
+}
+
+pub(crate) fn decode(backup: &[u8], passphrase: Option<&str>) -> DatabaseResult<Archive> {
+    let Some(backup) = backup.strip_prefix(MAGIC) else { return Err(DatabaseError::BadBackup); };
+    let [VERSION, wrapped, payload @ ..] = backup else { return Err(DatabaseError::BadBackup); };
+
+    let payload = match (wrapped, passphrase) {
+        (0, _) => payload.to_vec(),
+        (1, None) => return Err(DatabaseError::Encrypted),
+        (1, Some(passphrase)) => {
+            passphrase::open(passphrase, payload).ok_or(DatabaseError::WrongPassphrase)?
+        }
+        _ => return Err(DatabaseError::BadBackup),
+    };
+
+    bincode::deserialize(&payload).map_err(|_| DatabaseError::BadBackup)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn archive() -> Archive {
+        Archive {
+            seed: [7; 32],
+            patches: Vec::new(),
+            channels: vec![(Uuid::new_v4(), [9; 32])],
+        }
+    }
+
+    #[test]
+    fn wrapped_backup_needs_the_passphrase() {
+        let backup = encode(&archive(), Some("secret")).unwrap();
+
+        assert!(matches!(
+            decode(&backup, None),
+            Err(DatabaseError::Encrypted)
+        ));
+        assert!(matches!(
+            decode(&backup, Some("guess")),
+            Err(DatabaseError::WrongPassphrase)
+        ));
+        assert_eq!(decode(&backup, Some("secret")).unwrap().seed, [7; 32]);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut backup = encode(&archive(), None).unwrap();
+        backup[MAGIC.len()] = VERSION + 1;
+
+        assert!(matches!(
+            decode(&backup, None),
+            Err(DatabaseError::BadBackup)
+        ));
+    }
+}
@@ -1,9 +1,44 @@
+use super::sync::SyncDataId;
 use sea_orm::DbErr;
 
 #[derive(thiserror::Error, Debug)]
 pub enum DatabaseError {
     #[error(transparent)]
     DbErr(#[from] DbErr),
+    #[error("Database was created by a newer version, unknown migrations {0:?}")]
+    SchemaTooNew(Vec<String>),
+    #[error("Invite does not exist")]
+    UnknownInvite,
+    #[error("Invite was already used")]
+    InviteUsed,
+    #[error("Invite has expired")]
+    InviteExpired,
+    #[error("Attachment has {len} bytes, more than the maximum of {max}")]
+    AttachmentTooLarge { len: usize, max: usize },
+    #[error("Message has {len} bytes, more than the maximum of {max}")]
+    MessageTooLarge { len: usize, max: usize },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Database is encrypted, a passphrase is required")]
+    Encrypted,
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    #[error("Not an icechat keyfile, or of an unknown version")]
+    BadKeyfile,
+    #[error("Not an icechat backup, or of an unknown version")]
+    BadBackup,
+    #[error("Database already holds another identity")]
+    IdentityMismatch,
+    #[error("Invalid key {0}")]
+    InvalidKey(String),
+    #[error("Contact is blocked")]
+    Blocked,
+    #[error("Unknown message status {0}")]
+    UnknownStatus(i32),
+    #[error("Stored patch {0:?} is corrupted: {1}")]
+    CorruptedPatch(SyncDataId, bincode::Error),
+    #[error("Malformed sync message: {0}")]
+    MalformedMessage(#[from] bincode::Error),
 }
 impl From<sqlx::error::Error> for DatabaseError {
     fn from(value: sqlx::error::Error) -> Self {
@@ -0,0 +1,84 @@
+//! Portable container for the identity seed, see
+//! [`Database::export_identity`](super::Database::export_identity).
+//!
+//! A keyfile is `MAGIC || version || wrapped || payload`, where the payload is
+//! the 32-byte seed, or the seed sealed with a passphrase as in the `local`
+//! table when `wrapped` is 1.
+
+use super::{
+    error::{DatabaseError, DatabaseResult},
+    passphrase,
+};
+use crate::channel::Ed25519Seed;
+
+const MAGIC: &[u8] = b"ICECHATKEY";
+const VERSION: u8 = 1;
+
+pub(crate) fn encode(seed: &Ed25519Seed, passphrase: Option<&str>) -> Vec<u8> {
+    let (wrapped, payload) = match passphrase {
+        Some(passphrase) => (1, passphrase::seal(passphrase, seed.as_slice())),
+        None => (0, seed.to_vec()),
+    };
+
+    [MAGIC, &[VERSION, wrapped], &payload].concat()
+}
+
+pub(crate) fn decode(keyfile: &[u8], passphrase: Option<&str>) -> DatabaseResult<Ed25519Seed> {
+    let Some(keyfile) = keyfile.strip_prefix(MAGIC) else { return Err(DatabaseError::BadKeyfile); };
+    let [VERSION, wrapped, payload @ ..] = keyfile else { return Err(DatabaseError::BadKeyfile); };
+
+    let seed = match (wrapped, passphrase) {
+        (0, _) => payload.to_vec(),
+        (1, None) => return Err(DatabaseError::Encrypted),
+        (1, Some(passphrase)) => {
+            passphrase::open(passphrase, payload).ok_or(DatabaseError::WrongPassphrase)?
+        }
+        _ => return Err(DatabaseError::BadKeyfile),
+    };
+
+    Ok(Ed25519Seed::new(
+        seed.try_into().map_err(|_| DatabaseError::BadKeyfile)?,
+    ))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_keyfile_round_trips() {
+        let seed = Ed25519Seed::generate();
+
+        let decoded = decode(&encode(&seed, None), None).unwrap();
+
+        assert_eq!(decoded.public_key(), seed.public_key());
+    }
+
+    #[test]
+    fn wrapped_keyfile_needs_the_passphrase() {
+        let seed = Ed25519Seed::generate();
+        let keyfile = encode(&seed, Some("secret"));
+
+        assert!(matches!(
+            decode(&keyfile, None),
+            Err(DatabaseError::Encrypted)
+        ));
+        assert!(matches!(
+            decode(&keyfile, Some("guess")),
+            Err(DatabaseError::WrongPassphrase)
+        ));
+        let decoded = decode(&keyfile, Some("secret")).unwrap();
+        assert_eq!(decoded.public_key(), seed.public_key());
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut keyfile = encode(&Ed25519Seed::generate(), None);
+        keyfile[MAGIC.len()] = VERSION + 1;
+
+        assert!(matches!(
+            decode(&keyfile, None),
+            Err(DatabaseError::BadKeyfile)
+        ));
+    }
+}
@@ -0,0 +1,45 @@
+//! MIME type of the files sent with
+//! [`Database::send_file_stream`](super::Database::send_file_stream).
+
+/// Magic numbers of common files, for names without a known extension.
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+];
+
+/// Type of the file named `filename` whose payload starts with `head`, by
+/// its extension or else by its magic number.
+pub(crate) fn guess(filename: &str, head: &[u8]) -> Option<String> {
+    let mime = mime_guess::from_path(filename).first_raw().or_else(|| {
+        MAGIC
+            .iter()
+            .find(|(magic, _)| head.starts_with(magic))
+            .map(|(_, mime)| *mime)
+    });
+
+    mime.map(str::to_string)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn the_extension_decides() {
+        assert_eq!(guess("photo.PNG", b"").as_deref(), Some("image/png"));
+        assert_eq!(guess("a.zip", b"%PDF-").as_deref(), Some("application/zip"));
+    }
+
+    #[test]
+    fn files_without_a_known_extension_are_sniffed() {
+        assert_eq!(
+            guess("photo", b"\xff\xd8\xff\xe0").as_deref(),
+            Some("image/jpeg")
+        );
+        assert_eq!(guess("notes", b"plain text"), None);
+    }
+}
@@ -1,62 +1,664 @@
+mod backup;
 pub mod error;
+mod keyfile;
+mod mime;
+mod passphrase;
+pub mod payload;
 pub mod sqlite_sync;
 pub mod sync;
+mod thumbnail;
 
-use self::{error::DatabaseResult, sync::PatchSync};
-use crate::channel::{Ed25519Cert, Ed25519Seed};
+use self::{
+    error::{DatabaseError, DatabaseResult},
+    payload::{FilePayload, SpilledFile, DEFAULT_SPILL_BYTES},
+    sqlite_sync::{
+        decode_patch, remove_old_patches, remove_unused_snapshots, PatchFilter, SqliteSyncCtx,
+    },
+    sync::{PatchSync, SyncData, SyncDataId},
+};
+use crate::{
+    channel::{Channel, ConnectConfig, Ed25519Cert, Ed25519Seed},
+    pipe_sync::{SyncCounters, SyncLogContext},
+    transport::{IcepipeTransport, Transport},
+    SqliteChannel,
+};
 use entity::{
     crdt::{
-        sequence::{CrdtWritableSequence, CrdtWritableSequenceTransaction},
+        sequence::{
+            before_cursor, CrdtPosition, CrdtWritableSequence, CrdtWritableSequenceTransaction,
+        },
         writable::{CrdtWritable, CrdtWritableTransaction},
-        Author, CrdtAddOnly, CrdtInstance, CrdtOrd, CrdtTransaction,
+        Author, CrdtAddOnly, CrdtInstance, CrdtOrd, CrdtTransaction, CONFLICT_ON_CONTACT,
+        CONFLICT_ON_CONVERSATION,
+    },
+    entity::{
+        attachment, blob, blocked, channel, conflict_log, contact, conversation, device,
+        history_clear, initial_sync, invite, key_supersede, local, local_conversation_settings,
+        member, message, message_delivery, preference, receipt, snapshot,
+    },
+    patch::{
+        self,
+        attachment::{AttachmentMetaModel, BlobHash},
+        Patch,
     },
-    entity::{attachment, channel, contact, conversation, initial_sync, local, member, message},
-    patch::{self, attachment::AttachmentMetaModel, Patch},
     uuid::{SplitUuid, UuidValue},
 };
-use futures_util::future::LocalBoxFuture;
+use futures_util::{
+    future::LocalBoxFuture,
+    stream::{self, LocalBoxStream},
+    StreamExt, TryStreamExt,
+};
 use migration::MigratorTrait;
+use ring::signature::{UnparsedPublicKey, ED25519};
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DatabaseTransaction,
-    EntityTrait, ModelTrait, Order, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
-    SqlxSqliteConnector, TransactionTrait, TryIntoModel,
+    sea_query::{Expr, LikeExpr, OnConflict},
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, ConnectionTrait, DatabaseBackend,
+    DatabaseConnection, DatabaseTransaction, EntityTrait, IntoActiveModel, ModelTrait, Order,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Select, SqlxSqliteConnector, Statement,
+    TransactionTrait, TryIntoModel,
 };
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    sync::Arc,
+};
 use uuid::Uuid;
 
+/// Size of the chunks a file is split into by [`Database::send_file_stream`].
+pub const ATTACHMENT_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Longest text [`Database::send_message`] and [`Database::edit_message`]
+/// take. Its patch has to fit in a packet of the peer, see
+/// [`DEFAULT_MAX_PACKET_LEN`](crate::fragmentable::DEFAULT_MAX_PACKET_LEN).
+pub const MAX_MESSAGE_TEXT_BYTES: usize = 64 * 1024;
+
+/// Namespace of the UUIDv5 ids of control conversations, see
+/// [`Database::control_conversation_id`].
+pub const CONTROL_NAMESPACE: Uuid = uuid::uuid!("82a889db-2831-46a6-9d46-e1dd7397224d");
+
 pub struct Database {
     connection: DatabaseConnection,
     seed: Ed25519Seed,
     public: Ed25519Cert,
     user: i32,
+    retired: HashMap<i32, Ed25519Seed>,
+    relay: bool,
+    patch_filter: Option<PatchFilter>,
+    max_attachment_bytes: Option<usize>,
+    spill_bytes: usize,
+    connect_config: ConnectConfig,
+    hide_blocked: bool,
+    sync_interleave: Option<u32>,
 }
 impl Database {
+    /// Fails with [`DatabaseError::Encrypted`] if the private key was sealed
+    /// with a passphrase, see [`Database::connect_encrypted`].
     pub async fn connect(path: &str) -> DatabaseResult<Self> {
-        let connection = SqlitePoolOptions::new()
-            .min_connections(1)
-            .max_connections(1)
-            .max_lifetime(None)
-            .idle_timeout(None)
-            .connect_with(format!("sqlite://{path}?mode=rwc").parse::<SqliteConnectOptions>()?)
+        Self::connect_with(path, None, Ed25519Seed::generate()).await
+    }
+
+    /// Same as [`Database::connect`], with the private key sealed by a key
+    /// derived from `passphrase`. A database whose key is still in plaintext
+    /// is sealed on the way. Only the private key is encrypted, the rest of
+    /// the database is not.
+    pub async fn connect_encrypted(path: &str, passphrase: &str) -> DatabaseResult<Self> {
+        Self::connect_with(path, Some(passphrase), Ed25519Seed::generate()).await
+    }
+
+    /// Same as [`Database::connect`], with `seed` as the private key instead
+    /// of a random one, so that tests know who the authors are and which of
+    /// two concurrent writes wins. `seed` is ignored if the database already
+    /// has a key.
+    pub async fn connect_with_identity(path: &str, seed: Ed25519Seed) -> DatabaseResult<Self> {
+        Self::connect_with(path, None, seed).await
+    }
+
+    /// Identity of this database as a keyfile, to be given to
+    /// [`Database::import_identity`] on another device. The keyfile is
+    /// wrapped with `passphrase` if one is given.
+    pub fn export_identity(&self, passphrase: Option<&str>) -> Vec<u8> {
+        keyfile::encode(&self.seed, passphrase)
+    }
+
+    /// Creates the database at `path` with the identity of `keyfile`, see
+    /// [`Database::export_identity`]. Fails with
+    /// [`DatabaseError::IdentityMismatch`] if `path` already holds another
+    /// identity.
+    ///
+    /// The private key is sealed with `sealed_with` if one is given, as done by
+    /// [`Database::connect_encrypted`].
+    pub async fn import_identity(
+        path: &str,
+        keyfile: &[u8],
+        passphrase: Option<&str>,
+        sealed_with: Option<&str>,
+    ) -> DatabaseResult<Self> {
+        let seed = keyfile::decode(keyfile, passphrase)?;
+        let public = seed.public_key();
+
+        let database = Self::connect_with(path, sealed_with, seed).await?;
+        if database.public != public {
+            return Err(DatabaseError::IdentityMismatch);
+        }
+
+        Ok(database)
+    }
+
+    /// Writes every conversation, with its contacts, members, messages and
+    /// attachments, along with the identity and the channels, to `writer` as
+    /// an archive to be given to [`Database::restore`]. The archive is wrapped
+    /// with `passphrase` if one is given.
+    ///
+    /// Conversations are stored as the patches a new channel would be seeded
+    /// with, so the archive does not depend on the schema. Local-only state,
+    /// like stars, aliases, verified and blocked contacts or preferences, is
+    /// not part of it.
+    pub async fn backup(
+        &self,
+        mut writer: impl Write,
+        passphrase: Option<&str>,
+    ) -> DatabaseResult<()> {
+        let mut trans = self.connection.begin().await?;
+
+        let mut patches = Vec::new();
+        let mut uuids = HashMap::new();
+        for model in conversation::Entity::find().all(&trans).await? {
+            let id = model.id;
+            let conversation = Conversation::with_members(&trans, model).await?;
+            uuids.insert(id, conversation.uuid);
+
+            // Seeded as a snapshot that is rolled back with the transaction.
+            let snapshot = snapshot::ActiveModel {
+                id: ActiveValue::NotSet,
+                conversation: ActiveValue::Set(id),
+                options: ActiveValue::Set(Vec::new()),
+                sync_index: ActiveValue::Set(0),
+            }
+            .insert(&trans)
+            .await?;
+            Self::seed_snapshot(&mut trans, snapshot.id, conversation, Default::default()).await?;
+
+            for model in initial_sync::Entity::find()
+                .filter(initial_sync::Column::Snapshot.eq(snapshot.id))
+                .order_by(initial_sync::Column::Id, Order::Asc)
+                .all(&trans)
+                .await?
+            {
+                let id = SyncDataId::InitialSync(model.id);
+                patches.push(decode_patch(id, &model.payload)?);
+            }
+        }
+
+        let channels = channel::Entity::find()
+            .filter(channel::Column::LocalKey.is_null())
+            .find_also_related(entity::entity::key::Entity)
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|(channel, peer)| {
+                let peer = peer.unwrap().public.try_into().expect("Corrupted database");
+                (uuids[&channel.conversation], peer)
+            })
+            .collect();
+
+        trans.rollback().await?;
+
+        let archive = backup::Archive {
+            seed: self.seed.as_slice().try_into().unwrap(),
+            patches,
+            channels,
+        };
+        writer.write_all(&backup::encode(&archive, passphrase)?)?;
+
+        Ok(())
+    }
+
+    /// Creates the database at `path` from an archive written by
+    /// [`Database::backup`], replaying its patches and creating its channels
+    /// again. Fails with [`DatabaseError::IdentityMismatch`] if `path` already
+    /// holds another identity.
+    ///
+    /// The private key is sealed with `sealed_with` if one is given, as done by
+    /// [`Database::connect_encrypted`].
+    pub async fn restore(
+        path: &str,
+        mut reader: impl Read,
+        passphrase: Option<&str>,
+        sealed_with: Option<&str>,
+    ) -> DatabaseResult<Self> {
+        let mut archive = Vec::new();
+        reader.read_to_end(&mut archive)?;
+        let archive = backup::decode(&archive, passphrase)?;
+
+        let seed = Ed25519Seed::new(archive.seed);
+        let public = seed.public_key();
+        let database = Self::connect_with(path, sealed_with, seed).await?;
+        if database.public != public {
+            return Err(DatabaseError::IdentityMismatch);
+        }
+
+        let mut trans = database.connection.begin().await?;
+        for patch in archive.patches {
+            let Some(patch) = patch.merge(&mut trans).await else { continue; };
+            Self::save_patch_for_sync(&trans, patch).await?;
+        }
+        trans.commit().await?;
+
+        for (conversation, peer) in archive.channels {
+            let Some(conversation) = database.get_conversation(conversation).await? else { return Err(DatabaseError::BadBackup); };
+            database
+                .create_channel(conversation, Ed25519Cert(peer))
+                .await?;
+        }
+
+        Ok(database)
+    }
+
+    /// Messages of `conversation` as a JSON array, in conversation order, for
+    /// archival and debugging. Attachments are referred to by uuid and
+    /// filename, their payload is left out.
+    pub async fn export_conversation_json(
+        &self,
+        conversation: &Conversation,
+    ) -> DatabaseResult<String> {
+        let length = conversation.length(self).await?;
+        let messages = conversation
+            .get_messages_range(self, 0, length, MessageOrder::CausalSequence)
+            .await?;
+
+        let mut r = Vec::new();
+        for message in messages {
+            let content = match message.content {
+                Content::Text(text) => ExportedContent::Text { text },
+                Content::Attachment(filename, id, _) => {
+                    let attachment = AttachmentMetaModel::find_by_id(id)
+                        .one(&self.connection)
+                        .await?
+                        .unwrap();
+                    ExportedContent::Attachment {
+                        uuid: attachment.get_uuid().into(),
+                        filename,
+                    }
+                }
+                Content::Deleted => ExportedContent::Deleted,
+                Content::Blocked => ExportedContent::Blocked,
+            };
+
+            r.push(ExportedMessage {
+                uuid: message.uuid,
+                from: message.from.key.hex(),
+                sequence: message.crdt.sequence,
+                created_at: message.created_at,
+                status: message.status,
+                edited: message.meta.edited,
+                reply_to: message.meta.reply_to,
+                content,
+            });
+        }
+
+        Ok(serde_json::to_string(&r).expect("Messages serialize to JSON"))
+    }
+
+    /// Replaces the identity of this database with a newly generated one, for
+    /// when the private key leaked. Returns the new cert.
+    ///
+    /// The new key becomes a member of every conversation the user is part
+    /// of, next to a [`patch::KeySupersede`] signed by the old key. The old key
+    /// stays a member, so the history it authored is kept untouched. An
+    /// [`Author`] only breaks ties between writes of the same generation, so
+    /// writes of the old and the new author keep merging the same way on
+    /// every peer.
+    ///
+    /// Peers only trust the old cert, so the existing channels keep
+    /// authenticating with the old key in order to deliver these patches.
+    /// Channels to the same peers using the new key are created alongside
+    /// them. Each peer, on receiving the [`patch::KeySupersede`], replaces its
+    /// channel to the old cert by one to the new cert, see
+    /// [`Database::migrate_superseded_channels`], and both sides derive the
+    /// same channel name from the new cert. The old channels are dropped once
+    /// the new ones are in sync, and the old key once no channel uses it.
+    ///
+    /// If the private key is sealed, `passphrase` is required and seals the
+    /// new key as well.
+    pub async fn rotate_identity(
+        &mut self,
+        passphrase: Option<&str>,
+    ) -> DatabaseResult<Ed25519Cert> {
+        let mut trans = self.connection.begin().await?;
+
+        let local = local::Entity::find_by_id(self.user)
+            .one(&trans)
+            .await?
+            .unwrap();
+        let passphrase = match passphrase::is_sealed(&local.private) {
+            true => {
+                Self::open_private(&local.private, passphrase)?;
+                passphrase
+            }
+            false => None,
+        };
+
+        let seed = Ed25519Seed::generate();
+        let public = seed.public_key();
+        let old_key = self.patch_key();
+        let new_key = patch::Key::new_exact(&public.0);
+        let (new, _) = patch::Contact::get_or_create(new_key.clone(), &trans).await;
+
+        let mut retired: local::ActiveModel = local.into();
+        retired.retired = ActiveValue::Set(true);
+        retired.update(&trans).await?;
+        local::ActiveModel {
+            key: ActiveValue::Set(new.id),
+            private: ActiveValue::Set(match passphrase {
+                Some(passphrase) => passphrase::seal(passphrase, seed.as_slice()),
+                None => seed.to_vec(),
+            }),
+            retired: ActiveValue::Set(false),
+        }
+        .insert(&trans)
+        .await?;
+
+        let name = contact::Entity::find_by_id(self.user)
+            .one(&trans)
+            .await?
+            .map(|contact| contact.name)
+            .unwrap_or_default();
+        self.set_new_patch(
+            &mut trans,
+            patch::Contact {
+                key: new_key.clone(),
+                name,
+                crdt: Default::default(),
+            },
+        )
+        .await?;
+
+        let signature = self
+            .seed
+            .key_pair()
+            .sign(&patch::KeySupersede::signed_payload(&old_key, &new_key))
+            .as_ref()
+            .to_vec();
+        let memberships = member::Entity::find()
+            .filter(member::Column::Contact.eq(self.user))
+            .filter(member::Column::Removed.eq(false))
+            .find_also_related(conversation::Entity)
+            .all(&trans)
+            .await?;
+        for (_, conversation) in memberships {
+            let conversation = Uuid::from(conversation.expect("Corrupted database").get_uuid());
+            self.add_only_new_patch(
+                &mut trans,
+                patch::Member {
+                    key: new_key.clone(),
+                    conversation,
+                    crdt: Default::default(),
+                },
+            )
+            .await?;
+            self.add_only_new_patch(
+                &mut trans,
+                patch::KeySupersede {
+                    old: old_key.clone(),
+                    new: new_key.clone(),
+                    conversation,
+                    signature: signature.clone(),
+                    crdt: Default::default(),
+                },
+            )
+            .await?;
+        }
+
+        let channels = channel::Entity::find()
+            .filter(channel::Column::LocalKey.is_null())
+            .find_also_related(entity::entity::key::Entity)
+            .all(&trans)
+            .await?;
+        for (channel, peer) in channels {
+            let peer = Ed25519Cert(
+                peer.expect("Corrupted database")
+                    .public
+                    .as_slice()
+                    .try_into()
+                    .expect("Corrupted database"),
+            );
+            let conversation = conversation::Entity::find_by_id(channel.conversation)
+                .one(&trans)
+                .await?
+                .expect("Corrupted database");
+            let conversation = Conversation::with_members(&trans, conversation).await?;
+
+            let mut retired: channel::ActiveModel = channel.into();
+            retired.local_key = ActiveValue::Set(Some(self.user));
+            retired.update(&trans).await?;
+
+            self.trans_create_channel(&mut trans, conversation, peer, Default::default())
+                .await?;
+        }
+
+        trans.commit().await?;
+
+        let old_seed = std::mem::replace(&mut self.seed, seed);
+        self.retired.insert(self.user, old_seed);
+        self.public = public;
+        self.user = new.id;
+
+        Ok(public)
+    }
+
+    /// Whether the private key of the existing database at `path` is sealed
+    /// with a passphrase.
+    pub async fn is_encrypted(path: &str) -> DatabaseResult<bool> {
+        let connection = Self::open(path, "ro").await?;
+        let local = local::Entity::find()
+            .filter(local::Column::Retired.eq(false))
+            .one(&connection)
             .await?;
 
-        let connection = SqlxSqliteConnector::from_sqlx_sqlite_pool(connection);
+        Ok(local.is_some_and(|local| passphrase::is_sealed(&local.private)))
+    }
+
+    async fn connect_with(
+        path: &str,
+        passphrase: Option<&str>,
+        seed: Ed25519Seed,
+    ) -> DatabaseResult<Self> {
+        let connection = Self::open(path, "rwc").await?;
+        Self::check_schema(&connection).await?;
         migration::Migrator::up(&connection, None).await?;
-        Self::first_time(&connection).await?;
+        Self::seed_emptied_snapshots(&connection).await?;
+        Self::first_time(&connection, seed, passphrase).await?;
+        if let Some(passphrase) = passphrase {
+            Self::seal_private_key(&connection, passphrase).await?;
+        }
 
-        let (seed, user) = Self::fetch_user(&connection).await?;
+        let (seed, user) = Self::fetch_user(&connection, passphrase).await?;
         let public = seed.public_key();
+        let retired = Self::fetch_retired(&connection, passphrase).await?;
 
         Ok(Database {
             connection,
             seed,
             public,
             user,
+            retired,
+            relay: false,
+            patch_filter: None,
+            max_attachment_bytes: None,
+            spill_bytes: DEFAULT_SPILL_BYTES,
+            connect_config: Default::default(),
+            hide_blocked: false,
+            sync_interleave: Some(sync::DEFAULT_INTERLEAVE),
         })
     }
 
-    async fn first_time(conn: &DatabaseConnection) -> DatabaseResult<()> {
+    async fn open(path: &str, mode: &str) -> DatabaseResult<DatabaseConnection> {
+        let connection = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .max_lifetime(None)
+            .idle_timeout(None)
+            .connect_with(format!("sqlite://{path}?mode={mode}").parse::<SqliteConnectOptions>()?)
+            .await?;
+
+        Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(connection))
+    }
+
+    /// Creates a new database at `dst` by replaying every patch still present
+    /// in the `initial_sync` and `sync` tables of the database at `src`, which
+    /// is only read. The identity of `src` is kept, its private key is opened
+    /// and sealed again with `passphrase`, the same for both databases.
+    ///
+    /// Patches are dropped from `sync` once every channel acknowledged them, so
+    /// whatever was pruned that way is not recovered. Channels are local state
+    /// and are not rebuilt either.
+    pub async fn rebuild_from_patch_log(
+        src: &str,
+        dst: &str,
+        passphrase: Option<&str>,
+    ) -> DatabaseResult<()> {
+        let src = Self::open(src, "ro").await?;
+        let (seed, _) = Self::fetch_user(&src, passphrase).await?;
+
+        let dst = Self::open(dst, "rwc").await?;
+        Self::check_schema(&dst).await?;
+        migration::Migrator::up(&dst, None).await?;
+        Self::first_time(&dst, seed, passphrase).await?;
+
+        let mut trans = dst.begin().await?;
+        Self::replay_patch_log(&src, &mut trans).await?;
+        trans.commit().await?;
+
+        Ok(())
+    }
+
+    /// Merges every patch still present in the log of the database at
+    /// `other_path`, which is only read, into this one. Meant to combine the
+    /// databases of two devices that diverged while offline.
+    ///
+    /// The same limitations of [`Database::rebuild_from_patch_log`] apply,
+    /// patches already pruned from `other_path` are not merged.
+    pub async fn merge_from(&self, other_path: &str) -> DatabaseResult<MergeReport> {
+        let other = Self::open(other_path, "ro").await?;
+
+        let mut trans = self.connection.begin().await?;
+        let report = Self::replay_patch_log(&other, &mut trans).await?;
+        trans.commit().await?;
+
+        Ok(report)
+    }
+
+    async fn replay_patch_log(
+        src: &DatabaseConnection,
+        trans: &mut DatabaseTransaction,
+    ) -> DatabaseResult<MergeReport> {
+        let initial_patches = initial_sync::Entity::find()
+            .order_by(initial_sync::Column::Id, Order::Asc)
+            .all(src)
+            .await?
+            .into_iter()
+            .map(|model| (SyncDataId::InitialSync(model.id), model.payload));
+        let patches = entity::entity::sync::Entity::find()
+            .order_by(entity::entity::sync::Column::Id, Order::Asc)
+            .all(src)
+            .await?
+            .into_iter()
+            .map(|model| (SyncDataId::Global(model.id), model.payload));
+
+        let mut report = MergeReport::default();
+        for (id, payload) in initial_patches.chain(patches) {
+            report.read += 1;
+            let patch = match decode_patch(id, &payload) {
+                Ok(patch) => patch,
+                Err(e) => {
+                    log::warn!("Skipping {e}");
+                    continue;
+                }
+            };
+            let Some(patch) = patch.merge(trans).await else { continue; };
+
+            report.merged += 1;
+            Self::save_patch_for_sync(trans, patch).await?;
+        }
+
+        Ok(report)
+    }
+
+    /// The last `limit` patches of `conversation` still in the sync log, oldest
+    /// first. Patches that can not be read are logged and skipped, as nothing
+    /// tells which conversation they belong to.
+    pub async fn patch_log(
+        &self,
+        conversation: &Conversation,
+        limit: usize,
+    ) -> DatabaseResult<Vec<SyncData>> {
+        const PAGE: u64 = 256;
+
+        let mut r = Vec::new();
+        let mut offset = 0;
+        while r.len() < limit {
+            let models = entity::entity::sync::Entity::find()
+                .order_by(entity::entity::sync::Column::Id, Order::Desc)
+                .offset(offset)
+                .limit(PAGE)
+                .all(&self.connection)
+                .await?;
+            if models.is_empty() {
+                break;
+            }
+            offset += models.len() as u64;
+
+            for model in models {
+                let id = SyncDataId::Global(model.id);
+                let payload = match decode_patch(id, &model.payload) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        log::warn!("Skipping {e}");
+                        continue;
+                    }
+                };
+                let data = SyncData { id, payload };
+                if data.conversation() == Some(conversation.uuid) && r.len() < limit {
+                    r.push(data);
+                }
+            }
+        }
+        r.reverse();
+
+        Ok(r)
+    }
+
+    /// Refuses databases that had migrations applied by a newer build.
+    async fn check_schema(conn: &DatabaseConnection) -> DatabaseResult<()> {
+        let known = migration::Migrator::migrations()
+            .iter()
+            .map(|migration| migration.name().to_string())
+            .collect::<HashSet<_>>();
+
+        let unknown = migration::Migrator::get_migration_models(conn)
+            .await?
+            .into_iter()
+            .map(|model| model.version)
+            .filter(|version| !known.contains(version))
+            .collect::<Vec<_>>();
+
+        if !unknown.is_empty() {
+            return Err(DatabaseError::SchemaTooNew(unknown));
+        }
+
+        Ok(())
+    }
+
+    /// Stores `pvt_key` as the identity of a new database, sealed with
+    /// `passphrase` if one is given, so that it never touches the disk in
+    /// plaintext.
+    async fn first_time(
+        conn: &DatabaseConnection,
+        pvt_key: Ed25519Seed,
+        passphrase: Option<&str>,
+    ) -> DatabaseResult<()> {
         let trans = conn.begin().await?;
 
         let existent = local::Entity::find().count(&trans).await?;
@@ -64,15 +666,19 @@ impl Database {
             return Ok(());
         }
 
-        let pvt_key = Ed25519Seed::generate();
         let pub_key = pvt_key.public_key();
 
         let (pub_key, _) =
             patch::Contact::get_or_create(patch::Key::new_exact(&pub_key.0), &trans).await;
 
+        let private = match passphrase {
+            Some(passphrase) => passphrase::seal(passphrase, pvt_key.as_slice()),
+            None => pvt_key.to_vec(),
+        };
         local::ActiveModel {
             key: ActiveValue::Set(pub_key.id),
-            private: ActiveValue::Set(pvt_key.to_vec()),
+            private: ActiveValue::Set(private),
+            retired: ActiveValue::Set(false),
         }
         .insert(&trans)
         .await?;
@@ -82,13 +688,69 @@ impl Database {
         Ok(())
     }
 
-    async fn fetch_user(conn: &DatabaseConnection) -> DatabaseResult<(Ed25519Seed, i32)> {
+    /// Seals the current private key and the retired ones still kept, see
+    /// [`Database::rotate_identity`], of a database created without a
+    /// passphrase.
+    async fn seal_private_key(conn: &DatabaseConnection, passphrase: &str) -> DatabaseResult<()> {
+        let trans = conn.begin().await?;
+        for local in local::Entity::find().all(&trans).await? {
+            if passphrase::is_sealed(&local.private) {
+                continue;
+            }
+
+            let sealed = passphrase::seal(passphrase, &local.private);
+            let mut local: local::ActiveModel = local.into();
+            local.private = ActiveValue::Set(sealed);
+            local.update(&trans).await?;
+        }
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    async fn fetch_user(
+        conn: &DatabaseConnection,
+        passphrase: Option<&str>,
+    ) -> DatabaseResult<(Ed25519Seed, i32)> {
         let trans = conn.begin().await?;
-        let local = local::Entity::find().one(&trans).await?.unwrap();
+        let local = local::Entity::find()
+            .filter(local::Column::Retired.eq(false))
+            .one(&trans)
+            .await?
+            .unwrap();
+
+        Ok((Self::open_private(&local.private, passphrase)?, local.key))
+    }
+
+    /// Private keys replaced by [`Database::rotate_identity`] that are still
+    /// used by some channel, by the id of their key.
+    async fn fetch_retired(
+        conn: &DatabaseConnection,
+        passphrase: Option<&str>,
+    ) -> DatabaseResult<HashMap<i32, Ed25519Seed>> {
+        let mut r = HashMap::new();
+        for local in local::Entity::find()
+            .filter(local::Column::Retired.eq(true))
+            .all(conn)
+            .await?
+        {
+            r.insert(local.key, Self::open_private(&local.private, passphrase)?);
+        }
+
+        Ok(r)
+    }
+
+    fn open_private(private: &[u8], passphrase: Option<&str>) -> DatabaseResult<Ed25519Seed> {
+        let private = match (passphrase::is_sealed(private), passphrase) {
+            (false, _) => private.to_vec(),
+            (true, None) => return Err(DatabaseError::Encrypted),
+            (true, Some(passphrase)) => {
+                passphrase::open(passphrase, private).ok_or(DatabaseError::WrongPassphrase)?
+            }
+        };
 
-        Ok((
-            Ed25519Seed::new(local.private.try_into().expect("Corrupted database")),
-            local.key,
+        Ok(Ed25519Seed::new(
+            private.try_into().expect("Corrupted database"),
         ))
     }
 
@@ -96,6 +758,59 @@ impl Database {
         Ok(self.connection.begin().await?)
     }
 
+    /// In relay mode new channels are not seeded with the conversation, peers
+    /// only get the patches received after their channel was created. Those
+    /// patches are forwarded without being merged, channels started
+    /// afterwards leave the conversation tables as they are.
+    pub fn set_relay(&mut self, relay: bool) {
+        self.relay = relay;
+    }
+
+    /// Patches received by channels started afterwards go through `filter`,
+    /// see [`PatchFilter`].
+    pub fn set_patch_filter(&mut self, filter: Option<PatchFilter>) {
+        self.patch_filter = filter;
+    }
+
+    /// Channels started afterwards send a patch of the global log after each
+    /// `interleave` patches of their initial sync, see
+    /// [`PatchSync::with_interleave`]. `None` sends the whole initial sync
+    /// first.
+    pub fn set_sync_interleave(&mut self, interleave: Option<u32>) {
+        self.sync_interleave = interleave;
+    }
+
+    pub fn max_attachment_bytes(&self) -> Option<usize> {
+        self.max_attachment_bytes
+    }
+
+    /// Files larger than `max` are refused by [`Database::send_file`], `None`
+    /// allows any size.
+    pub fn set_max_attachment_bytes(&mut self, max: Option<usize>) {
+        self.max_attachment_bytes = max;
+    }
+
+    /// Files longer than `bytes` are reassembled in a temporary file by
+    /// [`Database::fetch_file_payload`], instead of memory.
+    pub fn set_spill_bytes(&mut self, bytes: usize) {
+        self.spill_bytes = bytes;
+    }
+
+    pub fn connect_config(&self) -> &ConnectConfig {
+        &self.connect_config
+    }
+
+    /// Servers used by the channels built by [`Database::channel`].
+    pub fn set_connect_config(&mut self, config: ConnectConfig) {
+        self.connect_config = config;
+    }
+
+    /// Whether messages from blocked contacts are shown as
+    /// [`Content::Blocked`], see [`Database::block_contact`].
+    pub fn set_hide_blocked(&mut self, hide: bool) {
+        self.hide_blocked = hide;
+    }
+
     pub fn private_key(&self) -> &Ed25519Seed {
         &self.seed
     }
@@ -142,6 +857,50 @@ impl Database {
         Ok(())
     }
 
+    /// Records whether the local user compared the
+    /// [`Ed25519Cert::safety_number`] with `cert`. Kept locally, peers never
+    /// learn about it.
+    pub async fn set_verified(&self, cert: &Ed25519Cert, verified: bool) -> DatabaseResult<()> {
+        let trans = self.connection.begin().await?;
+
+        let (_, contact) =
+            patch::Contact::get_or_create(patch::Key::new_exact(&cert.0), &trans).await;
+        contact::ActiveModel {
+            verified: ActiveValue::Set(verified),
+            ..contact.into_active_model()
+        }
+        .update(&trans)
+        .await?;
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    /// Shows `cert` as `alias` to the local user only, see
+    /// [`Contact::display_name`]. `None` goes back to the synced name.
+    pub async fn set_alias(&self, cert: &Ed25519Cert, alias: Option<String>) -> DatabaseResult<()> {
+        let trans = self.connection.begin().await?;
+
+        let (_, contact) =
+            patch::Contact::get_or_create(patch::Key::new_exact(&cert.0), &trans).await;
+        contact::ActiveModel {
+            alias: ActiveValue::Set(alias),
+            ..contact.into_active_model()
+        }
+        .update(&trans)
+        .await?;
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    pub async fn is_verified(&self, cert: &Ed25519Cert) -> DatabaseResult<bool> {
+        Ok(self
+            .get_contact(cert)
+            .await?
+            .is_some_and(|contact| contact.verified))
+    }
+
     pub async fn get_conversation(&self, id: Uuid) -> DatabaseResult<Option<Conversation>> {
         let trans = self.connection.begin().await?;
 
@@ -185,6 +944,7 @@ impl Database {
         Ok(())
     }
 
+    /// Every conversation, archived or not.
     pub async fn list_conversation(&self) -> DatabaseResult<Vec<Conversation>> {
         let trans = self.connection.begin().await?;
 
@@ -197,35 +957,305 @@ impl Database {
         Ok(r)
     }
 
-    pub async fn create_conversation(&self, title: Option<String>) -> DatabaseResult<Conversation> {
-        let mut trans = self.connection.begin().await?;
-        let id = Uuid::new_v4();
+    /// Same as [`Database::list_conversation`] without the archived
+    /// conversations, as shown by default.
+    pub async fn list_unarchived(&self) -> DatabaseResult<Vec<Conversation>> {
+        self.list_archived_or_not(false).await
+    }
 
-        let conversation = patch::Conversation {
-            id,
-            title,
-            crdt: Default::default(),
-        };
+    /// Conversations archived with [`Database::set_archived`].
+    pub async fn list_archived(&self) -> DatabaseResult<Vec<Conversation>> {
+        self.list_archived_or_not(true).await
+    }
 
-        trans.set(self.author(), conversation).await;
-        let conversation = Self::trans_get_conversation(&trans, id).await?.unwrap();
+    async fn list_archived_or_not(&self, archived: bool) -> DatabaseResult<Vec<Conversation>> {
+        let trans = self.connection.begin().await?;
 
-        member::ActiveModel {
-            contact: ActiveValue::Set(self.user),
-            conversation: ActiveValue::Set(conversation.id),
-            crdt_author: ActiveValue::Set(self.author().0),
+        let mut r = Vec::new();
+        for (conversation, settings) in conversation::Entity::find()
+            .find_also_related(local_conversation_settings::Entity)
+            .all(&trans)
+            .await?
+        {
+            if settings.is_some_and(|settings| settings.archived) == archived {
+                r.push(Conversation::with_members(&trans, conversation).await?);
+            }
         }
-        .insert(&trans)
+
+        Ok(r)
+    }
+
+    /// Every message of every conversation, grouped by conversation in the
+    /// order they were created and each in conversation order. Read in one
+    /// transaction, so that it is a consistent snapshot, as for a global search
+    /// index or an export.
+    pub async fn all_messages(&self) -> DatabaseResult<Vec<Message>> {
+        let trans = self.connection.begin().await?;
+
+        let conversations = conversation::Entity::find()
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|conversation| (conversation.id, conversation.get_uuid().into()))
+            .collect::<HashMap<i32, Uuid>>();
+
+        let query = message::Entity::find().order_by(message::Column::Conversation, Order::Asc);
+        let models = MessageOrder::CausalSequence
+            .sort(query, Order::Asc)
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|model| {
+                let conversation = conversations[&model.conversation];
+                (model, conversation)
+            })
+            .collect();
+
+        self.hydrate(&trans, models).await
+    }
+
+    /// Up to `limit` writes that were overwritten by a concurrent write from
+    /// another peer while merging, latest first. Only titles of conversations
+    /// and names of contacts are recorded.
+    pub async fn recent_conflicts(&self, limit: usize) -> DatabaseResult<Vec<Conflict>> {
+        Ok(conflict_log::Entity::find()
+            .order_by(conflict_log::Column::Id, Order::Desc)
+            .limit(limit as u64)
+            .all(&self.connection)
+            .await?
+            .into_iter()
+            .filter_map(|model| {
+                let id = model.id;
+                let conflict = Conflict::from_model(model);
+                if conflict.is_none() {
+                    log::warn!("Skipping unreadable conflict #{id}");
+                }
+                conflict
+            })
+            .collect())
+    }
+
+    /// Every conversation along with its last message that is not deleted and
+    /// how many messages from others were not read yet, as shown by a chat
+    /// list.
+    pub async fn conversation_previews(&self) -> DatabaseResult<Vec<ConversationPreview>> {
+        let trans = self.connection.begin().await?;
+
+        let contacts = contact::Entity::find()
+            .find_also_related(entity::entity::key::Entity)
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|(contact, key)| (contact.key, Contact::from((key.unwrap(), contact))))
+            .collect::<HashMap<_, _>>();
+
+        let mut members = HashMap::<_, Vec<_>>::new();
+        for member in member::Entity::find()
+            .filter(member::Column::Removed.eq(false))
+            .all(&trans)
+            .await?
+        {
+            let contact = contacts[&member.contact].clone();
+            members
+                .entry(member.conversation)
+                .or_default()
+                .push(contact);
+        }
+
+        let conversations = conversation::Entity::find().all(&trans).await?;
+        let uuids = conversations
+            .iter()
+            .map(|conversation| (conversation.id, conversation.get_uuid().into()))
+            .collect::<HashMap<i32, Uuid>>();
+
+        let last_messages = message::Entity::find()
+            .from_raw_sql(Statement::from_string(
+                DatabaseBackend::Sqlite,
+                "SELECT m.* FROM message AS m WHERE m.deleted = 0 AND NOT EXISTS (
+                    SELECT 1 FROM message AS n
+                    WHERE n.conversation = m.conversation AND n.deleted = 0 AND
+                        (n.crdt_sequence, n.crdt_author, n.uuid0, n.uuid1, n.uuid2, n.uuid3) >
+                        (m.crdt_sequence, m.crdt_author, m.uuid0, m.uuid1, m.uuid2, m.uuid3)
+                );"
+                .to_string(),
+            ))
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|model| {
+                let conversation = uuids[&model.conversation];
+                (model, conversation)
+            })
+            .collect();
+        let mut last_messages = self
+            .hydrate(&trans, last_messages)
+            .await?
+            .into_iter()
+            .map(|message| (message.conversation, message))
+            .collect::<HashMap<_, _>>();
+
+        let unread = self.trans_unread_counts(&trans).await?;
+
+        let mut r = Vec::new();
+        for model in conversations {
+            let id = model.id;
+            let conversation = Conversation {
+                uuid: model.get_uuid().into(),
+                title: model.title,
+                crdt: CrdtWritable {
+                    generation: model.crdt_generation,
+                    author: Author(model.crdt_author),
+                },
+                members: members.remove(&id).unwrap_or_default(),
+            };
+            let last_message = last_messages.remove(&conversation.uuid);
+
+            r.push(ConversationPreview {
+                conversation,
+                last_message,
+                unread: unread.get(&id).copied().unwrap_or_default(),
+            });
+        }
+
+        Ok(r)
+    }
+
+    /// How many messages from others in `conversation` are still
+    /// [`MessageStatus::Sent`], as in [`Database::new_messages`]. Deleted
+    /// messages are not counted.
+    pub async fn unread_count(&self, conversation: &Conversation) -> DatabaseResult<usize> {
+        let trans = self.connection.begin().await?;
+        let Some(id) = conversation.row_id(&trans).await? else { return Ok(0); };
+
+        let count = message::Entity::find()
+            .filter(message::Column::Conversation.eq(id))
+            .filter(message::Column::From.ne(self.user))
+            .filter(message::Column::Status.eq(i32::from(MessageStatus::Sent)))
+            .filter(message::Column::Deleted.eq(false))
+            .count(&trans)
+            .await?;
+
+        Ok(count as usize)
+    }
+
+    /// Same as [`Database::unread_count`], for every conversation at once.
+    pub async fn unread_counts(&self) -> DatabaseResult<HashMap<Uuid, usize>> {
+        let trans = self.connection.begin().await?;
+
+        let unread = self.trans_unread_counts(&trans).await?;
+
+        Ok(conversation::Entity::find()
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|model| {
+                let count = unread.get(&model.id).copied().unwrap_or_default();
+                (model.get_uuid().into(), count)
+            })
+            .collect())
+    }
+
+    /// Unread messages by conversation row id, conversations without any are
+    /// left out.
+    async fn trans_unread_counts(
+        &self,
+        trans: &DatabaseTransaction,
+    ) -> DatabaseResult<HashMap<i32, usize>> {
+        let mut unread = HashMap::new();
+        for row in trans
+            .query_all(Statement::from_sql_and_values(
+                DatabaseBackend::Sqlite,
+                "SELECT conversation, COUNT(*) AS unread FROM message
+                    WHERE \"from\" != ? AND status = ? AND deleted = 0 GROUP BY conversation;",
+                [self.user.into(), i32::from(MessageStatus::Sent).into()],
+            ))
+            .await?
+        {
+            let conversation: i32 = row.try_get("", "conversation")?;
+            let count: i64 = row.try_get("", "unread")?;
+            unread.insert(conversation, count as usize);
+        }
+
+        Ok(unread)
+    }
+
+    /// Creates a new conversation with the user as its only member.
+    ///
+    /// The conversation and the membership are also logged as patches to
+    /// sync, like any other change, and so are sent over the channels that
+    /// are connected when they are created, not only seeded to new channels.
+    /// This is what lets [`Database::rebuild_from_patch_log`] recreate the
+    /// conversation.
+    pub async fn create_conversation(&self, title: Option<String>) -> DatabaseResult<Conversation> {
+        self.create_conversation_with_id(Uuid::new_v4(), title)
+            .await
+    }
+
+    /// Creates the conversation `id`, with the user as a member, so that nodes
+    /// agreeing on `id` beforehand end up in the same conversation. If it
+    /// already exists it is returned as is, only the membership is added when
+    /// missing, so calling it again changes nothing.
+    pub async fn create_conversation_with_id(
+        &self,
+        id: Uuid,
+        title: Option<String>,
+    ) -> DatabaseResult<Conversation> {
+        let mut trans = self.connection.begin().await?;
+
+        if Self::trans_get_conversation(&trans, id).await?.is_none() {
+            self.set_new_patch(
+                &mut trans,
+                patch::Conversation {
+                    id,
+                    title,
+                    crdt: Default::default(),
+                },
+            )
+            .await?;
+        }
+        self.add_only_new_patch(
+            &mut trans,
+            patch::Member {
+                key: self.patch_key(),
+                conversation: id,
+                crdt: Default::default(),
+            },
+        )
         .await?;
 
         let conversation = Self::trans_get_conversation(&trans, id).await?.unwrap();
+        self.trans_create_device_channels(&mut trans, &conversation)
+            .await?;
         trans.commit().await?;
 
         Ok(conversation)
     }
 
+    /// Id of the control conversation of the server with key `cert`, the
+    /// UUIDv5 of the key in [`CONTROL_NAMESPACE`]. Both the server and its
+    /// control user derive it, so neither has to tell the other.
+    pub fn control_conversation_id(cert: &Ed25519Cert) -> Uuid {
+        Uuid::new_v5(&CONTROL_NAMESPACE, &cert.0)
+    }
+
+    /// Control conversation of the server with key `cert`, created with the
+    /// user as a member if missing. A conversation under the id servers
+    /// derived before [`Database::control_conversation_id`] is kept instead.
+    pub async fn control_conversation(&self, cert: &Ed25519Cert) -> DatabaseResult<Conversation> {
+        let mut legacy = [0; 16];
+        legacy.iter_mut().enumerate().for_each(|(i, legacy)| {
+            *legacy = cert.0[i] ^ cert.0[i * 2];
+        });
+        if let Some(conversation) = self.get_conversation(Uuid::from_bytes(legacy)).await? {
+            return Ok(conversation);
+        }
+
+        self.create_conversation_with_id(Self::control_conversation_id(cert), None)
+            .await
+    }
+
     pub async fn join_conversation(&self, uuid: Uuid) -> DatabaseResult<Conversation> {
-        let trans = self.connection.begin().await?;
+        let mut trans = self.connection.begin().await?;
 
         let existent = Self::trans_get_conversation(&trans, uuid).await?;
         if let Some(existent) = existent {
@@ -242,12 +1272,14 @@ impl Database {
             title: ActiveValue::Set(Default::default()),
             crdt_generation: ActiveValue::Set(Default::default()),
             crdt_author: ActiveValue::Set(Default::default()),
+            clock: ActiveValue::Set(None),
         }
         .save(&trans)
         .await?;
         let conversation = conversation.try_into_model().unwrap();
 
         let r = Conversation::with_members(&trans, conversation).await?;
+        self.trans_create_device_channels(&mut trans, &r).await?;
 
         trans.commit().await?;
         Ok(r)
@@ -263,30 +1295,33 @@ impl Database {
             .filter(message::Column::From.ne(self.user))
             .filter(message::Column::Status.eq(0));
         let models = match conversation {
-            Some(conversation) => models.filter(message::Column::Conversation.eq(conversation.id)),
+            Some(conversation) => {
+                let id = conversation.row_id(&trans).await?;
+                let Some(id) = id else { return Ok(Default::default()); };
+                models.filter(message::Column::Conversation.eq(id))
+            }
             None => models,
         };
         let models = models
             .find_also_related(conversation::Entity)
             .all(&trans)
-            .await?;
-
-        let mut r = Vec::new();
-        for model in models {
-            let (message, conversation) = model;
-            let conversation = conversation.unwrap();
-
-            r.push(Message::from_model(&trans, message, conversation.get_uuid().into()).await?);
-        }
+            .await?
+            .into_iter()
+            .map(|(message, conversation)| (message, conversation.unwrap().get_uuid().into()))
+            .collect();
 
-        Ok(r)
+        self.hydrate(&trans, models).await
     }
 
+    /// Sends `text` to the conversation, as a reply to the message with uuid
+    /// `reply_to` if given.
     pub async fn send_message(
         &self,
         conversation: Conversation,
         text: String,
-    ) -> DatabaseResult<()> {
+        reply_to: Option<Uuid>,
+    ) -> DatabaseResult<Message> {
+        Self::check_text_len(&text)?;
         let mut trans = self.connection.begin().await?;
         let id = Uuid::new_v4();
 
@@ -298,12 +1333,29 @@ impl Database {
                 conversation: conversation.uuid,
                 text,
                 attachment: None,
+                mime: None,
+                size: None,
+                thumbnail: None,
+                reply_to,
+                created_at: sync::system_clock(),
                 crdt: Default::default(),
             },
         )
         .await?;
+        Self::mark_sent(&trans, id).await?;
+        let message = Message::find(&trans, id, conversation.uuid).await?;
 
         trans.commit().await?;
+        Ok(message.expect("Message was just inserted"))
+    }
+
+    /// Refuses texts over [`MAX_MESSAGE_TEXT_BYTES`].
+    fn check_text_len(text: &str) -> DatabaseResult<()> {
+        let (len, max) = (text.len(), MAX_MESSAGE_TEXT_BYTES);
+        if len > max {
+            return Err(DatabaseError::MessageTooLarge { len, max });
+        }
+
         Ok(())
     }
 
@@ -313,20 +1365,115 @@ impl Database {
         filename: String,
         payload: Vec<u8>,
     ) -> DatabaseResult<()> {
+        let len = payload.len() as u64;
+
+        self.send_file_stream(conversation, filename, payload.as_slice(), len)
+            .await
+    }
+
+    /// Same as [`Database::send_file`], but the `len` bytes of the payload are
+    /// read from `reader` and stored as chunks of [`ATTACHMENT_CHUNK_BYTES`],
+    /// each one synced as its own patch, so that the file is never held in
+    /// memory.
+    ///
+    /// The payload is stored under its hash, a file that is already stored is
+    /// not stored again, but it is still synced to the conversation.
+    pub async fn send_file_stream<R: Read>(
+        &self,
+        conversation: Conversation,
+        filename: String,
+        reader: R,
+        len: u64,
+    ) -> DatabaseResult<()> {
+        if let Some(max) = self.max_attachment_bytes {
+            let len = len as usize;
+            if len > max {
+                return Err(DatabaseError::AttachmentTooLarge { len, max });
+            }
+        }
+
         let mut trans = self.connection.begin().await?;
 
+        // The hash is only known once the whole payload is read, meanwhile
+        // the chunks are stored under a placeholder shorter than any hash.
+        let placeholder = Uuid::new_v4().as_bytes().to_vec();
+        let mut hasher = blake3::Hasher::new();
+        let mut reader = reader.take(len);
+        let mut read = 0;
+        let mut mime = None;
+        for index in 0.. {
+            let mut data = Vec::with_capacity(ATTACHMENT_CHUNK_BYTES);
+            (&mut reader)
+                .take(ATTACHMENT_CHUNK_BYTES as u64)
+                .read_to_end(&mut data)?;
+            if index == 0 {
+                mime = mime::guess(&filename, &data);
+            }
+            if data.is_empty() {
+                break;
+            }
+            read += data.len() as u64;
+            hasher.update(&data);
+
+            blob::ActiveModel {
+                id: ActiveValue::NotSet,
+                hash: ActiveValue::Set(placeholder.clone()),
+                index: ActiveValue::Set(index),
+                data: ActiveValue::Set(data),
+                total: ActiveValue::Set(len as i64),
+                crdt_author: ActiveValue::Set(self.author().0),
+            }
+            .save(&trans)
+            .await?;
+        }
+        if read < len {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
+        // Chunks already stored under the hash are kept, which also completes
+        // a copy of the file still being received.
+        let hash = *hasher.finalize().as_bytes();
+        trans
+            .execute(Statement::from_sql_and_values(
+                DatabaseBackend::Sqlite,
+                "UPDATE OR IGNORE blob SET hash = ? WHERE hash = ?;",
+                [hash.to_vec().into(), placeholder.clone().into()],
+            ))
+            .await?;
+        blob::Entity::delete_many()
+            .filter(blob::Column::Hash.eq(placeholder))
+            .exec(&trans)
+            .await?;
+
         let attachment_id = Uuid::new_v4();
         self.add_only_new_patch(
             &mut trans,
             patch::Attachment {
                 id: attachment_id,
                 conversation: conversation.uuid,
-                payload: Some(payload),
+                hash: Some(hash),
                 crdt: Default::default(),
             },
         )
         .await?;
 
+        let mut image = (thumbnail::is_image(mime.as_deref())
+            && len <= thumbnail::MAX_SOURCE_BYTES)
+            .then(Vec::new);
+        let mut after = -1;
+        while let Some(chunk) = next_chunk(&trans, &hash, after).await? {
+            after = chunk.index;
+            if let Some(image) = &mut image {
+                image.extend_from_slice(&chunk.data);
+            }
+            Self::save_patch_for_sync(
+                &trans,
+                patch::AttachmentChunk::from((conversation.uuid, chunk)),
+            )
+            .await?;
+        }
+        let thumbnail = image.and_then(|image| thumbnail::generate(&image));
+
         let id = Uuid::new_v4();
         self.push_new_patch(
             &mut trans,
@@ -336,21 +1483,118 @@ impl Database {
                 conversation: conversation.uuid,
                 text: filename,
                 attachment: Some(attachment_id),
+                mime,
+                size: Some(len),
+                thumbnail,
+                reply_to: None,
+                created_at: sync::system_clock(),
                 crdt: Default::default(),
             },
         )
         .await?;
+        Self::mark_sent(&trans, id).await?;
 
         trans.commit().await?;
         Ok(())
     }
 
-    pub async fn fetch_file_payload(&self, id: i32) -> DatabaseResult<Option<Vec<u8>>> {
+    /// Payload of the attachment, the chunks received so far. Files longer
+    /// than [`Database::set_spill_bytes`] are written to a temporary file one
+    /// chunk at a time, so that they are never held in memory whole.
+    pub async fn fetch_file_payload(&self, id: i32) -> DatabaseResult<Option<FilePayload>> {
+        let Some(hash) = self.attachment_hash(id).await? else { return Ok(None); };
+
+        let first = next_chunk(&self.connection, &hash, -1).await?;
+        let len = first.map_or(0, |chunk| chunk.total as u64);
+        let mut chunks = self.fetch_chunks(hash);
+        if len <= self.spill_bytes as u64 {
+            let mut payload = Vec::new();
+            while let Some(chunk) = chunks.try_next().await? {
+                payload.extend(chunk);
+            }
+
+            return Ok(Some(FilePayload::Memory(payload)));
+        }
+
+        let (file, mut writer) = SpilledFile::create()?;
+        while let Some(chunk) = chunks.try_next().await? {
+            writer.write_all(&chunk)?;
+        }
+        writer.flush()?;
+
+        Ok(Some(FilePayload::Spilled(file)))
+    }
+
+    /// Bytes of the attachment received so far and its declared length.
+    /// `None` while the length is not known, which is before the first chunk
+    /// arrives.
+    pub async fn attachment_progress(&self, id: i32) -> DatabaseResult<Option<(u64, u64)>> {
+        let Some(hash) = self.attachment_hash(id).await? else { return Ok(None); };
+
+        let row = self
+            .connection
+            .query_one(Statement::from_sql_and_values(
+                DatabaseBackend::Sqlite,
+                "SELECT COUNT(*) AS chunks, SUM(LENGTH(data)) AS received, MAX(total) AS total \
+                FROM blob WHERE hash = ?;",
+                [hash.to_vec().into()],
+            ))
+            .await?;
+        let Some(row) = row else { return Ok(None); };
+        if row.try_get::<i64>("", "chunks")? == 0 {
+            return Ok(None);
+        }
+
+        let received = row.try_get::<i64>("", "received")? as u64;
+        let total = row.try_get::<i64>("", "total")? as u64;
+        Ok(Some((received, total)))
+    }
+
+    /// Payload of the attachment, one chunk at a time. Empty when the
+    /// attachment was not received yet.
+    pub fn fetch_file_stream(&self, id: i32) -> LocalBoxStream<'_, DatabaseResult<Vec<u8>>> {
+        stream::once(self.attachment_hash(id))
+            .map_ok(move |hash| match hash {
+                Some(hash) => self.fetch_chunks(hash),
+                None => stream::empty().boxed_local(),
+            })
+            .try_flatten()
+            .boxed_local()
+    }
+
+    /// Hash of the payload of the attachment, `None` while it is not known.
+    async fn attachment_hash(&self, id: i32) -> DatabaseResult<Option<BlobHash>> {
         let attachment = attachment::Entity::find_by_id(id)
             .one(&self.connection)
             .await?;
 
-        Ok(attachment.and_then(|attachment| attachment.payload))
+        Ok(attachment
+            .and_then(|attachment| attachment.hash)
+            .map(|hash| hash.try_into().unwrap()))
+    }
+
+    fn fetch_chunks(&self, hash: BlobHash) -> LocalBoxStream<'_, DatabaseResult<Vec<u8>>> {
+        stream::try_unfold(-1, move |after| async move {
+            let chunk = next_chunk(&self.connection, &hash, after).await?;
+
+            Ok(chunk.map(|chunk| (chunk.data, chunk.index)))
+        })
+        .boxed_local()
+    }
+
+    /// Peer of the channel that delivered the text of `message`, the last one
+    /// that did if it was edited. Unlike [`Message::from`], which the author
+    /// declares, it can be trusted. `None` for messages written locally or
+    /// only known from a snapshot.
+    pub async fn delivered_by(&self, message: &Message) -> DatabaseResult<Option<Ed25519Cert>> {
+        let delivery = message_delivery::Entity::find_by_id(message.id)
+            .find_also_related(entity::entity::key::Entity)
+            .one(&self.connection)
+            .await?;
+
+        Ok(delivery
+            .and_then(|(_, key)| key)
+            .map(|key| Ed25519Cert(key.public.try_into().expect("Corrupted database"))))
     }
 
     pub async fn set_message_status(
@@ -360,12 +1604,25 @@ impl Database {
     ) -> DatabaseResult<()> {
         let mut trans = self.connection.begin().await?;
 
+        self.trans_set_message_status(&mut trans, message.uuid, message.conversation, status)
+            .await?;
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    /// Replaces the text of the message for every peer. Concurrent edits
+    /// resolve to the one with the highest generation, then author.
+    pub async fn edit_message(&self, message: &Message, new_text: String) -> DatabaseResult<()> {
+        Self::check_text_len(&new_text)?;
+        let mut trans = self.connection.begin().await?;
+
         self.set_new_patch(
             &mut trans,
-            patch::MessageStatus {
+            patch::MessageEdit {
                 id: message.uuid,
                 conversation: message.conversation,
-                status: status.into(),
+                text: new_text,
                 crdt: Default::default(),
             },
         )
@@ -375,643 +1632,7899 @@ impl Database {
         Ok(())
     }
 
-    pub async fn list_channels(
-        &self,
-        conversation: &Conversation,
-    ) -> DatabaseResult<Vec<ChannelData>> {
-        let trans = self.connection.begin().await?;
-
-        let conversation = conversation::Entity::find_by_id(conversation.id)
-            .one(&trans)
-            .await?;
-        let Some(conversation) = conversation else { return Ok(Default::default()); };
-        let uuid = conversation.get_uuid().into();
-
-        let mut r = Vec::new();
-
-        for models in channel::Entity::find()
-            .filter(channel::Column::Conversation.eq(conversation.id))
-            .find_also_related(entity::entity::key::Entity)
-            .all(&trans)
-            .await?
-        {
-            let (channel, Some(peer)) = models else { panic!() };
-            let peer = Ed25519Cert(
-                peer.public
-                    .as_slice()
-                    .try_into()
-                    .expect("Corrupted database"),
-            );
+    /// Deletes the message for every peer. Its row is kept as a tombstone, so
+    /// indexes in the conversation do not shift, but its text is dropped.
+    pub async fn delete_message(&self, message: &Message) -> DatabaseResult<()> {
+        let mut trans = self.connection.begin().await?;
 
-            r.push(ChannelData::new(channel.id, uuid, peer, &self.seed));
-        }
+        self.set_new_patch(
+            &mut trans,
+            patch::MessageTombstone {
+                id: message.uuid,
+                conversation: message.conversation,
+                crdt: Default::default(),
+            },
+        )
+        .await?;
 
-        Ok(r)
+        trans.commit().await?;
+        Ok(())
     }
 
-    pub async fn create_channel(
+    /// Clears the messages of the conversation for every peer, those listed
+    /// before `before` or all of them. Clears only move forward: messages a peer
+    /// delivers afterwards from within the cleared history are dropped, as are
+    /// edits, deletions and statuses of cleared messages.
+    pub async fn clear_history(
         &self,
         conversation: Conversation,
-        peer: Ed25519Cert,
+        before: Option<&Message>,
     ) -> DatabaseResult<()> {
         let mut trans = self.connection.begin().await?;
 
-        let peer_key = patch::Key::new_exact(&peer.0);
-        let (peer, ..) = patch::Contact::get_or_create(peer_key.clone(), &trans).await;
-
-        let existent_count = channel::Entity::find()
-            .filter(channel::Column::Conversation.eq(conversation.id))
-            .filter(channel::Column::Peer.eq(peer.id))
-            .count(&trans)
-            .await?;
+        let before = match before {
+            Some(before) => CrdtPosition::new(before.crdt, before.uuid),
+            None => {
+                let Some(id) = conversation.row_id(&trans).await? else { return Ok(()); };
+                let last = message::Entity::find()
+                    .filter(message::Column::Conversation.eq(id))
+                    .order_by(message::Column::CrdtSequence, Order::Desc)
+                    .one(&trans)
+                    .await?;
+                let Some(last) = last else { return Ok(()); };
+
+                CrdtPosition::start_of(last.crdt_sequence + 1)
+            }
+        };
 
-        if existent_count > 0 {
-            return Ok(());
+        let clear = patch::HistoryClear {
+            conversation: conversation.uuid,
+            before,
+            author: self.author(),
+        };
+        if let Some(clear) = trans.merge(clear).await {
+            Self::save_patch_for_sync(&trans, clear).await?;
         }
 
-        self.add_only_new_patch(
-            &mut trans,
-            patch::Member {
-                key: peer_key,
-                conversation: conversation.uuid,
-                crdt: CrdtAddOnly(self.author()),
-            },
-        )
-        .await?;
+        trans.commit().await?;
+        Ok(())
+    }
 
-        let new_channel = channel::ActiveModel {
-            id: ActiveValue::NotSet,
-            conversation: ActiveValue::Set(conversation.id),
-            peer: ActiveValue::Set(peer.id),
-            sync_index: ActiveValue::Set(Self::current_sync_index(&trans).await?),
+    /// Stars, or unstars, the message. Stars are local, no patch is produced.
+    pub async fn set_starred(&self, message: &Message, starred: bool) -> DatabaseResult<()> {
+        let trans = self.connection.begin().await?;
+
+        message::ActiveModel {
+            id: ActiveValue::Unchanged(message.id),
+            starred: ActiveValue::Set(starred),
+            ..Default::default()
         }
-        .save(&trans)
+        .update(&trans)
         .await?;
 
-        Self::initial_sync(&mut trans, new_channel.id.unwrap(), conversation).await?;
-
         trans.commit().await?;
-
         Ok(())
     }
 
-    pub async fn remove_channel(
-        &self,
-        conversation: Conversation,
-        peer: Ed25519Cert,
-    ) -> DatabaseResult<()> {
+    /// Starred messages of every conversation, in conversation order.
+    pub async fn list_starred(&self) -> DatabaseResult<Vec<(Conversation, Message)>> {
         let trans = self.connection.begin().await?;
 
-        let peer_key = patch::Key::new_exact(&peer.0);
-        let (peer, ..) = patch::Contact::get_or_create(peer_key.clone(), &trans).await;
+        let query = message::Entity::find()
+            .filter(message::Column::Starred.eq(true))
+            .order_by(message::Column::Conversation, Order::Asc);
+        let models = MessageOrder::CausalSequence
+            .sort(query, Order::Asc)
+            .find_also_related(conversation::Entity)
+            .all(&trans)
+            .await?;
 
-        let existent = channel::Entity::find()
-            .filter(channel::Column::Conversation.eq(conversation.id))
-            .filter(channel::Column::Peer.eq(peer.id))
-            .one(&trans)
-            .await?;
+        let mut contacts = ContactCache::default();
+        let mut conversations = Vec::new();
+        let mut messages = Vec::new();
+        for (message, conversation) in models {
+            let conversation = conversation.expect("Corrupted database");
+            let uuid = conversation.get_uuid().into();
 
-        let Some(existent) = existent else { return Ok(()); };
-        existent.delete(&trans).await?;
-
-        trans.commit().await?;
-        Ok(())
-    }
+            conversations.push(
+                Conversation::with_cached_members(&trans, &mut contacts, conversation).await?,
+            );
+            messages.push((message, uuid));
+        }
+        let messages = self.hydrate(&trans, messages).await?;
 
-    pub fn start_sync(&self, channel: ChannelData) -> PatchSync<DatabaseTransaction> {
-        PatchSync::new(
-            channel.id,
-            channel.peer_cert.as_author(),
-            channel.conversation,
-        )
+        Ok(conversations.into_iter().zip(messages).collect())
     }
 
-    async fn initial_sync(
+    async fn trans_set_message_status(
+        &self,
         trans: &mut DatabaseTransaction,
-        channel_id: i32,
-        conversation: Conversation,
+        message: Uuid,
+        conversation: Uuid,
+        status: MessageStatus,
     ) -> DatabaseResult<()> {
-        Self::save_initial_patch(
+        self.set_new_patch(
             trans,
-            channel_id,
-            patch::Conversation {
-                id: conversation.uuid,
-                crdt: conversation.crdt,
-                title: conversation.title,
+            patch::MessageStatus {
+                id: message,
+                conversation,
+                status: status.into(),
+                crdt: Default::default(),
             },
         )
         .await?;
+        self.set_new_patch(
+            trans,
+            patch::Receipt {
+                message,
+                conversation,
+                member: self.patch_key(),
+                status: status.into(),
+                crdt: Default::default(),
+            },
+        )
+        .await
+    }
 
-        let contacts = contact::Entity::find()
-            .find_also_related(entity::entity::key::Entity)
-            .all(trans)
-            .await?;
-        for model in contacts {
-            let (contact, key) = model;
-            Self::save_initial_patch(
-                trans,
-                channel_id,
-                patch::Contact::from((key.unwrap(), contact)),
-            )
-            .await?;
-        }
+    /// Marks every message from others in `conversation` as read, but for
+    /// deleted ones.
+    pub async fn mark_read(&self, conversation: &Conversation) -> DatabaseResult<()> {
+        let mut trans = self.connection.begin().await?;
+        let Some(id) = conversation.row_id(&trans).await? else { return Ok(()); };
 
-        let members = member::Entity::find()
-            .filter(member::Column::Conversation.eq(conversation.id))
-            .find_also_related(entity::entity::key::Entity)
-            .all(trans)
-            .await?;
-        for model in members {
-            let (member, key) = model;
-            Self::save_initial_patch(
-                trans,
-                channel_id,
-                patch::Member::from((key.unwrap(), member, conversation.uuid)),
-            )
+        let unread = message::Entity::find()
+            .filter(message::Column::Conversation.eq(id))
+            .filter(message::Column::From.ne(self.user))
+            .filter(message::Column::Status.lt(i32::from(MessageStatus::Read)))
+            .filter(message::Column::Deleted.eq(false))
+            .all(&trans)
             .await?;
+        for message in unread {
+            let uuid = message.get_uuid().into();
+            self.trans_set_message_status(&mut trans, uuid, conversation.uuid, MessageStatus::Read)
+                .await?;
         }
 
-        let patches = attachment::Entity::find()
-            .filter(attachment::Column::Conversation.eq(conversation.id))
-            .all(trans)
-            .await?;
-        for patch in patches {
-            Self::save_initial_patch(
-                trans,
-                channel_id,
-                patch::Attachment::from((conversation.uuid, patch)),
-            )
-            .await?;
-        }
+        trans.commit().await?;
+        Ok(())
+    }
 
-        let messages = message::Entity::find()
-            .filter(message::Column::Conversation.eq(conversation.id))
-            .find_also_related(entity::entity::key::Entity)
-            .all(trans)
-            .await?;
-        for model in messages {
-            let (message, key) = model;
-            let attachment = match message.attachment {
-                Some(id) => Some(
-                    AttachmentMetaModel::find_by_id(id)
-                        .one(trans)
-                        .await?
-                        .unwrap(),
-                ),
-                None => None,
-            };
+    /// Members of the message's conversation, other than its sender, that
+    /// did not report having read it yet.
+    pub async fn members_pending_read(&self, message: &Message) -> DatabaseResult<Vec<Contact>> {
+        let trans = self.connection.begin().await?;
 
-            Self::save_initial_patch(
-                trans,
-                channel_id,
-                patch::NewMessage::from((
-                    message.clone(),
-                    key.unwrap(),
-                    conversation.uuid,
-                    attachment,
-                )),
-            )
-            .await?;
-            Self::save_initial_patch(
-                trans,
-                channel_id,
-                patch::MessageStatus::from((message, conversation.uuid)),
-            )
-            .await?;
-        }
+        let conversation = Self::trans_get_conversation(&trans, message.conversation).await?;
+        let Some(conversation) = conversation else { return Ok(Default::default()); };
 
-        Ok(())
+        let uuid_filter = SplitUuid::from(message.uuid).to_filter::<receipt::Column>();
+        let read = receipt::Entity::find()
+            .filter(uuid_filter.0)
+            .filter(uuid_filter.1)
+            .filter(uuid_filter.2)
+            .filter(uuid_filter.3)
+            .filter(receipt::Column::Status.gte(i32::from(MessageStatus::Read)))
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|receipt| receipt.contact)
+            .collect::<HashSet<_>>();
+
+        Ok(conversation
+            .members
+            .into_iter()
+            .filter(|member| member.key != message.from.key)
+            .filter(|member| !read.contains(&member.id))
+            .collect())
     }
 
-    async fn set_new_patch<P: CrdtInstance<Crdt = CrdtWritable> + Into<Patch> + 'static>(
-        &self,
-        trans: &mut DatabaseTransaction,
-        patch: P,
-    ) -> DatabaseResult<()>
-    where
-        DatabaseTransaction: CrdtWritableTransaction<P>,
-    {
-        let patch = trans.set(self.author(), patch).await;
-
-        Self::save_patch_for_sync(trans, patch).await?;
+    pub async fn do_not_disturb(&self) -> DatabaseResult<DoNotDisturb> {
+        self.preference(DoNotDisturb::KEY).await
+    }
 
-        Ok(())
+    pub async fn set_do_not_disturb(&self, value: DoNotDisturb) -> DatabaseResult<()> {
+        self.set_preference(DoNotDisturb::KEY, &value).await
     }
 
-    async fn push_new_patch<P: CrdtInstance<Crdt = CrdtWritableSequence> + Into<Patch> + 'static>(
-        &self,
-        trans: &mut DatabaseTransaction,
-        patch: P,
-    ) -> DatabaseResult<()>
-    where
-        DatabaseTransaction: CrdtWritableSequenceTransaction<P>,
-    {
-        let patch = trans.push(self.author(), patch).await;
+    /// Mutes, or unmutes, the notifications of `conversation`. Muting is
+    /// local, no patch is produced.
+    pub async fn set_muted(&self, conversation: &Conversation, muted: bool) -> DatabaseResult<()> {
+        let trans = self.connection.begin().await?;
+        let Some(id) = conversation.row_id(&trans).await? else { return Ok(()); };
 
-        Self::save_patch_for_sync(trans, patch).await?;
+        local_conversation_settings::Entity::insert(local_conversation_settings::ActiveModel {
+            conversation: ActiveValue::Set(id),
+            muted: ActiveValue::Set(muted),
+            archived: ActiveValue::NotSet,
+        })
+        .on_conflict(
+            OnConflict::column(local_conversation_settings::Column::Conversation)
+                .update_column(local_conversation_settings::Column::Muted)
+                .to_owned(),
+        )
+        .exec_without_returning(&trans)
+        .await?;
 
+        trans.commit().await?;
         Ok(())
     }
 
-    async fn add_only_new_patch<P: CrdtInstance + Into<Patch> + 'static>(
-        &self,
-        trans: &mut DatabaseTransaction,
-        mut patch: P,
-    ) -> DatabaseResult<bool>
-    where
-        DatabaseTransaction: CrdtTransaction<P>,
-    {
-        patch.set_crdt(patch.crdt().next(self.author()));
-        let patch = trans.merge(patch).await;
-        let Some(patch) = patch else { return Ok(false); };
+    pub async fn is_muted(&self, conversation: &Conversation) -> DatabaseResult<bool> {
+        let trans = self.connection.begin().await?;
+        let Some(id) = conversation.row_id(&trans).await? else { return Ok(false); };
 
-        Self::save_patch_for_sync(trans, patch).await?;
+        let settings = local_conversation_settings::Entity::find_by_id(id)
+            .one(&trans)
+            .await?;
 
-        Ok(true)
+        Ok(settings.map(|settings| settings.muted).unwrap_or_default())
     }
 
-    async fn save_patch_for_sync<P: Into<Patch>>(
-        trans: &DatabaseTransaction,
-        patch: P,
+    /// Archives, or unarchives, `conversation`. An archived conversation is
+    /// still synced and receives messages, it is only left out of
+    /// [`Database::list_unarchived`]. Archiving is local, no patch is produced.
+    pub async fn set_archived(
+        &self,
+        conversation: &Conversation,
+        archived: bool,
     ) -> DatabaseResult<()> {
-        let patch: Patch = patch.into();
+        let trans = self.connection.begin().await?;
+        let Some(id) = conversation.row_id(&trans).await? else { return Ok(()); };
 
-        entity::entity::sync::ActiveModel {
-            id: ActiveValue::NotSet,
-            payload: ActiveValue::Set(bincode::serialize(&patch).unwrap()),
-        }
-        .save(trans)
+        local_conversation_settings::Entity::insert(local_conversation_settings::ActiveModel {
+            conversation: ActiveValue::Set(id),
+            muted: ActiveValue::NotSet,
+            archived: ActiveValue::Set(archived),
+        })
+        .on_conflict(
+            OnConflict::column(local_conversation_settings::Column::Conversation)
+                .update_column(local_conversation_settings::Column::Archived)
+                .to_owned(),
+        )
+        .exec_without_returning(&trans)
         .await?;
 
+        trans.commit().await?;
         Ok(())
     }
 
-    async fn save_initial_patch<P: Into<Patch>>(
-        trans: &DatabaseTransaction,
-        channel_id: i32,
-        patch: P,
-    ) -> DatabaseResult<()> {
-        let patch: Patch = patch.into();
+    pub async fn is_archived(&self, conversation: &Conversation) -> DatabaseResult<bool> {
+        let trans = self.connection.begin().await?;
+        let Some(id) = conversation.row_id(&trans).await? else { return Ok(false); };
 
-        initial_sync::ActiveModel {
-            id: ActiveValue::NotSet,
-            channel: ActiveValue::Set(channel_id),
-            payload: ActiveValue::Set(bincode::serialize(&patch).unwrap()),
-        }
-        .save(trans)
-        .await?;
+        let settings = local_conversation_settings::Entity::find_by_id(id)
+            .one(&trans)
+            .await?;
 
-        Ok(())
+        Ok(settings
+            .map(|settings| settings.archived)
+            .unwrap_or_default())
     }
 
-    async fn current_sync_index(trans: &DatabaseTransaction) -> DatabaseResult<i32> {
-        Ok(entity::entity::sync::Entity::find()
-            .order_by(entity::entity::sync::Column::Id, Order::Desc)
-            .one(trans)
-            .await?
-            .map(|sync| sync.id)
-            .unwrap_or(0))
+    /// Whether new messages should be notified at `now`, an unix timestamp in
+    /// seconds. Messages are still delivered either way.
+    pub async fn should_notify(&self, now: i64) -> DatabaseResult<bool> {
+        Ok(!self.do_not_disturb().await?.active(now))
     }
-}
 
-pub struct SharedDatabase {}
-impl SharedDatabase {
-    pub fn with_user(user: Uuid) -> DatabaseResult<Self> {
-        let _ = user;
-        unimplemented!()
+    /// Value kept locally under `key`, or the default if there is none. Meant
+    /// for the settings of the applications built on the database as well,
+    /// which should prefix their keys with their name. A value that no longer
+    /// reads as `T`, say after its type changed, is also taken as the default.
+    pub async fn preference<T: DeserializeOwned + Default>(&self, key: &str) -> DatabaseResult<T> {
+        let existent = preference::Entity::find_by_id(key.to_string())
+            .one(&self.connection)
+            .await?;
+
+        Ok(existent
+            .and_then(|existent| match bincode::deserialize(&existent.value) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    log::warn!("Ignoring unreadable preference {key}: {e}");
+                    None
+                }
+            })
+            .unwrap_or_default())
     }
 
-    pub fn add_contact(&mut self, contact: Contact) -> DatabaseResult<()> {
-        let _ = contact;
-        unimplemented!()
+    /// Keeps `value` locally under `key`, see [`Database::preference`].
+    pub async fn set_preference<T: Serialize>(&self, key: &str, value: &T) -> DatabaseResult<()> {
+        preference::Entity::insert(preference::ActiveModel {
+            key: ActiveValue::Set(key.to_string()),
+            value: ActiveValue::Set(bincode::serialize(value).unwrap()),
+        })
+        .on_conflict(
+            OnConflict::column(preference::Column::Key)
+                .update_column(preference::Column::Value)
+                .to_owned(),
+        )
+        .exec(&self.connection)
+        .await?;
+
+        Ok(())
     }
 
-    pub fn add_message(&mut self, message: Message) -> DatabaseResult<()> {
-        let _ = message;
-        unimplemented!()
+    pub async fn list_channels(
+        &self,
+        conversation: &Conversation,
+    ) -> DatabaseResult<Vec<ChannelData>> {
+        let trans = self.connection.begin().await?;
+
+        let Some(id) = conversation.row_id(&trans).await? else { return Ok(Default::default()); };
+        let uuid = conversation.uuid;
+
+        let mut r = Vec::new();
+
+        for models in channel::Entity::find()
+            .filter(channel::Column::Conversation.eq(id))
+            .find_also_related(entity::entity::key::Entity)
+            .all(&trans)
+            .await?
+        {
+            let (channel, Some(peer)) = models else { panic!() };
+            let peer = Ed25519Cert(
+                peer.public
+                    .as_slice()
+                    .try_into()
+                    .expect("Corrupted database"),
+            );
+
+            let mut data =
+                ChannelData::new(channel.id, uuid, peer, self.seed_for(channel.local_key));
+            data.local_key = channel.local_key;
+            data.own_device = Self::trans_is_paired(&trans, &peer).await?;
+            r.push(data);
+        }
+
+        Ok(r)
     }
 
-    pub fn set_message(&mut self, index: usize, message: &Message) -> DatabaseResult<()> {
-        let _ = index;
-        let _ = message;
-        unimplemented!()
+    /// Index of the sync log up to which the peer of `channel` acknowledged
+    /// patches, `None` if the channel was removed.
+    pub async fn channel_sync_index(&self, channel: &ChannelData) -> DatabaseResult<Option<i32>> {
+        let channel = channel::Entity::find_by_id(channel.id)
+            .one(&self.connection)
+            .await?;
+
+        Ok(channel.map(|channel| channel.sync_index))
     }
 
-    pub fn list_messages(&self) -> std::vec::IntoIter<DatabaseResult<Message>> {
-        unimplemented!()
+    /// Channel to `peer` on `conversation` using the current identity, ready
+    /// to be connected, if one was created.
+    pub async fn channel_for(
+        &self,
+        conversation: &Conversation,
+        peer: &Ed25519Cert,
+    ) -> DatabaseResult<Option<SqliteChannel>> {
+        let channel = self
+            .list_channels(conversation)
+            .await?
+            .into_iter()
+            .find(|channel| channel.peer_cert == *peer && channel.local_key.is_none());
+
+        Ok(channel.map(|channel| self.channel(channel)))
     }
 
-    pub fn list_contact(&self) -> std::vec::IntoIter<DatabaseResult<Contact>> {
-        unimplemented!()
+    /// Channel ready to be connected through the servers of
+    /// [`Database::set_connect_config`].
+    pub fn channel(&self, channel: ChannelData) -> SqliteChannel {
+        let transport = IcepipeTransport {
+            config: self.connect_config.clone(),
+        };
+
+        self.channel_over(channel, transport)
     }
 
-    pub fn get_contact(&self, uuid: Uuid) -> DatabaseResult<Option<Contact>> {
-        let _ = uuid;
-        unimplemented!()
+    /// Same as [`Database::channel`], connecting through `transport`.
+    pub fn channel_over<T: Transport>(
+        &self,
+        channel: ChannelData,
+        transport: T,
+    ) -> Channel<PatchSync<DatabaseTransaction>, T> {
+        let seed = self.seed_for(channel.local_key).clone();
+
+        Channel::with_transport(channel, seed, transport)
     }
 
-    pub fn save(&mut self) -> Vec<u8> {
-        unimplemented!()
+    /// Private key a channel authenticates with, an old one for the channels
+    /// kept by [`Database::rotate_identity`].
+    fn seed_for(&self, local_key: Option<i32>) -> &Ed25519Seed {
+        match local_key {
+            Some(key) => self.retired.get(&key).expect("Corrupted database"),
+            None => &self.seed,
+        }
     }
 
-    pub fn load_with_user(data: &[u8], user: Uuid) -> DatabaseResult<Self> {
-        let _ = data;
-        let _ = user;
-        unimplemented!()
+    /// Drops the sync state no channel needs anymore, then shrinks the file.
+    ///
+    /// Removes the snapshots no channel drains, the snapshot patches every
+    /// channel draining them acked, and the global patches every channel
+    /// acked. Those are removed in a transaction, and `VACUUM` runs after
+    /// it, so channels may stay connected.
+    pub async fn compact(&self) -> DatabaseResult<CompactStats> {
+        let trans = self.connection.begin().await?;
+        let initial_sync_before = initial_sync::Entity::find().count(&trans).await?;
+        let sync_before = entity::entity::sync::Entity::find().count(&trans).await?;
+
+        remove_unused_snapshots(&trans).await?;
+        trans
+            .execute_unprepared(
+                "DELETE FROM initial_sync WHERE id <= \
+                (SELECT MIN(snapshot_index) FROM channel WHERE snapshot = initial_sync.snapshot);",
+            )
+            .await?;
+        remove_old_patches(&trans).await?;
+
+        let initial_sync_removed =
+            initial_sync_before - initial_sync::Entity::find().count(&trans).await?;
+        let sync_removed = sync_before - entity::entity::sync::Entity::find().count(&trans).await?;
+        trans.commit().await?;
+
+        let size_before = self.file_size().await?;
+        self.connection.execute_unprepared("VACUUM;").await?;
+        let size_after = self.file_size().await?;
+
+        Ok(CompactStats {
+            initial_sync_removed,
+            sync_removed,
+            bytes_reclaimed: size_before.saturating_sub(size_after),
+        })
     }
 
-    pub fn start_sync(&self) -> UnimplementedSync {
-        UnimplementedSync
+    async fn file_size(&self) -> DatabaseResult<u64> {
+        let pragma = |name: &'static str| async move {
+            let value = self
+                .connection
+                .query_one(Statement::from_string(
+                    DatabaseBackend::Sqlite,
+                    format!("PRAGMA {name};"),
+                ))
+                .await?
+                .map(|row| row.try_get::<i64>("", name))
+                .transpose()?
+                .unwrap_or_default();
+            DatabaseResult::Ok(value as u64)
+        };
+
+        Ok(pragma("page_count").await? * pragma("page_size").await?)
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Conversation {
-    id: i32,
-    pub uuid: Uuid,
-    pub title: Option<String>,
-    pub crdt: CrdtWritable,
-    pub members: Vec<Contact>,
-}
-impl Conversation {
-    async fn with_members(
-        trans: &DatabaseTransaction,
-        conversation: conversation::Model,
-    ) -> DatabaseResult<Conversation> {
-        let mut members = Vec::new();
+    /// Number of patches still to be sent across all channels, each channel
+    /// counting only the patches of its conversation. A patch pending on
+    /// several channels is counted once.
+    pub async fn sync_backlog(&self) -> DatabaseResult<usize> {
+        let trans = self.connection.begin().await?;
 
-        for models in member::Entity::find()
-            .find_also_related(contact::Entity)
-            .filter(member::Column::Conversation.eq(conversation.id))
-            .all(trans)
+        let initial = trans
+            .query_one(Statement::from_string(
+                DatabaseBackend::Sqlite,
+                "SELECT COUNT(*) AS pending FROM initial_sync i WHERE EXISTS \
+                (SELECT 1 FROM channel c WHERE c.snapshot = i.snapshot AND c.snapshot_index < i.id);"
+                    .to_owned(),
+            ))
             .await?
-        {
-            let contact = models.1.unwrap();
-            let key = entity::entity::key::Entity::find_by_id(contact.key)
-                .one(trans)
-                .await?
-                .unwrap();
+            .map(|row| row.try_get::<i64>("", "pending"))
+            .transpose()?
+            .unwrap_or_default() as u64;
 
-            members.push(Contact {
-                id: contact.key,
-                key: Ed25519Cert(key.public.try_into().unwrap()),
-                name: contact.name,
-            })
+        let conversations = conversation::Entity::find()
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|conversation| (conversation.id, conversation.get_uuid().into()))
+            .collect::<HashMap<i32, Uuid>>();
+        let channels = channel::Entity::find()
+            .all(&trans)
+            .await?
+            .into_iter()
+            .map(|channel| (channel.sync_index, conversations[&channel.conversation]))
+            .collect::<Vec<_>>();
+
+        // A patch is only sent over the channels of its conversation, those
+        // of other conversations ack it without sending.
+        let behind = channels.iter().map(|(sync_index, _)| *sync_index).min();
+        let mut global = 0;
+        if let Some(behind) = behind {
+            let patches = entity::entity::sync::Entity::find()
+                .filter(entity::entity::sync::Column::Id.gt(behind))
+                .all(&trans)
+                .await?;
+            for model in patches {
+                let id = SyncDataId::Global(model.id);
+                let Ok(payload) = decode_patch(id, &model.payload) else { continue; };
+                let conversation = SyncData { id, payload }.conversation();
+                let pending = channels.iter().any(|(sync_index, channel)| {
+                    *sync_index < model.id && conversation.unwrap_or(*channel) == *channel
+                });
+                global += pending as u64;
+            }
         }
 
-        Ok(Conversation {
-            id: conversation.id,
-            uuid: conversation.get_uuid().into(),
-            title: conversation.title,
-            crdt: CrdtWritable {
-                generation: conversation.crdt_generation,
-                author: Author(conversation.crdt_author),
+        Ok((initial + global) as usize)
+    }
+
+    /// Bookmark of the sync log, the id of its latest patch. Taken right after
+    /// a change, see [`Database::delivered_to_all`].
+    pub async fn sync_bookmark(&self) -> DatabaseResult<i32> {
+        let trans = self.connection.begin().await?;
+
+        Self::current_sync_index(&trans).await
+    }
+
+    /// Whether every channel of the conversation acked the patches up to
+    /// `bookmark`. A channel still draining its initial sync has not, the
+    /// bookmarked patches may be part of it.
+    pub async fn delivered_to_all(
+        &self,
+        conversation: &Conversation,
+        bookmark: i32,
+    ) -> DatabaseResult<bool> {
+        let trans = self.connection.begin().await?;
+        let Some(id) = conversation.row_id(&trans).await? else { return Ok(true); };
+
+        let behind = channel::Entity::find()
+            .filter(channel::Column::Conversation.eq(id))
+            .filter(
+                Condition::any()
+                    .add(channel::Column::SyncIndex.lt(bookmark))
+                    .add(channel::Column::Snapshot.is_not_null()),
+            )
+            .count(&trans)
+            .await?;
+
+        Ok(behind == 0)
+    }
+
+    /// Whether `message` reached any peer yet, judged by the channels of its
+    /// conversation having acked the patch that sent it. Messages received,
+    /// or sent before this was kept, read as delivered.
+    pub async fn delivery_state(&self, message: &Message) -> DatabaseResult<DeliveryState> {
+        let trans = self.connection.begin().await?;
+        let row = message::Entity::find_by_id(message.id).one(&trans).await?;
+        let Some(row) = row else { return Ok(DeliveryState::Delivered); };
+        let Some(sync_index) = row.sync_index else { return Ok(DeliveryState::Delivered); };
+
+        let channels = channel::Entity::find()
+            .filter(channel::Column::Conversation.eq(row.conversation))
+            .all(&trans)
+            .await?;
+        if channels.is_empty() {
+            return Ok(DeliveryState::Failed);
+        }
+
+        // A channel still draining its initial sync was created after the
+        // message, its index does not mean the peer acked anything.
+        let acked = channels
+            .iter()
+            .any(|channel| channel.snapshot.is_none() && channel.sync_index >= sync_index);
+        Ok(match acked {
+            true => DeliveryState::Delivered,
+            false => DeliveryState::Pending,
+        })
+    }
+
+    pub async fn create_channel(
+        &self,
+        conversation: Conversation,
+        peer: Ed25519Cert,
+    ) -> DatabaseResult<()> {
+        self.create_channel_with_history(conversation, peer, HistoryPolicy::All)
+            .await
+    }
+
+    /// Same as [`Database::create_channel`], but only the messages allowed by
+    /// `history` are seeded to the peer. Conversation metadata, contacts and
+    /// members are always seeded.
+    pub async fn create_channel_with_history(
+        &self,
+        conversation: Conversation,
+        peer: Ed25519Cert,
+        history: HistoryPolicy,
+    ) -> DatabaseResult<()> {
+        let options = InitialSyncOptions {
+            history,
+            ..Default::default()
+        };
+
+        self.create_channel_with_options(conversation, peer, options)
+            .await
+    }
+
+    /// Same as [`Database::create_channel`], seeding the peer according to
+    /// `options`.
+    pub async fn create_channel_with_options(
+        &self,
+        conversation: Conversation,
+        peer: Ed25519Cert,
+        options: InitialSyncOptions,
+    ) -> DatabaseResult<()> {
+        let mut trans = self.connection.begin().await?;
+
+        self.trans_create_channel(&mut trans, conversation, peer, options)
+            .await?;
+
+        trans.commit().await?;
+
+        Ok(())
+    }
+
+    async fn trans_create_channel(
+        &self,
+        trans: &mut DatabaseTransaction,
+        conversation: Conversation,
+        peer: Ed25519Cert,
+        options: InitialSyncOptions,
+    ) -> DatabaseResult<()> {
+        if !peer.is_valid() {
+            return Err(DatabaseError::InvalidKey(peer.hex()));
+        }
+        if Self::trans_is_blocked(trans, &peer).await? {
+            return Err(DatabaseError::Blocked);
+        }
+
+        let peer_key = patch::Key::new_exact(&peer.0);
+        let (peer, ..) = patch::Contact::get_or_create(peer_key.clone(), trans).await;
+        let id = patch::Conversation::get_or_create(conversation.uuid, trans)
+            .await
+            .id;
+
+        let existent_count = channel::Entity::find()
+            .filter(channel::Column::Conversation.eq(id))
+            .filter(channel::Column::Peer.eq(peer.id))
+            .filter(channel::Column::LocalKey.is_null())
+            .count(trans)
+            .await?;
+
+        if existent_count > 0 {
+            return Ok(());
+        }
+
+        self.add_only_new_patch(
+            trans,
+            patch::Member {
+                key: peer_key.clone(),
+                conversation: conversation.uuid,
+                crdt: CrdtAddOnly(self.author()),
             },
-            members,
+        )
+        .await?;
+
+        let removal: Option<(_, patch::MemberRemoval)> =
+            trans.existent((peer_key.clone(), conversation.uuid)).await;
+        if matches!(removal, Some((_, removal)) if removal.removed) {
+            self.set_new_patch(
+                trans,
+                patch::MemberRemoval {
+                    key: peer_key,
+                    conversation: conversation.uuid,
+                    removed: false,
+                    crdt: Default::default(),
+                },
+            )
+            .await?;
+        }
+
+        let (sync_index, snapshot) = match self.relay {
+            true => (Self::current_sync_index(trans).await?, None),
+            false => {
+                let snapshot = Self::initial_sync(trans, conversation, options).await?;
+                (snapshot.sync_index, Some(snapshot.id))
+            }
+        };
+
+        channel::ActiveModel {
+            id: ActiveValue::NotSet,
+            conversation: ActiveValue::Set(id),
+            peer: ActiveValue::Set(peer.id),
+            sync_index: ActiveValue::Set(sync_index),
+            snapshot: ActiveValue::Set(snapshot),
+            snapshot_index: ActiveValue::Set(0),
+            local_key: ActiveValue::Set(None),
+        }
+        .save(trans)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_channel(
+        &self,
+        conversation: Conversation,
+        peer: Ed25519Cert,
+    ) -> DatabaseResult<()> {
+        let trans = self.connection.begin().await?;
+
+        Self::trans_remove_channel(&trans, &conversation, peer).await?;
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    async fn trans_remove_channel(
+        trans: &DatabaseTransaction,
+        conversation: &Conversation,
+        peer: Ed25519Cert,
+    ) -> DatabaseResult<()> {
+        let Some(id) = conversation.row_id(trans).await? else { return Ok(()); };
+        let peer_key = patch::Key::new_exact(&peer.0);
+        let (peer, ..) = patch::Contact::get_or_create(peer_key.clone(), trans).await;
+
+        let existent = channel::Entity::find()
+            .filter(channel::Column::Conversation.eq(id))
+            .filter(channel::Column::Peer.eq(peer.id))
+            .all(trans)
+            .await?;
+
+        for existent in existent {
+            existent.delete(trans).await?;
+        }
+        remove_unused_snapshots(trans).await?;
+
+        Ok(())
+    }
+
+    /// Drops every channel to `peer`, in all conversations, also removing it
+    /// as a member of them when `remove_member` is set. Returns how many
+    /// channels were dropped.
+    pub async fn revoke_peer(
+        &self,
+        peer: &Ed25519Cert,
+        remove_member: bool,
+    ) -> DatabaseResult<usize> {
+        let mut trans = self.connection.begin().await?;
+
+        let key = entity::entity::key::Entity::find()
+            .filter(entity::entity::key::Column::Public.eq(peer.0.to_vec()))
+            .one(&trans)
+            .await?;
+        let Some(key) = key else { return Ok(0); };
+
+        let channels = channel::Entity::find()
+            .filter(channel::Column::Peer.eq(key.id))
+            .find_also_related(conversation::Entity)
+            .all(&trans)
+            .await?;
+        let count = channels.len();
+
+        for (channel, conversation) in channels {
+            if remove_member {
+                let conversation = conversation.expect("Corrupted database");
+                self.set_new_patch(
+                    &mut trans,
+                    patch::MemberRemoval {
+                        key: patch::Key::new_exact(&peer.0),
+                        conversation: conversation.get_uuid().into(),
+                        removed: true,
+                        crdt: Default::default(),
+                    },
+                )
+                .await?;
+            }
+            channel.delete(&trans).await?;
+        }
+        remove_unused_snapshots(&trans).await?;
+
+        trans.commit().await?;
+        Ok(count)
+    }
+
+    /// Ignores `cert` from now on: its channels are dropped, no new channel to
+    /// it can be created, and patches it authored are acked but neither
+    /// merged nor relayed. What it already sent is kept, and can be hidden
+    /// with [`Database::set_hide_blocked`].
+    pub async fn block_contact(&self, cert: &Ed25519Cert) -> DatabaseResult<()> {
+        let trans = self.connection.begin().await?;
+
+        blocked::Entity::insert(blocked::ActiveModel {
+            public: ActiveValue::Set(cert.0.to_vec()),
+            author: ActiveValue::Set(cert.as_author().0),
         })
+        .on_conflict(
+            OnConflict::column(blocked::Column::Public)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(&trans)
+        .await?;
+
+        let key = entity::entity::key::Entity::find()
+            .filter(entity::entity::key::Column::Public.eq(cert.0.to_vec()))
+            .one(&trans)
+            .await?;
+        if let Some(key) = key {
+            channel::Entity::delete_many()
+                .filter(channel::Column::Peer.eq(key.id))
+                .exec(&trans)
+                .await?;
+            remove_unused_snapshots(&trans).await?;
+        }
+
+        trans.commit().await?;
+        Ok(())
     }
 
-    pub async fn length(&self, database: &Database) -> DatabaseResult<usize> {
-        let count = message::Entity::find()
-            .filter(message::Column::Conversation.eq(self.id))
-            .count(&database.connection)
+    pub async fn unblock_contact(&self, cert: &Ed25519Cert) -> DatabaseResult<()> {
+        blocked::Entity::delete_by_id(cert.0.to_vec())
+            .exec(&self.connection)
             .await?;
 
-        Ok(count as usize)
+        Ok(())
+    }
+
+    /// Treats `cert` as another device of the local user, usually one running
+    /// the same identity: every conversation, present and future, gets a
+    /// channel to it, and patches authored here are synced through it instead
+    /// of being skipped as the peer's own.
+    ///
+    /// Devices sharing an identity author as the same [`Author`]. Messages
+    /// sent on both at once share a sequence as well, they are told apart by
+    /// their uuid and listed in the same order on every peer, see
+    /// [`CrdtPosition`]. The same value edited on both at once still ties, and
+    /// each device keeps its own edit until the next one.
+    pub async fn pair_device(&self, cert: &Ed25519Cert) -> DatabaseResult<()> {
+        if !cert.is_valid() {
+            return Err(DatabaseError::InvalidKey(cert.hex()));
+        }
+
+        let mut trans = self.connection.begin().await?;
+
+        device::Entity::insert(device::ActiveModel {
+            public: ActiveValue::Set(cert.0.to_vec()),
+        })
+        .on_conflict(
+            OnConflict::column(device::Column::Public)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(&trans)
+        .await?;
+
+        for conversation in conversation::Entity::find().all(&trans).await? {
+            let conversation = Conversation::with_members(&trans, conversation).await?;
+            self.trans_create_channel(&mut trans, conversation, *cert, Default::default())
+                .await?;
+        }
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    pub async fn list_paired_devices(&self) -> DatabaseResult<Vec<Ed25519Cert>> {
+        Ok(device::Entity::find()
+            .all(&self.connection)
+            .await?
+            .into_iter()
+            .map(|device| Ed25519Cert(device.public.try_into().expect("Corrupted database")))
+            .collect())
     }
 
-    pub async fn get_message(
-        &self,
-        database: &Database,
-        index: usize,
-    ) -> DatabaseResult<Option<Message>> {
-        let trans = database.connection.begin().await?;
+    async fn trans_is_paired(
+        trans: &impl ConnectionTrait,
+        cert: &Ed25519Cert,
+    ) -> DatabaseResult<bool> {
+        let paired = device::Entity::find_by_id(cert.0.to_vec())
+            .count(trans)
+            .await?;
+
+        Ok(paired > 0)
+    }
+
+    /// Channels from `conversation` to every paired device, see
+    /// [`Database::pair_device`].
+    async fn trans_create_device_channels(
+        &self,
+        trans: &mut DatabaseTransaction,
+        conversation: &Conversation,
+    ) -> DatabaseResult<()> {
+        for device in device::Entity::find().all(&*trans).await? {
+            let cert = Ed25519Cert(device.public.try_into().expect("Corrupted database"));
+            self.trans_create_channel(trans, conversation.clone(), cert, Default::default())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_blocked(&self) -> DatabaseResult<Vec<Ed25519Cert>> {
+        Ok(blocked::Entity::find()
+            .all(&self.connection)
+            .await?
+            .into_iter()
+            .map(|blocked| Ed25519Cert(blocked.public.try_into().expect("Corrupted database")))
+            .collect())
+    }
+
+    async fn trans_is_blocked(
+        trans: &impl ConnectionTrait,
+        cert: &Ed25519Cert,
+    ) -> DatabaseResult<bool> {
+        let blocked = blocked::Entity::find_by_id(cert.0.to_vec())
+            .count(trans)
+            .await?;
+
+        Ok(blocked > 0)
+    }
+
+    /// Turns `models` into messages of the conversation paired with each,
+    /// loading all their senders with a single query.
+    async fn hydrate(
+        &self,
+        trans: &DatabaseTransaction,
+        models: Vec<(message::Model, Uuid)>,
+    ) -> DatabaseResult<Vec<Message>> {
+        let mut contacts = ContactCache::default();
+        contacts
+            .preload(trans, models.iter().map(|(model, _)| model.from))
+            .await?;
+        let blocked = match self.hide_blocked {
+            true => blocked::Entity::find()
+                .all(trans)
+                .await?
+                .into_iter()
+                .map(|blocked| blocked.public)
+                .collect(),
+            false => vec![],
+        };
+
+        let mut r = Vec::new();
+        for (model, conversation) in models {
+            let mut message =
+                Message::from_cached_model(trans, &mut contacts, model, conversation).await?;
+            if blocked.contains(&message.from.key.0.to_vec()) {
+                message.content = Content::Blocked;
+            }
+            r.push(message);
+        }
+
+        Ok(r)
+    }
+
+    async fn hide_if_blocked(
+        &self,
+        trans: &DatabaseTransaction,
+        mut message: Message,
+    ) -> DatabaseResult<Message> {
+        if self.hide_blocked && Self::trans_is_blocked(trans, &message.from.key).await? {
+            message.content = Content::Blocked;
+        }
+
+        Ok(message)
+    }
+
+    /// Replaces every channel to a peer that rotated its identity by a channel
+    /// to its new cert, see [`Database::rotate_identity`]. A
+    /// [`patch::KeySupersede`] whose signature does not check against the old
+    /// key is ignored.
+    ///
+    /// Also drops the channels kept with an old identity of this user once the
+    /// channel replacing them is in sync, and forgets the old private key once
+    /// no channel uses it. Returns how many channels were created or dropped.
+    pub async fn migrate_superseded_channels(&self) -> DatabaseResult<usize> {
+        let mut trans = self.connection.begin().await?;
+        let mut count = 0;
+
+        let supersedes = key_supersede::Entity::find()
+            .find_also_related(conversation::Entity)
+            .all(&trans)
+            .await?;
+        for (supersede, conversation) in supersedes {
+            let channel = channel::Entity::find()
+                .filter(channel::Column::Conversation.eq(supersede.conversation))
+                .filter(channel::Column::Peer.eq(supersede.old))
+                .filter(channel::Column::LocalKey.is_null())
+                .one(&trans)
+                .await?;
+            let Some(channel) = channel else { continue; };
+
+            let old = entity::entity::key::Entity::find_by_id(supersede.old)
+                .one(&trans)
+                .await?
+                .expect("Corrupted database");
+            let new = entity::entity::key::Entity::find_by_id(supersede.new)
+                .one(&trans)
+                .await?
+                .expect("Corrupted database");
+            let Ok(old_key) = patch::Key::new(old.public) else { continue; };
+            let Ok(new_key) = patch::Key::new(new.public) else { continue; };
+            let payload = patch::KeySupersede::signed_payload(&old_key, &new_key);
+            if UnparsedPublicKey::new(&ED25519, &*old_key)
+                .verify(&payload, &supersede.signature)
+                .is_err()
+            {
+                continue;
+            }
+
+            let conversation = conversation.expect("Corrupted database");
+            let conversation = Conversation::with_members(&trans, conversation).await?;
+            let peer = Ed25519Cert((*new_key).try_into().expect("Key has 32 bytes"));
+
+            channel.delete(&trans).await?;
+            self.trans_create_channel(&mut trans, conversation, peer, Default::default())
+                .await?;
+            count += 1;
+        }
+
+        let retired = channel::Entity::find()
+            .filter(channel::Column::LocalKey.is_not_null())
+            .all(&trans)
+            .await?;
+        for channel in retired {
+            let replaced = channel::Entity::find()
+                .filter(channel::Column::Conversation.eq(channel.conversation))
+                .filter(channel::Column::Peer.eq(channel.peer))
+                .filter(channel::Column::LocalKey.is_null())
+                .filter(channel::Column::Snapshot.is_null())
+                .count(&trans)
+                .await?;
+            if replaced == 0 {
+                continue;
+            }
+
+            channel.delete(&trans).await?;
+            count += 1;
+        }
+        remove_unused_snapshots(&trans).await?;
+
+        for local in local::Entity::find()
+            .filter(local::Column::Retired.eq(true))
+            .all(&trans)
+            .await?
+        {
+            let used = channel::Entity::find()
+                .filter(channel::Column::LocalKey.eq(local.key))
+                .count(&trans)
+                .await?;
+            if used == 0 {
+                local.delete(&trans).await?;
+            }
+        }
+
+        trans.commit().await?;
+        Ok(count)
+    }
+
+    /// Removes `peer` from the conversation and drops the channel to it.
+    pub async fn remove_member(
+        &self,
+        conversation: &Conversation,
+        peer: Ed25519Cert,
+    ) -> DatabaseResult<()> {
+        let mut trans = self.connection.begin().await?;
+
+        self.trans_remove_member(&mut trans, conversation, peer)
+            .await?;
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    async fn trans_remove_member(
+        &self,
+        trans: &mut DatabaseTransaction,
+        conversation: &Conversation,
+        peer: Ed25519Cert,
+    ) -> DatabaseResult<()> {
+        self.set_new_patch(
+            trans,
+            patch::MemberRemoval {
+                key: patch::Key::new_exact(&peer.0),
+                conversation: conversation.uuid,
+                removed: true,
+                crdt: Default::default(),
+            },
+        )
+        .await?;
+
+        Self::trans_remove_channel(trans, conversation, peer).await
+    }
+
+    /// Makes `members` the exact member list of the conversation, adding and
+    /// removing only the difference. The local user is always kept.
+    pub async fn set_members(
+        &self,
+        conversation: &Conversation,
+        members: &[Ed25519Cert],
+    ) -> DatabaseResult<()> {
+        let mut trans = self.connection.begin().await?;
+
+        let conversation = Self::trans_get_conversation(&trans, conversation.uuid)
+            .await?
+            .unwrap_or_else(|| conversation.clone());
+        let wanted = members
+            .iter()
+            .filter(|member| *member != self.cert())
+            .collect::<HashSet<_>>();
+        let current = conversation
+            .members
+            .iter()
+            .map(|member| &member.key)
+            .filter(|member| *member != self.cert())
+            .collect::<HashSet<_>>();
+
+        for removed in current.difference(&wanted) {
+            self.trans_remove_member(&mut trans, &conversation, **removed)
+                .await?;
+        }
+
+        for added in wanted.difference(&current) {
+            self.trans_create_channel(
+                &mut trans,
+                conversation.clone(),
+                **added,
+                Default::default(),
+            )
+            .await?;
+        }
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    /// Creates a token that lets a peer join `conversation` through
+    /// [`Database::redeem_invite`]. `expires` is an unix timestamp in seconds.
+    ///
+    /// Tokens are stored only locally and enforced by this host when redeemed,
+    /// they give no cryptographic guarantee.
+    pub async fn create_invite(
+        &self,
+        conversation: &Conversation,
+        single_use: bool,
+        expires: Option<i64>,
+    ) -> DatabaseResult<InviteToken> {
+        let trans = self.connection.begin().await?;
+
+        let id = patch::Conversation::get_or_create(conversation.uuid, &trans)
+            .await
+            .id;
+        let token = Uuid::new_v4();
+        let uuid = SplitUuid::from(token);
+        invite::ActiveModel {
+            id: ActiveValue::NotSet,
+            uuid0: ActiveValue::Set(uuid.0),
+            uuid1: ActiveValue::Set(uuid.1),
+            uuid2: ActiveValue::Set(uuid.2),
+            uuid3: ActiveValue::Set(uuid.3),
+            conversation: ActiveValue::Set(id),
+            single_use: ActiveValue::Set(single_use),
+            expires: ActiveValue::Set(expires),
+            used: ActiveValue::Set(false),
+        }
+        .insert(&trans)
+        .await?;
+
+        trans.commit().await?;
+        Ok(InviteToken(token))
+    }
+
+    /// Validates `token` at `now`, an unix timestamp in seconds, and creates a
+    /// channel to `peer` on the conversation it was created for.
+    pub async fn redeem_invite(
+        &self,
+        token: InviteToken,
+        peer: Ed25519Cert,
+        now: i64,
+    ) -> DatabaseResult<Conversation> {
+        let mut trans = self.connection.begin().await?;
+
+        let uuid_filter = SplitUuid::from(token.0).to_filter::<invite::Column>();
+        let existent = invite::Entity::find()
+            .filter(uuid_filter.0)
+            .filter(uuid_filter.1)
+            .filter(uuid_filter.2)
+            .filter(uuid_filter.3)
+            .find_also_related(conversation::Entity)
+            .one(&trans)
+            .await?;
+        let (existent, conversation) = existent.ok_or(DatabaseError::UnknownInvite)?;
+
+        if existent.used {
+            return Err(DatabaseError::InviteUsed);
+        }
+        if existent.expires.is_some_and(|expires| now >= expires) {
+            return Err(DatabaseError::InviteExpired);
+        }
+        if existent.single_use {
+            invite::ActiveModel {
+                used: ActiveValue::Set(true),
+                ..existent.into_active_model()
+            }
+            .save(&trans)
+            .await?;
+        }
+
+        let conversation = Conversation::with_members(&trans, conversation.unwrap()).await?;
+        self.trans_create_channel(&mut trans, conversation.clone(), peer, Default::default())
+            .await?;
+
+        trans.commit().await?;
+        Ok(conversation)
+    }
+
+    pub fn start_sync(&self, channel: ChannelData) -> PatchSync<DatabaseTransaction> {
+        let ctx = SqliteSyncCtx {
+            channel: channel.id,
+            filter: self.patch_filter,
+            relay: self.relay,
+            log_context: channel.log_context(),
+        };
+
+        PatchSync::new(ctx, channel.peer_cert.as_author(), channel.conversation)
+            .with_log_context(channel.log_context())
+            .with_own_device(channel.own_device)
+            .with_interleave(self.sync_interleave)
+            .with_handshake()
+    }
+
+    /// Snapshot of `conversation` to seed a new channel with. Channels created
+    /// with the same options share the latest snapshot, each draining it with
+    /// its own cursor, as long as the patches since it are still in the sync
+    /// log.
+    async fn initial_sync(
+        trans: &mut DatabaseTransaction,
+        conversation: Conversation,
+        options: InitialSyncOptions,
+    ) -> DatabaseResult<snapshot::Model> {
+        let id = patch::Conversation::get_or_create(conversation.uuid, trans)
+            .await
+            .id;
+        let key = bincode::serialize(&options)?;
+
+        let latest = snapshot::Entity::find()
+            .filter(snapshot::Column::Conversation.eq(id))
+            .filter(snapshot::Column::Options.eq(key.clone()))
+            .order_by(snapshot::Column::Id, Order::Desc)
+            .one(trans)
+            .await?;
+        if let Some(latest) = latest {
+            if Self::in_sync_log(trans, latest.sync_index).await?
+                && Self::is_whole(trans, &latest).await?
+            {
+                return Ok(latest);
+            }
+        }
+
+        let snapshot = snapshot::ActiveModel {
+            id: ActiveValue::NotSet,
+            conversation: ActiveValue::Set(id),
+            options: ActiveValue::Set(key),
+            sync_index: ActiveValue::Set(Self::current_sync_index(trans).await?),
+        }
+        .insert(trans)
+        .await?;
+        Self::seed_snapshot(trans, snapshot.id, conversation, options).await?;
+
+        Ok(snapshot)
+    }
+
+    /// Seeds again the snapshots a migration emptied after dropping patches it
+    /// could no longer read. Any other snapshot holds at least its conversation.
+    async fn seed_emptied_snapshots(connection: &DatabaseConnection) -> DatabaseResult<()> {
+        let mut trans = connection.begin().await?;
+
+        let snapshots = snapshot::Entity::find()
+            .find_also_related(conversation::Entity)
+            .all(&trans)
+            .await?;
+        for (snapshot, conversation) in snapshots {
+            let seeded = initial_sync::Entity::find()
+                .filter(initial_sync::Column::Snapshot.eq(snapshot.id))
+                .count(&trans)
+                .await?;
+            if seeded > 0 {
+                continue;
+            }
+
+            let conversation = conversation.expect("Corrupted database");
+            let conversation = Conversation::with_members(&trans, conversation).await?;
+            let options = bincode::deserialize(&snapshot.options).unwrap_or_default();
+            Self::seed_snapshot(&mut trans, snapshot.id, conversation, options).await?;
+        }
+
+        trans.commit().await?;
+        Ok(())
+    }
+
+    /// Whether `snapshot` still starts with its conversation, that is,
+    /// [`Database::compact`] did not drop the patches its channels already got.
+    async fn is_whole(
+        trans: &DatabaseTransaction,
+        snapshot: &snapshot::Model,
+    ) -> DatabaseResult<bool> {
+        let first = initial_sync::Entity::find()
+            .filter(initial_sync::Column::Snapshot.eq(snapshot.id))
+            .order_by(initial_sync::Column::Id, Order::Asc)
+            .one(trans)
+            .await?;
+
+        Ok(first.is_some_and(|first| {
+            matches!(
+                bincode::deserialize(&first.payload),
+                Ok(Patch::Conversation(_))
+            )
+        }))
+    }
+
+    /// Whether every patch after `index` is still in the sync log. Patches are
+    /// pruned up to the sync index of the channel that is most behind.
+    async fn in_sync_log(trans: &DatabaseTransaction, index: i32) -> DatabaseResult<bool> {
+        if index == Self::current_sync_index(trans).await? {
+            return Ok(true);
+        }
+
+        let behind = channel::Entity::find()
+            .filter(channel::Column::SyncIndex.lte(index))
+            .count(trans)
+            .await?;
+
+        Ok(behind > 0)
+    }
+
+    async fn seed_snapshot(
+        trans: &mut DatabaseTransaction,
+        snapshot_id: i32,
+        conversation: Conversation,
+        options: InitialSyncOptions,
+    ) -> DatabaseResult<()> {
+        let InitialSyncOptions {
+            history,
+            message_status,
+        } = options;
+        let id = patch::Conversation::get_or_create(conversation.uuid, trans)
+            .await
+            .id;
+
+        Self::save_initial_patch(
+            trans,
+            snapshot_id,
+            patch::Conversation {
+                id: conversation.uuid,
+                crdt: conversation.crdt,
+                title: conversation.title,
+            },
+        )
+        .await?;
+
+        let contacts = contact::Entity::find()
+            .find_also_related(entity::entity::key::Entity)
+            .all(trans)
+            .await?;
+        for model in contacts {
+            let (contact, key) = model;
+            Self::save_initial_patch(
+                trans,
+                snapshot_id,
+                patch::Contact::from((key.unwrap(), contact)),
+            )
+            .await?;
+        }
+
+        let members = member::Entity::find()
+            .filter(member::Column::Conversation.eq(id))
+            .find_also_related(entity::entity::key::Entity)
+            .all(trans)
+            .await?;
+        for model in members {
+            let (member, key) = model;
+            let key = key.unwrap();
+            let removal =
+                patch::MemberRemoval::from((key.clone(), member.clone(), conversation.uuid));
+
+            Self::save_initial_patch(
+                trans,
+                snapshot_id,
+                patch::Member::from((key, member, conversation.uuid)),
+            )
+            .await?;
+            if removal.crdt != CrdtWritable::default() {
+                Self::save_initial_patch(trans, snapshot_id, removal).await?;
+            }
+        }
+
+        let supersedes = key_supersede::Entity::find()
+            .filter(key_supersede::Column::Conversation.eq(id))
+            .all(trans)
+            .await?;
+        for supersede in supersedes {
+            let old = entity::entity::key::Entity::find_by_id(supersede.old)
+                .one(trans)
+                .await?
+                .unwrap();
+            let new = entity::entity::key::Entity::find_by_id(supersede.new)
+                .one(trans)
+                .await?
+                .unwrap();
+
+            Self::save_initial_patch(
+                trans,
+                snapshot_id,
+                patch::KeySupersede::from((old, new, supersede, conversation.uuid)),
+            )
+            .await?;
+        }
+
+        let clear = history_clear::Entity::find_by_id(id).one(trans).await?;
+        if let Some(clear) = clear {
+            Self::save_initial_patch(
+                trans,
+                snapshot_id,
+                patch::HistoryClear::from((clear, conversation.uuid)),
+            )
+            .await?;
+        }
+
+        let messages = match Self::history_condition(trans, id, history).await? {
+            Some(condition) => {
+                message::Entity::find()
+                    .filter(message::Column::Conversation.eq(id))
+                    .filter(condition)
+                    .find_also_related(entity::entity::key::Entity)
+                    .all(trans)
+                    .await?
+            }
+            None => Vec::new(),
+        };
+        let seeded = messages
+            .iter()
+            .map(|(message, _)| Uuid::from(message.get_uuid()))
+            .collect::<HashSet<_>>();
+
+        // Only the files of seeded messages, a file no message refers to
+        // anymore was cleared along with its message.
+        let patches = attachment::Entity::find()
+            .filter(attachment::Column::Conversation.eq(id))
+            .filter(
+                attachment::Column::Id.is_in(
+                    messages
+                        .iter()
+                        .filter_map(|(message, _)| message.attachment),
+                ),
+            );
+        // The file of a deleted message must not spread any further, unless a
+        // message that is still shown refers to it too.
+        let shown = messages
+            .iter()
+            .filter(|(message, _)| !message.deleted)
+            .filter_map(|(message, _)| message.attachment)
+            .collect::<HashSet<_>>();
+        let deleted = messages
+            .iter()
+            .filter(|(message, _)| message.deleted)
+            .filter_map(|(message, _)| message.attachment)
+            .filter(|attachment| !shown.contains(attachment))
+            .collect::<HashSet<_>>();
+        for patch in patches.all(trans).await? {
+            if deleted.contains(&patch.id) {
+                continue;
+            }
+            let patch = patch::Attachment::from((conversation.uuid, patch));
+            let hash = patch.hash;
+            Self::save_initial_patch(trans, snapshot_id, patch).await?;
+
+            let Some(hash) = hash else { continue };
+            let mut after = -1;
+            while let Some(chunk) = next_chunk(trans, &hash, after).await? {
+                after = chunk.index;
+                Self::save_initial_patch(
+                    trans,
+                    snapshot_id,
+                    patch::AttachmentChunk::from((conversation.uuid, chunk)),
+                )
+                .await?;
+            }
+        }
+
+        for model in messages {
+            let (message, key) = model;
+            let attachment = match message.attachment {
+                Some(id) => Some(
+                    AttachmentMetaModel::find_by_id(id)
+                        .one(trans)
+                        .await?
+                        .unwrap(),
+                ),
+                None => None,
+            };
+
+            Self::save_initial_patch(
+                trans,
+                snapshot_id,
+                patch::NewMessage::from((
+                    message.clone(),
+                    key.unwrap(),
+                    conversation.uuid,
+                    attachment,
+                )),
+            )
+            .await?;
+            if message.text_crdt_generation > 0 {
+                Self::save_initial_patch(
+                    trans,
+                    snapshot_id,
+                    patch::MessageEdit::from((message.clone(), conversation.uuid)),
+                )
+                .await?;
+            }
+            if message.deleted {
+                Self::save_initial_patch(
+                    trans,
+                    snapshot_id,
+                    patch::MessageTombstone::from((message.clone(), conversation.uuid)),
+                )
+                .await?;
+            }
+            if message_status {
+                Self::save_initial_patch(
+                    trans,
+                    snapshot_id,
+                    patch::MessageStatus::from((message, conversation.uuid)),
+                )
+                .await?;
+            }
+        }
+
+        if message_status {
+            let receipts = receipt::Entity::find()
+                .filter(receipt::Column::Conversation.eq(id))
+                .find_also_related(entity::entity::key::Entity)
+                .all(trans)
+                .await?;
+            for model in receipts {
+                let (receipt, key) = model;
+                if !seeded.contains(&receipt.get_uuid().into()) {
+                    continue;
+                }
+
+                Self::save_initial_patch(
+                    trans,
+                    snapshot_id,
+                    patch::Receipt::from((receipt, key.unwrap(), conversation.uuid)),
+                )
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Condition selecting the messages allowed by `history`, `None` when no
+    /// message is.
+    async fn history_condition(
+        trans: &DatabaseTransaction,
+        conversation: i32,
+        history: HistoryPolicy,
+    ) -> DatabaseResult<Option<Condition>> {
+        let HistoryPolicy::Since(cursor) = history else { return Ok(Some(Condition::all())); };
+
+        let uuid_filter = SplitUuid::from(cursor).to_filter::<message::Column>();
+        let cursor = message::Entity::find()
+            .filter(message::Column::Conversation.eq(conversation))
+            .filter(uuid_filter.0)
+            .filter(uuid_filter.1)
+            .filter(uuid_filter.2)
+            .filter(uuid_filter.3)
+            .one(trans)
+            .await?;
+        let Some(cursor) = cursor else { return Ok(None); };
+        let position = CrdtPosition {
+            sequence: cursor.crdt_sequence,
+            author: Author(cursor.crdt_author),
+            id: cursor.get_uuid().into(),
+        };
+
+        Ok(Some(position.following()))
+    }
+
+    async fn set_new_patch<P: CrdtInstance<Crdt = CrdtWritable> + Into<Patch> + 'static>(
+        &self,
+        trans: &mut DatabaseTransaction,
+        patch: P,
+    ) -> DatabaseResult<()>
+    where
+        DatabaseTransaction: CrdtWritableTransaction<P>,
+    {
+        let patch = trans.set(self.author(), patch).await;
+
+        Self::save_patch_for_sync(trans, patch).await?;
+
+        Ok(())
+    }
+
+    async fn push_new_patch<P: CrdtInstance<Crdt = CrdtWritableSequence> + Into<Patch> + 'static>(
+        &self,
+        trans: &mut DatabaseTransaction,
+        patch: P,
+    ) -> DatabaseResult<()>
+    where
+        DatabaseTransaction: CrdtWritableSequenceTransaction<P>,
+    {
+        let patch = trans.push(self.author(), patch).await;
+
+        Self::save_patch_for_sync(trans, patch).await?;
+
+        Ok(())
+    }
+
+    async fn add_only_new_patch<P: CrdtInstance + Into<Patch> + 'static>(
+        &self,
+        trans: &mut DatabaseTransaction,
+        mut patch: P,
+    ) -> DatabaseResult<bool>
+    where
+        DatabaseTransaction: CrdtTransaction<P>,
+    {
+        patch.set_crdt(patch.crdt().next(self.author()));
+        let patch = trans.merge(patch).await;
+        let Some(patch) = patch else { return Ok(false); };
+
+        Self::save_patch_for_sync(trans, patch).await?;
+
+        Ok(true)
+    }
+
+    async fn save_patch_for_sync<P: Into<Patch>>(
+        trans: &DatabaseTransaction,
+        patch: P,
+    ) -> DatabaseResult<()> {
+        let patch: Patch = patch.into();
+
+        entity::entity::sync::ActiveModel {
+            id: ActiveValue::NotSet,
+            payload: ActiveValue::Set(bincode::serialize(&patch).unwrap()),
+        }
+        .save(trans)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn save_initial_patch<P: Into<Patch>>(
+        trans: &DatabaseTransaction,
+        snapshot_id: i32,
+        patch: P,
+    ) -> DatabaseResult<()> {
+        let patch: Patch = patch.into();
+
+        initial_sync::ActiveModel {
+            id: ActiveValue::NotSet,
+            snapshot: ActiveValue::Set(snapshot_id),
+            payload: ActiveValue::Set(bincode::serialize(&patch).unwrap()),
+        }
+        .save(trans)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Keeps the index of the patch just pushed as the one that sent the
+    /// message `id`, see [`Database::delivery_state`].
+    async fn mark_sent(trans: &DatabaseTransaction, id: Uuid) -> DatabaseResult<()> {
+        let uuid_filter = SplitUuid::from(id).to_filter::<message::Column>();
+        let message = message::Entity::find()
+            .filter(uuid_filter.0)
+            .filter(uuid_filter.1)
+            .filter(uuid_filter.2)
+            .filter(uuid_filter.3)
+            .one(trans)
+            .await?
+            .expect("Message was just inserted");
+
+        message::ActiveModel {
+            sync_index: ActiveValue::Set(Some(Self::current_sync_index(trans).await?)),
+            ..message.into_active_model()
+        }
+        .save(trans)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn current_sync_index(trans: &DatabaseTransaction) -> DatabaseResult<i32> {
+        Ok(entity::entity::sync::Entity::find()
+            .order_by(entity::entity::sync::Column::Id, Order::Desc)
+            .one(trans)
+            .await?
+            .map(|sync| sync.id)
+            .unwrap_or(0))
+    }
+}
+
+/// Chunk of the blob `hash` following the one at index `after`.
+async fn next_chunk<C: ConnectionTrait>(
+    connection: &C,
+    hash: &BlobHash,
+    after: i32,
+) -> DatabaseResult<Option<blob::Model>> {
+    Ok(blob::Entity::find()
+        .filter(blob::Column::Hash.eq(hash.to_vec()))
+        .filter(blob::Column::Index.gt(after))
+        .order_by(blob::Column::Index, Order::Asc)
+        .one(connection)
+        .await?)
+}
+
+pub struct SharedDatabase {}
+impl SharedDatabase {
+    pub fn with_user(user: Uuid) -> DatabaseResult<Self> {
+        let _ = user;
+        unimplemented!()
+    }
+
+    pub fn add_contact(&mut self, contact: Contact) -> DatabaseResult<()> {
+        let _ = contact;
+        unimplemented!()
+    }
+
+    pub fn add_message(&mut self, message: Message) -> DatabaseResult<()> {
+        let _ = message;
+        unimplemented!()
+    }
+
+    pub fn set_message(&mut self, index: usize, message: &Message) -> DatabaseResult<()> {
+        let _ = index;
+        let _ = message;
+        unimplemented!()
+    }
+
+    pub fn list_messages(&self) -> std::vec::IntoIter<DatabaseResult<Message>> {
+        unimplemented!()
+    }
+
+    pub fn list_contact(&self) -> std::vec::IntoIter<DatabaseResult<Contact>> {
+        unimplemented!()
+    }
+
+    pub fn get_contact(&self, uuid: Uuid) -> DatabaseResult<Option<Contact>> {
+        let _ = uuid;
+        unimplemented!()
+    }
+
+    pub fn save(&mut self) -> Vec<u8> {
+        unimplemented!()
+    }
+
+    pub fn load_with_user(data: &[u8], user: Uuid) -> DatabaseResult<Self> {
+        let _ = data;
+        let _ = user;
+        unimplemented!()
+    }
+
+    pub fn start_sync(&self) -> UnimplementedSync {
+        UnimplementedSync
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Conversation {
+    pub uuid: Uuid,
+    pub title: Option<String>,
+    pub crdt: CrdtWritable,
+    pub members: Vec<Contact>,
+}
+impl Conversation {
+    async fn with_members(
+        trans: &DatabaseTransaction,
+        conversation: conversation::Model,
+    ) -> DatabaseResult<Conversation> {
+        Self::with_cached_members(trans, &mut Default::default(), conversation).await
+    }
+
+    async fn with_cached_members(
+        trans: &DatabaseTransaction,
+        contacts: &mut ContactCache,
+        conversation: conversation::Model,
+    ) -> DatabaseResult<Conversation> {
+        let ids = member::Entity::find()
+            .filter(member::Column::Conversation.eq(conversation.id))
+            .filter(member::Column::Removed.eq(false))
+            .all(trans)
+            .await?
+            .into_iter()
+            .map(|member| member.contact)
+            .collect::<Vec<_>>();
+        contacts.preload(trans, ids.iter().copied()).await?;
+
+        let mut members = Vec::new();
+        for id in ids {
+            members.push(contacts.get(trans, id).await?);
+        }
+
+        Ok(Conversation {
+            uuid: conversation.get_uuid().into(),
+            title: conversation.title,
+            crdt: CrdtWritable {
+                generation: conversation.crdt_generation,
+                author: Author(conversation.crdt_author),
+            },
+            members,
+        })
+    }
+
+    /// Describes who among `typers` is typing, naming them by their member
+    /// name. Empty when nobody is.
+    pub fn typing_label(&self, typers: &[Ed25519Cert]) -> String {
+        let name = |typer: &Ed25519Cert| {
+            self.members
+                .iter()
+                .find(|member| member.key == *typer && !member.display_name().is_empty())
+                .map(|member| member.display_name())
+                .unwrap_or("Someone")
+        };
+
+        match typers {
+            [] => String::new(),
+            [typer] => format!("{} is typing", name(typer)),
+            [first, second] => format!("{} and {} are typing", name(first), name(second)),
+            _ => "Several people are typing".to_string(),
+        }
+    }
+
+    /// Members, other than the local user, that there is no channel to.
+    pub async fn mesh_gaps(&self, database: &Database) -> DatabaseResult<Vec<Ed25519Cert>> {
+        let channels = database.list_channels(self).await?;
+
+        Ok(self
+            .members
+            .iter()
+            .map(|member| member.key)
+            .filter(|key| key != database.cert())
+            .filter(|key| !channels.iter().any(|channel| channel.peer_cert == *key))
+            .collect())
+    }
+
+    /// Row id of the conversation, looked up by uuid as it is not stable
+    /// across databases.
+    async fn row_id(&self, trans: &DatabaseTransaction) -> DatabaseResult<Option<i32>> {
+        let uuid_filter = SplitUuid::from(self.uuid).to_filter::<conversation::Column>();
+
+        Ok(conversation::Entity::find()
+            .filter(uuid_filter.0)
+            .filter(uuid_filter.1)
+            .filter(uuid_filter.2)
+            .filter(uuid_filter.3)
+            .one(trans)
+            .await?
+            .map(|conversation| conversation.id))
+    }
+
+    pub async fn length(&self, database: &Database) -> DatabaseResult<usize> {
+        let trans = database.connection.begin().await?;
+        let Some(id) = self.row_id(&trans).await? else { return Ok(0); };
+
+        let count = message::Entity::find()
+            .filter(message::Column::Conversation.eq(id))
+            .count(&trans)
+            .await?;
+
+        Ok(count as usize)
+    }
+
+    pub async fn get_message(
+        &self,
+        database: &Database,
+        index: usize,
+    ) -> DatabaseResult<Option<Message>> {
+        let trans = database.connection.begin().await?;
+        let Some(id) = self.row_id(&trans).await? else { return Ok(None); };
+
+        let query = message::Entity::find().filter(message::Column::Conversation.eq(id));
+        let message = MessageOrder::CausalSequence
+            .sort(query, Order::Asc)
+            .offset(Some(index as u64))
+            .one(&trans)
+            .await?;
+
+        let Some(message) = message else { return Ok(None); };
+        let message = Message::from_model(&trans, message, self.uuid).await?;
+        Ok(Some(database.hide_if_blocked(&trans, message).await?))
+    }
+
+    /// Up to `limit` messages right before `before`, or the last ones when
+    /// `None`, in `order`. Pass the first message of a page to get the
+    /// previous one.
+    pub async fn messages_page(
+        &self,
+        database: &Database,
+        before: Option<&Message>,
+        limit: usize,
+        order: MessageOrder,
+    ) -> DatabaseResult<Vec<Message>> {
+        let trans = database.connection.begin().await?;
+        let Some(id) = self.row_id(&trans).await? else { return Ok(Default::default()); };
+
+        let mut query = message::Entity::find().filter(message::Column::Conversation.eq(id));
+        if let Some(before) = before {
+            let position = CrdtPosition::new(before.crdt, before.uuid);
+            query = query.filter(match order {
+                MessageOrder::CausalSequence => position.preceding(),
+                MessageOrder::Timestamp => {
+                    let mut cursor = vec![(message::Column::CreatedAt, before.created_at)];
+                    cursor.extend(position.cursor());
+                    before_cursor(&cursor)
+                }
+            });
+        }
+        query = order.sort(query, Order::Desc);
+        let mut models = query.limit(limit as u64).all(&trans).await?;
+        models.reverse();
+
+        self.hydrate(database, &trans, models).await
+    }
+
+    /// Up to `count` messages from the one at `start` on, in `order`, read in
+    /// one transaction. With [`MessageOrder::CausalSequence`] the same
+    /// messages as `count` calls to [`Conversation::get_message`].
+    pub async fn get_messages_range(
+        &self,
+        database: &Database,
+        start: usize,
+        count: usize,
+        order: MessageOrder,
+    ) -> DatabaseResult<Vec<Message>> {
+        let trans = database.connection.begin().await?;
+        let Some(id) = self.row_id(&trans).await? else { return Ok(Default::default()); };
+
+        let query = message::Entity::find().filter(message::Column::Conversation.eq(id));
+        let models = order
+            .sort(query, Order::Asc)
+            .offset(Some(start as u64))
+            .limit(count as u64)
+            .all(&trans)
+            .await?;
+
+        self.hydrate(database, &trans, models).await
+    }
+
+    async fn hydrate(
+        &self,
+        database: &Database,
+        trans: &DatabaseTransaction,
+        models: Vec<message::Model>,
+    ) -> DatabaseResult<Vec<Message>> {
+        let models = models.into_iter().map(|model| (model, self.uuid));
+        database.hydrate(trans, models.collect()).await
+    }
+
+    pub async fn get_message_by_uuid(
+        &self,
+        database: &Database,
+        uuid: Uuid,
+    ) -> DatabaseResult<Option<Message>> {
+        let trans = database.connection.begin().await?;
+
+        let Some(message) = Message::find(&trans, uuid, self.uuid).await? else { return Ok(None); };
+        Ok(Some(database.hide_if_blocked(&trans, message).await?))
+    }
+
+    /// Messages whose text contains `term`, in conversation order.
+    pub async fn search(&self, database: &Database, term: &str) -> DatabaseResult<Vec<Message>> {
+        let trans = database.connection.begin().await?;
+        let Some(id) = self.row_id(&trans).await? else { return Ok(Default::default()); };
+
+        let query = message::Entity::find()
+            .filter(message::Column::Conversation.eq(id))
+            .filter(Expr::col(message::Column::Text).like(like_contains(term)))
+            .filter(message::Column::Deleted.eq(false));
+        let models = MessageOrder::CausalSequence
+            .sort(query, Order::Asc)
+            .all(&trans)
+            .await?;
+
+        let mut contacts = ContactCache::default();
+        contacts
+            .preload(&trans, models.iter().map(|model| model.from))
+            .await?;
+
+        let mut r = Vec::new();
+        for model in models {
+            r.push(Message::from_cached_model(&trans, &mut contacts, model, self.uuid).await?);
+        }
+
+        Ok(r)
+    }
+
+    /// Same matches as [`Conversation::search`], best match first.
+    ///
+    /// Each occurrence of the term counts, occurrences as a whole word count
+    /// extra, and newer messages win ties.
+    pub async fn search_ranked(
+        &self,
+        database: &Database,
+        term: &str,
+    ) -> DatabaseResult<Vec<Message>> {
+        let messages = self.search(database, term).await?;
+        let len = messages.len() as f64;
+
+        let mut scored = messages
+            .into_iter()
+            .enumerate()
+            .map(|(position, message)| {
+                let recency = position as f64 / len;
+                (relevance(message.text(), term) as f64 + recency, message)
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(scored.into_iter().map(|(_, message)| message).collect())
+    }
+}
+
+/// Pattern matching texts that contain `term`, with the wildcards of `LIKE`
+/// in it taken literally.
+fn like_contains(term: &str) -> LikeExpr {
+    let mut pattern = String::from("%");
+    for c in term.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+
+    LikeExpr::new(pattern).escape('\\')
+}
+
+fn relevance(text: &str, term: &str) -> usize {
+    let text = text.to_lowercase();
+    let term = term.to_lowercase();
+    if term.is_empty() {
+        return 0;
+    }
+
+    let is_boundary = |c: Option<char>| !c.is_some_and(char::is_alphanumeric);
+
+    text.match_indices(&term)
+        .map(|(start, _)| {
+            let before = text[..start].chars().next_back();
+            let after = text[start + term.len()..].chars().next();
+
+            match is_boundary(before) && is_boundary(after) {
+                true => 3,
+                false => 2,
+            }
+        })
+        .sum()
+}
+
+/// Summary of a conversation, see [`Database::conversation_previews`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationPreview {
+    pub conversation: Conversation,
+    /// Latest message in conversation order.
+    pub last_message: Option<Message>,
+    /// Messages from others that were not read yet.
+    pub unread: usize,
+}
+
+/// A write lost to a concurrent write of the same value, see
+/// [`Database::recent_conflicts`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub target: ConflictTarget,
+    /// Generation both writes had.
+    pub generation: i32,
+    pub kept_author: Author,
+    /// Value kept, a title or a name.
+    pub kept: Option<String>,
+    pub discarded_author: Author,
+    /// Value that was overwritten.
+    pub discarded: Option<String>,
+}
+
+/// What two writes were setting, see [`Conflict`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictTarget {
+    /// Title of the conversation.
+    Conversation(Uuid),
+    /// Name of the contact.
+    Contact(Ed25519Cert),
+}
+impl Conflict {
+    /// `None` for a kind of conflict this build does not know, or a target it
+    /// can not read.
+    fn from_model(model: conflict_log::Model) -> Option<Self> {
+        let target = match model.kind.as_str() {
+            CONFLICT_ON_CONVERSATION => {
+                ConflictTarget::Conversation(Uuid::from_slice(&model.target).ok()?)
+            }
+            CONFLICT_ON_CONTACT => {
+                ConflictTarget::Contact(Ed25519Cert(model.target.try_into().ok()?))
+            }
+            _ => return None,
+        };
+
+        Some(Conflict {
+            target,
+            generation: model.generation,
+            kept_author: Author(model.kept_author),
+            kept: model.kept_value,
+            discarded_author: Author(model.discarded_author),
+            discarded: model.discarded_value,
+        })
+    }
+}
+
+/// Order of the messages listed by [`Conversation::messages_page`] and
+/// [`Conversation::get_messages_range`].
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageOrder {
+    /// Conversation order, the same on every peer.
+    #[default]
+    CausalSequence,
+    /// By [`Message::created_at`], as told by the clock of each sender, so a
+    /// reply may show before what it answers. Ties are in conversation order.
+    Timestamp,
+}
+impl MessageOrder {
+    fn columns(self) -> &'static [message::Column] {
+        const POSITION: [message::Column; 6] = [
+            message::Column::CrdtSequence,
+            message::Column::CrdtAuthor,
+            message::Column::Uuid0,
+            message::Column::Uuid1,
+            message::Column::Uuid2,
+            message::Column::Uuid3,
+        ];
+
+        match self {
+            MessageOrder::CausalSequence => &POSITION,
+            MessageOrder::Timestamp => &[
+                message::Column::CreatedAt,
+                POSITION[0],
+                POSITION[1],
+                POSITION[2],
+                POSITION[3],
+                POSITION[4],
+                POSITION[5],
+            ],
+        }
+    }
+
+    /// Sorts `query` in this order, or in reverse with [`Order::Desc`].
+    fn sort(self, mut query: Select<message::Entity>, order: Order) -> Select<message::Entity> {
+        for column in self.columns() {
+            query = query.order_by(*column, order.clone());
+        }
+
+        query
+    }
+}
+
+/// Which messages of a conversation are seeded to a new channel.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HistoryPolicy {
+    /// Every message.
+    #[default]
+    All,
+    /// Only messages after the one with the given uuid. If that message is not
+    /// part of the conversation no message is seeded.
+    Since(Uuid),
+}
+
+/// App-wide pause of notifications, kept only locally.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DoNotDisturb {
+    #[default]
+    Off,
+    /// Paused until the given unix timestamp in seconds, or until turned off.
+    On(Option<i64>),
+}
+impl DoNotDisturb {
+    const KEY: &str = "do_not_disturb";
+
+    pub fn active(&self, now: i64) -> bool {
+        match self {
+            DoNotDisturb::Off => false,
+            DoNotDisturb::On(until) => until.is_none_or(|until| now < until),
+        }
+    }
+}
+
+/// Outcome of [`Database::merge_from`].
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Patches read from the other database.
+    pub read: usize,
+    /// Patches that changed this database.
+    pub merged: usize,
+}
+
+/// Invite to a conversation, see [`Database::create_invite`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InviteToken(pub Uuid);
+
+/// What [`Database::compact`] removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactStats {
+    pub initial_sync_removed: u64,
+    pub sync_removed: u64,
+    /// By how much the database file shrank.
+    pub bytes_reclaimed: u64,
+}
+
+/// How a new channel is seeded, see [`Database::create_channel_with_options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InitialSyncOptions {
+    pub history: HistoryPolicy,
+    /// Also seed the status and receipts of every seeded message. Peers that
+    /// only keep the content, like an archival observer, can skip them to
+    /// halve the seeded rows.
+    pub message_status: bool,
+}
+impl Default for InitialSyncOptions {
+    fn default() -> Self {
+        InitialSyncOptions {
+            history: Default::default(),
+            message_status: true,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Contact {
+    id: i32,
+    pub key: Ed25519Cert,
+    pub name: String,
+    /// Confirmed by the local user, never synced, see
+    /// [`Database::set_verified`].
+    pub verified: bool,
+    /// Name given by the local user, never synced, see
+    /// [`Database::set_alias`].
+    pub alias: Option<String>,
+}
+impl Contact {
+    /// Identifies the contact across databases, unlike its row id.
+    pub fn cert(&self) -> Ed25519Cert {
+        self.key
+    }
+
+    /// The alias when there is one, the synced name otherwise.
+    pub fn display_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+/// Contacts already read within a transaction, keyed by their row id, so
+/// that hydrating many messages from the same senders reads each only once.
+#[derive(Default)]
+struct ContactCache(HashMap<i32, Contact>);
+impl ContactCache {
+    /// Reads, in a single query, those of `ids` not read yet.
+    async fn preload(
+        &mut self,
+        trans: &DatabaseTransaction,
+        ids: impl IntoIterator<Item = i32>,
+    ) -> DatabaseResult<()> {
+        let missing = ids
+            .into_iter()
+            .filter(|id| !self.0.contains_key(id))
+            .collect::<HashSet<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        for (contact, key) in contact::Entity::find()
+            .find_also_related(entity::entity::key::Entity)
+            .filter(contact::Column::Key.is_in(missing))
+            .all(trans)
+            .await?
+        {
+            let contact = Contact::from((key.expect("Corrupted database"), contact));
+            self.0.insert(contact.id, contact);
+        }
+
+        Ok(())
+    }
+
+    async fn get(&mut self, trans: &DatabaseTransaction, id: i32) -> DatabaseResult<Contact> {
+        self.preload(trans, [id]).await?;
+
+        Ok(self.0.get(&id).expect("Corrupted database").clone())
+    }
+}
+
+impl From<(entity::entity::key::Model, contact::Model)> for Contact {
+    fn from((key, contact): (entity::entity::key::Model, contact::Model)) -> Self {
+        let key = Ed25519Cert(key.public.as_slice().try_into().unwrap());
+
+        Contact {
+            id: contact.key,
+            key,
+            name: contact.name,
+            verified: contact.verified,
+            alias: contact.alias,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    id: i32,
+    pub uuid: Uuid,
+    pub from: Contact,
+    pub conversation: Uuid,
+    pub content: Content,
+    pub status: MessageStatus,
+    /// Bookmarked by the local user, never synced.
+    pub starred: bool,
+    pub meta: MessageMeta,
+    /// Position in the conversation, see [`Conversation::messages_page`].
+    pub crdt: CrdtWritableSequence,
+    /// Unix milliseconds when it was sent, by the sender's clock, or 0 if it
+    /// was sent before timestamps were kept. Only meant to be shown, peers'
+    /// clocks may disagree.
+    pub created_at: i64,
+}
+impl Message {
+    pub async fn from_model(
+        trans: &DatabaseTransaction,
+        message: message::Model,
+        conversation: Uuid,
+    ) -> DatabaseResult<Self> {
+        Self::from_cached_model(trans, &mut Default::default(), message, conversation).await
+    }
+
+    async fn from_cached_model(
+        trans: &DatabaseTransaction,
+        contacts: &mut ContactCache,
+        message: message::Model,
+        conversation: Uuid,
+    ) -> DatabaseResult<Self> {
+        let from = contacts.get(trans, message.from).await?;
+
+        Ok(Self::with_sender(message, from, conversation))
+    }
+
+    async fn find(
+        trans: &DatabaseTransaction,
+        uuid: Uuid,
+        conversation: Uuid,
+    ) -> DatabaseResult<Option<Self>> {
+        let uuid_filter = SplitUuid::from(uuid).to_filter::<message::Column>();
+        let message = message::Entity::find()
+            .filter(uuid_filter.0)
+            .filter(uuid_filter.1)
+            .filter(uuid_filter.2)
+            .filter(uuid_filter.3)
+            .one(trans)
+            .await?;
+
+        let Some(message) = message else { return Ok(None); };
+        Ok(Some(Self::from_model(trans, message, conversation).await?))
+    }
+
+    fn with_sender(message: message::Model, from: Contact, conversation: Uuid) -> Self {
+        Message {
+            id: message.id,
+            uuid: message.get_uuid().into(),
+            from,
+            conversation,
+            content: match (message.deleted, message.attachment) {
+                (true, _) => Content::Deleted,
+                (false, Some(attachment)) => Content::Attachment(
+                    message.text,
+                    attachment,
+                    AttachmentInfo {
+                        mime: message.mime,
+                        size: message.size.map(|size| size as u64),
+                        thumbnail: message.thumbnail,
+                    },
+                ),
+                (false, None) => Content::Text(message.text),
+            },
+            // Statuses added by newer peers are shown as the oldest one.
+            status: MessageStatus::try_from(message.status).unwrap_or_default(),
+            starred: message.starred,
+            meta: MessageMeta {
+                edited: message.text_crdt_generation > 0,
+                deleted: message.deleted,
+                reply_to: message
+                    .reply_to
+                    .map(|uuid| Uuid::from_slice(&uuid).expect("Corrupted database")),
+            },
+            crdt: CrdtWritableSequence {
+                writable: CrdtWritable {
+                    generation: message.crdt_generation,
+                    author: Author(message.crdt_author),
+                },
+                sequence: message.crdt_sequence,
+            },
+            created_at: message.created_at,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match &self.content {
+            Content::Text(text) => text,
+            Content::Attachment(text, ..) => text,
+            Content::Deleted | Content::Blocked => "",
+        }
+    }
+}
+
+/// What happened to a message besides its content, for the badges shown
+/// along with it.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageMeta {
+    pub edited: bool,
+    pub deleted: bool,
+    /// Uuid of the message this one replies to, which may not have arrived
+    /// yet.
+    pub reply_to: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    Text(String),
+    /// Filename, id of the attachment and what the sender declared of it.
+    Attachment(String, i32, AttachmentInfo),
+    /// The message was deleted, see [`Database::delete_message`].
+    Deleted,
+    /// The sender is blocked, see [`Database::set_hide_blocked`].
+    Blocked,
+}
+impl Default for Content {
+    fn default() -> Self {
+        Content::Text(Default::default())
+    }
+}
+
+/// Known before the payload of an attachment is fetched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttachmentInfo {
+    /// `None` when the sender did not recognize the type.
+    pub mime: Option<String>,
+    /// Length of the payload, `None` for attachments sent before it was
+    /// declared.
+    pub size: Option<u64>,
+    /// JPEG preview of images.
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+/// A message as written by [`Database::export_conversation_json`].
+#[derive(Serialize)]
+struct ExportedMessage {
+    uuid: Uuid,
+    /// Hex cert of the sender.
+    from: String,
+    sequence: i32,
+    created_at: i64,
+    status: MessageStatus,
+    edited: bool,
+    reply_to: Option<Uuid>,
+    #[serde(flatten)]
+    content: ExportedContent,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ExportedContent {
+    Text { text: String },
+    Attachment { uuid: Uuid, filename: String },
+    Deleted,
+    Blocked,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    #[default]
+    Sent,
+    Delivered,
+    Read,
+}
+impl TryFrom<i32> for MessageStatus {
+    type Error = DatabaseError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MessageStatus::Sent),
+            1 => Ok(MessageStatus::Delivered),
+            2 => Ok(MessageStatus::Read),
+            _ => Err(DatabaseError::UnknownStatus(value)),
+        }
+    }
+}
+impl From<MessageStatus> for i32 {
+    fn from(value: MessageStatus) -> Self {
+        match value {
+            MessageStatus::Sent => 0,
+            MessageStatus::Delivered => 1,
+            MessageStatus::Read => 2,
+        }
+    }
+}
+
+/// See [`Database::delivery_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+    /// Stored locally, no peer acked it yet.
+    Pending,
+    /// Acked by at least one peer.
+    Delivered,
+    /// The conversation has no channel to send it through.
+    Failed,
+}
+
+pub trait DbSync {
+    type Database;
+    type Message: Serialize + DeserializeOwned + std::fmt::Debug;
+
+    fn tx<'a>(
+        &'a mut self,
+        database: &'a mut Self::Database,
+    ) -> LocalBoxFuture<'a, DatabaseResult<Option<Self::Message>>>;
+    fn rx<'a>(
+        &'a mut self,
+        database: &'a mut Self::Database,
+        message: Self::Message,
+    ) -> LocalBoxFuture<'a, DatabaseResult<()>>;
+
+    /// Estimated offset of the peer's clock in milliseconds, positive when it
+    /// is ahead, once known.
+    fn peer_clock_skew(&self) -> Option<i64> {
+        None
+    }
+
+    /// Tells the peer that `author`, the local user, is typing, see
+    /// [`sync::Ephemeral::Typing`].
+    fn send_typing(&mut self, _author: Author) {}
+
+    /// Whether the peer reported typing within [`sync::TYPING_TIMEOUT`].
+    fn peer_typing(&self) -> bool {
+        false
+    }
+
+    /// Whether `message`, just returned by [`DbSync::tx`], is worth
+    /// compressing, see [`PipeSync::set_compression`](crate::pipe_sync::PipeSync::set_compression).
+    fn compressible(&self, _message: &Self::Message) -> bool {
+        true
+    }
+    /// Queues a message the peer answers right away, returning whether the
+    /// peer is known to answer it, see
+    /// [`PipeSync::set_keepalive`](crate::pipe_sync::PipeSync::set_keepalive).
+    fn ping(&mut self) -> bool {
+        false
+    }
+
+    /// Adds `capabilities` to those told to the peer in the handshake, only
+    /// before it is sent.
+    fn offer(&mut self, _capabilities: sync::Capabilities) {}
+
+    /// Capabilities both ends support, none before the peer's handshake.
+    fn capabilities(&self) -> sync::Capabilities {
+        Default::default()
+    }
+
+    /// Whether the peer's handshake is still awaited, see
+    /// [`PipeSync::set_keepalive`](crate::pipe_sync::PipeSync::set_keepalive).
+    fn handshaking(&self) -> bool {
+        false
+    }
+
+    /// Counts patches into `counters`, see
+    /// [`PipeSync::set_counters`](crate::pipe_sync::PipeSync::set_counters).
+    fn set_counters(&mut self, _counters: Arc<SyncCounters>) {}
+}
+
+#[derive(Default)]
+pub struct UnimplementedSync;
+impl DbSync for UnimplementedSync {
+    type Database = SharedDatabase;
+    type Message = Vec<u8>;
+
+    fn tx<'a>(
+        &'a mut self,
+        database: &'a mut Self::Database,
+    ) -> LocalBoxFuture<'a, DatabaseResult<Option<Vec<u8>>>> {
+        let _ = database;
+        unimplemented!()
+    }
+
+    fn rx<'a>(
+        &'a mut self,
+        database: &'a mut Self::Database,
+        message: Vec<u8>,
+    ) -> LocalBoxFuture<'a, DatabaseResult<()>> {
+        let _ = database;
+        let _ = message;
+        unimplemented!()
+    }
+}
+
+pub struct LocalDatabase {}
+impl LocalDatabase {
+    pub fn with_user(user: Uuid) -> DatabaseResult<LocalDatabase> {
+        let _ = user;
+        unimplemented!()
+    }
+
+    pub fn user(&self) -> Uuid {
+        unimplemented!()
+    }
+
+    pub fn set(&mut self, data: LocalDatabaseData) -> DatabaseResult<()> {
+        let _ = data;
+        unimplemented!()
+    }
+
+    pub fn get(&self) -> DatabaseResult<LocalDatabaseData> {
+        unimplemented!()
+    }
+
+    pub fn save(&mut self) -> Vec<u8> {
+        unimplemented!()
+    }
+
+    pub fn load(data: &[u8]) -> DatabaseResult<Self> {
+        let _ = data;
+        unimplemented!()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalDatabaseData {
+    pub user: Uuid,
+    pub channels: Vec<ChannelData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChannelData {
+    id: i32,
+    pub conversation: Uuid,
+    pub peer_cert: Ed25519Cert,
+    pub channel: String,
+    /// The peer is another device of the local user, see
+    /// [`Database::pair_device`].
+    pub own_device: bool,
+    local_key: Option<i32>,
+}
+impl ChannelData {
+    pub fn new(
+        id: i32,
+        conversation: Uuid,
+        peer_cert: Ed25519Cert,
+        private: &Ed25519Seed,
+    ) -> ChannelData {
+        let channel = private.x25519_agree(&format!("channel:{conversation}"), &peer_cert);
+
+        ChannelData {
+            id,
+            conversation,
+            peer_cert,
+            channel,
+            own_device: false,
+            local_key: None,
+        }
+    }
+
+    /// See [`SyncLogContext`].
+    pub fn log_context(&self) -> SyncLogContext {
+        SyncLogContext {
+            peer: self.peer_cert,
+            conversation: self.conversation,
+            channel: self.id,
+        }
+    }
+
+    /// Whether the channel still uses a key replaced by
+    /// [`Database::rotate_identity`].
+    pub fn retired(&self) -> bool {
+        self.local_key.is_some()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::channel::ChannelStateLabel;
+    use std::path::PathBuf;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("icechat-{}.sqlite", Uuid::new_v4()))
+    }
+
+    /// Hands `sync` the handshake of a peer as recent as itself, for the
+    /// patches it sends to be taken.
+    async fn greet(sync: &mut PatchSync<DatabaseTransaction>, trans: &mut DatabaseTransaction) {
+        let hello = sync::PatchSyncMessage::Hello {
+            clock: 0,
+            capabilities: sync::Capabilities::WIDE_AUTHOR,
+        };
+        sync.rx(trans, hello).await.unwrap();
+    }
+
+    /// Patches of the snapshot the channel is seeded with.
+    async fn seeded_patches(database: &Database, channel: &ChannelData) -> Vec<Patch> {
+        let channel = channel::Entity::find_by_id(channel.id)
+            .one(&database.connection)
+            .await
+            .unwrap()
+            .unwrap();
+
+        initial_sync::Entity::find()
+            .filter(initial_sync::Column::Snapshot.eq(channel.snapshot.unwrap()))
+            .order_by(initial_sync::Column::Id, Order::Asc)
+            .all(&database.connection)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|model| bincode::deserialize(&model.payload).unwrap())
+            .collect()
+    }
+
+    mod given_a_database_encrypted_with_a_passphrase {
+        use super::*;
+
+        type Given = (String, Ed25519Cert);
+        async fn given() -> Given {
+            let path = temp_path().to_string_lossy().into_owned();
+            let database = Database::connect_encrypted(&path, "secret").await.unwrap();
+
+            (path, *database.cert())
+        }
+
+        #[tokio::test]
+        async fn then_the_private_key_is_not_stored_in_plaintext() {
+            let (path, ..) = given().await;
+
+            assert!(Database::is_encrypted(&path).await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn then_it_opens_with_the_same_passphrase() {
+            let (path, cert) = given().await;
+
+            let database = Database::connect_encrypted(&path, "secret").await.unwrap();
+
+            assert_eq!(*database.cert(), cert);
+        }
+
+        #[tokio::test]
+        async fn then_it_does_not_open_with_another_passphrase() {
+            let (path, ..) = given().await;
+
+            let r = Database::connect_encrypted(&path, "guess").await;
+
+            assert!(matches!(r, Err(DatabaseError::WrongPassphrase)));
+        }
+
+        #[tokio::test]
+        async fn then_it_does_not_open_without_a_passphrase() {
+            let (path, ..) = given().await;
+
+            let r = Database::connect(&path).await;
+
+            assert!(matches!(r, Err(DatabaseError::Encrypted)));
+        }
+
+        #[tokio::test]
+        async fn then_rebuilding_it_keeps_the_private_key_sealed() {
+            let (path, cert) = given().await;
+            let rebuilt = temp_path().to_string_lossy().into_owned();
+
+            Database::rebuild_from_patch_log(&path, &rebuilt, Some("secret"))
+                .await
+                .unwrap();
+
+            assert!(Database::is_encrypted(&rebuilt).await.unwrap());
+            let database = Database::connect_encrypted(&rebuilt, "secret")
+                .await
+                .unwrap();
+            assert_eq!(*database.cert(), cert);
+        }
+    }
+
+    mod given_an_exported_identity {
+        use super::*;
+
+        type Given = (Database, Vec<u8>);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let keyfile = database.export_identity(Some("secret"));
+
+            (database, keyfile)
+        }
+
+        #[tokio::test]
+        async fn then_a_fresh_database_takes_it() {
+            let (database, keyfile) = given().await;
+
+            let imported = Database::import_identity(":memory:", &keyfile, Some("secret"), None)
+                .await
+                .unwrap();
+
+            assert_eq!(imported.cert(), database.cert());
+            let user = imported.get_contact(database.cert()).await.unwrap();
+            assert!(user.is_some());
+        }
+
+        #[tokio::test]
+        async fn then_a_database_with_another_identity_refuses_it() {
+            let (_, keyfile) = given().await;
+            let path = temp_path().to_string_lossy().into_owned();
+            Database::connect(&path).await.unwrap();
+
+            let r = Database::import_identity(&path, &keyfile, Some("secret"), None).await;
+
+            assert!(matches!(r, Err(DatabaseError::IdentityMismatch)));
+        }
+
+        #[tokio::test]
+        async fn then_the_imported_private_key_can_be_sealed() {
+            let (database, keyfile) = given().await;
+            let path = temp_path().to_string_lossy().into_owned();
+
+            Database::import_identity(&path, &keyfile, Some("secret"), Some("local"))
+                .await
+                .unwrap();
+
+            assert!(Database::is_encrypted(&path).await.unwrap());
+            let imported = Database::connect_encrypted(&path, "local").await.unwrap();
+            assert_eq!(imported.cert(), database.cert());
+        }
+    }
+
+    mod given_a_backup {
+        use super::*;
+
+        type Given = (Database, Vec<u8>);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let peer = Ed25519Seed::generate().public_key();
+            database
+                .save_contact(Contact {
+                    key: peer,
+                    name: "Peer".to_string(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            for title in ["First", "Second"] {
+                let conversation = database
+                    .create_conversation(Some(title.to_string()))
+                    .await
+                    .unwrap();
+                for i in 0..3 {
+                    database
+                        .send_message(conversation.clone(), format!("{title} {i}"), None)
+                        .await
+                        .unwrap();
+                }
+                database
+                    .send_file(
+                        conversation.clone(),
+                        "file.bin".to_string(),
+                        vec![7; 100_000],
+                    )
+                    .await
+                    .unwrap();
+                database.create_channel(conversation, peer).await.unwrap();
+            }
+            let mut backup = Vec::new();
+            database.backup(&mut backup, Some("secret")).await.unwrap();
+
+            (database, backup)
+        }
+
+        type Summary = (Uuid, Option<String>, Vec<(Ed25519Cert, String)>);
+        async fn conversations(database: &Database) -> Vec<Summary> {
+            let mut r = database
+                .list_conversation()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|conversation| {
+                    let members = conversation
+                        .members
+                        .into_iter()
+                        .map(|member| (member.key, member.name))
+                        .collect();
+                    (conversation.uuid, conversation.title, members)
+                })
+                .collect::<Vec<_>>();
+            r.sort();
+            r
+        }
+
+        async fn messages(database: &Database, uuid: Uuid) -> Vec<(Uuid, Ed25519Cert, String)> {
+            let conversation = database.get_conversation(uuid).await.unwrap().unwrap();
+            let length = conversation.length(database).await.unwrap();
+
+            let mut r = Vec::new();
+            for message in conversation
+                .get_messages_range(database, 0, length, MessageOrder::CausalSequence)
+                .await
+                .unwrap()
+            {
+                let content = match message.content {
+                    Content::Text(text) => text,
+                    Content::Attachment(name, id, _) => {
+                        let payload = database.fetch_file_payload(id).await.unwrap();
+                        let payload = payload.unwrap().into_bytes().unwrap();
+                        format!("{name} {}", payload.len())
+                    }
+                    content => panic!("Unexpected {content:?}"),
+                };
+                r.push((message.uuid, message.from.key, content));
+            }
+            r
+        }
+
+        #[tokio::test]
+        async fn then_it_restores_every_conversation() {
+            let (database, backup) = given().await;
+
+            let restored = Database::restore(":memory:", backup.as_slice(), Some("secret"), None)
+                .await
+                .unwrap();
+
+            assert_eq!(restored.cert(), database.cert());
+            let expected = conversations(&database).await;
+            assert_eq!(expected.len(), 2);
+            assert_eq!(conversations(&restored).await, expected);
+            for (uuid, ..) in expected {
+                let expected = messages(&database, uuid).await;
+                assert_eq!(expected.len(), 4);
+                assert_eq!(messages(&restored, uuid).await, expected);
+            }
+        }
+
+        #[tokio::test]
+        async fn then_it_restores_the_channels() {
+            let (database, backup) = given().await;
+
+            let restored = Database::restore(":memory:", backup.as_slice(), Some("secret"), None)
+                .await
+                .unwrap();
+
+            for conversation in database.list_conversation().await.unwrap() {
+                let peers = |channels: Vec<ChannelData>| {
+                    channels
+                        .into_iter()
+                        .map(|channel| channel.peer_cert)
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(
+                    peers(restored.list_channels(&conversation).await.unwrap()),
+                    peers(database.list_channels(&conversation).await.unwrap()),
+                );
+            }
+        }
+
+        #[tokio::test]
+        async fn then_it_leaves_the_database_untouched() {
+            let (database, _) = given().await;
+
+            let backlog = database.sync_backlog().await.unwrap();
+            let snapshots = snapshot::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+            database.backup(Vec::new(), None).await.unwrap();
+
+            assert_eq!(database.sync_backlog().await.unwrap(), backlog);
+            assert_eq!(
+                snapshot::Entity::find()
+                    .count(&database.connection)
+                    .await
+                    .unwrap(),
+                snapshots
+            );
+        }
+
+        #[tokio::test]
+        async fn then_it_needs_the_passphrase() {
+            let (_, backup) = given().await;
+
+            let r = Database::restore(":memory:", backup.as_slice(), Some("guess"), None).await;
+
+            assert!(matches!(r, Err(DatabaseError::WrongPassphrase)));
+        }
+
+        #[tokio::test]
+        async fn then_a_database_with_another_identity_refuses_it() {
+            let (_, backup) = given().await;
+            let path = temp_path().to_string_lossy().into_owned();
+            Database::connect(&path).await.unwrap();
+
+            let r = Database::restore(&path, backup.as_slice(), Some("secret"), None).await;
+
+            assert!(matches!(r, Err(DatabaseError::IdentityMismatch)));
+        }
+
+        #[tokio::test]
+        async fn then_the_restored_private_key_can_be_sealed() {
+            let (database, backup) = given().await;
+            let path = temp_path().to_string_lossy().into_owned();
+
+            Database::restore(&path, backup.as_slice(), Some("secret"), Some("local"))
+                .await
+                .unwrap();
+
+            assert!(Database::is_encrypted(&path).await.unwrap());
+            let restored = Database::connect_encrypted(&path, "local").await.unwrap();
+            assert_eq!(restored.cert(), database.cert());
+        }
+    }
+
+    mod given_a_sent_message_with_a_timestamp {
+        use super::*;
+
+        type Given = (Database, Message, i64, i64);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let before = sync::system_clock();
+            let message = database
+                .send_message(conversation, "Hello".to_string(), None)
+                .await
+                .unwrap();
+            let after = sync::system_clock();
+
+            (database, message, before, after)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_stamped_with_the_senders_clock() {
+            let (_, message, before, after) = given().await;
+
+            assert!((before..=after).contains(&message.created_at));
+        }
+
+        #[tokio::test]
+        async fn then_the_timestamp_is_synced() {
+            let (database, message, ..) = given().await;
+            let mut backup = Vec::new();
+            database.backup(&mut backup, None).await.unwrap();
+
+            let restored = Database::restore(":memory:", backup.as_slice(), None, None)
+                .await
+                .unwrap();
+
+            let conversation = restored
+                .get_conversation(message.conversation)
+                .await
+                .unwrap()
+                .unwrap();
+            let synced = conversation
+                .get_message_by_uuid(&restored, message.uuid)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(synced.created_at, message.created_at);
+        }
+    }
+
+    mod given_a_muted_conversation {
+        use super::*;
+
+        type Given = (Database, String, Conversation, Conversation);
+        async fn given() -> Given {
+            let path = temp_path().to_string_lossy().into_owned();
+            let database = Database::connect(&path).await.unwrap();
+            let muted = database.create_conversation(None).await.unwrap();
+            let other = database.create_conversation(None).await.unwrap();
+            database
+                .create_channel(muted.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            database.set_muted(&muted, true).await.unwrap();
+
+            (database, path, muted, other)
+        }
+
+        #[tokio::test]
+        async fn then_only_it_is_muted() {
+            let (database, _, muted, other) = given().await;
+
+            assert!(database.is_muted(&muted).await.unwrap());
+            assert!(!database.is_muted(&other).await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn then_it_can_be_unmuted() {
+            let (database, _, muted, ..) = given().await;
+
+            database.set_muted(&muted, false).await.unwrap();
+
+            assert!(!database.is_muted(&muted).await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn then_no_patch_is_synced() {
+            let (database, _, muted, ..) = given().await;
+            let backlog = database.sync_backlog().await.unwrap();
+
+            database.set_muted(&muted, false).await.unwrap();
+            database.set_muted(&muted, true).await.unwrap();
+
+            assert_eq!(database.sync_backlog().await.unwrap(), backlog);
+        }
+
+        #[tokio::test]
+        async fn then_it_stays_muted_after_a_restart() {
+            let (database, path, muted, ..) = given().await;
+            drop(database);
+
+            let database = Database::connect(&path).await.unwrap();
+
+            assert!(database.is_muted(&muted).await.unwrap());
+        }
+    }
+
+    mod given_an_archived_conversation {
+        use super::*;
+        use crate::testing::sync_until_idle;
+
+        type Given = (Database, Conversation, Conversation, Database, Conversation);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let peer = Database::connect(":memory:").await.unwrap();
+            let archived = database.create_conversation(None).await.unwrap();
+            let other = database.create_conversation(None).await.unwrap();
+            let joined = peer.join_conversation(archived.uuid).await.unwrap();
+            database
+                .create_channel(archived.clone(), *peer.cert())
+                .await
+                .unwrap();
+            peer.create_channel(joined.clone(), *database.cert())
+                .await
+                .unwrap();
+            database.set_archived(&archived, true).await.unwrap();
+
+            (database, archived, other, peer, joined)
+        }
+
+        fn uuids(conversations: Vec<Conversation>) -> Vec<Uuid> {
+            conversations
+                .into_iter()
+                .map(|conversation| conversation.uuid)
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn then_it_is_listed_apart() {
+            let (database, archived, other, ..) = given().await;
+
+            assert!(database.is_archived(&archived).await.unwrap());
+            assert!(!database.is_archived(&other).await.unwrap());
+            let unarchived = database.list_unarchived().await.unwrap();
+            assert_eq!(uuids(unarchived), [other.uuid]);
+            let archived_list = database.list_archived().await.unwrap();
+            assert_eq!(uuids(archived_list), [archived.uuid]);
+            assert_eq!(database.list_conversation().await.unwrap().len(), 2);
+        }
+
+        #[tokio::test]
+        async fn then_it_can_be_unarchived() {
+            let (database, archived, ..) = given().await;
+
+            database.set_archived(&archived, false).await.unwrap();
+
+            assert!(!database.is_archived(&archived).await.unwrap());
+            assert!(database.list_archived().await.unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn then_muting_it_keeps_it_archived() {
+            let (database, archived, ..) = given().await;
+
+            database.set_muted(&archived, true).await.unwrap();
+
+            assert!(database.is_archived(&archived).await.unwrap());
+            assert!(database.is_muted(&archived).await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn then_no_patch_is_synced() {
+            let (database, archived, ..) = given().await;
+            let backlog = database.sync_backlog().await.unwrap();
+
+            database.set_archived(&archived, false).await.unwrap();
+            database.set_archived(&archived, true).await.unwrap();
+
+            assert_eq!(database.sync_backlog().await.unwrap(), backlog);
+        }
+
+        #[tokio::test]
+        async fn then_it_still_receives_messages() {
+            let (database, archived, _, peer, joined) = given().await;
+            peer.send_message(joined, "still here".to_string(), None)
+                .await
+                .unwrap();
+
+            sync_until_idle(&database, &peer).await.unwrap();
+
+            let received = database.new_messages(Some(&archived)).await.unwrap();
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0].text(), "still here");
+        }
+    }
+
+    mod given_a_message_with_an_unknown_status {
+        use super::*;
+
+        type Given = (Database, Conversation, Message);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let message = database
+                .send_message(conversation.clone(), "Hello".to_string(), None)
+                .await
+                .unwrap();
+
+            let mut trans = database.begin().await.unwrap();
+            Patch::from(patch::MessageStatus {
+                id: message.uuid,
+                conversation: conversation.uuid,
+                status: 3,
+                crdt: CrdtWritable {
+                    generation: 1,
+                    author: Ed25519Seed::generate().public_key().as_author(),
+                },
+            })
+            .merge(&mut trans)
+            .await
+            .unwrap();
+            trans.commit().await.unwrap();
+
+            (database, conversation, message)
+        }
+
+        #[tokio::test]
+        async fn then_it_reads_as_sent() {
+            let (database, conversation, message) = given().await;
+
+            let message = conversation
+                .get_message_by_uuid(&database, message.uuid)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(message.status, MessageStatus::Sent);
+        }
+
+        #[test]
+        fn then_converting_it_fails() {
+            assert!(matches!(
+                MessageStatus::try_from(3),
+                Err(DatabaseError::UnknownStatus(3))
+            ));
+            assert_eq!(MessageStatus::try_from(2).unwrap(), MessageStatus::Read);
+        }
+    }
+
+    mod given_a_conversation_to_export {
+        use super::*;
+
+        type Given = (Database, Conversation, Vec<Message>);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let first = database
+                .send_message(conversation.clone(), "Hello".to_string(), None)
+                .await
+                .unwrap();
+            let reply = database
+                .send_message(conversation.clone(), "World".to_string(), Some(first.uuid))
+                .await
+                .unwrap();
+            database
+                .send_file(
+                    conversation.clone(),
+                    "file.bin".to_string(),
+                    vec![7; 100_000],
+                )
+                .await
+                .unwrap();
+            let deleted = database
+                .send_message(conversation.clone(), "Oops".to_string(), None)
+                .await
+                .unwrap();
+            database.delete_message(&deleted).await.unwrap();
+
+            (database, conversation, vec![first, reply, deleted])
+        }
+
+        async fn export(database: &Database, conversation: &Conversation) -> serde_json::Value {
+            let json = database
+                .export_conversation_json(conversation)
+                .await
+                .unwrap();
+            serde_json::from_str(&json).unwrap()
+        }
+
+        #[tokio::test]
+        async fn then_messages_are_in_conversation_order() {
+            let (database, conversation, messages) = given().await;
+
+            let json = export(&database, &conversation).await;
+
+            let json = json.as_array().unwrap();
+            assert_eq!(json.len(), 4);
+            assert_eq!(json[0]["uuid"], messages[0].uuid.to_string());
+            assert_eq!(json[0]["from"], database.cert().hex());
+            assert_eq!(json[0]["status"], "sent");
+            assert_eq!(json[0]["type"], "text");
+            assert_eq!(json[0]["text"], "Hello");
+            assert_eq!(json[0]["created_at"], messages[0].created_at);
+            assert_eq!(json[1]["reply_to"], messages[0].uuid.to_string());
+            assert_eq!(json[3]["type"], "deleted");
+            let sequences = json
+                .iter()
+                .map(|message| message["sequence"].as_i64().unwrap())
+                .collect::<Vec<_>>();
+            assert!(sequences.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        #[tokio::test]
+        async fn then_attachments_are_referenced_only() {
+            let (database, conversation, _) = given().await;
+
+            let json = export(&database, &conversation).await;
+
+            let attachment = &json[2];
+            assert_eq!(attachment["type"], "attachment");
+            assert_eq!(attachment["filename"], "file.bin");
+            assert!(attachment["uuid"].as_str().unwrap().parse::<Uuid>().is_ok());
+            assert!(json.to_string().len() < 10_000);
+        }
+    }
+
+    mod given_messages_in_two_conversations {
+        use super::*;
+
+        type Given = (Database, [Conversation; 2]);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let first = database.create_conversation(None).await.unwrap();
+            let second = database.create_conversation(None).await.unwrap();
+            for (conversation, text) in [
+                (&second, "second 1"),
+                (&first, "first 1"),
+                (&second, "second 2"),
+                (&first, "first 2"),
+            ] {
+                database
+                    .send_message(conversation.clone(), text.to_string(), None)
+                    .await
+                    .unwrap();
+            }
+
+            (database, [first, second])
+        }
+
+        #[tokio::test]
+        async fn then_all_messages_are_listed_by_conversation_then_in_order() {
+            let (database, [first, second]) = given().await;
+
+            let messages = database.all_messages().await.unwrap();
+
+            let listed = messages
+                .iter()
+                .map(|message| (message.conversation, message.text()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                listed,
+                [
+                    (first.uuid, "first 1"),
+                    (first.uuid, "first 2"),
+                    (second.uuid, "second 1"),
+                    (second.uuid, "second 2"),
+                ]
+            );
+        }
+
+        #[tokio::test]
+        async fn then_they_are_the_messages_of_each_conversation() {
+            let (database, conversations) = given().await;
+
+            let messages = database.all_messages().await.unwrap();
+
+            let mut expected = Vec::new();
+            for conversation in conversations {
+                expected.extend(
+                    conversation
+                        .get_messages_range(&database, 0, 10, MessageOrder::CausalSequence)
+                        .await
+                        .unwrap(),
+                );
+            }
+            assert_eq!(messages, expected);
+        }
+    }
+
+    mod given_a_plaintext_database {
+        use super::*;
+
+        type Given = (String, Ed25519Cert);
+        async fn given() -> Given {
+            let path = temp_path().to_string_lossy().into_owned();
+            let database = Database::connect(&path).await.unwrap();
+
+            (path, *database.cert())
+        }
+
+        #[tokio::test]
+        async fn then_it_is_not_encrypted() {
+            let (path, ..) = given().await;
+
+            assert!(!Database::is_encrypted(&path).await.unwrap());
+        }
+
+        mod when_it_is_opened_with_a_passphrase {
+            use super::*;
+
+            async fn given() -> Given {
+                let (path, cert) = super::given().await;
+                Database::connect_encrypted(&path, "secret").await.unwrap();
+
+                (path, cert)
+            }
+
+            #[tokio::test]
+            async fn then_its_key_is_sealed_and_kept() {
+                let (path, cert) = given().await;
+
+                assert!(Database::is_encrypted(&path).await.unwrap());
+                let database = Database::connect_encrypted(&path, "secret").await.unwrap();
+                assert_eq!(*database.cert(), cert);
+            }
+        }
+    }
+
+    mod given_a_verified_contact {
+        use super::*;
+
+        type Given = (Database, Conversation, Ed25519Cert);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let peer = Ed25519Seed::generate().public_key();
+            database
+                .create_channel(conversation.clone(), peer)
+                .await
+                .unwrap();
+
+            database.set_verified(&peer, true).await.unwrap();
+
+            (database, conversation, peer)
+        }
+
+        #[tokio::test]
+        async fn then_only_it_is_verified() {
+            let (database, _, peer) = given().await;
+            let stranger = Ed25519Seed::generate().public_key();
+
+            assert!(database.is_verified(&peer).await.unwrap());
+            assert!(!database.is_verified(&stranger).await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn then_it_is_verified_among_the_members() {
+            let (database, conversation, peer) = given().await;
+
+            let conversation = database.get_conversation(conversation.uuid).await.unwrap();
+
+            let members = conversation.unwrap().members;
+            let member = members.iter().find(|member| member.key == peer).unwrap();
+            assert!(member.verified);
+        }
+
+        #[tokio::test]
+        async fn then_a_new_name_from_the_peer_keeps_it_verified() {
+            let (database, _, peer) = given().await;
+
+            let mut trans = database.begin().await.unwrap();
+            Patch::from(patch::Contact {
+                key: patch::Key::new_exact(&peer.0),
+                name: "Renamed".to_string(),
+                crdt: CrdtWritable {
+                    generation: 1,
+                    author: peer.as_author(),
+                },
+            })
+            .merge(&mut trans)
+            .await;
+            trans.commit().await.unwrap();
+
+            let contact = database.get_contact(&peer).await.unwrap().unwrap();
+            assert_eq!(contact.name, "Renamed");
+            assert!(contact.verified);
+        }
+
+        #[tokio::test]
+        async fn then_it_can_be_unverified() {
+            let (database, _, peer) = given().await;
+
+            database.set_verified(&peer, false).await.unwrap();
+
+            assert!(!database.is_verified(&peer).await.unwrap());
+        }
+    }
+
+    mod given_concurrent_names_for_a_contact {
+        use super::*;
+
+        type Given = (Database, Ed25519Cert, [patch::Contact; 2]);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let peer = Ed25519Seed::from_entropy([1; 32]).public_key();
+            let device = Ed25519Seed::from_entropy([2; 32]).public_key();
+            let [kept, discarded] = match peer.as_author() > device.as_author() {
+                true => [(peer, "Peer"), (device, "Device")],
+                false => [(device, "Device"), (peer, "Peer")],
+            }
+            .map(|(author, name)| patch::Contact {
+                key: patch::Key::new_exact(&peer.0),
+                name: name.to_string(),
+                crdt: CrdtWritable {
+                    generation: 1,
+                    author: author.as_author(),
+                },
+            });
+
+            let mut trans = database.begin().await.unwrap();
+            for contact in [discarded.clone(), kept.clone()] {
+                Patch::from(contact).merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            (database, peer, [kept, discarded])
+        }
+
+        #[tokio::test]
+        async fn then_the_overwritten_name_is_recorded() {
+            let (database, peer, [kept, discarded]) = given().await;
+
+            let conflicts = database.recent_conflicts(10).await.unwrap();
+
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].target, ConflictTarget::Contact(peer));
+            assert_eq!(conflicts[0].kept, Some(kept.name));
+            assert_eq!(conflicts[0].discarded, Some(discarded.name));
+        }
+    }
+
+    mod given_a_contact_with_an_alias {
+        use super::*;
+
+        type Given = (Database, Conversation, Ed25519Cert);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let peer = Ed25519Seed::generate().public_key();
+            database
+                .create_channel(conversation.clone(), peer)
+                .await
+                .unwrap();
+            database
+                .save_contact(Contact {
+                    key: peer,
+                    name: "Robert".to_string(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+
+            database
+                .set_alias(&peer, Some("Bob".to_string()))
+                .await
+                .unwrap();
+
+            (database, conversation, peer)
+        }
+
+        async fn member(
+            database: &Database,
+            conversation: &Conversation,
+            key: Ed25519Cert,
+        ) -> Contact {
+            let conversation = database.get_conversation(conversation.uuid).await.unwrap();
+
+            let members = conversation.unwrap().members;
+            members
+                .into_iter()
+                .find(|member| member.key == key)
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn then_members_show_the_alias() {
+            let (database, conversation, peer) = given().await;
+
+            let member = member(&database, &conversation, peer).await;
+
+            assert_eq!(member.name, "Robert");
+            assert_eq!(member.display_name(), "Bob");
+        }
+
+        #[tokio::test]
+        async fn then_messages_show_the_alias() {
+            let (database, conversation, ..) = given().await;
+            database
+                .set_alias(database.cert(), Some("Me".to_string()))
+                .await
+                .unwrap();
+
+            let message = database
+                .send_message(conversation, "hello".to_string(), None)
+                .await
+                .unwrap();
+
+            assert_eq!(message.from.display_name(), "Me");
+        }
+
+        #[tokio::test]
+        async fn then_the_alias_is_never_synced() {
+            let (database, conversation, ..) = given().await;
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+
+            let logged = database.patch_log(&conversation, usize::MAX).await.unwrap();
+            let mut seeded = vec![];
+            for channel in database.list_channels(&conversation).await.unwrap() {
+                seeded.extend(seeded_patches(&database, &channel).await);
+            }
+
+            let patches = logged.into_iter().map(|data| data.payload).chain(seeded);
+            assert!(patches
+                .map(|patch| format!("{patch:?}"))
+                .all(|patch| !patch.contains("Bob")));
+        }
+
+        #[tokio::test]
+        async fn then_clearing_it_shows_the_name_again() {
+            let (database, conversation, peer) = given().await;
+
+            database.set_alias(&peer, None).await.unwrap();
+
+            let member = member(&database, &conversation, peer).await;
+            assert_eq!(member.display_name(), "Robert");
+        }
+    }
+
+    mod given_a_key_off_the_curve {
+        use super::*;
+
+        type Given = (Database, Conversation, Ed25519Cert);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+
+            (database, conversation, Ed25519Cert([2; 32]))
+        }
+
+        #[tokio::test]
+        async fn then_no_channel_to_it_can_be_created() {
+            let (database, conversation, peer) = given().await;
+
+            let created = database.create_channel(conversation.clone(), peer).await;
+
+            assert!(matches!(created, Err(DatabaseError::InvalidKey(_))));
+            assert!(database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    mod given_a_blocked_contact {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (Database, Database, Conversation);
+        async fn given() -> Given {
+            let user = Database::connect(":memory:").await.unwrap();
+            let peer = Database::connect(":memory:").await.unwrap();
+            let conversation = user.create_conversation(None).await.unwrap();
+            user.create_channel(conversation.clone(), *peer.cert())
+                .await
+                .unwrap();
+            let joined = peer.join_conversation(conversation.uuid).await.unwrap();
+            peer.send_message(joined, "before".to_string(), None)
+                .await
+                .unwrap();
+            let mut trans = user.begin().await.unwrap();
+            for data in peer.patch_log(&conversation, usize::MAX).await.unwrap() {
+                data.payload.merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            user.block_contact(peer.cert()).await.unwrap();
+
+            (user, peer, conversation)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_listed() {
+            let (user, peer, _) = given().await;
+
+            assert_eq!(user.list_blocked().await.unwrap(), vec![*peer.cert()]);
+        }
+
+        #[tokio::test]
+        async fn then_its_channel_is_dropped() {
+            let (user, _, conversation) = given().await;
+
+            assert!(user.list_channels(&conversation).await.unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn then_no_channel_to_it_can_be_created() {
+            let (user, peer, conversation) = given().await;
+
+            let created = user.create_channel(conversation, *peer.cert()).await;
+
+            assert!(matches!(created, Err(DatabaseError::Blocked)));
+        }
+
+        #[tokio::test]
+        async fn then_its_later_patches_are_not_merged() {
+            let (user, peer, conversation) = given().await;
+            peer.send_message(conversation.clone(), "after".to_string(), None)
+                .await
+                .unwrap();
+
+            let mut trans = user.begin().await.unwrap();
+            for data in peer.patch_log(&conversation, usize::MAX).await.unwrap() {
+                let merged = SyncDataSource::merge(&mut trans, SqliteSyncCtx::from(0), data)
+                    .await
+                    .unwrap();
+                assert_eq!(merged, None);
+            }
+            trans.commit().await.unwrap();
+
+            assert_eq!(conversation.length(&user).await.unwrap(), 1);
+        }
+
+        #[tokio::test]
+        async fn then_its_earlier_messages_can_be_hidden() {
+            let (mut user, _, conversation) = given().await;
+
+            let shown = conversation.get_message(&user, 0).await.unwrap();
+            user.set_hide_blocked(true);
+            let hidden = conversation.get_message(&user, 0).await.unwrap();
+
+            assert_eq!(shown.unwrap().content, Content::Text("before".to_string()));
+            assert_eq!(hidden.unwrap().content, Content::Blocked);
+        }
+
+        #[tokio::test]
+        async fn then_pages_of_messages_hide_them_too() {
+            let (mut user, _, conversation) = given().await;
+
+            user.set_hide_blocked(true);
+            let page = conversation
+                .messages_page(&user, None, 10, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            assert_eq!(page.len(), 1);
+            assert_eq!(page[0].content, Content::Blocked);
+        }
+
+        #[tokio::test]
+        async fn then_previews_new_and_starred_messages_hide_them_too() {
+            let (mut user, _, conversation) = given().await;
+            let message = conversation.get_message(&user, 0).await.unwrap().unwrap();
+            user.set_starred(&message, true).await.unwrap();
+
+            user.set_hide_blocked(true);
+            let previews = user.conversation_previews().await.unwrap();
+            let new = user.new_messages(Some(&conversation)).await.unwrap();
+            let starred = user.list_starred().await.unwrap();
+
+            let preview = previews[0].last_message.as_ref().unwrap();
+            assert_eq!(preview.content, Content::Blocked);
+            assert_eq!(new.len(), 1);
+            assert_eq!(new[0].content, Content::Blocked);
+            assert_eq!(starred.len(), 1);
+            assert_eq!(starred[0].1.content, Content::Blocked);
+        }
+
+        #[tokio::test]
+        async fn then_unblocking_allows_channels_again() {
+            let (user, peer, conversation) = given().await;
+
+            user.unblock_contact(peer.cert()).await.unwrap();
+
+            assert!(user.list_blocked().await.unwrap().is_empty());
+            user.create_channel(conversation.clone(), *peer.cert())
+                .await
+                .unwrap();
+            assert_eq!(user.list_channels(&conversation).await.unwrap().len(), 1);
+        }
+    }
+
+    mod given_a_rotated_identity {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (String, Database, Database, Conversation, Ed25519Cert);
+        async fn given() -> Given {
+            let path = temp_path().to_string_lossy().into_owned();
+            let mut user = Database::connect(&path).await.unwrap();
+            let peer = Database::connect(":memory:").await.unwrap();
+            let conversation = user.create_conversation(None).await.unwrap();
+            user.create_channel(conversation.clone(), *peer.cert())
+                .await
+                .unwrap();
+            peer.merge_from(&path).await.unwrap();
+            peer.create_channel(conversation.clone(), *user.cert())
+                .await
+                .unwrap();
+            let old = *user.cert();
+
+            user.rotate_identity(None).await.unwrap();
+
+            (path, user, peer, conversation, old)
+        }
+
+        #[tokio::test]
+        async fn then_both_keys_are_members() {
+            let (_, user, _, conversation, old) = given().await;
+
+            let conversation = user.get_conversation(conversation.uuid).await.unwrap();
+
+            let members = conversation.unwrap().members;
+            assert_ne!(*user.cert(), old);
+            assert!(members.iter().any(|member| member.cert() == old));
+            assert!(members.iter().any(|member| member.cert() == *user.cert()));
+        }
+
+        #[tokio::test]
+        async fn then_the_old_channel_keeps_the_old_key() {
+            let (_, user, peer, conversation, old) = given().await;
+
+            let channels = user.list_channels(&conversation).await.unwrap();
+            let to_user = peer.channel_for(&conversation, &old).await.unwrap();
+
+            assert_eq!(channels.len(), 2);
+            let retired = channels.iter().find(|channel| channel.retired()).unwrap();
+            assert_eq!(retired.channel, to_user.unwrap().channel().channel);
+        }
+
+        #[tokio::test]
+        async fn then_the_identity_survives_reopening() {
+            let (path, user, _, conversation, _) = given().await;
+            let channels = user.list_channels(&conversation).await.unwrap();
+            drop(user);
+
+            let reopened = Database::connect(&path).await.unwrap();
+
+            assert_eq!(
+                reopened.list_channels(&conversation).await.unwrap(),
+                channels
+            );
+        }
+
+        mod when_the_peer_receives_the_patches {
+            use super::*;
+
+            async fn given() -> Given {
+                let (path, user, peer, conversation, old) = super::given().await;
+                peer.merge_from(&path).await.unwrap();
+
+                (path, user, peer, conversation, old)
+            }
+
+            #[tokio::test]
+            async fn then_it_moves_its_channel_to_the_new_cert() {
+                let (_, user, peer, conversation, old) = given().await;
+
+                let count = peer.migrate_superseded_channels().await.unwrap();
+
+                assert_eq!(count, 1);
+                let to_new = peer.channel_for(&conversation, user.cert()).await.unwrap();
+                let from_new = user.channel_for(&conversation, peer.cert()).await.unwrap();
+                assert_eq!(
+                    to_new.unwrap().channel().channel,
+                    from_new.unwrap().channel().channel
+                );
+                let to_old = peer.channel_for(&conversation, &old).await.unwrap();
+                assert!(to_old.is_none());
+            }
+        }
+
+        mod when_a_forged_supersede_is_received {
+            use super::*;
+
+            async fn given() -> Given {
+                let (path, user, peer, conversation, old) = super::given().await;
+                let mut trans = peer.begin().await.unwrap();
+                Patch::from(patch::KeySupersede {
+                    old: patch::Key::new_exact(&old.0),
+                    new: patch::Key::new_exact(&Ed25519Seed::generate().public_key().0),
+                    conversation: conversation.uuid,
+                    signature: vec![0; 64],
+                    crdt: CrdtAddOnly(Author(1)),
+                })
+                .merge(&mut trans)
+                .await
+                .unwrap();
+                trans.commit().await.unwrap();
+
+                (path, user, peer, conversation, old)
+            }
+
+            #[tokio::test]
+            async fn then_the_channel_is_kept() {
+                let (_, _, peer, conversation, old) = given().await;
+
+                let count = peer.migrate_superseded_channels().await.unwrap();
+
+                assert_eq!(count, 0);
+                let to_old = peer.channel_for(&conversation, &old).await.unwrap();
+                assert!(to_old.is_some());
+            }
+        }
+
+        mod when_the_new_channel_is_in_sync {
+            use super::*;
+
+            async fn given() -> Given {
+                let (path, user, peer, conversation, old) = super::given().await;
+                let channel = user.channel_for(&conversation, peer.cert()).await.unwrap();
+                let id = channel.unwrap().channel().id;
+
+                let mut trans = user.begin().await.unwrap();
+                while let Some(data) = trans.next(id.into(), (0, 0)).await.unwrap() {
+                    trans.ack(id.into(), data.id).await.unwrap();
+                }
+                trans.commit().await.unwrap();
+
+                (path, user, peer, conversation, old)
+            }
+
+            #[tokio::test]
+            async fn then_the_old_channel_and_key_are_dropped() {
+                let (_, user, _, conversation, _) = given().await;
+
+                let count = user.migrate_superseded_channels().await.unwrap();
+
+                assert_eq!(count, 1);
+                let channels = user.list_channels(&conversation).await.unwrap();
+                assert!(channels.iter().all(|channel| !channel.retired()));
+                let retired = local::Entity::find()
+                    .filter(local::Column::Retired.eq(true))
+                    .count(&user.connection)
+                    .await
+                    .unwrap();
+                assert_eq!(retired, 0);
+            }
+        }
+    }
+
+    mod given_a_conversation_with_a_patch_log {
+        use super::*;
+
+        type Given = (Database, Conversation);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let other = database.create_conversation(None).await.unwrap();
+            for conversation in [&conversation, &other] {
+                database
+                    .send_message(conversation.clone(), "hello".to_string(), None)
+                    .await
+                    .unwrap();
+            }
+            let message = conversation
+                .get_message(&database, 0)
+                .await
+                .unwrap()
+                .unwrap();
+            database
+                .set_message_status(&message, MessageStatus::Read)
+                .await
+                .unwrap();
+
+            (database, conversation)
+        }
+
+        #[tokio::test]
+        async fn then_the_log_lists_its_patches_in_order() {
+            let (database, conversation, ..) = given().await;
+
+            let log = database.patch_log(&conversation, 10).await.unwrap();
+            let log = log.iter().map(SyncData::kind).collect::<Vec<_>>();
+
+            assert_eq!(
+                log,
+                [
+                    "Conversation",
+                    "Member",
+                    "NewTextMessage",
+                    "MessageStatus",
+                    "Receipt"
+                ]
+            );
+        }
+
+        #[tokio::test]
+        async fn then_the_log_is_limited_to_the_latest_patches() {
+            let (database, conversation, ..) = given().await;
+
+            let log = database.patch_log(&conversation, 2).await.unwrap();
+            let log = log.iter().map(SyncData::kind).collect::<Vec<_>>();
+
+            assert_eq!(log, ["MessageStatus", "Receipt"]);
+        }
+    }
+
+    mod given_a_member_without_a_channel {
+        use super::*;
+
+        type Given = (Database, Conversation, Ed25519Cert, Ed25519Cert);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let [connected, missing] = [(); 2].map(|_| Ed25519Seed::generate().public_key());
+            for peer in [connected, missing] {
+                database
+                    .create_channel(conversation.clone(), peer)
+                    .await
+                    .unwrap();
+            }
+            database
+                .remove_channel(conversation.clone(), missing)
+                .await
+                .unwrap();
+            let conversation = database
+                .get_conversation(conversation.uuid)
+                .await
+                .unwrap()
+                .unwrap();
+
+            (database, conversation, connected, missing)
+        }
+
+        #[tokio::test]
+        async fn then_only_it_is_a_gap_in_the_mesh() {
+            let (database, conversation, _, missing, ..) = given().await;
+
+            let gaps = conversation.mesh_gaps(&database).await.unwrap();
+
+            assert_eq!(gaps, vec![missing]);
+        }
+    }
+
+    mod given_a_peer_in_several_conversations {
+        use super::*;
+
+        type Given = (Database, [Conversation; 2], Ed25519Cert, Ed25519Cert);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversations = [
+                database.create_conversation(None).await.unwrap(),
+                database.create_conversation(None).await.unwrap(),
+            ];
+            let [revoked, other] = [(); 2].map(|_| Ed25519Seed::generate().public_key());
+            for conversation in conversations.iter() {
+                database
+                    .create_channel(conversation.clone(), revoked)
+                    .await
+                    .unwrap();
+            }
+            database
+                .create_channel(conversations[0].clone(), other)
+                .await
+                .unwrap();
+
+            (database, conversations, revoked, other)
+        }
+
+        async fn peers(database: &Database, conversation: &Conversation) -> Vec<Ed25519Cert> {
+            let channels = database.list_channels(conversation).await.unwrap();
+            channels
+                .into_iter()
+                .map(|channel| channel.peer_cert)
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn then_revoking_it_removes_exactly_its_channels() {
+            let (database, [first, second], revoked, other, ..) = given().await;
+
+            let removed = database.revoke_peer(&revoked, false).await.unwrap();
+
+            assert_eq!(removed, 2);
+            assert_eq!(peers(&database, &first).await, vec![other]);
+            assert_eq!(peers(&database, &second).await, vec![]);
+        }
+
+        #[tokio::test]
+        async fn then_revoking_it_can_also_remove_its_membership() {
+            let (database, conversations, revoked, ..) = given().await;
+
+            database.revoke_peer(&revoked, true).await.unwrap();
+
+            for conversation in conversations {
+                let conversation = database
+                    .get_conversation(conversation.uuid)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert!(!conversation
+                    .members
+                    .iter()
+                    .any(|member| member.key == revoked));
+            }
+        }
+
+        #[tokio::test]
+        async fn then_revoking_an_unknown_peer_removes_nothing() {
+            let (database, ..) = given().await;
+
+            let unknown = Ed25519Seed::generate().public_key();
+            let removed = database.revoke_peer(&unknown, true).await.unwrap();
+
+            assert_eq!(removed, 0);
+        }
+    }
+
+    mod given_a_maximum_attachment_size {
+        use super::*;
+
+        type Given = (Database, Conversation);
+        async fn given() -> Given {
+            let mut database = Database::connect(":memory:").await.unwrap();
+            database.set_max_attachment_bytes(Some(16));
+            let conversation = database.create_conversation(None).await.unwrap();
+
+            (database, conversation)
+        }
+
+        #[tokio::test]
+        async fn then_a_larger_file_is_rejected_before_anything_is_written() {
+            let (database, conversation, ..) = given().await;
+
+            let r = database
+                .send_file(conversation.clone(), "big".to_string(), vec![0; 17])
+                .await;
+
+            assert!(matches!(
+                r,
+                Err(DatabaseError::AttachmentTooLarge { len: 17, max: 16 })
+            ));
+            let attachments = attachment::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+            assert_eq!(attachments, 0);
+            assert_eq!(conversation.length(&database).await.unwrap(), 0);
+        }
+
+        #[tokio::test]
+        async fn then_a_file_within_the_limit_is_sent() {
+            let (database, conversation, ..) = given().await;
+
+            database
+                .send_file(conversation.clone(), "small".to_string(), vec![0; 16])
+                .await
+                .unwrap();
+
+            assert_eq!(conversation.length(&database).await.unwrap(), 1);
+        }
+    }
+
+    mod given_a_text_over_the_maximum_message_size {
+        use super::*;
+
+        type Given = (Database, Conversation, String);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let text = "x".repeat(MAX_MESSAGE_TEXT_BYTES + 1);
+
+            (database, conversation, text)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_not_sent() {
+            let (database, conversation, text) = given().await;
+
+            let r = database
+                .send_message(conversation.clone(), text, None)
+                .await;
+
+            assert!(matches!(r, Err(DatabaseError::MessageTooLarge { .. })));
+            assert_eq!(conversation.length(&database).await.unwrap(), 0);
+        }
+
+        #[tokio::test]
+        async fn then_no_message_is_edited_to_it() {
+            let (database, conversation, text) = given().await;
+            let message = database
+                .send_message(conversation.clone(), "short".to_string(), None)
+                .await
+                .unwrap();
+
+            let r = database.edit_message(&message, text).await;
+
+            assert!(matches!(r, Err(DatabaseError::MessageTooLarge { .. })));
+            let message = conversation.get_message(&database, 0).await.unwrap();
+            assert_eq!(message.unwrap().text(), "short");
+        }
+    }
+
+    mod given_a_file_sent_as_a_stream {
+        use super::*;
+
+        type Given = (Database, Conversation, Vec<u8>, i32);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let payload = (0..ATTACHMENT_CHUNK_BYTES * 2 + 10)
+                .map(|i| i as u8)
+                .collect::<Vec<_>>();
+
+            database
+                .send_file_stream(
+                    conversation.clone(),
+                    "big".to_string(),
+                    payload.as_slice(),
+                    payload.len() as u64,
+                )
+                .await
+                .unwrap();
+            let message = conversation.get_message(&database, 0).await.unwrap();
+            let Content::Attachment(_, attachment, _) = message.unwrap().content else { panic!() };
+
+            (database, conversation, payload, attachment)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_stored_in_chunks() {
+            let (database, _, _, attachment) = given().await;
+
+            let hash = database.attachment_hash(attachment).await.unwrap().unwrap();
+            let chunks = blob::Entity::find()
+                .filter(blob::Column::Hash.eq(hash.to_vec()))
+                .count(&database.connection)
+                .await
+                .unwrap();
+
+            assert_eq!(chunks, 3);
+        }
+
+        #[tokio::test]
+        async fn then_the_whole_payload_is_fetched() {
+            let (database, _, payload, attachment) = given().await;
+
+            let fetched = database
+                .fetch_file_payload(attachment)
+                .await
+                .unwrap()
+                .map(|payload| payload.into_bytes().unwrap());
+
+            assert_eq!(fetched, Some(payload));
+        }
+
+        #[tokio::test]
+        async fn then_it_is_fetched_in_memory_up_to_the_spill_threshold() {
+            let (mut database, _, payload, attachment) = given().await;
+            database.set_spill_bytes(payload.len());
+
+            let fetched = database.fetch_file_payload(attachment).await.unwrap();
+
+            let Some(FilePayload::Memory(fetched)) = fetched else { panic!() };
+            assert_eq!(fetched, payload);
+        }
+
+        #[tokio::test]
+        async fn then_it_is_fetched_through_a_temporary_file_past_the_spill_threshold() {
+            let (mut database, _, payload, attachment) = given().await;
+            database.set_spill_bytes(payload.len() - 1);
+
+            let fetched = database.fetch_file_payload(attachment).await.unwrap();
+
+            let Some(FilePayload::Spilled(file)) = fetched else { panic!() };
+            let path = file.path().to_owned();
+            assert_eq!(std::fs::read(&path).unwrap(), payload);
+            drop(file);
+            assert!(!path.exists());
+        }
+
+        #[tokio::test]
+        async fn then_it_is_streamed_back_in_order() {
+            let (database, _, payload, attachment) = given().await;
+
+            let chunks = database
+                .fetch_file_stream(attachment)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+
+            assert_eq!(chunks.len(), 3);
+            assert_eq!(chunks.concat(), payload);
+        }
+
+        #[tokio::test]
+        async fn then_the_chunks_are_seeded_to_new_channels() {
+            let (database, conversation, payload, _) = given().await;
+
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let seeded = seeded_patches(&database, &channel[0]).await;
+
+            let chunks = seeded
+                .into_iter()
+                .filter_map(|patch| match patch {
+                    Patch::AttachmentChunk(chunk) => Some(chunk.data),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(chunks.concat(), payload);
+        }
+
+        #[tokio::test]
+        async fn then_the_maximum_size_is_enforced_on_the_declared_length() {
+            let (mut database, conversation, payload, _) = given().await;
+            database.set_max_attachment_bytes(Some(ATTACHMENT_CHUNK_BYTES));
+
+            let r = database
+                .send_file_stream(
+                    conversation.clone(),
+                    "big".to_string(),
+                    payload.as_slice(),
+                    payload.len() as u64,
+                )
+                .await;
+
+            assert!(matches!(
+                r,
+                Err(DatabaseError::AttachmentTooLarge { max, .. }) if max == ATTACHMENT_CHUNK_BYTES
+            ));
+            assert_eq!(conversation.length(&database).await.unwrap(), 1);
+        }
+
+        #[tokio::test]
+        async fn then_a_reader_shorter_than_declared_is_rejected() {
+            let (database, conversation, payload, _) = given().await;
+
+            let r = database
+                .send_file_stream(
+                    conversation.clone(),
+                    "big".to_string(),
+                    payload.as_slice(),
+                    payload.len() as u64 + 1,
+                )
+                .await;
+
+            assert!(matches!(r, Err(DatabaseError::Io(_))));
+            assert_eq!(conversation.length(&database).await.unwrap(), 1);
+        }
+
+        #[tokio::test]
+        async fn then_the_progress_is_complete() {
+            let (database, _, payload, attachment) = given().await;
+
+            let progress = database.attachment_progress(attachment).await.unwrap();
+
+            let len = payload.len() as u64;
+            assert_eq!(progress, Some((len, len)));
+        }
+
+        mod when_the_last_chunk_has_not_arrived {
+            use super::*;
+
+            async fn given() -> Given {
+                let (database, conversation, payload, attachment) = super::given().await;
+                let hash = database.attachment_hash(attachment).await.unwrap().unwrap();
+                blob::Entity::delete_many()
+                    .filter(blob::Column::Hash.eq(hash.to_vec()))
+                    .filter(blob::Column::Index.eq(2))
+                    .exec(&database.connection)
+                    .await
+                    .unwrap();
+
+                (database, conversation, payload, attachment)
+            }
+
+            #[tokio::test]
+            async fn then_the_progress_is_partial() {
+                let (database, _, payload, attachment) = given().await;
+
+                let progress = database.attachment_progress(attachment).await.unwrap();
+
+                let len = payload.len() as u64;
+                assert_eq!(progress, Some((len - 10, len)));
+            }
+        }
+    }
+
+    mod given_a_file_cut_off_mid_transfer {
+        use super::*;
+        use crate::database::sync::{InternTable, PatchSyncMessage};
+
+        /// Index of the attachment chunk in `message`, if it holds one.
+        fn chunk_index(conversation: Uuid, message: &PatchSyncMessage) -> Option<i32> {
+            let data = match message {
+                PatchSyncMessage::Data(data) => data.clone(),
+                PatchSyncMessage::Interned(data) => {
+                    InternTable::new(conversation).expand(bincode::deserialize(data).unwrap())
+                }
+                _ => return None,
+            };
+
+            match data.payload {
+                Patch::AttachmentChunk(chunk) => Some(chunk.index),
+                _ => None,
+            }
+        }
+
+        type Given = (Database, Conversation, Vec<u8>, Vec<i32>);
+        async fn given() -> Given {
+            let alice = Database::connect(":memory:").await.unwrap();
+            let bob = Database::connect(":memory:").await.unwrap();
+            let conversation = alice.create_conversation(None).await.unwrap();
+            let joined = bob.join_conversation(conversation.uuid).await.unwrap();
+            alice
+                .create_channel(conversation.clone(), bob.private_key().public_key())
+                .await
+                .unwrap();
+            bob.create_channel(joined.clone(), alice.private_key().public_key())
+                .await
+                .unwrap();
+            let to_bob = alice.list_channels(&conversation).await.unwrap().remove(0);
+            let to_alice = bob.list_channels(&joined).await.unwrap().remove(0);
+
+            let payload = (0..ATTACHMENT_CHUNK_BYTES * 3 + 10)
+                .map(|i| i as u8)
+                .collect::<Vec<_>>();
+            alice
+                .send_file(conversation.clone(), "big".to_string(), payload.clone())
+                .await
+                .unwrap();
+
+            // Bob receives two chunks, then the channel drops before any of
+            // his acks reach Alice.
+            let mut alice_trans = alice.begin().await.unwrap();
+            let mut bob_trans = bob.begin().await.unwrap();
+            let mut from_alice = alice.start_sync(to_bob.clone());
+            let mut from_bob = bob.start_sync(to_alice.clone());
+            greet(&mut from_alice, &mut alice_trans).await;
+            let mut received = 0;
+            while received < 2 {
+                let message = from_alice.tx(&mut alice_trans).await.unwrap().unwrap();
+                if chunk_index(conversation.uuid, &message).is_some() {
+                    received += 1;
+                }
+                from_bob.rx(&mut bob_trans, message).await.unwrap();
+            }
+            alice_trans.commit().await.unwrap();
+            bob_trans.commit().await.unwrap();
+
+            let mut alice_trans = alice.begin().await.unwrap();
+            let mut bob_trans = bob.begin().await.unwrap();
+            let mut from_alice = alice.start_sync(to_bob);
+            let mut from_bob = bob.start_sync(to_alice);
+            let mut resent = Vec::new();
+            loop {
+                let to_alice = from_bob.tx(&mut bob_trans).await.unwrap();
+                if let Some(message) = &to_alice {
+                    from_alice
+                        .rx(&mut alice_trans, message.clone())
+                        .await
+                        .unwrap();
+                }
+                let to_bob = from_alice.tx(&mut alice_trans).await.unwrap();
+                if let Some(message) = &to_bob {
+                    resent.extend(chunk_index(conversation.uuid, message));
+                    from_bob.rx(&mut bob_trans, message.clone()).await.unwrap();
+                }
+                if to_alice.is_none() && to_bob.is_none() {
+                    break;
+                }
+            }
+            alice_trans.commit().await.unwrap();
+            bob_trans.commit().await.unwrap();
+
+            (bob, joined, payload, resent)
+        }
+
+        #[tokio::test]
+        async fn then_only_the_missing_chunks_are_sent_again() {
+            let (.., resent) = given().await;
+
+            assert_eq!(resent, vec![2, 3]);
+        }
+
+        #[tokio::test]
+        async fn then_the_whole_payload_is_received() {
+            let (bob, joined, payload, ..) = given().await;
+
+            let message = joined.get_message(&bob, 0).await.unwrap();
+            let Content::Attachment(_, attachment, _) = message.unwrap().content else { panic!() };
+            let fetched = bob
+                .fetch_file_payload(attachment)
+                .await
+                .unwrap()
+                .map(|payload| payload.into_bytes().unwrap());
+            assert_eq!(fetched, Some(payload));
+        }
+
+        mod when_the_first_chunk_was_acked {
+            use super::*;
+
+            type Given = (Database, Conversation, Vec<u8>, Vec<i32>, bool);
+            async fn given() -> Given {
+                let alice = Database::connect(":memory:").await.unwrap();
+                let bob = Database::connect(":memory:").await.unwrap();
+                let conversation = alice.create_conversation(None).await.unwrap();
+                let joined = bob.join_conversation(conversation.uuid).await.unwrap();
+                alice
+                    .create_channel(conversation.clone(), bob.private_key().public_key())
+                    .await
+                    .unwrap();
+                bob.create_channel(joined.clone(), alice.private_key().public_key())
+                    .await
+                    .unwrap();
+                let to_bob = alice.list_channels(&conversation).await.unwrap().remove(0);
+                let to_alice = bob.list_channels(&joined).await.unwrap().remove(0);
+
+                let payload = (0..ATTACHMENT_CHUNK_BYTES * 3 + 10)
+                    .map(|i| i as u8)
+                    .collect::<Vec<_>>();
+                alice
+                    .send_file(conversation.clone(), "big".to_string(), payload.clone())
+                    .await
+                    .unwrap();
+
+                // Bob's acks reach Alice up to the first chunk, then the
+                // channel drops right after he receives the second one.
+                let mut alice_trans = alice.begin().await.unwrap();
+                let mut bob_trans = bob.begin().await.unwrap();
+                let mut from_alice = alice.start_sync(to_bob.clone());
+                let mut from_bob = bob.start_sync(to_alice.clone());
+                greet(&mut from_alice, &mut alice_trans).await;
+                let mut received = 0;
+                while received < 2 {
+                    let message = from_alice.tx(&mut alice_trans).await.unwrap().unwrap();
+                    if chunk_index(conversation.uuid, &message).is_some() {
+                        received += 1;
+                    }
+                    from_bob.rx(&mut bob_trans, message).await.unwrap();
+                    while received < 2 {
+                        let Some(message) = from_bob.tx(&mut bob_trans).await.unwrap() else { break };
+                        from_alice.rx(&mut alice_trans, message).await.unwrap();
+                    }
+                }
+                alice_trans.commit().await.unwrap();
+                bob_trans.commit().await.unwrap();
+
+                let mut alice_trans = alice.begin().await.unwrap();
+                let mut bob_trans = bob.begin().await.unwrap();
+                let mut from_alice = alice.start_sync(to_bob);
+                let mut from_bob = bob.start_sync(to_alice);
+                let mut resent = Vec::new();
+                let mut resumed = false;
+                loop {
+                    let to_alice = from_bob.tx(&mut bob_trans).await.unwrap();
+                    if let Some(message) = &to_alice {
+                        resumed |= matches!(message, PatchSyncMessage::Resume(_));
+                        from_alice
+                            .rx(&mut alice_trans, message.clone())
+                            .await
+                            .unwrap();
+                    }
+                    let to_bob = from_alice.tx(&mut alice_trans).await.unwrap();
+                    if let Some(message) = &to_bob {
+                        resent.extend(chunk_index(conversation.uuid, message));
+                        from_bob.rx(&mut bob_trans, message.clone()).await.unwrap();
+                    }
+                    if to_alice.is_none() && to_bob.is_none() {
+                        break;
+                    }
+                }
+                alice_trans.commit().await.unwrap();
+                bob_trans.commit().await.unwrap();
+
+                (bob, joined, payload, resent, resumed)
+            }
+
+            #[tokio::test]
+            async fn then_neither_acked_nor_held_chunks_are_sent_again() {
+                let (.., resent, resumed) = given().await;
+
+                assert!(resumed);
+                assert_eq!(resent, vec![2, 3]);
+            }
+
+            #[tokio::test]
+            async fn then_the_whole_payload_is_received() {
+                let (bob, joined, payload, ..) = given().await;
+
+                let message = joined.get_message(&bob, 0).await.unwrap();
+                let Content::Attachment(_, attachment, _) = message.unwrap().content else { panic!() };
+                let fetched = bob
+                    .fetch_file_payload(attachment)
+                    .await
+                    .unwrap()
+                    .map(|payload| payload.into_bytes().unwrap());
+                assert_eq!(fetched, Some(payload));
+            }
+        }
+    }
+
+    mod given_a_file_sent_to_two_conversations {
+        use super::*;
+
+        type Given = (Database, Conversation, Conversation, Vec<u8>);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let first = database.create_conversation(None).await.unwrap();
+            let second = database.create_conversation(None).await.unwrap();
+            let payload = (0..ATTACHMENT_CHUNK_BYTES * 2 + 10)
+                .map(|i| i as u8)
+                .collect::<Vec<_>>();
+
+            for conversation in [&first, &second] {
+                database
+                    .send_file(conversation.clone(), "same".to_string(), payload.clone())
+                    .await
+                    .unwrap();
+            }
+
+            (database, first, second, payload)
+        }
+
+        #[tokio::test]
+        async fn then_the_payload_is_stored_once() {
+            let (database, ..) = given().await;
+
+            let chunks = blob::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+
+            assert_eq!(chunks, 3);
+        }
+
+        #[tokio::test]
+        async fn then_both_attachments_fetch_the_payload() {
+            let (database, first, second, payload) = given().await;
+
+            for conversation in [first, second] {
+                let message = conversation.get_message(&database, 0).await.unwrap();
+                let Content::Attachment(_, attachment, _) = message.unwrap().content else { panic!() };
+
+                let fetched = database
+                    .fetch_file_payload(attachment)
+                    .await
+                    .unwrap()
+                    .map(|payload| payload.into_bytes().unwrap());
+                assert_eq!(fetched.as_ref(), Some(&payload));
+            }
+        }
+
+        #[tokio::test]
+        async fn then_the_payload_is_synced_to_the_second_conversation() {
+            let (database, _, second, payload) = given().await;
+            let peer = Database::connect(":memory:").await.unwrap();
+
+            let mut trans = peer.begin().await.unwrap();
+            for data in database.patch_log(&second, usize::MAX).await.unwrap() {
+                data.payload.merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            let attachment = attachment::Entity::find()
+                .one(&peer.connection)
+                .await
+                .unwrap()
+                .unwrap();
+            let fetched = peer
+                .fetch_file_payload(attachment.id)
+                .await
+                .unwrap()
+                .map(|payload| payload.into_bytes().unwrap());
+            assert_eq!(fetched, Some(payload));
+        }
+    }
+
+    mod given_an_image_sent {
+        use super::*;
+
+        type Given = (Database, Conversation, u64);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let png = thumbnail::tests::a_png(300, 150);
+            let len = png.len() as u64;
+            database
+                .send_file(conversation.clone(), "photo.png".to_string(), png)
+                .await
+                .unwrap();
+
+            (database, conversation, len)
+        }
+
+        fn preview_size(info: &AttachmentInfo) -> (u32, u32) {
+            let thumbnail = image::load_from_memory(info.thumbnail.as_ref().unwrap()).unwrap();
+
+            (thumbnail.width(), thumbnail.height())
+        }
+
+        async fn info(database: &Database, conversation: &Conversation) -> AttachmentInfo {
+            let message = conversation.get_message(database, 0).await.unwrap();
+            let Content::Attachment(_, _, info) = message.unwrap().content else { panic!() };
+
+            info
+        }
+
+        #[tokio::test]
+        async fn then_its_type_and_size_are_declared() {
+            let (database, conversation, len) = given().await;
+
+            let info = info(&database, &conversation).await;
+
+            assert_eq!(info.mime.as_deref(), Some("image/png"));
+            assert_eq!(info.size, Some(len));
+        }
+
+        #[tokio::test]
+        async fn then_a_preview_is_attached() {
+            let (database, conversation, _) = given().await;
+
+            let info = info(&database, &conversation).await;
+
+            assert_eq!(preview_size(&info), (128, 64));
+        }
+
+        #[tokio::test]
+        async fn then_images_that_do_not_decode_have_no_preview() {
+            let (database, conversation, _) = given().await;
+
+            database
+                .send_file(conversation.clone(), "broken.png".to_string(), vec![0; 100])
+                .await
+                .unwrap();
+
+            let message = conversation.get_message(&database, 1).await.unwrap();
+            let Content::Attachment(_, _, info) = message.unwrap().content else { panic!() };
+            assert_eq!(info.mime.as_deref(), Some("image/png"));
+            assert_eq!(info.thumbnail, None);
+        }
+
+        #[tokio::test]
+        async fn then_peers_receive_them() {
+            let (database, conversation, len) = given().await;
+            let peer = Database::connect(":memory:").await.unwrap();
+
+            let mut trans = peer.begin().await.unwrap();
+            for data in database.patch_log(&conversation, usize::MAX).await.unwrap() {
+                data.payload.merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            let joined = peer.get_conversation(conversation.uuid).await.unwrap();
+            let info = info(&peer, &joined.unwrap()).await;
+            assert_eq!(info.mime.as_deref(), Some("image/png"));
+            assert_eq!(info.size, Some(len));
+            assert_eq!(preview_size(&info), (128, 64));
+        }
+
+        #[tokio::test]
+        async fn then_an_attachment_from_an_older_peer_declares_nothing() {
+            let (database, conversation, _) = given().await;
+            let log = database.patch_log(&conversation, usize::MAX).await.unwrap();
+            let mut trans = database.begin().await.unwrap();
+            for data in log {
+                let Patch::NewTypedAttachmentMessage(message) = data.payload else { continue; };
+                let mut legacy = message.into_crdt();
+                legacy.id = Uuid::new_v4();
+                legacy.size = None;
+                legacy.crdt.sequence += 1;
+                Patch::from(legacy).merge(&mut trans).await.unwrap();
+            }
+            trans.commit().await.unwrap();
+
+            let message = conversation.get_message(&database, 1).await.unwrap();
+            let Content::Attachment(name, _, info) = message.unwrap().content else { panic!() };
+            assert_eq!(name, "photo.png");
+            assert_eq!(info, AttachmentInfo::default());
+        }
+    }
+
+    mod given_a_sent_message {
+        use super::*;
+
+        type Given = (Database, Conversation, Message);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let message = database
+                .send_message(conversation.clone(), "hello".to_string(), None)
+                .await
+                .unwrap();
+
+            (database, conversation, message)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_fetchable_by_uuid() {
+            let (database, conversation, message, ..) = given().await;
+
+            let fetched = conversation
+                .get_message_by_uuid(&database, message.uuid)
+                .await
+                .unwrap();
+
+            assert_eq!(fetched, Some(message));
+        }
+
+        #[tokio::test]
+        async fn then_it_is_returned_as_stored() {
+            let (database, conversation, message, ..) = given().await;
+
+            let stored = conversation.get_message(&database, 0).await.unwrap();
+
+            assert_eq!(message.text(), "hello");
+            assert_eq!(stored, Some(message));
+        }
+    }
+
+    mod given_a_channel_seeding_a_conversation_when_a_message_is_sent {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (Database, ChannelData);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            for text in ["one", "two", "three"] {
+                database
+                    .send_message(conversation.clone(), text.to_string(), None)
+                    .await
+                    .unwrap();
+            }
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            database
+                .send_message(conversation.clone(), "live".to_string(), None)
+                .await
+                .unwrap();
+            let channel = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .remove(0);
+
+            (database, channel)
+        }
+
+        #[tokio::test]
+        async fn then_the_initial_sync_comes_first() {
+            let (database, channel) = given().await;
+            let mut trans = database.begin().await.unwrap();
+
+            let next = trans.next(channel.id.into(), (0, 0)).await.unwrap();
+
+            assert!(matches!(
+                next,
+                Some(SyncData {
+                    id: SyncDataId::InitialSync(_),
+                    ..
+                })
+            ));
+        }
+
+        #[tokio::test]
+        async fn then_the_message_can_skip_ahead_of_the_initial_sync() {
+            let (database, channel) = given().await;
+            let mut trans = database.begin().await.unwrap();
+
+            let next = trans.next_live(channel.id.into(), (0, 0)).await.unwrap();
+
+            let Some(SyncData {
+                id: SyncDataId::Global(_),
+                payload: Patch::NewTextMessage(message),
+            }) = next
+            else {
+                panic!("{next:?}")
+            };
+            assert_eq!(message.text, "live");
+        }
+    }
+
+    mod given_channels_with_pending_patches {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (Database, Conversation, Vec<ChannelData>);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            for _ in 0..2 {
+                database
+                    .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                    .await
+                    .unwrap();
+            }
+            database
+                .send_message(conversation.clone(), "hello".to_string(), None)
+                .await
+                .unwrap();
+            let channels = database.list_channels(&conversation).await.unwrap();
+
+            (database, conversation, channels)
+        }
+
+        async fn ack_all(database: &Database, channel: &ChannelData) {
+            let mut trans = database.begin().await.unwrap();
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+            }
+            trans.commit().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn then_shared_patches_are_counted_once() {
+            let (database, ..) = given().await;
+
+            let seeded = initial_sync::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+            // Both channels share the snapshot taken for the first one, the second
+            // peer joining and the message are pending on both.
+            assert_eq!(database.sync_backlog().await.unwrap(), seeded as usize + 2);
+        }
+
+        #[tokio::test]
+        async fn then_the_backlog_clears_once_every_channel_acks() {
+            let (database, _, channels, ..) = given().await;
+            let before = database.sync_backlog().await.unwrap();
+
+            ack_all(&database, &channels[0]).await;
+            let during = database.sync_backlog().await.unwrap();
+            ack_all(&database, &channels[1]).await;
+            let after = database.sync_backlog().await.unwrap();
+
+            // Everything is still pending on the second channel.
+            assert_eq!(during, before);
+            assert_eq!(after, 0);
+        }
+
+        #[tokio::test]
+        async fn then_patches_of_other_conversations_are_not_counted() {
+            let (database, ..) = given().await;
+            let before = database.sync_backlog().await.unwrap();
+
+            let other = database.create_conversation(None).await.unwrap();
+            database
+                .send_message(other, "elsewhere".to_string(), None)
+                .await
+                .unwrap();
+
+            assert_eq!(database.sync_backlog().await.unwrap(), before);
+        }
+    }
+
+    mod given_a_starred_message {
+        use super::*;
+
+        type Given = (Database, Conversation, Message, i32);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let other = database.create_conversation(None).await.unwrap();
+            for conversation in [&conversation, &other] {
+                database
+                    .send_message(conversation.clone(), "hello".to_string(), None)
+                    .await
+                    .unwrap();
+            }
+            let message = database
+                .send_message(conversation.clone(), "important".to_string(), None)
+                .await
+                .unwrap();
+            let bookmark = database.sync_bookmark().await.unwrap();
+
+            database.set_starred(&message, true).await.unwrap();
+
+            (database, conversation, message, bookmark)
+        }
+
+        #[tokio::test]
+        async fn then_no_patch_is_produced() {
+            let (database, _, _, bookmark) = given().await;
+
+            assert_eq!(database.sync_bookmark().await.unwrap(), bookmark);
+        }
+
+        #[tokio::test]
+        async fn then_it_is_the_only_starred_message() {
+            let (database, conversation, message, ..) = given().await;
+
+            let starred = database.list_starred().await.unwrap();
+
+            assert_eq!(starred.len(), 1);
+            assert_eq!(starred[0].0.uuid, conversation.uuid);
+            assert_eq!(starred[0].1.uuid, message.uuid);
+            assert!(starred[0].1.starred);
+        }
+
+        mod when_it_is_unstarred {
+            use super::*;
+
+            async fn given() -> Given {
+                let (database, conversation, message, bookmark) = super::given().await;
+                database.set_starred(&message, false).await.unwrap();
+
+                (database, conversation, message, bookmark)
+            }
+
+            #[tokio::test]
+            async fn then_nothing_is_starred() {
+                let (database, ..) = given().await;
+
+                assert!(database.list_starred().await.unwrap().is_empty());
+            }
+        }
+    }
+
+    mod given_an_edited_message {
+        use super::*;
+
+        type Given = (Database, Conversation);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let message = database
+                .send_message(conversation.clone(), "helo".to_string(), None)
+                .await
+                .unwrap();
+
+            database
+                .edit_message(&message, "hello".to_string())
+                .await
+                .unwrap();
+
+            (database, conversation)
+        }
+
+        #[tokio::test]
+        async fn then_it_shows_the_new_text() {
+            let (database, conversation) = given().await;
+
+            let message = conversation.get_message(&database, 0).await.unwrap();
+            let message = message.unwrap();
+
+            assert_eq!(message.text(), "hello");
+            assert!(message.meta.edited);
+        }
+
+        #[tokio::test]
+        async fn then_the_edit_is_seeded_to_new_channels() {
+            let (database, conversation) = given().await;
+
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let seeded = seeded_patches(&database, &channel[0]).await;
+
+            assert!(seeded.iter().any(|patch| matches!(
+                patch,
+                Patch::MessageEdit(edit) if edit.text == "hello"
+            )));
+        }
+    }
+
+    mod given_a_removed_member {
+        use super::*;
+
+        type Given = (Database, Conversation, Ed25519Cert);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let removed = Ed25519Seed::generate().public_key();
+            database
+                .create_channel(conversation.clone(), removed)
+                .await
+                .unwrap();
+
+            database
+                .remove_member(&conversation, removed)
+                .await
+                .unwrap();
+
+            (database, conversation, removed)
+        }
+
+        async fn members(database: &Database, conversation: &Conversation) -> Vec<Ed25519Cert> {
+            database
+                .get_conversation(conversation.uuid)
+                .await
+                .unwrap()
+                .unwrap()
+                .members
+                .into_iter()
+                .map(|member| member.key)
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn then_it_is_no_longer_a_member() {
+            let (database, conversation, removed) = given().await;
+
+            assert!(!members(&database, &conversation).await.contains(&removed));
+            assert!(database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .is_empty());
+        }
+
+        #[tokio::test]
+        async fn then_it_stays_removed_after_a_full_resync() {
+            let (database, conversation, removed) = given().await;
+            let newcomer = Ed25519Seed::generate().public_key();
+            database
+                .create_channel(conversation.clone(), newcomer)
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let other = Database::connect(":memory:").await.unwrap();
+
+            let mut trans = other.begin().await.unwrap();
+            for patch in seeded_patches(&database, &channel[0]).await {
+                patch.merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            let members = members(&other, &conversation).await;
+            assert!(members.contains(&newcomer));
+            assert!(!members.contains(&removed));
+        }
+
+        #[tokio::test]
+        async fn then_the_addition_arriving_late_does_not_restore_it() {
+            let (database, conversation, removed) = given().await;
+            let patches = database.patch_log(&conversation, usize::MAX).await.unwrap();
+            let other = Database::connect(":memory:").await.unwrap();
+
+            let mut trans = other.begin().await.unwrap();
+            for data in patches.into_iter().rev() {
+                data.payload.merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            assert!(!members(&other, &conversation).await.contains(&removed));
+        }
+    }
+
+    mod given_a_long_conversation {
+        use super::*;
+
+        type Given = (Database, Conversation);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let peer = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            peer.join_conversation(conversation.uuid).await.unwrap();
+
+            for i in 0..12 {
+                for (sender, text) in [(&database, "mine"), (&peer, "theirs")] {
+                    sender
+                        .send_message(conversation.clone(), format!("{text} {i}"), None)
+                        .await
+                        .unwrap();
+                }
+            }
+            let mut trans = database.begin().await.unwrap();
+            for data in peer.patch_log(&conversation, usize::MAX).await.unwrap() {
+                data.payload.merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            (database, conversation)
+        }
+
+        #[tokio::test]
+        async fn then_the_first_page_is_the_latest_messages() {
+            let (database, conversation) = given().await;
+
+            let page = conversation
+                .messages_page(&database, None, 5, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            let length = conversation.length(&database).await.unwrap();
+            let mut latest = vec![];
+            for index in length - 5..length {
+                latest.push(conversation.get_message(&database, index).await.unwrap());
+            }
+            assert_eq!(page.into_iter().map(Some).collect::<Vec<_>>(), latest);
+        }
+
+        #[tokio::test]
+        async fn then_walking_back_visits_every_message_in_order() {
+            let (database, conversation) = given().await;
+
+            let mut walked = vec![];
+            let mut before = None;
+            loop {
+                let page = conversation
+                    .messages_page(&database, before.as_ref(), 5, MessageOrder::CausalSequence)
+                    .await
+                    .unwrap();
+                let Some(first) = page.first() else { break; };
+                before = Some(first.clone());
+                walked.splice(0..0, page);
+            }
+
+            assert_eq!(walked.len(), 24);
+            for (index, message) in walked.iter().enumerate() {
+                let expected = conversation.get_message(&database, index).await.unwrap();
+                assert_eq!(Some(message), expected.as_ref());
+            }
+        }
+
+        #[tokio::test]
+        async fn then_a_range_matches_the_messages_one_by_one() {
+            let (database, conversation) = given().await;
+
+            let range = conversation
+                .get_messages_range(&database, 7, 10, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            assert_eq!(range.len(), 10);
+            for (index, message) in (7..).zip(range) {
+                let expected = conversation.get_message(&database, index).await.unwrap();
+                assert_eq!(Some(message), expected);
+            }
+        }
+
+        #[tokio::test]
+        async fn then_a_range_stops_at_the_end() {
+            let (database, conversation) = given().await;
+
+            let range = conversation
+                .get_messages_range(&database, 20, 10, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            assert_eq!(range.len(), 4);
+        }
+    }
+
+    mod given_messages_authored_concurrently {
+        use super::*;
+
+        type Given = (Database, Conversation);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let peer = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            peer.join_conversation(conversation.uuid).await.unwrap();
+
+            // Neither has seen the messages of the other, they take the same
+            // positions, and the clock tells every message of one apart
+            // from those of the other.
+            for (sender, text) in [(&database, "mine"), (&peer, "theirs")] {
+                for i in 0..3 {
+                    sender
+                        .send_message(conversation.clone(), format!("{text} {i}"), None)
+                        .await
+                        .unwrap();
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+            let mut trans = database.begin().await.unwrap();
+            for data in peer.patch_log(&conversation, usize::MAX).await.unwrap() {
+                data.payload.merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            (database, conversation)
+        }
+
+        fn texts(messages: &[Message]) -> Vec<&str> {
+            messages.iter().map(Message::text).collect()
+        }
+
+        async fn walk_back(
+            database: &Database,
+            conversation: &Conversation,
+            order: MessageOrder,
+        ) -> Vec<Message> {
+            let mut walked = vec![];
+            let mut before = None;
+            loop {
+                let page = conversation
+                    .messages_page(database, before.as_ref(), 2, order)
+                    .await
+                    .unwrap();
+                let Some(first) = page.first() else { break; };
+                before = Some(first.clone());
+                walked.splice(0..0, page);
+            }
+
+            walked
+        }
+
+        #[tokio::test]
+        async fn then_the_two_orders_differ() {
+            let (database, conversation) = given().await;
+
+            let causal = conversation
+                .get_messages_range(&database, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+            let timestamp = conversation
+                .get_messages_range(&database, 0, 6, MessageOrder::Timestamp)
+                .await
+                .unwrap();
+
+            assert_ne!(texts(&causal), texts(&timestamp));
+            assert_eq!(
+                texts(&timestamp),
+                ["mine 0", "mine 1", "mine 2", "theirs 0", "theirs 1", "theirs 2"]
+            );
+        }
+
+        #[tokio::test]
+        async fn then_the_causal_order_follows_the_positions() {
+            let (database, conversation) = given().await;
+
+            let causal = conversation
+                .get_messages_range(&database, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            let positions = causal.iter().map(|message| message.crdt);
+            let positions = positions
+                .map(|crdt| (crdt.sequence, crdt.writable.author))
+                .collect::<Vec<_>>();
+            assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        #[tokio::test]
+        async fn then_the_timestamp_order_follows_the_clock() {
+            let (database, conversation) = given().await;
+
+            let timestamp = conversation
+                .get_messages_range(&database, 0, 6, MessageOrder::Timestamp)
+                .await
+                .unwrap();
+
+            assert!(timestamp
+                .windows(2)
+                .all(|pair| pair[0].created_at <= pair[1].created_at));
+        }
+
+        #[tokio::test]
+        async fn then_walking_back_matches_the_range_in_either_order() {
+            let (database, conversation) = given().await;
+
+            for order in [MessageOrder::CausalSequence, MessageOrder::Timestamp] {
+                let walked = walk_back(&database, &conversation, order).await;
+                let range = conversation
+                    .get_messages_range(&database, 0, 6, order)
+                    .await
+                    .unwrap();
+
+                assert_eq!(walked, range);
+            }
+        }
+    }
+
+    mod given_a_page_from_a_single_sender {
+        use super::*;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        type Given = (Database, Conversation, Arc<AtomicUsize>);
+        async fn given() -> Given {
+            let mut database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            for i in 0..50 {
+                database
+                    .send_message(conversation.clone(), format!("{i}"), None)
+                    .await
+                    .unwrap();
+            }
+
+            let queries = Arc::new(AtomicUsize::new(0));
+            let counter = queries.clone();
+            database.connection.set_metric_callback(move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            });
+
+            (database, conversation, queries)
+        }
+
+        #[tokio::test]
+        async fn then_loading_it_takes_a_fixed_number_of_queries() {
+            let (database, conversation, queries) = given().await;
+
+            let page = conversation
+                .messages_page(&database, None, 50, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            assert_eq!(page.len(), 50);
+            assert_eq!(queries.load(Ordering::Relaxed), 3);
+        }
+
+        #[tokio::test]
+        async fn then_searching_it_reads_the_sender_once() {
+            let (database, conversation, queries) = given().await;
+
+            let found = conversation.search(&database, "").await.unwrap();
+
+            assert_eq!(found.len(), 50);
+            assert_eq!(queries.load(Ordering::Relaxed), 3);
+        }
+    }
+
+    mod given_corrupted_patches_in_the_sync_tables {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (Database, ChannelData, usize);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .remove(0);
+            let seeded = seeded_patches(&database, &channel).await.len();
+
+            let first = initial_sync::Entity::find()
+                .order_by(initial_sync::Column::Id, Order::Asc)
+                .one(&database.connection)
+                .await
+                .unwrap()
+                .unwrap();
+            initial_sync::ActiveModel {
+                payload: ActiveValue::Set(vec![0xff; 7]),
+                ..first.into_active_model()
+            }
+            .update(&database.connection)
+            .await
+            .unwrap();
+
+            database
+                .send_message(conversation.clone(), "before".to_string(), None)
+                .await
+                .unwrap();
+            entity::entity::sync::ActiveModel {
+                id: ActiveValue::NotSet,
+                payload: ActiveValue::Set(vec![0xff; 7]),
+            }
+            .insert(&database.connection)
+            .await
+            .unwrap();
+            database
+                .send_message(conversation, "after".to_string(), None)
+                .await
+                .unwrap();
+
+            (database, channel, seeded)
+        }
+
+        async fn drain(database: &Database, channel: &ChannelData) -> Vec<SyncData> {
+            let mut trans = database.begin().await.unwrap();
+            let ctx = SqliteSyncCtx::from(channel.id);
+
+            let mut r = Vec::new();
+            while let Some(data) = trans.next(ctx, (0, 0)).await.unwrap() {
+                trans.ack(ctx, data.id).await.unwrap();
+                r.push(data);
+            }
+            trans.commit().await.unwrap();
+
+            r
+        }
+
+        fn texts(patches: &[SyncData]) -> Vec<&str> {
+            patches
+                .iter()
+                .filter_map(|data| match &data.payload {
+                    Patch::NewTextMessage(message) => Some(message.text.as_str()),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn then_the_valid_patches_are_still_sent() {
+            let (database, channel, seeded) = given().await;
+
+            let sent = drain(&database, &channel).await;
+
+            let initial = sent
+                .iter()
+                .filter(|data| matches!(data.id, SyncDataId::InitialSync(_)))
+                .count();
+            assert_eq!(initial, seeded - 1);
+            assert_eq!(texts(&sent), vec!["before", "after"]);
+        }
+
+        #[tokio::test]
+        async fn then_the_corrupted_rows_are_dropped() {
+            let (database, channel, ..) = given().await;
+
+            drain(&database, &channel).await;
+
+            let corrupted = entity::entity::sync::Entity::find()
+                .filter(entity::entity::sync::Column::Payload.eq(vec![0xffu8; 7]))
+                .count(&database.connection)
+                .await
+                .unwrap();
+            assert_eq!(corrupted, 0);
+        }
+
+        #[tokio::test]
+        async fn then_the_patch_log_skips_them() {
+            let (database, channel, ..) = given().await;
+            let conversation = database
+                .get_conversation(channel.conversation)
+                .await
+                .unwrap()
+                .unwrap();
+
+            let log = database.patch_log(&conversation, 10).await.unwrap();
+
+            assert_eq!(texts(&log), vec!["before", "after"]);
+        }
+    }
+
+    mod given_a_database_to_compact {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (Database, ChannelData);
+        async fn given() -> Given {
+            let path = temp_path();
+            let database = Database::connect(path.to_str().unwrap()).await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            for i in 0..100 {
+                database
+                    .send_message(conversation.clone(), format!("{i:01000}"), None)
+                    .await
+                    .unwrap();
+            }
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .remove(0);
+
+            (database, channel)
+        }
+
+        async fn initial_sync_len(database: &Database) -> u64 {
+            initial_sync::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn then_snapshots_of_deleted_channels_are_removed() {
+            let (database, channel) = given().await;
+            let seeded = initial_sync_len(&database).await;
+            channel::Entity::delete_by_id(channel.id)
+                .exec(&database.connection)
+                .await
+                .unwrap();
+
+            let stats = database.compact().await.unwrap();
+
+            assert!(seeded > 100);
+            assert_eq!(stats.initial_sync_removed, seeded);
+            assert!(stats.bytes_reclaimed > 100_000);
+            assert_eq!(initial_sync_len(&database).await, 0);
+        }
+
+        #[tokio::test]
+        async fn then_acked_snapshot_patches_are_removed() {
+            let (database, channel) = given().await;
+            let ids = initial_sync::Entity::find()
+                .order_by(initial_sync::Column::Id, Order::Asc)
+                .all(&database.connection)
+                .await
+                .unwrap();
+            let mut trans = database.begin().await.unwrap();
+            let ctx = SqliteSyncCtx::from(channel.id);
+            trans
+                .ack(ctx, SyncDataId::InitialSync(ids[9].id))
+                .await
+                .unwrap();
+            trans.commit().await.unwrap();
+            let backlog = database.sync_backlog().await.unwrap();
+
+            let stats = database.compact().await.unwrap();
+
+            assert_eq!(stats.initial_sync_removed, 10);
+            assert_eq!(database.sync_backlog().await.unwrap(), backlog);
+        }
+
+        #[tokio::test]
+        async fn then_a_snapshot_drained_halfway_is_not_reused() {
+            let (database, channel) = given().await;
+            let ids = initial_sync::Entity::find()
+                .order_by(initial_sync::Column::Id, Order::Asc)
+                .all(&database.connection)
+                .await
+                .unwrap();
+            let mut trans = database.begin().await.unwrap();
+            let ctx = SqliteSyncCtx::from(channel.id);
+            trans
+                .ack(ctx, SyncDataId::InitialSync(ids[ids.len() / 2].id))
+                .await
+                .unwrap();
+            trans.commit().await.unwrap();
+            database.compact().await.unwrap();
+
+            let conversation = database.list_conversation().await.unwrap().remove(0);
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let second = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|second| second.id != channel.id)
+                .unwrap();
+            let seeded = seeded_patches(&database, &second).await;
+
+            assert!(matches!(seeded[0], Patch::Conversation(_)));
+        }
+
+        #[tokio::test]
+        async fn then_compacting_again_removes_nothing() {
+            let (database, ..) = given().await;
+            database.compact().await.unwrap();
+
+            let stats = database.compact().await.unwrap();
+
+            assert_eq!(stats.initial_sync_removed, 0);
+            assert_eq!(stats.sync_removed, 0);
+        }
+    }
+
+    mod given_a_reply {
+        use super::*;
+
+        type Given = (Database, Conversation, Message);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let original = database
+                .send_message(conversation.clone(), "question".to_string(), None)
+                .await
+                .unwrap();
+
+            database
+                .send_message(
+                    conversation.clone(),
+                    "answer".to_string(),
+                    Some(original.uuid),
+                )
+                .await
+                .unwrap();
+
+            (database, conversation, original)
+        }
+
+        #[tokio::test]
+        async fn then_it_refers_to_the_original() {
+            let (database, conversation, original) = given().await;
+
+            let reply = conversation.get_message(&database, 1).await.unwrap();
+
+            assert_eq!(reply.unwrap().meta.reply_to, Some(original.uuid));
+        }
+
+        #[tokio::test]
+        async fn then_it_is_seeded_to_new_channels() {
+            let (database, conversation, original) = given().await;
+
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let seeded = seeded_patches(&database, &channel[0]).await;
+
+            assert!(seeded.iter().any(|patch| matches!(
+                patch,
+                Patch::NewReplyMessage(reply) if reply.reply_to == original.uuid
+            )));
+        }
+
+        #[tokio::test]
+        async fn then_another_database_merges_it() {
+            let (database, conversation, original) = given().await;
+            let patches = database.patch_log(&conversation, usize::MAX).await.unwrap();
+            let other = Database::connect(":memory:").await.unwrap();
+
+            let mut trans = other.begin().await.unwrap();
+            for data in patches {
+                data.payload.merge(&mut trans).await;
+            }
+            trans.commit().await.unwrap();
+
+            let reply = conversation.get_message(&other, 1).await.unwrap();
+            assert_eq!(reply.unwrap().meta.reply_to, Some(original.uuid));
+        }
+
+        #[tokio::test]
+        async fn then_once_edited_its_meta_reports_the_edit_and_the_reply() {
+            let (database, conversation, original) = given().await;
+            let reply = conversation.get_message(&database, 1).await.unwrap();
+
+            database
+                .edit_message(&reply.unwrap(), "better answer".to_string())
+                .await
+                .unwrap();
+
+            let reply = conversation.get_message(&database, 1).await.unwrap();
+            assert_eq!(
+                reply.unwrap().meta,
+                MessageMeta {
+                    edited: true,
+                    deleted: false,
+                    reply_to: Some(original.uuid),
+                }
+            );
+        }
+    }
+
+    mod given_a_deleted_message {
+        use super::*;
+
+        type Given = (Database, Conversation, Message);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let message = database
+                .send_message(conversation.clone(), "secret".to_string(), None)
+                .await
+                .unwrap();
+            database
+                .send_message(conversation.clone(), "hello".to_string(), None)
+                .await
+                .unwrap();
+
+            database.delete_message(&message).await.unwrap();
+
+            (database, conversation, message)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_shown_as_deleted() {
+            let (database, conversation, ..) = given().await;
+
+            let message = conversation.get_message(&database, 0).await.unwrap();
+
+            assert_eq!(conversation.length(&database).await.unwrap(), 2);
+            assert_eq!(message.unwrap().content, Content::Deleted);
+        }
+
+        #[tokio::test]
+        async fn then_its_meta_reports_the_deletion() {
+            let (database, conversation, ..) = given().await;
+
+            let message = conversation.get_message(&database, 0).await.unwrap();
+
+            assert!(message.unwrap().meta.deleted);
+        }
+
+        #[tokio::test]
+        async fn then_its_text_is_not_found() {
+            let (database, conversation, ..) = given().await;
+
+            let found = conversation.search(&database, "secret").await.unwrap();
+
+            assert!(found.is_empty());
+        }
+
+        mod when_a_new_channel_is_seeded {
+            use super::*;
+
+            async fn given() -> (Database, Conversation, Message, Vec<Patch>) {
+                let (database, conversation, message) = super::given().await;
+                database
+                    .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                    .await
+                    .unwrap();
+                let channel = database.list_channels(&conversation).await.unwrap();
+                let seeded = seeded_patches(&database, &channel[0]).await;
+
+                (database, conversation, message, seeded)
+            }
+
+            #[tokio::test]
+            async fn then_the_tombstone_is_seeded() {
+                let (_, _, message, seeded) = given().await;
+
+                assert!(seeded.iter().any(|patch| matches!(
+                    patch,
+                    Patch::MessageTombstone(tombstone) if tombstone.id == message.uuid
+                )));
+            }
+
+            #[tokio::test]
+            async fn then_the_peer_sees_it_deleted() {
+                let (_, conversation, _, seeded) = given().await;
+                let peer = Database::connect(":memory:").await.unwrap();
+
+                let mut trans = peer.begin().await.unwrap();
+                for patch in seeded {
+                    patch.merge(&mut trans).await;
+                }
+                trans.commit().await.unwrap();
+
+                let message = conversation.get_message(&peer, 0).await.unwrap();
+                assert_eq!(message.unwrap().content, Content::Deleted);
+            }
+
+            #[tokio::test]
+            async fn then_merging_the_tombstone_again_changes_nothing() {
+                let (database, _, _, seeded) = given().await;
+                let tombstone = seeded
+                    .into_iter()
+                    .find(|patch| matches!(patch, Patch::MessageTombstone(_)))
+                    .unwrap();
+
+                let mut trans = database.begin().await.unwrap();
+                let merged = tombstone.merge(&mut trans).await;
+
+                assert_eq!(merged, None);
+            }
+        }
+    }
+
+    mod given_a_message_delivered_by_a_peer {
+        use super::*;
+        use crate::{database::sync::SyncDataSource, testing::sync_until_idle};
+
+        type Given = (Database, Conversation, Database, Message);
+        async fn given() -> Given {
+            let server = Database::connect(":memory:").await.unwrap();
+            let alice = Database::connect(":memory:").await.unwrap();
+            let conversation = server.create_conversation(None).await.unwrap();
+            let joined = alice.join_conversation(conversation.uuid).await.unwrap();
+            server
+                .create_channel(conversation.clone(), *alice.cert())
+                .await
+                .unwrap();
+            alice
+                .create_channel(joined.clone(), *server.cert())
+                .await
+                .unwrap();
+            alice
+                .send_message(joined, "sent".to_string(), None)
+                .await
+                .unwrap();
+            sync_until_idle(&server, &alice).await.unwrap();
+            let message = server.new_messages(Some(&conversation)).await.unwrap();
+
+            (server, conversation, alice, message[0].clone())
+        }
+
+        #[tokio::test]
+        async fn then_its_peer_delivered_it() {
+            let (server, _, alice, message) = given().await;
+
+            let delivered_by = server.delivered_by(&message).await.unwrap();
+
+            assert_eq!(delivered_by, Some(*alice.cert()));
+        }
+
+        #[tokio::test]
+        async fn then_a_local_message_was_delivered_by_nobody() {
+            let (server, conversation, ..) = given().await;
+
+            let message = server
+                .send_message(conversation, "local".to_string(), None)
+                .await
+                .unwrap();
+
+            assert_eq!(server.delivered_by(&message).await.unwrap(), None);
+        }
+
+        #[tokio::test]
+        async fn then_another_peer_claiming_to_be_its_author_is_told_apart() {
+            let (server, conversation, alice, message) = given().await;
+            let mallory = Ed25519Seed::generate().public_key();
+            server
+                .create_channel(conversation.clone(), mallory)
+                .await
+                .unwrap();
+            let channel = server
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|channel| channel.peer_cert == mallory)
+                .unwrap();
+
+            let forged = patch::NewTextMessage {
+                id: Uuid::new_v4(),
+                from: patch::Key::new_exact(&alice.cert().0),
+                conversation: conversation.uuid,
+                text: "forged".to_string(),
+                created_at: 0,
+                crdt: message.crdt,
+            };
+            let mut trans = server.begin().await.unwrap();
+            let data = SyncData {
+                id: SyncDataId::Global(1),
+                payload: Patch::NewTextMessage(forged),
+            };
+            SyncDataSource::merge(&mut trans, channel.id.into(), data)
+                .await
+                .unwrap()
+                .unwrap();
+            trans.commit().await.unwrap();
+            let forged = server
+                .new_messages(Some(&conversation))
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|message| message.text() == "forged")
+                .unwrap();
+
+            assert_eq!(forged.from.key, *alice.cert());
+            assert_eq!(server.delivered_by(&forged).await.unwrap(), Some(mallory));
+        }
+    }
+
+    mod given_a_deleted_file {
+        use super::*;
+
+        #[tokio::test]
+        async fn then_it_is_not_seeded_to_a_new_channel() {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            database
+                .send_file(conversation.clone(), "secret.txt".to_string(), vec![1; 17])
+                .await
+                .unwrap();
+            let message = conversation.get_message(&database, 0).await.unwrap();
+            database.delete_message(&message.unwrap()).await.unwrap();
+
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let seeded = seeded_patches(&database, &channel[0]).await;
+
+            assert!(!seeded
+                .iter()
+                .any(|patch| matches!(patch, Patch::Attachment(_) | Patch::AttachmentChunk(_))));
+        }
+    }
+
+    mod given_a_message_sent_to_two_channels {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (Database, Conversation, Vec<ChannelData>, i32);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            for _ in 0..2 {
+                database
+                    .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                    .await
+                    .unwrap();
+            }
+            let channels = database.list_channels(&conversation).await.unwrap();
+            for channel in channels.iter() {
+                ack_all(&database, channel).await;
+            }
+
+            database
+                .send_message(conversation.clone(), "hello".to_string(), None)
+                .await
+                .unwrap();
+            let bookmark = database.sync_bookmark().await.unwrap();
+
+            (database, conversation, channels, bookmark)
+        }
+
+        async fn ack_all(database: &Database, channel: &ChannelData) {
+            let mut trans = database.begin().await.unwrap();
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+            }
+            trans.commit().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn then_it_is_not_delivered_to_all() {
+            let (database, conversation, _, bookmark) = given().await;
+
+            let delivered = database
+                .delivered_to_all(&conversation, bookmark)
+                .await
+                .unwrap();
+
+            assert!(!delivered);
+        }
+
+        #[tokio::test]
+        async fn then_one_channel_acking_is_not_enough() {
+            let (database, conversation, channels, bookmark) = given().await;
+
+            ack_all(&database, &channels[0]).await;
+            let delivered = database
+                .delivered_to_all(&conversation, bookmark)
+                .await
+                .unwrap();
+
+            assert!(!delivered);
+        }
+
+        #[tokio::test]
+        async fn then_it_is_delivered_once_both_channels_ack() {
+            let (database, conversation, channels, bookmark) = given().await;
+
+            for channel in channels.iter() {
+                ack_all(&database, channel).await;
+            }
+            let delivered = database
+                .delivered_to_all(&conversation, bookmark)
+                .await
+                .unwrap();
+
+            assert!(delivered);
+        }
+    }
+
+    mod given_a_conversation_created_with_an_id {
+        use super::*;
+
+        type Given = (Database, Uuid, Conversation);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let id = Uuid::new_v4();
+            let conversation = database
+                .create_conversation_with_id(id, Some("control".to_string()))
+                .await
+                .unwrap();
+
+            (database, id, conversation)
+        }
+
+        #[tokio::test]
+        async fn then_it_has_that_id_and_the_user_as_member() {
+            let (database, id, conversation) = given().await;
+
+            assert_eq!(conversation.uuid, id);
+            assert_eq!(conversation.title.as_deref(), Some("control"));
+            assert_eq!(conversation.members.len(), 1);
+            assert_eq!(conversation.members[0].key, *database.cert());
+        }
+
+        #[tokio::test]
+        async fn then_creating_it_again_changes_nothing() {
+            let (database, id, conversation) = given().await;
+            let log = entity::entity::sync::Entity::find().count(&database.connection);
+            let log = log.await.unwrap();
+
+            let again = database
+                .create_conversation_with_id(id, Some("other".to_string()))
+                .await
+                .unwrap();
+
+            let patches = entity::entity::sync::Entity::find().count(&database.connection);
+            assert_eq!(again, conversation);
+            assert_eq!(patches.await.unwrap(), log);
+            assert_eq!(database.list_conversation().await.unwrap().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn then_a_joined_conversation_gains_the_membership() {
+            let database = Database::connect(":memory:").await.unwrap();
+            let id = Uuid::new_v4();
+            database.join_conversation(id).await.unwrap();
+
+            let conversation = database
+                .create_conversation_with_id(id, None)
+                .await
+                .unwrap();
+
+            assert_eq!(conversation.members.len(), 1);
+        }
+    }
+
+    mod given_the_key_of_a_server {
+        use super::*;
+
+        fn given() -> Ed25519Cert {
+            Ed25519Seed::new([7; 32]).public_key()
+        }
+
+        #[test]
+        fn then_its_control_conversation_id_is_pinned() {
+            let cert = given();
+
+            let id = Database::control_conversation_id(&cert);
+
+            assert_eq!(id, uuid::uuid!("8e3a8679-795b-5fcf-ad68-161c49fcef8e"));
+            assert_eq!(id.get_version_num(), 5);
+        }
+
+        #[tokio::test]
+        async fn then_the_control_conversation_has_that_id() {
+            let cert = given();
+            let database = Database::connect(":memory:").await.unwrap();
+
+            let control = database.control_conversation(&cert).await.unwrap();
+            let again = database.control_conversation(&cert).await.unwrap();
+
+            assert_eq!(control.uuid, Database::control_conversation_id(&cert));
+            assert_eq!(again, control);
+            assert_eq!(control.members.len(), 1);
+        }
+
+        #[tokio::test]
+        async fn then_a_control_conversation_under_the_old_id_is_kept() {
+            let cert = given();
+            let database = Database::connect(":memory:").await.unwrap();
+            let mut legacy = [0; 16];
+            for (i, legacy) in legacy.iter_mut().enumerate() {
+                *legacy = cert.0[i] ^ cert.0[i * 2];
+            }
+            let legacy = database
+                .join_conversation(Uuid::from_bytes(legacy))
+                .await
+                .unwrap();
+
+            let control = database.control_conversation(&cert).await.unwrap();
+
+            assert_eq!(control.uuid, legacy.uuid);
+            assert_eq!(database.list_conversation().await.unwrap().len(), 1);
+        }
+    }
+
+    mod given_two_peers_with_pinned_identities {
+        use super::*;
+        use crate::testing::sync_until_idle;
+
+        const ALICE: [u8; 32] = [1; 32];
+        const BOB: [u8; 32] = [2; 32];
+
+        type Given = (Database, Conversation, Database, Conversation);
+        async fn given() -> Given {
+            let alice =
+                Database::connect_with_identity(":memory:", Ed25519Seed::from_entropy(ALICE))
+                    .await
+                    .unwrap();
+            let bob = Database::connect_with_identity(":memory:", Ed25519Seed::from_entropy(BOB))
+                .await
+                .unwrap();
+            let conversation = alice.create_conversation(None).await.unwrap();
+            let joined = bob.join_conversation(conversation.uuid).await.unwrap();
+            alice
+                .create_channel(conversation.clone(), *bob.cert())
+                .await
+                .unwrap();
+            bob.create_channel(joined.clone(), *alice.cert())
+                .await
+                .unwrap();
+
+            (alice, conversation, bob, joined)
+        }
+
+        #[tokio::test]
+        async fn then_the_same_seed_gives_the_same_identity() {
+            let (alice, ..) = given().await;
+
+            let again =
+                Database::connect_with_identity(":memory:", Ed25519Seed::from_entropy(ALICE))
+                    .await
+                    .unwrap();
+
+            assert_eq!(again.cert(), alice.cert());
+            assert_eq!(*alice.cert(), Ed25519Seed::from_entropy(ALICE).public_key());
+        }
+
+        #[tokio::test]
+        async fn then_concurrent_messages_are_ordered_by_author_on_both_peers() {
+            let (alice, conversation, bob, joined) = given().await;
+            alice
+                .send_message(conversation.clone(), "from alice".to_string(), None)
+                .await
+                .unwrap();
+            bob.send_message(joined.clone(), "from bob".to_string(), None)
+                .await
+                .unwrap();
+
+            sync_until_idle(&alice, &bob).await.unwrap();
+
+            for (database, conversation) in [(alice, conversation), (bob, joined)] {
+                let messages = conversation
+                    .get_messages_range(&database, 0, 2, MessageOrder::CausalSequence)
+                    .await
+                    .unwrap();
+                let texts = messages.iter().map(Message::text).collect::<Vec<_>>();
+                assert_eq!(texts, ["from bob", "from alice"]);
+            }
+        }
+    }
+
+    mod given_two_devices_of_the_same_identity {
+        use super::*;
+
+        type Given = (Database, Conversation, Database, Conversation);
+        async fn given() -> Given {
+            let seed = Ed25519Seed::generate();
+            let laptop = Database::connect_with(":memory:", None, seed.clone())
+                .await
+                .unwrap();
+            let phone = Database::connect_with(":memory:", None, seed)
+                .await
+                .unwrap();
+            let conversation = laptop.create_conversation(None).await.unwrap();
+            let joined = phone.join_conversation(conversation.uuid).await.unwrap();
+
+            laptop.pair_device(phone.cert()).await.unwrap();
+            phone.pair_device(laptop.cert()).await.unwrap();
+
+            (laptop, conversation, phone, joined)
+        }
+
+        #[tokio::test]
+        async fn then_the_conversation_has_a_channel_to_the_other_device() {
+            let (laptop, conversation, phone, _) = given().await;
+
+            let channels = laptop.list_channels(&conversation).await.unwrap();
+
+            assert_eq!(channels.len(), 1);
+            assert_eq!(channels[0].peer_cert, *phone.cert());
+            assert!(channels[0].own_device);
+            assert_eq!(laptop.list_paired_devices().await.unwrap(), [*phone.cert()]);
+        }
+
+        #[tokio::test]
+        async fn then_conversations_created_afterwards_have_one_too() {
+            let (laptop, _, _, _) = given().await;
+
+            let conversation = laptop.create_conversation(None).await.unwrap();
+            let channels = laptop.list_channels(&conversation).await.unwrap();
+
+            assert_eq!(channels.len(), 1);
+            assert!(channels[0].own_device);
+        }
+
+        #[tokio::test]
+        async fn then_a_message_sent_on_one_reaches_the_other() {
+            let (laptop, conversation, phone, joined) = given().await;
+            laptop
+                .send_message(conversation.clone(), "hello".to_string(), None)
+                .await
+                .unwrap();
+
+            let to_phone = laptop.list_channels(&conversation).await.unwrap().remove(0);
+            let to_laptop = phone.list_channels(&joined).await.unwrap().remove(0);
+            let mut laptop_trans = laptop.begin().await.unwrap();
+            let mut phone_trans = phone.begin().await.unwrap();
+            let mut from_laptop = laptop.start_sync(to_phone);
+            let mut from_phone = phone.start_sync(to_laptop);
+            greet(&mut from_laptop, &mut laptop_trans).await;
+            while let Some(message) = from_laptop.tx(&mut laptop_trans).await.unwrap() {
+                from_phone.rx(&mut phone_trans, message).await.unwrap();
+            }
+            laptop_trans.commit().await.unwrap();
+            phone_trans.commit().await.unwrap();
+
+            let messages = joined
+                .messages_page(&phone, None, 10, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].content, Content::Text("hello".to_string()));
+            assert_eq!(messages[0].from.key, *laptop.cert());
+        }
+    }
+
+    mod given_two_devices_of_the_same_identity_sending_at_once {
+        use super::*;
+
+        type Given = (Database, Conversation, Database, Conversation);
+        async fn given() -> Given {
+            let seed = Ed25519Seed::generate();
+            let laptop = Database::connect_with(":memory:", None, seed.clone())
+                .await
+                .unwrap();
+            let phone = Database::connect_with(":memory:", None, seed)
+                .await
+                .unwrap();
+            let conversation = laptop.create_conversation(None).await.unwrap();
+            let joined = phone.join_conversation(conversation.uuid).await.unwrap();
+            laptop.pair_device(phone.cert()).await.unwrap();
+            phone.pair_device(laptop.cert()).await.unwrap();
+
+            for i in 0..3 {
+                laptop
+                    .send_message(conversation.clone(), format!("laptop {i}"), None)
+                    .await
+                    .unwrap();
+                phone
+                    .send_message(joined.clone(), format!("phone {i}"), None)
+                    .await
+                    .unwrap();
+            }
+            exchange(&laptop, &conversation, &phone, &joined).await;
+            exchange(&phone, &joined, &laptop, &conversation).await;
+
+            (laptop, conversation, phone, joined)
+        }
+
+        async fn exchange(
+            from: &Database,
+            from_conversation: &Conversation,
+            to: &Database,
+            to_conversation: &Conversation,
+        ) {
+            let channel = from
+                .list_channels(from_conversation)
+                .await
+                .unwrap()
+                .remove(0);
+            let back = to.list_channels(to_conversation).await.unwrap().remove(0);
+            let mut from_trans = from.begin().await.unwrap();
+            let mut to_trans = to.begin().await.unwrap();
+            let mut tx = from.start_sync(channel);
+            let mut rx = to.start_sync(back);
+            greet(&mut tx, &mut from_trans).await;
+            while let Some(message) = tx.tx(&mut from_trans).await.unwrap() {
+                rx.rx(&mut to_trans, message).await.unwrap();
+            }
+            from_trans.commit().await.unwrap();
+            to_trans.commit().await.unwrap();
+        }
+
+        async fn walk_back(database: &Database, conversation: &Conversation) -> Vec<Message> {
+            let mut walked = vec![];
+            let mut before = None;
+            loop {
+                let page = conversation
+                    .messages_page(database, before.as_ref(), 1, MessageOrder::CausalSequence)
+                    .await
+                    .unwrap();
+                let Some(first) = page.first() else { break; };
+                before = Some(first.clone());
+                walked.splice(0..0, page);
+            }
+
+            walked
+        }
+
+        fn uuids(messages: &[Message]) -> Vec<Uuid> {
+            messages.iter().map(|message| message.uuid).collect()
+        }
+
+        #[tokio::test]
+        async fn then_their_messages_share_positions() {
+            let (laptop, conversation, ..) = given().await;
+
+            let messages = conversation
+                .get_messages_range(&laptop, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            assert_eq!(messages.len(), 6);
+            assert_eq!(messages[0].crdt, messages[1].crdt);
+        }
+
+        #[tokio::test]
+        async fn then_both_list_them_in_the_same_order() {
+            let (laptop, conversation, phone, joined) = given().await;
+
+            let on_laptop = conversation
+                .get_messages_range(&laptop, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+            let on_phone = joined
+                .get_messages_range(&phone, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            assert_eq!(uuids(&on_laptop), uuids(&on_phone));
+        }
+
+        #[tokio::test]
+        async fn then_walking_back_one_by_one_visits_every_message() {
+            let (laptop, conversation, phone, joined) = given().await;
+
+            let listed = conversation
+                .get_messages_range(&laptop, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            let on_laptop = walk_back(&laptop, &conversation).await;
+            let on_phone = walk_back(&phone, &joined).await;
+
+            assert_eq!(uuids(&on_laptop), uuids(&listed));
+            assert_eq!(uuids(&on_phone), uuids(&listed));
+        }
+
+        #[tokio::test]
+        async fn then_a_clear_before_one_of_them_keeps_the_same_messages_on_both() {
+            let (laptop, conversation, phone, joined) = given().await;
+            let listed = conversation
+                .get_messages_range(&laptop, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+
+            laptop
+                .clear_history(conversation.clone(), Some(&listed[1]))
+                .await
+                .unwrap();
+            exchange(&laptop, &conversation, &phone, &joined).await;
+
+            let on_laptop = conversation
+                .get_messages_range(&laptop, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+            let on_phone = joined
+                .get_messages_range(&phone, 0, 6, MessageOrder::CausalSequence)
+                .await
+                .unwrap();
+            assert_eq!(uuids(&on_laptop), uuids(&listed[1..]));
+            assert_eq!(uuids(&on_phone), uuids(&listed[1..]));
+        }
+    }
+
+    mod given_a_conversation_whose_history_is_cleared {
+        use super::*;
+
+        type Given = (Database, Conversation, Vec<Patch>, Vec<Patch>);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let deleted = database
+                .send_message(conversation.clone(), "first".to_string(), None)
+                .await
+                .unwrap();
+            database
+                .send_message(conversation.clone(), "second".to_string(), None)
+                .await
+                .unwrap();
+            database.delete_message(&deleted).await.unwrap();
+
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let before = seeded_patches(&database, &channel[0]).await;
+            database
+                .remove_channel(conversation.clone(), channel[0].peer_cert)
+                .await
+                .unwrap();
+
+            database
+                .clear_history(conversation.clone(), None)
+                .await
+                .unwrap();
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let after = seeded_patches(&database, &channel[0]).await;
+
+            (database, conversation, before, after)
+        }
+
+        async fn merge_all(database: &Database, patches: Vec<Patch>) -> usize {
+            let mut trans = database.begin().await.unwrap();
+            let mut merged = 0;
+            for patch in patches {
+                merged += patch.merge(&mut trans).await.is_some() as usize;
+            }
+            trans.commit().await.unwrap();
+
+            merged
+        }
+
+        #[tokio::test]
+        async fn then_its_messages_are_gone() {
+            let (database, conversation, ..) = given().await;
+
+            assert_eq!(conversation.length(&database).await.unwrap(), 0);
+        }
+
+        #[tokio::test]
+        async fn then_the_clear_is_seeded() {
+            let (_, conversation, _, after) = given().await;
+
+            assert!(after.iter().any(|patch| matches!(
+                patch,
+                Patch::HistoryClear(clear) if clear.conversation == conversation.uuid
+            )));
+        }
+
+        #[tokio::test]
+        async fn then_a_message_sent_afterwards_is_kept() {
+            let (database, conversation, ..) = given().await;
+
+            database
+                .send_message(conversation.clone(), "third".to_string(), None)
+                .await
+                .unwrap();
+
+            let message = conversation.get_message(&database, 0).await.unwrap();
+            assert_eq!(conversation.length(&database).await.unwrap(), 1);
+            assert_eq!(message.unwrap().text(), "third");
+        }
+
+        #[tokio::test]
+        async fn then_a_message_sent_afterwards_reaches_peers() {
+            use crate::database::sync::SyncDataSource;
+            let (database, conversation, ..) = given().await;
+            database
+                .send_message(conversation.clone(), "third".to_string(), None)
+                .await
+                .unwrap();
+
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let ctx = SqliteSyncCtx::from(channel[0].id);
+            let mut trans = database.begin().await.unwrap();
+            let mut patches = Vec::new();
+            while let Some(data) = trans.next(ctx, (0, 0)).await.unwrap() {
+                trans.ack(ctx, data.id).await.unwrap();
+                patches.push(data.payload);
+            }
+            let peer = Database::connect(":memory:").await.unwrap();
+            merge_all(&peer, patches).await;
+
+            let message = conversation.get_message(&peer, 0).await.unwrap();
+            assert_eq!(conversation.length(&peer).await.unwrap(), 1);
+            assert_eq!(message.unwrap().text(), "third");
+        }
+
+        #[tokio::test]
+        async fn then_a_lagging_peer_does_not_resurrect_the_messages() {
+            let (database, conversation, before, _) = given().await;
+
+            let old_messages = before
+                .into_iter()
+                .filter(|patch| !matches!(patch, Patch::Conversation(_)))
+                .collect();
+            let merged = merge_all(&database, old_messages).await;
+
+            assert_eq!(merged, 0);
+            assert_eq!(conversation.length(&database).await.unwrap(), 0);
+        }
+
+        #[tokio::test]
+        async fn then_an_older_clear_is_ignored() {
+            let (database, conversation, ..) = given().await;
+
+            let older = patch::HistoryClear {
+                conversation: conversation.uuid,
+                before: CrdtPosition {
+                    sequence: 0,
+                    author: Author(0),
+                    id: Uuid::nil(),
+                },
+                author: Author(0),
+            };
+            let mut trans = database.begin().await.unwrap();
+            let merged = trans.merge(older).await;
+
+            assert_eq!(merged, None);
+        }
+
+        #[tokio::test]
+        async fn then_a_peer_converges_whichever_arrives_first() {
+            let (_, conversation, before, after) = given().await;
+            let messages_first = Database::connect(":memory:").await.unwrap();
+            let clear_first = Database::connect(":memory:").await.unwrap();
+
+            merge_all(&messages_first, before.clone()).await;
+            merge_all(&messages_first, after.clone()).await;
+            merge_all(&clear_first, after).await;
+            merge_all(&clear_first, before).await;
+
+            for peer in [messages_first, clear_first] {
+                assert_eq!(conversation.length(&peer).await.unwrap(), 0);
+                let rows = message::Entity::find().all(&peer.connection).await.unwrap();
+                assert!(rows.is_empty());
+            }
+        }
+    }
+
+    mod given_a_file_whose_history_is_cleared {
+        use super::*;
+
+        type Given = (Database, Conversation);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let payload = vec![1; ATTACHMENT_CHUNK_BYTES + 10];
+            database
+                .send_file(conversation.clone(), "secret.txt".to_string(), payload)
+                .await
+                .unwrap();
+            database
+                .clear_history(conversation.clone(), None)
+                .await
+                .unwrap();
+
+            (database, conversation)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_not_seeded_to_a_new_channel() {
+            let (database, conversation) = given().await;
+
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap();
+            let seeded = seeded_patches(&database, &channel[0]).await;
+
+            assert!(!seeded
+                .iter()
+                .any(|patch| matches!(patch, Patch::Attachment(_) | Patch::AttachmentChunk(_))));
+        }
+
+        #[tokio::test]
+        async fn then_its_payload_is_deleted() {
+            let (database, ..) = given().await;
+
+            let attachments = attachment::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+            let chunks = blob::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+
+            assert_eq!(attachments, 0);
+            assert_eq!(chunks, 0);
+        }
+    }
+    mod given_a_message_sent_while_no_channel_is_connected {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (Database, Conversation, ChannelData, Message);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .remove(0);
+
+            let message = database
+                .send_message(conversation.clone(), "hello".to_string(), None)
+                .await
+                .unwrap();
+
+            (database, conversation, channel, message)
+        }
+
+        #[tokio::test]
+        async fn then_it_is_pending() {
+            let (database, _, _, message) = given().await;
+
+            let state = database.delivery_state(&message).await.unwrap();
+
+            assert_eq!(state, DeliveryState::Pending);
+        }
+
+        #[tokio::test]
+        async fn then_it_is_delivered_once_the_channel_acks_it() {
+            let (database, _, channel, message) = given().await;
+
+            let mut trans = database.begin().await.unwrap();
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+            }
+            trans.commit().await.unwrap();
+            let state = database.delivery_state(&message).await.unwrap();
+
+            assert_eq!(state, DeliveryState::Delivered);
+        }
+
+        #[tokio::test]
+        async fn then_the_channel_sync_index_follows_the_acks() {
+            let (database, _, channel, _) = given().await;
+            let before = database.channel_sync_index(&channel).await.unwrap();
+
+            let mut trans = database.begin().await.unwrap();
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+            }
+            trans.commit().await.unwrap();
+            let after = database.channel_sync_index(&channel).await.unwrap();
+
+            assert!(before.is_some());
+            assert!(before < after);
+        }
+
+        #[tokio::test]
+        async fn then_it_fails_once_the_conversation_has_no_channel() {
+            let (database, conversation, channel, message) = given().await;
+
+            database
+                .remove_channel(conversation, channel.peer_cert)
+                .await
+                .unwrap();
+            let state = database.delivery_state(&message).await.unwrap();
+
+            assert_eq!(state, DeliveryState::Failed);
+        }
+    }
+
+    mod given_a_channel_that_synced_everything {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (Database, Conversation, ChannelData);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database.list_channels(&conversation).await.unwrap()[0].clone();
+
+            let mut trans = database.begin().await.unwrap();
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+            }
+            trans.commit().await.unwrap();
+
+            (database, conversation, channel)
+        }
+
+        mod when_a_message_is_sent_while_it_is_offline {
+            use super::*;
+
+            async fn given() -> Given {
+                let (database, conversation, channel) = super::given().await;
+                database
+                    .send_message(conversation.clone(), "response".to_string(), None)
+                    .await
+                    .unwrap();
+
+                (database, conversation, channel)
+            }
+
+            #[tokio::test]
+            async fn then_it_is_sent_once_the_channel_reconnects() {
+                let (database, _, channel) = given().await;
+
+                let mut trans = database.begin().await.unwrap();
+                let data = trans.next(channel.id.into(), (0, 0)).await.unwrap();
+
+                let Some(Patch::NewTextMessage(message)) = data.map(|data| data.payload) else { panic!() };
+                assert_eq!(message.text, "response");
+            }
+        }
+    }
+
+    mod given_a_conversation_with_three_channels {
+        use super::*;
+        use crate::database::sync::{SyncDataId, SyncDataSource};
+
+        type Given = (Database, Conversation, Vec<ChannelData>);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            database
+                .send_message(conversation.clone(), "hello".to_string(), None)
+                .await
+                .unwrap();
+            for _ in 0..3 {
+                database
+                    .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                    .await
+                    .unwrap();
+            }
+            let channels = database.list_channels(&conversation).await.unwrap();
+
+            (database, conversation, channels)
+        }
+
+        async fn drain(database: &Database, channel: &ChannelData) -> Vec<SyncDataId> {
+            let mut trans = database.begin().await.unwrap();
+            let mut drained = vec![];
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+                drained.push(data.id);
+            }
+            trans.commit().await.unwrap();
+
+            drained
+        }
+
+        #[tokio::test]
+        async fn then_they_share_one_snapshot() {
+            let (database, _, channels) = given().await;
+
+            let snapshots = snapshot::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+            let seeded = seeded_patches(&database, &channels[0]).await;
+
+            assert_eq!(snapshots, 1);
+            for channel in channels.iter() {
+                assert_eq!(seeded_patches(&database, channel).await, seeded);
+            }
+        }
+
+        #[tokio::test]
+        async fn then_each_channel_drains_the_snapshot_on_its_own() {
+            let (database, _, channels) = given().await;
+
+            let first = drain(&database, &channels[0]).await;
+            let second = drain(&database, &channels[1]).await;
+
+            assert_eq!(first, second);
+            assert!(matches!(first[0], SyncDataId::InitialSync(_)));
+        }
+
+        #[tokio::test]
+        async fn then_the_snapshot_is_removed_once_every_channel_drained_it() {
+            let (database, _, channels) = given().await;
+
+            for channel in channels.iter() {
+                drain(&database, channel).await;
+            }
+
+            let left = initial_sync::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+            assert_eq!(left, 0);
+        }
+
+        #[tokio::test]
+        async fn then_a_channel_with_other_options_gets_its_own_snapshot() {
+            let (database, conversation, ..) = given().await;
+
+            database
+                .create_channel_with_options(
+                    conversation.clone(),
+                    Ed25519Seed::generate().public_key(),
+                    InitialSyncOptions {
+                        message_status: false,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+
+            let snapshots = snapshot::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+            assert_eq!(snapshots, 2);
+        }
+    }
+
+    mod given_a_relay_database {
+        use super::*;
+        use crate::database::sync::{PatchSyncMessage, SyncData};
+
+        type Given = (Database, [(Ed25519Cert, ChannelData); 2]);
+        async fn given() -> Given {
+            let mut database = Database::connect(":memory:").await.unwrap();
+            database.set_relay(true);
+            let conversation = database.join_conversation(Uuid::new_v4()).await.unwrap();
+            database
+                .send_message(conversation.clone(), "before".to_string(), None)
+                .await
+                .unwrap();
+
+            let peers = [(); 2].map(|_| Ed25519Seed::generate().public_key());
+            for peer in peers {
+                database
+                    .create_channel(conversation.clone(), peer)
+                    .await
+                    .unwrap();
+            }
+            let channels = database.list_channels(&conversation).await.unwrap();
+            let channels = peers.map(|peer| {
+                let channel = channels.iter().find(|channel| channel.peer_cert == peer);
+                (peer, channel.unwrap().clone())
+            });
+
+            (database, channels)
+        }
+
+        #[tokio::test]
+        async fn then_channels_are_not_seeded() {
+            let (database, ..) = given().await;
+
+            let seeded = initial_sync::Entity::find()
+                .count(&database.connection)
+                .await
+                .unwrap();
+
+            assert_eq!(seeded, 0);
+        }
+
+        #[tokio::test]
+        async fn then_a_live_patch_is_forwarded_to_the_other_peer() {
+            let (database, [(a, from_a), (_, to_b)], ..) = given().await;
+            let patch = Patch::from(patch::NewTextMessage {
+                id: Uuid::new_v4(),
+                from: patch::Key::new_exact(&a.0),
+                conversation: from_a.conversation,
+                text: "live".to_string(),
+                created_at: 0,
+                crdt: CrdtWritableSequence {
+                    writable: CrdtWritable {
+                        generation: 0,
+                        author: a.as_author(),
+                    },
+                    sequence: 1,
+                },
+            });
+
+            let mut trans = database.begin().await.unwrap();
+            let mut from_a = database.start_sync(from_a);
+            let mut to_b = database.start_sync(to_b);
+            greet(&mut from_a, &mut trans).await;
+            greet(&mut to_b, &mut trans).await;
+            let data = SyncData {
+                id: 1.into(),
+                payload: patch.clone(),
+            };
+            from_a
+                .rx(&mut trans, PatchSyncMessage::Data(data))
+                .await
+                .unwrap();
+            let mut forwarded = Vec::new();
+            while let Some(message) = to_b.tx(&mut trans).await.unwrap() {
+                let PatchSyncMessage::Data(data) = message else { continue; };
+                forwarded.push(data.payload);
+            }
+            trans.commit().await.unwrap();
+
+            assert!(forwarded.contains(&patch));
+            assert!(!forwarded.iter().any(
+                |patch| matches!(patch, Patch::NewTextMessage(message) if message.text == "before")
+            ));
+        }
+
+        mod when_a_live_patch_is_received_twice {
+            use super::*;
+
+            async fn given() -> (Database, ChannelData) {
+                let (database, [(a, from_a), (_, to_b)]) = super::given().await;
+                let patch = Patch::from(patch::NewTextMessage {
+                    id: Uuid::new_v4(),
+                    from: patch::Key::new_exact(&a.0),
+                    conversation: from_a.conversation,
+                    text: "live".to_string(),
+                    created_at: 0,
+                    crdt: CrdtWritableSequence {
+                        writable: CrdtWritable {
+                            generation: 0,
+                            author: a.as_author(),
+                        },
+                        sequence: 1,
+                    },
+                });
+
+                let mut trans = database.begin().await.unwrap();
+                let mut from_a = database.start_sync(from_a);
+                greet(&mut from_a, &mut trans).await;
+                for id in [1, 2] {
+                    let data = SyncData {
+                        id: id.into(),
+                        payload: patch.clone(),
+                    };
+                    from_a
+                        .rx(&mut trans, PatchSyncMessage::Data(data))
+                        .await
+                        .unwrap();
+                }
+                trans.commit().await.unwrap();
+
+                (database, to_b)
+            }
+
+            #[tokio::test]
+            async fn then_it_is_not_merged() {
+                let (database, _) = given().await;
+
+                let messages = message::Entity::find()
+                    .filter(message::Column::Text.eq("live"))
+                    .count(&database.connection)
+                    .await
+                    .unwrap();
+
+                assert_eq!(messages, 0);
+            }
+
+            #[tokio::test]
+            async fn then_it_is_forwarded_once() {
+                let (database, to_b) = given().await;
+
+                let mut trans = database.begin().await.unwrap();
+                let mut to_b = database.start_sync(to_b);
+                greet(&mut to_b, &mut trans).await;
+                let mut forwarded = 0;
+                while let Some(message) = to_b.tx(&mut trans).await.unwrap() {
+                    let PatchSyncMessage::Data(data) = message else { continue; };
+                    forwarded += matches!(
+                        data.payload, Patch::NewTextMessage(message) if message.text == "live"
+                    ) as usize;
+                }
+
+                assert_eq!(forwarded, 1);
+            }
+        }
+    }
+
+    mod given_a_database_filtering_long_messages {
+        use super::*;
+        use crate::database::sync::PatchSyncMessage;
+
+        fn short_messages_only(patch: Patch) -> Option<Patch> {
+            match &patch {
+                Patch::NewTextMessage(message) if message.text.len() > 10 => None,
+                _ => Some(patch),
+            }
+        }
+
+        type Given = (Database, Conversation, ChannelData, Ed25519Cert);
+        async fn given() -> Given {
+            let mut database = Database::connect(":memory:").await.unwrap();
+            database.set_patch_filter(Some(short_messages_only));
+            let conversation = database.join_conversation(Uuid::new_v4()).await.unwrap();
+            let peer = Ed25519Seed::generate().public_key();
+            database
+                .create_channel(conversation.clone(), peer)
+                .await
+                .unwrap();
+            let channel = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .remove(0);
+
+            (database, conversation, channel, peer)
+        }
+
+        fn message_from(
+            peer: &Ed25519Cert,
+            conversation: Uuid,
+            sequence: i32,
+            text: &str,
+        ) -> SyncData {
+            SyncData {
+                id: sequence.into(),
+                payload: Patch::from(patch::NewTextMessage {
+                    id: Uuid::new_v4(),
+                    from: patch::Key::new_exact(&peer.0),
+                    conversation,
+                    text: text.to_string(),
+                    created_at: 0,
+                    crdt: CrdtWritableSequence {
+                        writable: CrdtWritable {
+                            generation: 0,
+                            author: peer.as_author(),
+                        },
+                        sequence,
+                    },
+                }),
+            }
+        }
+
+        mod when_the_peer_sends_a_short_and_a_long_message {
+            use super::*;
+
+            type Given = (Database, Conversation, Vec<SyncDataId>);
+            async fn given() -> Given {
+                let (database, conversation, channel, peer) = super::given().await;
+                let messages = [
+                    message_from(&peer, conversation.uuid, 1, "short"),
+                    message_from(&peer, conversation.uuid, 2, "way too long to pass"),
+                ];
+
+                let mut trans = database.begin().await.unwrap();
+                let mut sync = database.start_sync(channel);
+                greet(&mut sync, &mut trans).await;
+                for data in messages {
+                    sync.rx(&mut trans, PatchSyncMessage::Data(data))
+                        .await
+                        .unwrap();
+                }
+                let mut acks = Vec::new();
+                while let Some(message) = sync.tx(&mut trans).await.unwrap() {
+                    if let PatchSyncMessage::Ack(id) = message {
+                        acks.push(id);
+                    }
+                }
+                trans.commit().await.unwrap();
+
+                (database, conversation, acks)
+            }
+
+            #[tokio::test]
+            async fn then_only_the_short_message_is_stored() {
+                let (database, conversation, ..) = given().await;
+
+                let mut texts = vec![];
+                for i in 0..conversation.length(&database).await.unwrap() {
+                    let message = conversation.get_message(&database, i).await.unwrap();
+                    texts.push(message.unwrap().text().to_string());
+                }
+
+                assert_eq!(texts, vec!["short"]);
+            }
+
+            #[tokio::test]
+            async fn then_both_messages_are_acked() {
+                let (_, _, acks, ..) = given().await;
+
+                assert_eq!(acks, vec![1.into(), 2.into()]);
+            }
+        }
+    }
+
+    mod given_a_conversation_with_named_members {
+        use super::*;
+
+        type Given = (Conversation, [Ed25519Cert; 4]);
+        fn given() -> Given {
+            let certs = [(); 4].map(|_| Ed25519Seed::generate().public_key());
+            let members = ["Alice", "Bob", "Carol", ""]
+                .into_iter()
+                .zip(certs)
+                .map(|(name, key)| Contact {
+                    key,
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect();
+            let conversation = Conversation {
+                uuid: Uuid::new_v4(),
+                title: None,
+                crdt: Default::default(),
+                members,
+            };
+
+            (conversation, certs)
+        }
+
+        #[test]
+        fn then_nobody_typing_has_no_label() {
+            let (conversation, ..) = given();
+
+            assert_eq!(conversation.typing_label(&[]), "");
+        }
+
+        #[test]
+        fn then_one_typer_is_named() {
+            let (conversation, [alice, ..]) = given();
+
+            assert_eq!(conversation.typing_label(&[alice]), "Alice is typing");
+        }
+
+        #[test]
+        fn then_two_typers_are_named() {
+            let (conversation, [alice, bob, ..]) = given();
+
+            assert_eq!(
+                conversation.typing_label(&[alice, bob]),
+                "Alice and Bob are typing"
+            );
+        }
+
+        #[test]
+        fn then_many_typers_are_not_named() {
+            let (conversation, [alice, bob, carol, ..]) = given();
+
+            assert_eq!(
+                conversation.typing_label(&[alice, bob, carol]),
+                "Several people are typing"
+            );
+        }
+
+        #[test]
+        fn then_typers_without_a_name_fall_back() {
+            let (conversation, [alice, _, _, unnamed]) = given();
+            let stranger = Ed25519Seed::generate().public_key();
+
+            assert_eq!(conversation.typing_label(&[unnamed]), "Someone is typing");
+            assert_eq!(
+                conversation.typing_label(&[stranger, alice]),
+                "Someone and Alice are typing"
+            );
+        }
+    }
+
+    mod given_do_not_disturb_until_a_deadline {
+        use super::*;
+
+        type Given = (Database,);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            database
+                .set_do_not_disturb(DoNotDisturb::On(Some(1000)))
+                .await
+                .unwrap();
+
+            (database,)
+        }
+
+        #[tokio::test]
+        async fn then_nothing_is_notified_before_the_deadline() {
+            let (database, ..) = given().await;
+
+            assert!(!database.should_notify(999).await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn then_it_expires_at_the_deadline() {
+            let (database, ..) = given().await;
+
+            assert!(database.should_notify(1000).await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn then_turning_it_off_resumes_notifications() {
+            let (database, ..) = given().await;
+
+            database
+                .set_do_not_disturb(DoNotDisturb::Off)
+                .await
+                .unwrap();
+
+            assert!(database.should_notify(0).await.unwrap());
+            assert_eq!(database.do_not_disturb().await.unwrap(), DoNotDisturb::Off);
+        }
+    }
+
+    mod given_a_preference_stored_as_another_type {
+        use super::*;
+
+        type Given = (Database,);
+        async fn given() -> Given {
+            let database = Database::connect(":memory:").await.unwrap();
+            database.set_preference("test.limit", &1u8).await.unwrap();
+
+            (database,)
+        }
+
+        #[tokio::test]
+        async fn then_it_reads_as_the_default() {
+            let (database, ..) = given().await;
+
+            let limit: u64 = database.preference("test.limit").await.unwrap();
+
+            assert_eq!(limit, 0);
+        }
+
+        #[tokio::test]
+        async fn then_setting_it_again_replaces_it() {
+            let (database, ..) = given().await;
+
+            database.set_preference("test.limit", &7u64).await.unwrap();
+
+            let limit: u64 = database.preference("test.limit").await.unwrap();
+            assert_eq!(limit, 7);
+        }
+    }
+
+    mod given_a_download_interrupted_before_payloads_were_stored_by_hash {
+        use super::*;
+
+        type Given = (PathBuf, Database, Conversation, Uuid, Vec<u8>);
+        async fn given() -> Given {
+            let path = temp_path();
+            let database = Database::connect(path.to_str().unwrap()).await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            let row = conversation.row_id(&database.begin().await.unwrap()).await;
+            let row = row.unwrap().unwrap();
+            drop(database);
+
+            let connection = Database::open(path.to_str().unwrap(), "rwc").await.unwrap();
+            let migrations = migration::Migrator::migrations();
+            let blob = migrations
+                .iter()
+                .position(|migration| migration.name() == "m20230430_000001_attachment_blob")
+                .unwrap();
+            let steps = (migrations.len() - blob) as u32;
+            migration::Migrator::down(&connection, Some(steps))
+                .await
+                .unwrap();
+
+            let uuid = Uuid::new_v4();
+            let split = SplitUuid::from(uuid);
+            let payload = (0..ATTACHMENT_CHUNK_BYTES + 10)
+                .map(|i| i as u8)
+                .collect::<Vec<_>>();
+            connection
+                .execute(Statement::from_sql_and_values(
+                    DatabaseBackend::Sqlite,
+                    "INSERT INTO attachment (uuid0, uuid1, uuid2, uuid3, conversation, crdt_author) \
+                    VALUES (?, ?, ?, ?, ?, 5);",
+                    [
+                        split.0.into(),
+                        split.1.into(),
+                        split.2.into(),
+                        split.3.into(),
+                        row.into(),
+                    ],
+                ))
+                .await
+                .unwrap();
+            connection
+                .execute(Statement::from_sql_and_values(
+                    DatabaseBackend::Sqlite,
+                    "INSERT INTO attachment_chunk (attachment, \"index\", data, crdt_author, total) \
+                    SELECT id, 0, ?, 5, ? FROM attachment;",
+                    [
+                        payload[..ATTACHMENT_CHUNK_BYTES].to_vec().into(),
+                        (payload.len() as i64).into(),
+                    ],
+                ))
+                .await
+                .unwrap();
+            drop(connection);
+
+            let database = Database::connect(path.to_str().unwrap()).await.unwrap();
+
+            (path, database, conversation, uuid, payload)
+        }
+
+        #[tokio::test]
+        async fn then_it_resumes_once_the_attachment_is_synced_again() {
+            let (path, database, conversation, uuid, payload) = given().await;
+            let hash = *blake3::hash(&payload).as_bytes();
+
+            let mut trans = database.begin().await.unwrap();
+            let attachment = patch::Attachment {
+                id: uuid,
+                conversation: conversation.uuid,
+                hash: Some(hash),
+                crdt: CrdtAddOnly(Author(5)),
+            };
+            assert!(Patch::from(attachment).merge(&mut trans).await.is_some());
+            trans.commit().await.unwrap();
+            let id = attachment::Entity::find()
+                .one(&database.connection)
+                .await
+                .unwrap()
+                .unwrap()
+                .id;
+            let progress = database.attachment_progress(id).await.unwrap();
+
+            let mut trans = database.begin().await.unwrap();
+            let chunk = patch::AttachmentChunk {
+                hash,
+                conversation: conversation.uuid,
+                index: 1,
+                data: payload[ATTACHMENT_CHUNK_BYTES..].to_vec(),
+                total: payload.len() as u64,
+                crdt: CrdtAddOnly(Author(5)),
+            };
+            Patch::from(chunk).merge(&mut trans).await.unwrap();
+            trans.commit().await.unwrap();
+            let received = database
+                .fetch_file_payload(id)
+                .await
+                .unwrap()
+                .map(|payload| payload.into_bytes().unwrap());
+            std::fs::remove_file(&path).unwrap();
+
+            let len = payload.len() as u64;
+            assert_eq!(progress, Some((ATTACHMENT_CHUNK_BYTES as u64, len)));
+            assert_eq!(received, Some(payload));
+        }
+    }
+
+    mod given_patches_stored_before_messages_carried_their_time {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (PathBuf, DatabaseConnection);
+        async fn given() -> Given {
+            let path = temp_path();
+            let database = Database::connect(path.to_str().unwrap()).await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .remove(0);
+            let mut trans = database.begin().await.unwrap();
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+            }
+            trans.commit().await.unwrap();
+            drop(database);
+
+            let connection = Database::open(path.to_str().unwrap(), "rwc").await.unwrap();
+            let migrations = migration::Migrator::migrations();
+            let created_at = migrations
+                .iter()
+                .position(|migration| migration.name() == "m20230428_000001_message_created_at")
+                .unwrap();
+            let steps = (migrations.len() - created_at) as u32;
+            migration::Migrator::down(&connection, Some(steps))
+                .await
+                .unwrap();
+
+            // Stand in for patches in the encoding from before the migration.
+            for sql in [
+                "INSERT INTO sync (payload) VALUES (x'ff');",
+                "INSERT INTO initial_sync (snapshot, payload) SELECT id, x'ff' FROM snapshot;",
+            ] {
+                connection.execute_unprepared(sql).await.unwrap();
+            }
+            migration::Migrator::up(&connection, Some(1)).await.unwrap();
+
+            (path, connection)
+        }
+
+        async fn count(connection: &DatabaseConnection, sql: &str) -> i64 {
+            let row = connection
+                .query_one(Statement::from_string(
+                    DatabaseBackend::Sqlite,
+                    sql.to_string(),
+                ))
+                .await
+                .unwrap()
+                .unwrap();
+
+            row.try_get("", "n").unwrap()
+        }
+
+        #[tokio::test]
+        async fn then_the_stored_patches_are_dropped() {
+            let (path, connection) = given().await;
+
+            let log = count(&connection, "SELECT COUNT(*) AS n FROM sync;").await;
+            let seeded = count(&connection, "SELECT COUNT(*) AS n FROM initial_sync;").await;
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(log, 0);
+            assert_eq!(seeded, 0);
+        }
+
+        #[tokio::test]
+        async fn then_the_channel_is_seeded_again() {
+            let (path, connection) = given().await;
+
+            let reseeded = count(
+                &connection,
+                "SELECT COUNT(*) AS n FROM channel WHERE snapshot IS NOT NULL;",
+            )
+            .await;
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(reseeded, 1);
+        }
+    }
+
+    mod given_a_database_from_before_authors_were_widened {
+        use super::*;
+        use crate::database::sync::SyncDataSource;
+
+        type Given = (PathBuf, Database, ChannelData, ChannelData);
+        async fn given() -> Given {
+            let path = temp_path();
+            let database = Database::connect(path.to_str().unwrap()).await.unwrap();
+            let conversation = database.create_conversation(None).await.unwrap();
+            database
+                .send_message(conversation.clone(), "seeded".to_string(), None)
+                .await
+                .unwrap();
+            database
+                .create_channel(conversation.clone(), Ed25519Seed::generate().public_key())
+                .await
+                .unwrap();
+            let channel = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .remove(0);
+            let mut trans = database.begin().await.unwrap();
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+            }
+            trans.commit().await.unwrap();
+            database
+                .send_message(conversation.clone(), "pending".to_string(), None)
+                .await
+                .unwrap();
+            database
+                .create_channel_with_options(
+                    conversation.clone(),
+                    Ed25519Seed::generate().public_key(),
+                    InitialSyncOptions {
+                        message_status: false,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+            let draining = database
+                .list_channels(&conversation)
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|draining| draining.id != channel.id)
+                .unwrap();
+
+            let narrow = database.cert().0[..16]
+                .chunks(4)
+                .map(|chunk| i32::from_le_bytes(chunk.try_into().unwrap()))
+                .fold(0, |a, b| a ^ b);
+            for sql in [
+                "DELETE FROM seaql_migrations WHERE version = 'm20230506_000001_wide_author'"
+                    .to_string(),
+                "UPDATE sync SET payload = x'ff'".to_string(),
+                format!("UPDATE message SET crdt_author = {narrow}"),
+                format!("UPDATE contact SET crdt_author = {narrow}"),
+            ] {
+                database
+                    .connection
+                    .execute(Statement::from_string(DatabaseBackend::Sqlite, sql))
+                    .await
+                    .unwrap();
+            }
+            drop(database);
+
+            let database = Database::connect(path.to_str().unwrap()).await.unwrap();
+
+            (path, database, channel, draining)
+        }
+
+        #[tokio::test]
+        async fn then_a_channel_still_seeding_keeps_its_options() {
+            let (path, database, _, channel) = given().await;
+
+            let seeded = seeded_patches(&database, &channel).await;
+            std::fs::remove_file(&path).unwrap();
+
+            assert!(!seeded
+                .iter()
+                .any(|patch| matches!(patch, Patch::MessageStatus(_))));
+        }
+
+        #[tokio::test]
+        async fn then_every_author_is_widened() {
+            let (path, database, ..) = given().await;
+
+            let messages = message::Entity::find()
+                .all(&database.connection)
+                .await
+                .unwrap();
+            let contacts = contact::Entity::find()
+                .all(&database.connection)
+                .await
+                .unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let author = database.cert().as_author().0;
+            assert!(messages.iter().all(|message| message.crdt_author == author));
+            assert!(contacts.iter().all(|contact| contact.crdt_author == author));
+        }
+
+        #[tokio::test]
+        async fn then_the_channel_is_seeded_again() {
+            let (path, database, channel, _) = given().await;
+
+            let seeded = seeded_patches(&database, &channel).await;
+            std::fs::remove_file(&path).unwrap();
+
+            let texts = seeded.iter().filter_map(|patch| match patch {
+                Patch::NewTextMessage(message) => Some(message.text.as_str()),
+                _ => None,
+            });
+            assert_eq!(texts.collect::<Vec<_>>(), ["seeded", "pending"]);
+        }
+
+        #[tokio::test]
+        async fn then_the_unreadable_patches_are_dropped() {
+            let (path, database, channel, _) = given().await;
+
+            let mut trans = database.begin().await.unwrap();
+            let mut drained = 0;
+            while let Some(data) = trans.next(channel.id.into(), (0, 0)).await.unwrap() {
+                trans.ack(channel.id.into(), data.id).await.unwrap();
+                drained += 1;
+            }
+
+            let log = entity::entity::sync::Entity::find()
+                .count(&trans)
+                .await
+                .unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(log, 0);
+            assert!(drained > 0);
+        }
+    }
+
+    mod given_a_database_migrated_by_a_newer_version {
+        use super::*;
+
+        type Given = (PathBuf,);
+        async fn given() -> Given {
+            let path = temp_path();
+            let database = Database::connect(path.to_str().unwrap()).await.unwrap();
+            database
+                .connection
+                .execute(Statement::from_string(
+                    DatabaseBackend::Sqlite,
+                    "INSERT INTO seaql_migrations (version, applied_at) \
+                     VALUES ('m29990101_000001_from_the_future', 0)"
+                        .to_string(),
+                ))
+                .await
+                .unwrap();
 
-        let message = message::Entity::find()
-            .filter(message::Column::Conversation.eq(self.id))
-            .order_by(message::Column::CrdtSequence, Order::Asc)
-            .order_by(message::Column::CrdtAuthor, Order::Asc)
-            .offset(Some(index as u64))
-            .one(&trans)
-            .await?;
+            (path,)
+        }
 
-        let Some(message) = message else { return Ok(None); };
-        Ok(Some(Message::from_model(&trans, message, self.uuid).await?))
-    }
-}
+        #[tokio::test]
+        async fn then_connecting_is_refused() {
+            let (path, ..) = given().await;
 
-#[derive(Default, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Contact {
-    id: i32,
-    pub key: Ed25519Cert,
-    pub name: String,
-}
-impl From<(entity::entity::key::Model, contact::Model)> for Contact {
-    fn from((key, contact): (entity::entity::key::Model, contact::Model)) -> Self {
-        let key = Ed25519Cert(key.public.as_slice().try_into().unwrap());
+            let database = Database::connect(path.to_str().unwrap()).await;
+            std::fs::remove_file(&path).unwrap();
 
-        Contact {
-            id: contact.key,
-            key,
-            name: contact.name,
+            assert!(matches!(
+                database,
+                Err(DatabaseError::SchemaTooNew(unknown))
+                    if unknown == ["m29990101_000001_from_the_future"]
+            ));
         }
     }
-}
 
-#[derive(Default, Debug, Clone, PartialEq, Eq)]
-pub struct Message {
-    id: i32,
-    pub uuid: Uuid,
-    pub from: Contact,
-    pub conversation: Uuid,
-    pub content: Content,
-    pub status: MessageStatus,
-}
-impl Message {
-    pub async fn from_model(
-        trans: &DatabaseTransaction,
-        message: message::Model,
-        conversation: Uuid,
-    ) -> DatabaseResult<Self> {
-        let (contact, key) = contact::Entity::find_by_id(message.from)
-            .find_also_related(entity::entity::key::Entity)
-            .one(trans)
-            .await?
-            .unwrap();
-        let key = key.unwrap();
-        let attachment = match message.attachment {
-            Some(id) => Some(
-                AttachmentMetaModel::find_by_id(id)
-                    .one(trans)
-                    .await?
-                    .unwrap(),
-            ),
-            None => None,
-        };
+    mod given_a_database_with_history {
+        use super::*;
 
-        Ok(Message {
-            id: message.id,
-            uuid: message.get_uuid().into(),
-            from: (key, contact).into(),
-            conversation,
-            content: match attachment {
-                Some(attachment) => Content::Attachment(message.text, attachment.id),
-                None => Content::Text(message.text),
-            },
-            status: message.status.into(),
-        })
-    }
+        type Given = (PathBuf, Database);
+        async fn given() -> Given {
+            let path = temp_path();
+            let database = Database::connect(path.to_str().unwrap()).await.unwrap();
 
-    pub fn text(&self) -> &str {
-        match &self.content {
-            Content::Text(text) => text,
-            Content::Attachment(text, _) => text,
+            let mut user = database
+                .get_contact(database.cert())
+                .await
+                .unwrap()
+                .unwrap();
+            user.name = "Me".to_string();
+            database.save_contact(user).await.unwrap();
+
+            for title in ["First", "Second"] {
+                let conversation = database
+                    .create_conversation(Some(title.to_string()))
+                    .await
+                    .unwrap();
+                for text in ["hi", "there"] {
+                    database
+                        .send_message(conversation.clone(), format!("{text} {title}"), None)
+                        .await
+                        .unwrap();
+                }
+            }
+
+            (path, database)
         }
-    }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Content {
-    Text(String),
-    Attachment(String, i32),
-}
-impl Default for Content {
-    fn default() -> Self {
-        Content::Text(Default::default())
-    }
-}
+        async fn contents(database: &Database) -> Vec<(Uuid, Option<String>, Vec<String>)> {
+            let mut r = Vec::new();
+            for conversation in database.list_conversation().await.unwrap() {
+                let mut messages = Vec::new();
+                for i in 0..conversation.length(database).await.unwrap() {
+                    let message = conversation
+                        .get_message(database, i)
+                        .await
+                        .unwrap()
+                        .unwrap();
+                    messages.push(format!("{}: {}", message.from.name, message.text()));
+                }
+
+                r.push((conversation.uuid, conversation.title, messages));
+            }
+            r.sort();
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
-pub enum MessageStatus {
-    #[default]
-    Sent,
-    Delivered,
-    Read,
-}
-impl From<i32> for MessageStatus {
-    fn from(value: i32) -> Self {
-        match value {
-            0 => MessageStatus::Sent,
-            1 => MessageStatus::Delivered,
-            2 => MessageStatus::Read,
-            _ => panic!(),
+            r
         }
-    }
-}
-impl From<MessageStatus> for i32 {
-    fn from(value: MessageStatus) -> Self {
-        match value {
-            MessageStatus::Sent => 0,
-            MessageStatus::Delivered => 1,
-            MessageStatus::Read => 2,
+
+        #[tokio::test]
+        async fn then_rebuilding_from_the_patch_log_restores_it() {
+            let (path, database, ..) = given().await;
+            let rebuilt_path = temp_path();
+
+            Database::rebuild_from_patch_log(
+                path.to_str().unwrap(),
+                rebuilt_path.to_str().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+            let rebuilt = Database::connect(rebuilt_path.to_str().unwrap())
+                .await
+                .unwrap();
+            std::fs::remove_file(&path).unwrap();
+            std::fs::remove_file(&rebuilt_path).unwrap();
+
+            assert_eq!(rebuilt.cert(), database.cert());
+            assert_eq!(contents(&rebuilt).await, contents(&database).await);
+            assert_eq!(contents(&rebuilt).await.len(), 2);
         }
     }
-}
 
-pub trait DbSync {
-    type Database;
-    type Message: Serialize + DeserializeOwned + std::fmt::Debug;
+    mod given_two_devices_that_diverged {
+        use super::*;
 
-    fn tx<'a>(
-        &'a mut self,
-        database: &'a mut Self::Database,
-    ) -> LocalBoxFuture<'a, DatabaseResult<Option<Self::Message>>>;
-    fn rx<'a>(
-        &'a mut self,
-        database: &'a mut Self::Database,
-        message: Self::Message,
-    ) -> LocalBoxFuture<'a, DatabaseResult<()>>;
-}
+        type Given = ((PathBuf, Database), (PathBuf, Database));
+        async fn given() -> Given {
+            let [a_path, b_path] = [(); 2].map(|_| temp_path());
+            let a = Database::connect(a_path.to_str().unwrap()).await.unwrap();
+            let b = Database::connect(b_path.to_str().unwrap()).await.unwrap();
 
-#[derive(Default)]
-pub struct UnimplementedSync;
-impl DbSync for UnimplementedSync {
-    type Database = SharedDatabase;
-    type Message = Vec<u8>;
+            let conversation = a.create_conversation(None).await.unwrap();
+            a.send_message(conversation.clone(), "first".to_string(), None)
+                .await
+                .unwrap();
+            b.merge_from(a_path.to_str().unwrap()).await.unwrap();
 
-    fn tx<'a>(
-        &'a mut self,
-        database: &'a mut Self::Database,
-    ) -> LocalBoxFuture<'a, DatabaseResult<Option<Vec<u8>>>> {
-        let _ = database;
-        unimplemented!()
-    }
+            for text in ["from a", "again from a"] {
+                a.send_message(conversation.clone(), text.to_string(), None)
+                    .await
+                    .unwrap();
+            }
+            b.send_message(conversation.clone(), "from b".to_string(), None)
+                .await
+                .unwrap();
 
-    fn rx<'a>(
-        &'a mut self,
-        database: &'a mut Self::Database,
-        message: Vec<u8>,
-    ) -> LocalBoxFuture<'a, DatabaseResult<()>> {
-        let _ = database;
-        let _ = message;
-        unimplemented!()
-    }
-}
+            ((a_path, a), (b_path, b))
+        }
 
-pub struct LocalDatabase {}
-impl LocalDatabase {
-    pub fn with_user(user: Uuid) -> DatabaseResult<LocalDatabase> {
-        let _ = user;
-        unimplemented!()
-    }
+        async fn texts(database: &Database) -> Vec<String> {
+            let conversation = database.list_conversation().await.unwrap().remove(0);
+            let mut r = Vec::new();
+            for i in 0..conversation.length(database).await.unwrap() {
+                let message = conversation.get_message(database, i).await.unwrap();
+                r.push(message.unwrap().text().to_string());
+            }
 
-    pub fn user(&self) -> Uuid {
-        unimplemented!()
-    }
+            r
+        }
 
-    pub fn set(&mut self, data: LocalDatabaseData) -> DatabaseResult<()> {
-        let _ = data;
-        unimplemented!()
+        #[tokio::test]
+        async fn then_merging_both_ways_yields_both_sets_in_the_same_order() {
+            let ((a_path, a), (b_path, b)) = given().await;
+
+            let report = a.merge_from(b_path.to_str().unwrap()).await.unwrap();
+            b.merge_from(a_path.to_str().unwrap()).await.unwrap();
+            std::fs::remove_file(&a_path).unwrap();
+            std::fs::remove_file(&b_path).unwrap();
+
+            let merged = texts(&a).await;
+            assert!(report.merged > 0 && report.merged <= report.read);
+            assert_eq!(merged, texts(&b).await);
+            assert_eq!(merged[0], "first");
+            let from_a = merged.iter().filter(|text| text.contains("from a"));
+            assert_eq!(from_a.collect::<Vec<_>>(), ["from a", "again from a"]);
+            let mut sorted = merged.clone();
+            sorted.sort();
+            assert_eq!(sorted, ["again from a", "first", "from a", "from b"]);
+        }
     }
 
-    pub fn get(&self) -> DatabaseResult<LocalDatabaseData> {
-        unimplemented!()
-    }
+    mod given_a_conversation_from_another_database {
+        use super::*;
 
-    pub fn save(&mut self) -> Vec<u8> {
-        unimplemented!()
-    }
+        type Given = (Database, Conversation);
+        async fn given() -> Given {
+            let origin = Database::connect(":memory:").await.unwrap();
+            let conversation = origin
+                .create_conversation(Some("Shared".to_string()))
+                .await
+                .unwrap();
+            for text in ["hello", "world"] {
+                origin
+                    .send_message(conversation.clone(), text.to_string(), None)
+                    .await
+                    .unwrap();
+            }
 
-    pub fn load(data: &[u8]) -> DatabaseResult<Self> {
-        let _ = data;
-        unimplemented!()
-    }
-}
+            let database = Database::connect(":memory:").await.unwrap();
+            database
+                .create_conversation(Some("Takes the first row id".to_string()))
+                .await
+                .unwrap();
+            let mut trans = database.begin().await.unwrap();
+            Database::replay_patch_log(&origin.connection, &mut trans)
+                .await
+                .unwrap();
+            trans.commit().await.unwrap();
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct LocalDatabaseData {
-    pub user: Uuid,
-    pub channels: Vec<ChannelData>,
-}
+            (database, conversation)
+        }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct ChannelData {
-    id: i32,
-    pub conversation: Uuid,
-    pub peer_cert: Ed25519Cert,
-    pub channel: String,
-}
-impl ChannelData {
-    pub fn new(
-        id: i32,
-        conversation: Uuid,
-        peer_cert: Ed25519Cert,
-        private: &Ed25519Seed,
-    ) -> ChannelData {
-        let channel = private.x25519_agree(&format!("channel:{conversation}"), &peer_cert);
+        #[tokio::test]
+        async fn then_its_messages_are_found_by_uuid() {
+            let (database, conversation, ..) = given().await;
 
-        ChannelData {
-            id,
-            conversation,
-            peer_cert,
-            channel,
+            let second = conversation.get_message(&database, 1).await.unwrap();
+
+            assert_eq!(conversation.length(&database).await.unwrap(), 2);
+            assert_eq!(second.unwrap().text(), "world");
         }
-    }
-}
 
-#[cfg(test)]
-pub mod tests {
-    use super::*;
+        #[tokio::test]
+        async fn then_channels_are_created_on_the_same_conversation() {
+            let (database, conversation, ..) = given().await;
+            let peer = Ed25519Seed::generate().public_key();
+
+            database
+                .create_channel(conversation.clone(), peer)
+                .await
+                .unwrap();
+            let fetched = database
+                .get_conversation(conversation.uuid)
+                .await
+                .unwrap()
+                .unwrap();
+
+            let channels = database.list_channels(&fetched).await.unwrap();
+            assert_eq!(channels.len(), 1);
+            assert_eq!(channels[0].peer_cert, peer);
+            assert!(fetched.members.iter().any(|member| member.cert() == peer));
+        }
+    }
 
     mod given_an_empty_database {
         use super::*;
@@ -1056,6 +9569,771 @@ pub mod tests {
                     vec![conversation]
                 );
             }
+
+            mod when_the_member_set_is_replaced {
+                use super::*;
+
+                type Given = (Database, Conversation, [Ed25519Cert; 3], usize);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    let [a, b, c] = [(); 3].map(|_| Ed25519Seed::generate().public_key());
+                    database.set_members(&conversation, &[a, b]).await.unwrap();
+                    let patches_before = sync_log_len(&database).await;
+
+                    database.set_members(&conversation, &[b, c]).await.unwrap();
+
+                    (database, conversation, [a, b, c], patches_before)
+                }
+
+                async fn sync_log_len(database: &Database) -> usize {
+                    entity::entity::sync::Entity::find()
+                        .count(&database.connection)
+                        .await
+                        .unwrap() as usize
+                }
+
+                #[tokio::test]
+                async fn then_only_the_new_set_and_the_user_are_members() {
+                    let (database, conversation, [_, b, c], ..) = given().await;
+
+                    let conversation = database
+                        .get_conversation(conversation.uuid)
+                        .await
+                        .unwrap()
+                        .unwrap();
+                    let members = conversation
+                        .members
+                        .iter()
+                        .map(|member| member.key)
+                        .collect::<HashSet<_>>();
+
+                    assert_eq!(members, HashSet::from([*database.cert(), b, c]));
+                }
+
+                #[tokio::test]
+                async fn then_only_the_new_set_has_channels() {
+                    let (database, conversation, [_, b, c], ..) = given().await;
+
+                    let channels = database
+                        .list_channels(&conversation)
+                        .await
+                        .unwrap()
+                        .into_iter()
+                        .map(|channel| channel.peer_cert)
+                        .collect::<HashSet<_>>();
+
+                    assert_eq!(channels, HashSet::from([b, c]));
+                }
+
+                #[tokio::test]
+                async fn then_only_the_removal_and_the_addition_are_synced() {
+                    let (database, .., patches_before) = given().await;
+
+                    assert_eq!(sync_log_len(&database).await, patches_before + 2);
+                }
+            }
+
+            mod when_a_message_is_sent_to_a_group {
+                use super::*;
+
+                type Given = (Database, Message, [Ed25519Cert; 2]);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    let [a, b] = [(); 2].map(|_| Ed25519Seed::generate().public_key());
+                    database.set_members(&conversation, &[a, b]).await.unwrap();
+                    database
+                        .send_message(conversation.clone(), "hello".to_string(), None)
+                        .await
+                        .unwrap();
+                    let message = conversation
+                        .get_message(&database, 0)
+                        .await
+                        .unwrap()
+                        .unwrap();
+
+                    (database, message, [a, b])
+                }
+
+                async fn receive_receipt(
+                    database: &Database,
+                    message: &Message,
+                    member: Ed25519Cert,
+                    status: MessageStatus,
+                ) {
+                    let mut trans = database.begin().await.unwrap();
+                    Patch::from(patch::Receipt {
+                        message: message.uuid,
+                        conversation: message.conversation,
+                        member: patch::Key::new_exact(&member.0),
+                        status: status.into(),
+                        crdt: CrdtWritable {
+                            generation: 1,
+                            author: member.as_author(),
+                        },
+                    })
+                    .merge(&mut trans)
+                    .await;
+                    trans.commit().await.unwrap();
+                }
+
+                #[tokio::test]
+                async fn then_only_members_that_did_not_read_are_pending() {
+                    let (database, message, [a, b], ..) = given().await;
+
+                    receive_receipt(&database, &message, a, MessageStatus::Read).await;
+                    receive_receipt(&database, &message, b, MessageStatus::Delivered).await;
+
+                    let pending = database.members_pending_read(&message).await.unwrap();
+                    let pending = pending.iter().map(|member| member.key).collect::<Vec<_>>();
+
+                    assert_eq!(pending, vec![b]);
+                }
+            }
+
+            mod when_a_peer_is_added_with_history_since_a_message {
+                use super::*;
+
+                type Given = (Database, Ed25519Cert, Vec<Patch>);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    for text in ["first", "second", "third"] {
+                        database
+                            .send_message(conversation.clone(), text.to_string(), None)
+                            .await
+                            .unwrap();
+                    }
+                    let cursor = conversation
+                        .get_message(&database, 1)
+                        .await
+                        .unwrap()
+                        .unwrap();
+
+                    let peer = Ed25519Seed::generate().public_key();
+                    database
+                        .create_channel_with_history(
+                            conversation.clone(),
+                            peer,
+                            HistoryPolicy::Since(cursor.uuid),
+                        )
+                        .await
+                        .unwrap();
+
+                    let channel = database.list_channels(&conversation).await.unwrap();
+                    let seeded = seeded_patches(&database, &channel[0]).await;
+
+                    (database, peer, seeded)
+                }
+
+                #[tokio::test]
+                async fn then_only_later_messages_are_seeded() {
+                    let (.., seeded) = given().await;
+
+                    let texts = seeded
+                        .iter()
+                        .filter_map(|patch| match patch {
+                            Patch::NewTextMessage(message) => Some(message.text.as_str()),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(texts, vec!["third"]);
+                }
+
+                #[tokio::test]
+                async fn then_membership_is_still_seeded() {
+                    let (database, peer, seeded, ..) = given().await;
+
+                    let mut members = seeded
+                        .iter()
+                        .filter_map(|patch| match patch {
+                            Patch::Member(member) => Some(member.key.clone()),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    members.sort();
+
+                    let mut expected = vec![database.patch_key(), patch::Key::new_exact(&peer.0)];
+                    expected.sort();
+                    assert_eq!(members, expected);
+                }
+            }
+
+            mod when_a_peer_is_added_without_message_status {
+                use super::*;
+
+                type Given = (Database, Vec<Patch>);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    for text in ["first", "second"] {
+                        database
+                            .send_message(conversation.clone(), text.to_string(), None)
+                            .await
+                            .unwrap();
+                    }
+
+                    database
+                        .create_channel_with_options(
+                            conversation.clone(),
+                            Ed25519Seed::generate().public_key(),
+                            InitialSyncOptions {
+                                message_status: false,
+                                ..Default::default()
+                            },
+                        )
+                        .await
+                        .unwrap();
+
+                    let channel = database.list_channels(&conversation).await.unwrap();
+                    let seeded = seeded_patches(&database, &channel[0]).await;
+
+                    (database, seeded)
+                }
+
+                #[tokio::test]
+                async fn then_no_status_is_seeded() {
+                    let (_, seeded, ..) = given().await;
+
+                    assert!(!seeded
+                        .iter()
+                        .any(|patch| matches!(patch, Patch::MessageStatus(_) | Patch::Receipt(_))));
+                }
+
+                #[tokio::test]
+                async fn then_the_peer_reassembles_the_messages() {
+                    let (database, seeded, ..) = given().await;
+                    let peer = Database::connect(":memory:").await.unwrap();
+
+                    let mut trans = peer.begin().await.unwrap();
+                    for patch in seeded {
+                        patch.merge(&mut trans).await;
+                    }
+                    trans.commit().await.unwrap();
+
+                    let conversation = database.list_conversation().await.unwrap().remove(0);
+                    let mut texts = vec![];
+                    for i in 0..conversation.length(&peer).await.unwrap() {
+                        let message = conversation.get_message(&peer, i).await.unwrap();
+                        texts.push(message.unwrap().text().to_string());
+                    }
+                    assert_eq!(texts, vec!["first", "second"]);
+                }
+            }
+
+            mod when_a_channel_is_created {
+                use super::*;
+
+                type Given = (Database, Conversation, Ed25519Cert);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    let peer = Ed25519Seed::generate().public_key();
+                    database
+                        .create_channel(conversation.clone(), peer)
+                        .await
+                        .unwrap();
+
+                    (database, conversation, peer)
+                }
+
+                #[tokio::test]
+                async fn then_a_channel_for_the_peer_is_built() {
+                    let (database, conversation, peer, ..) = given().await;
+
+                    let channel = database.channel_for(&conversation, &peer).await.unwrap();
+
+                    let channel = channel.unwrap();
+                    assert_eq!(channel.channel().peer_cert, peer);
+                    assert_eq!(channel.state(), ChannelStateLabel::Offline);
+                }
+
+                #[tokio::test]
+                async fn then_there_is_no_channel_for_other_peers() {
+                    let (database, conversation, ..) = given().await;
+                    let other = Ed25519Seed::generate().public_key();
+
+                    let channel = database.channel_for(&conversation, &other).await.unwrap();
+
+                    assert!(channel.is_none());
+                }
+            }
+
+            mod when_its_title_is_cleared_at_a_higher_generation {
+                use super::*;
+
+                type Given = (Database, Database, Uuid);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    let other = Database::connect(":memory:").await.unwrap();
+                    let [a, b] = [(); 2].map(|_| Ed25519Seed::generate().public_key());
+                    let set = patch::Conversation {
+                        id: conversation.uuid,
+                        title: Some("Set".to_string()),
+                        crdt: CrdtWritable {
+                            generation: 5,
+                            author: a.as_author(),
+                        },
+                    };
+                    let clear = patch::Conversation {
+                        id: conversation.uuid,
+                        title: None,
+                        crdt: CrdtWritable {
+                            generation: 6,
+                            author: b.as_author(),
+                        },
+                    };
+
+                    merge(&database, [set.clone(), clear.clone()]).await;
+                    merge(&other, [clear, set]).await;
+
+                    (database, other, conversation.uuid)
+                }
+
+                async fn merge(database: &Database, patches: [patch::Conversation; 2]) {
+                    let mut trans = database.begin().await.unwrap();
+                    for patch in patches {
+                        Patch::from(patch).merge(&mut trans).await;
+                    }
+                    trans.commit().await.unwrap();
+                }
+
+                #[tokio::test]
+                async fn then_the_clear_wins_on_both_peers() {
+                    let (database, other, uuid, ..) = given().await;
+
+                    for database in [database, other] {
+                        let conversation = database.get_conversation(uuid).await.unwrap();
+                        assert_eq!(conversation.unwrap().title, None);
+                    }
+                }
+            }
+
+            mod when_its_title_is_set_concurrently {
+                use super::*;
+
+                type Given = (Database, Database, Uuid, [patch::Conversation; 2]);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    let other = Database::connect(":memory:").await.unwrap();
+                    let [a, b] =
+                        [[1; 32], [2; 32]].map(|seed| Ed25519Seed::from_entropy(seed).public_key());
+                    let [kept, discarded] = match a.as_author() > b.as_author() {
+                        true => [(a, "By a"), (b, "By b")],
+                        false => [(b, "By b"), (a, "By a")],
+                    }
+                    .map(|(author, title)| patch::Conversation {
+                        id: conversation.uuid,
+                        title: Some(title.to_string()),
+                        crdt: CrdtWritable {
+                            generation: 5,
+                            author: author.as_author(),
+                        },
+                    });
+
+                    merge(&database, [kept.clone(), discarded.clone()]).await;
+                    merge(&other, [discarded.clone(), kept.clone()]).await;
+
+                    (database, other, conversation.uuid, [kept, discarded])
+                }
+
+                async fn merge(database: &Database, patches: [patch::Conversation; 2]) {
+                    let mut trans = database.begin().await.unwrap();
+                    for patch in patches {
+                        Patch::from(patch).merge(&mut trans).await;
+                    }
+                    trans.commit().await.unwrap();
+                }
+
+                #[tokio::test]
+                async fn then_both_peers_keep_the_same_title() {
+                    let (database, other, uuid, [kept, _]) = given().await;
+
+                    for database in [database, other] {
+                        let conversation = database.get_conversation(uuid).await.unwrap();
+                        assert_eq!(conversation.unwrap().title, kept.title);
+                    }
+                }
+
+                #[tokio::test]
+                async fn then_both_peers_record_the_conflict() {
+                    let (database, other, uuid, [kept, discarded]) = given().await;
+
+                    for database in [database, other] {
+                        let conflicts = database.recent_conflicts(10).await.unwrap();
+                        assert_eq!(
+                            conflicts,
+                            [Conflict {
+                                target: ConflictTarget::Conversation(uuid),
+                                generation: 5,
+                                kept_author: kept.crdt.author,
+                                kept: kept.title.clone(),
+                                discarded_author: discarded.crdt.author,
+                                discarded: discarded.title.clone(),
+                            }]
+                        );
+                    }
+                }
+
+                #[tokio::test]
+                async fn then_the_discarded_write_arriving_again_is_recorded_once() {
+                    let (database, other, _, [kept, discarded]) = given().await;
+
+                    merge(&database, [discarded.clone(), kept.clone()]).await;
+                    merge(&other, [discarded, kept]).await;
+
+                    for database in [database, other] {
+                        assert_eq!(database.recent_conflicts(10).await.unwrap().len(), 1);
+                    }
+                }
+
+                #[tokio::test]
+                async fn then_conflicts_of_an_unknown_kind_are_skipped() {
+                    let (database, ..) = given().await;
+                    conflict_log::ActiveModel {
+                        id: ActiveValue::NotSet,
+                        kind: ActiveValue::Set("reaction".to_string()),
+                        target: ActiveValue::Set(vec![1, 2, 3]),
+                        generation: ActiveValue::Set(1),
+                        kept_author: ActiveValue::Set(1),
+                        kept_value: ActiveValue::Set(None),
+                        discarded_author: ActiveValue::Set(2),
+                        discarded_value: ActiveValue::Set(None),
+                    }
+                    .insert(&database.connection)
+                    .await
+                    .unwrap();
+
+                    let conflicts = database.recent_conflicts(10).await.unwrap();
+
+                    assert_eq!(conflicts.len(), 1);
+                }
+
+                #[tokio::test]
+                async fn then_a_repeated_or_later_write_is_no_conflict() {
+                    let (database, _, _, [kept, discarded]) = given().await;
+                    let later = patch::Conversation {
+                        title: None,
+                        crdt: CrdtWritable {
+                            generation: 6,
+                            ..discarded.crdt
+                        },
+                        ..discarded
+                    };
+
+                    merge(&database, [kept, later]).await;
+
+                    assert_eq!(database.recent_conflicts(10).await.unwrap().len(), 1);
+                }
+            }
+
+            mod when_other_conversations_receive_messages {
+                use super::*;
+
+                type Given = (Database, [Conversation; 3]);
+                async fn given() -> Given {
+                    let (database, first, ..) = super::given().await;
+                    let second = database.create_conversation(None).await.unwrap();
+                    let empty = database.create_conversation(None).await.unwrap();
+                    let peer = Ed25519Seed::generate().public_key();
+
+                    database
+                        .send_message(first.clone(), "mine".to_string(), None)
+                        .await
+                        .unwrap();
+                    receive_message(&database, &first, peer, "hi", 1).await;
+                    receive_message(&database, &first, peer, "there", 2).await;
+                    receive_message(&database, &second, peer, "read me", 0).await;
+                    let read = second.get_message(&database, 0).await.unwrap().unwrap();
+                    database
+                        .set_message_status(&read, MessageStatus::Read)
+                        .await
+                        .unwrap();
+
+                    (database, [first, second, empty])
+                }
+
+                async fn receive_message(
+                    database: &Database,
+                    conversation: &Conversation,
+                    peer: Ed25519Cert,
+                    text: &str,
+                    sequence: i32,
+                ) {
+                    let mut trans = database.begin().await.unwrap();
+                    Patch::from(patch::NewTextMessage {
+                        id: Uuid::new_v4(),
+                        from: patch::Key::new_exact(&peer.0),
+                        conversation: conversation.uuid,
+                        text: text.to_string(),
+                        created_at: 0,
+                        crdt: CrdtWritableSequence {
+                            writable: CrdtWritable {
+                                generation: 0,
+                                author: peer.as_author(),
+                            },
+                            sequence,
+                        },
+                    })
+                    .merge(&mut trans)
+                    .await;
+                    trans.commit().await.unwrap();
+                }
+
+                #[tokio::test]
+                async fn then_previews_show_the_latest_message_and_unread_count() {
+                    let (database, conversations, ..) = given().await;
+
+                    let previews = database.conversation_previews().await.unwrap();
+                    let previews = previews
+                        .iter()
+                        .map(|preview| {
+                            (
+                                preview.conversation.uuid,
+                                preview.last_message.as_ref().map(Message::text),
+                                preview.unread,
+                            )
+                        })
+                        .collect::<Vec<_>>();
+
+                    let [first, second, empty] =
+                        conversations.map(|conversation| conversation.uuid);
+                    assert_eq!(
+                        previews,
+                        vec![
+                            (first, Some("there"), 2),
+                            (second, Some("read me"), 0),
+                            (empty, None, 0),
+                        ]
+                    );
+                }
+
+                #[tokio::test]
+                async fn then_marking_read_zeroes_the_unread_count() {
+                    let (database, [first, ..], ..) = given().await;
+
+                    database.mark_read(&first).await.unwrap();
+
+                    let previews = database.conversation_previews().await.unwrap();
+                    assert!(previews.iter().all(|preview| preview.unread == 0));
+                    let counts = database.unread_counts().await.unwrap();
+                    assert!(counts.values().all(|count| *count == 0));
+                    let last = first.get_message(&database, 2).await.unwrap().unwrap();
+                    assert_eq!(last.status, MessageStatus::Read);
+                    assert!(database
+                        .members_pending_read(&last)
+                        .await
+                        .unwrap()
+                        .is_empty());
+                }
+
+                #[tokio::test]
+                async fn then_unread_counts_match_the_previews() {
+                    let (database, [first, second, empty], ..) = given().await;
+
+                    assert_eq!(database.unread_count(&first).await.unwrap(), 2);
+                    assert_eq!(database.unread_count(&second).await.unwrap(), 0);
+                    assert_eq!(
+                        database.unread_counts().await.unwrap(),
+                        HashMap::from([(first.uuid, 2), (second.uuid, 0), (empty.uuid, 0)])
+                    );
+                }
+
+                #[tokio::test]
+                async fn then_delivered_messages_are_no_longer_unread() {
+                    let (database, [first, ..], ..) = given().await;
+
+                    for message in database.new_messages(Some(&first)).await.unwrap() {
+                        database
+                            .set_message_status(&message, MessageStatus::Delivered)
+                            .await
+                            .unwrap();
+                    }
+
+                    assert!(database
+                        .new_messages(Some(&first))
+                        .await
+                        .unwrap()
+                        .is_empty());
+                    assert_eq!(database.unread_count(&first).await.unwrap(), 0);
+                }
+
+                #[tokio::test]
+                async fn then_a_deleted_last_message_is_not_previewed() {
+                    let (database, [first, ..]) = given().await;
+                    let there = first.get_message(&database, 2).await.unwrap().unwrap();
+                    let before = first.get_message(&database, 1).await.unwrap().unwrap();
+
+                    database.delete_message(&there).await.unwrap();
+
+                    let previews = database.conversation_previews().await.unwrap();
+                    let preview = previews
+                        .iter()
+                        .find(|preview| preview.conversation.uuid == first.uuid)
+                        .unwrap();
+                    assert_eq!(preview.last_message.as_ref().unwrap().uuid, before.uuid);
+                }
+
+                mod when_an_unread_message_is_deleted {
+                    use super::*;
+
+                    async fn given() -> super::Given {
+                        let (database, conversations) = super::given().await;
+                        let there = conversations[0]
+                            .get_message(&database, 2)
+                            .await
+                            .unwrap()
+                            .unwrap();
+                        database.delete_message(&there).await.unwrap();
+
+                        (database, conversations)
+                    }
+
+                    #[tokio::test]
+                    async fn then_it_is_not_counted() {
+                        let (database, [first, ..]) = given().await;
+
+                        let counts = database.unread_counts().await.unwrap();
+
+                        assert_eq!(database.unread_count(&first).await.unwrap(), 1);
+                        assert_eq!(counts[&first.uuid], 1);
+                    }
+
+                    #[tokio::test]
+                    async fn then_marking_read_leaves_it_alone() {
+                        let (database, [first, ..]) = given().await;
+
+                        database.mark_read(&first).await.unwrap();
+
+                        let there = first.get_message(&database, 2).await.unwrap().unwrap();
+                        assert_eq!(there.status, MessageStatus::Sent);
+                    }
+                }
+
+                #[tokio::test]
+                async fn then_previews_carry_the_conversations() {
+                    let (database, ..) = given().await;
+
+                    let previews = database.conversation_previews().await.unwrap();
+                    let previews = previews
+                        .into_iter()
+                        .map(|preview| preview.conversation)
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(previews, database.list_conversation().await.unwrap());
+                }
+            }
+
+            mod when_invites_are_created {
+                use super::*;
+
+                type Given = (Database, Conversation, InviteToken, InviteToken);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    let single_use = database
+                        .create_invite(&conversation, true, None)
+                        .await
+                        .unwrap();
+                    let expiring = database
+                        .create_invite(&conversation, false, Some(1000))
+                        .await
+                        .unwrap();
+
+                    (database, conversation, single_use, expiring)
+                }
+
+                #[tokio::test]
+                async fn then_a_single_use_invite_is_rejected_on_second_use() {
+                    let (database, conversation, single_use, ..) = given().await;
+                    let [a, b] = [(); 2].map(|_| Ed25519Seed::generate().public_key());
+
+                    let joined = database.redeem_invite(single_use, a, 0).await.unwrap();
+                    let again = database.redeem_invite(single_use, b, 0).await;
+
+                    assert_eq!(joined.uuid, conversation.uuid);
+                    assert!(matches!(again, Err(DatabaseError::InviteUsed)));
+                    let channels = database.list_channels(&conversation).await.unwrap();
+                    assert_eq!(channels.len(), 1);
+                    assert_eq!(channels[0].peer_cert, a);
+                }
+
+                #[tokio::test]
+                async fn then_an_expiring_invite_is_rejected_after_its_deadline() {
+                    let (database, _, _, expiring, ..) = given().await;
+                    let [a, b] = [(); 2].map(|_| Ed25519Seed::generate().public_key());
+
+                    let before = database.redeem_invite(expiring, a, 999).await;
+                    let after = database.redeem_invite(expiring, b, 1000).await;
+
+                    assert!(before.is_ok());
+                    assert!(matches!(after, Err(DatabaseError::InviteExpired)));
+                }
+
+                #[tokio::test]
+                async fn then_an_unknown_invite_is_rejected() {
+                    let (database, ..) = given().await;
+                    let peer = Ed25519Seed::generate().public_key();
+
+                    let r = database
+                        .redeem_invite(InviteToken(Uuid::new_v4()), peer, 0)
+                        .await;
+
+                    assert!(matches!(r, Err(DatabaseError::UnknownInvite)));
+                }
+            }
+
+            mod when_messages_mentioning_a_term_are_sent {
+                use super::*;
+
+                type Given = (Database, Conversation);
+                async fn given() -> Given {
+                    let (database, conversation, ..) = super::given().await;
+                    for text in ["lunch? yes, lunch!", "lunch at noon?", "see you"] {
+                        database
+                            .send_message(conversation.clone(), text.to_string(), None)
+                            .await
+                            .unwrap();
+                    }
+
+                    (database, conversation)
+                }
+
+                #[tokio::test]
+                async fn then_search_returns_matches_in_order() {
+                    let (database, conversation, ..) = given().await;
+
+                    let found = conversation.search(&database, "lunch").await.unwrap();
+                    let found = found.iter().map(Message::text).collect::<Vec<_>>();
+
+                    assert_eq!(found, vec!["lunch? yes, lunch!", "lunch at noon?"]);
+                }
+
+                #[tokio::test]
+                async fn then_wildcards_in_the_term_are_taken_literally() {
+                    let (database, conversation, ..) = given().await;
+                    database
+                        .send_message(conversation.clone(), "100% sure".to_string(), None)
+                        .await
+                        .unwrap();
+
+                    let percent = conversation.search(&database, "100%").await.unwrap();
+                    let underscore = conversation.search(&database, "0_").await.unwrap();
+
+                    let percent = percent.iter().map(Message::text).collect::<Vec<_>>();
+                    assert_eq!(percent, vec!["100% sure"]);
+                    assert!(underscore.is_empty());
+                }
+
+                #[tokio::test]
+                async fn then_more_occurrences_rank_first() {
+                    let (database, conversation, ..) = given().await;
+
+                    let found = conversation
+                        .search_ranked(&database, "lunch")
+                        .await
+                        .unwrap();
+                    let found = found.iter().map(Message::text).collect::<Vec<_>>();
+
+                    assert_eq!(found, vec!["lunch? yes, lunch!", "lunch at noon?"]);
+                }
+            }
         }
     }
 }
@@ -0,0 +1,90 @@
+//! Sealing of the private seed stored in the `local` table with a key derived
+//! from a passphrase.
+//!
+//! A sealed seed is laid out as `salt || nonce || ciphertext || tag`, which is
+//! never 32 bytes long, so it can be told apart from a plaintext seed.
+
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::num::NonZeroU32;
+
+const SALT_LEN: usize = 16;
+const ITERATIONS: u32 = 100_000;
+
+pub(crate) fn is_sealed(private: &[u8]) -> bool {
+    private.len() != 32
+}
+
+pub(crate) fn seal(passphrase: &str, seed: &[u8]) -> Vec<u8> {
+    let rng = SystemRandom::new();
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill(&mut salt).expect("No source of randomness");
+    rng.fill(&mut nonce).expect("No source of randomness");
+
+    let mut sealed = seed.to_vec();
+    key(passphrase, &salt)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut sealed,
+        )
+        .expect("Seed is small enough to be sealed");
+
+    [&salt[..], &nonce[..], &sealed[..]].concat()
+}
+
+/// `None` when the passphrase is wrong or `sealed` is corrupted.
+pub(crate) fn open(passphrase: &str, sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < SALT_LEN + NONCE_LEN {
+        return None;
+    }
+    let (salt, sealed) = sealed.split_at(SALT_LEN);
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+
+    let mut sealed = sealed.to_vec();
+    let seed = key(passphrase, salt)
+        .open_in_place(nonce, Aad::empty(), &mut sealed)
+        .ok()?;
+
+    Some(seed.to_vec())
+}
+
+fn key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_seed_opens_with_the_same_passphrase() {
+        let seed = [7; 32];
+
+        let sealed = seal("correct horse", &seed);
+
+        assert!(is_sealed(&sealed));
+        assert_eq!(open("correct horse", &sealed), Some(seed.to_vec()));
+    }
+
+    #[test]
+    fn sealed_seed_does_not_open_with_another_passphrase() {
+        let sealed = seal("correct horse", &[7; 32]);
+
+        assert_eq!(open("battery staple", &sealed), None);
+    }
+}
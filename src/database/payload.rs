@@ -0,0 +1,84 @@
+//! Payloads reassembled by
+//! [`Database::fetch_file_payload`](super::Database::fetch_file_payload).
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Cursor, Read},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// Files longer than this are reassembled on disk by default.
+pub const DEFAULT_SPILL_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FilePayload {
+    Memory(Vec<u8>),
+    /// Too long to be held in memory, see
+    /// [`Database::set_spill_bytes`](super::Database::set_spill_bytes).
+    Spilled(SpilledFile),
+}
+impl FilePayload {
+    /// Reads the payload from wherever it is.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        Ok(match self {
+            FilePayload::Memory(payload) => Box::new(Cursor::new(payload)),
+            FilePayload::Spilled(file) => Box::new(BufReader::new(File::open(file.path())?)),
+        })
+    }
+
+    /// The whole payload in memory, read back from the file if spilled.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            FilePayload::Memory(payload) => Ok(payload),
+            FilePayload::Spilled(file) => std::fs::read(file.path()),
+        }
+    }
+}
+
+/// Temporary file, removed once dropped.
+#[derive(Debug)]
+pub struct SpilledFile {
+    path: PathBuf,
+}
+impl SpilledFile {
+    pub(crate) fn create() -> io::Result<(Self, BufWriter<File>)> {
+        let path = std::env::temp_dir().join(format!("icechat-{}.part", Uuid::new_v4()));
+        let file = File::create(&path)?;
+
+        Ok((SpilledFile { path }, BufWriter::new(file)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+impl Drop for SpilledFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("Could not remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn a_spilled_file_is_removed_once_dropped() {
+        let (file, mut writer) = SpilledFile::create().unwrap();
+        writer.write_all(b"payload").unwrap();
+        writer.flush().unwrap();
+        let path = file.path().to_owned();
+
+        let payload = FilePayload::Spilled(file);
+        let mut read = Vec::new();
+        payload.reader().unwrap().read_to_end(&mut read).unwrap();
+        drop(payload);
+
+        assert_eq!(read, b"payload");
+        assert!(!path.exists());
+    }
+}
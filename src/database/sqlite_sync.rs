@@ -1,69 +1,91 @@
 use super::{
-    error::DatabaseResult,
+    error::{DatabaseError, DatabaseResult},
     sync::{SyncData, SyncDataId, SyncDataSource},
 };
+use crate::pipe_sync::SyncLogContext;
 use entity::{
-    entity::{channel, initial_sync},
-    patch::Patch,
+    entity::{blocked, channel, initial_sync, message, message_delivery},
+    patch::{attachment::BlobHash, Patch},
+    uuid::SplitUuid,
 };
 use futures_util::{future::LocalBoxFuture, FutureExt};
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseTransaction, EntityTrait,
-    IntoActiveModel, ModelTrait, Order, QueryFilter, QueryOrder, Statement,
+    sea_query::OnConflict, ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait,
+    DatabaseTransaction, EntityTrait, IntoActiveModel, Order, PaginatorTrait, QueryFilter,
+    QueryOrder, Statement,
 };
 
+/// Decides, before it is merged, what happens to a patch received from a
+/// peer. Returning the patch accepts it, possibly rewritten, returning `None`
+/// rejects it: the patch is acked but neither applied nor relayed.
+///
+/// Filtering is advisory, peers that sync directly with each other still
+/// exchange the rejected patches.
+pub type PatchFilter = fn(Patch) -> Option<Patch>;
+
+/// Context of the sync over one channel of the sqlite database.
+#[derive(Clone, Copy, Debug)]
+pub struct SqliteSyncCtx {
+    pub channel: i32,
+    pub filter: Option<PatchFilter>,
+    /// Received patches are only forwarded to the other channels, see
+    /// [`Database::set_relay`](super::Database::set_relay).
+    pub relay: bool,
+    pub log_context: SyncLogContext,
+}
+impl From<i32> for SqliteSyncCtx {
+    fn from(channel: i32) -> Self {
+        SqliteSyncCtx {
+            channel,
+            filter: None,
+            relay: false,
+            log_context: Default::default(),
+        }
+    }
+}
+
 impl SyncDataSource for DatabaseTransaction {
-    type Ctx = i32;
+    type Ctx = SqliteSyncCtx;
 
     fn next(
         &mut self,
-        channel_id: i32,
+        ctx: SqliteSyncCtx,
         (min_initial, min_global): (i32, i32),
     ) -> LocalBoxFuture<DatabaseResult<Option<SyncData>>> {
         async move {
-            let initial_sync = initial_sync::Entity::find()
-                .filter(initial_sync::Column::Channel.eq(channel_id))
-                .filter(initial_sync::Column::Id.gt(min_initial))
-                .order_by(initial_sync::Column::Id, sea_orm::Order::Asc)
-                .one(self)
-                .await?;
-
-            if let Some(initial_sync) = initial_sync {
-                let patch: Patch = bincode::deserialize(&initial_sync.payload).unwrap();
+            let channel = channel::Entity::find_by_id(ctx.channel).one(self).await?;
+            let Some(channel) = channel else { return Ok(None); };
 
-                return Ok(Some(SyncData {
-                    id: SyncDataId::InitialSync(initial_sync.id),
-                    payload: patch,
-                }));
+            if let Some(data) = next_initial_sync(self, ctx, &channel, min_initial).await? {
+                return Ok(Some(data));
             }
+            next_global(self, ctx, &channel, min_global).await
+        }
+        .boxed_local()
+    }
 
-            let channel = channel::Entity::find_by_id(channel_id).one(self).await?;
+    fn next_live(
+        &mut self,
+        ctx: SqliteSyncCtx,
+        (min_initial, min_global): (i32, i32),
+    ) -> LocalBoxFuture<DatabaseResult<Option<SyncData>>> {
+        async move {
+            let channel = channel::Entity::find_by_id(ctx.channel).one(self).await?;
             let Some(channel) = channel else { return Ok(None); };
-            let sync = entity::entity::sync::Entity::find()
-                .filter(entity::entity::sync::Column::Id.gt(channel.sync_index))
-                .filter(entity::entity::sync::Column::Id.gt(min_global))
-                .one(self)
-                .await?;
-
-            if let Some(sync) = sync {
-                let patch: Patch = bincode::deserialize(&sync.payload).unwrap();
 
-                return Ok(Some(SyncData {
-                    id: SyncDataId::Global(sync.id),
-                    payload: patch,
-                }));
+            if let Some(data) = next_global(self, ctx, &channel, min_global).await? {
+                return Ok(Some(data));
             }
-
-            Ok(None)
+            next_initial_sync(self, ctx, &channel, min_initial).await
         }
         .boxed_local()
     }
 
-    fn ack(&mut self, channel_id: i32, id: SyncDataId) -> LocalBoxFuture<DatabaseResult<()>> {
+    fn ack(&mut self, ctx: SqliteSyncCtx, id: SyncDataId) -> LocalBoxFuture<DatabaseResult<()>> {
         async move {
             match id {
                 SyncDataId::Global(id) => {
-                    let channel = channel::Entity::find_by_id(channel_id).one(self).await?;
+                    let channel = channel::Entity::find_by_id(ctx.channel).one(self).await?;
                     let Some(channel) = channel else { return Ok(()); };
 
                     if channel.sync_index >= id {
@@ -80,14 +102,33 @@ impl SyncDataSource for DatabaseTransaction {
                     Ok(())
                 }
                 SyncDataId::InitialSync(id) => {
+                    let channel = channel::Entity::find_by_id(ctx.channel).one(self).await?;
+                    let Some(channel) = channel else { return Ok(()); };
+                    let Some(snapshot) = channel.snapshot else { return Ok(()); };
+
                     let initial_sync = initial_sync::Entity::find_by_id(id).one(self).await?;
                     let Some(initial_sync) = initial_sync else { return Ok(()); };
 
-                    if initial_sync.channel != channel_id {
+                    if initial_sync.snapshot != snapshot || channel.snapshot_index >= id {
                         return Ok(());
                     }
 
-                    initial_sync.delete(self).await?;
+                    let remaining = initial_sync::Entity::find()
+                        .filter(initial_sync::Column::Snapshot.eq(snapshot))
+                        .filter(initial_sync::Column::Id.gt(id))
+                        .count(self)
+                        .await?;
+                    let drained = remaining == 0;
+
+                    let channel = channel::ActiveModel {
+                        snapshot: ActiveValue::Set((!drained).then_some(snapshot)),
+                        snapshot_index: ActiveValue::Set(id),
+                        ..channel.into_active_model()
+                    };
+                    channel.save(self).await?;
+                    if drained {
+                        remove_unused_snapshots(self).await?;
+                    }
 
                     Ok(())
                 }
@@ -98,11 +139,32 @@ impl SyncDataSource for DatabaseTransaction {
 
     fn merge(
         &mut self,
-        _channel_id: i32,
+        ctx: SqliteSyncCtx,
         data: SyncData,
     ) -> LocalBoxFuture<DatabaseResult<Option<SyncData>>> {
         async move {
-            let merged = data.payload.merge(self).await;
+            let blocked = blocked::Entity::find()
+                .filter(blocked::Column::Author.eq(data.author().0))
+                .count(self)
+                .await?;
+            if blocked > 0 {
+                return Ok(None);
+            }
+
+            let payload = match ctx.filter {
+                Some(filter) => filter(data.payload),
+                None => Some(data.payload),
+            };
+            let Some(payload) = payload else { return Ok(None); };
+
+            if ctx.relay {
+                return relay(self, data.id, payload).await;
+            }
+
+            let merged = payload.merge(self).await;
+            if let Some(merged) = &merged {
+                record_delivery(self, ctx.channel, merged).await?;
+            }
             let merged = merged.map(|payload| SyncData {
                 id: data.id,
                 payload,
@@ -113,7 +175,7 @@ impl SyncDataSource for DatabaseTransaction {
         .boxed_local()
     }
 
-    fn save(&mut self, _channel_id: i32, data: SyncData) -> LocalBoxFuture<DatabaseResult<()>> {
+    fn save(&mut self, _ctx: SqliteSyncCtx, data: SyncData) -> LocalBoxFuture<DatabaseResult<()>> {
         async move {
             let payload = bincode::serialize(&data.payload).unwrap();
 
@@ -128,9 +190,192 @@ impl SyncDataSource for DatabaseTransaction {
         }
         .boxed_local()
     }
+
+    fn partial_payloads(
+        &mut self,
+        ctx: SqliteSyncCtx,
+    ) -> LocalBoxFuture<DatabaseResult<Vec<(BlobHash, i32)>>> {
+        async move {
+            let channel = channel::Entity::find_by_id(ctx.channel).one(self).await?;
+            let Some(channel) = channel else { return Ok(Vec::new()); };
+
+            // Resumes after the chunks received in a row from the first one,
+            // chunks received past a gap are sent again.
+            let payloads = self
+                .query_all(Statement::from_sql_and_values(
+                    sea_orm::DatabaseBackend::Sqlite,
+                    "SELECT blob.hash AS hash, MIN(blob.\"index\") + 1 AS missing FROM blob \
+                    WHERE blob.hash IN ( \
+                        SELECT hash FROM attachment WHERE conversation = ? \
+                        INTERSECT \
+                        SELECT hash FROM blob GROUP BY hash \
+                        HAVING MIN(\"index\") = 0 AND SUM(LENGTH(data)) < MAX(total) \
+                    ) AND NOT EXISTS ( \
+                        SELECT 1 FROM blob AS next \
+                        WHERE next.hash = blob.hash AND next.\"index\" = blob.\"index\" + 1 \
+                    ) GROUP BY blob.hash;",
+                    [channel.conversation.into()],
+                ))
+                .await?;
+
+            let mut partial = Vec::with_capacity(payloads.len());
+            for payload in payloads {
+                let hash = payload.try_get::<Vec<u8>>("", "hash")?;
+                let missing = payload.try_get::<i32>("", "missing")?;
+                if let Ok(hash) = hash.try_into() {
+                    partial.push((hash, missing));
+                }
+            }
+
+            Ok(partial)
+        }
+        .boxed_local()
+    }
+}
+
+/// Next patch of the snapshot `channel` is draining, after `minimum`.
+async fn next_initial_sync(
+    trans: &DatabaseTransaction,
+    ctx: SqliteSyncCtx,
+    channel: &channel::Model,
+    minimum: i32,
+) -> DatabaseResult<Option<SyncData>> {
+    let Some(snapshot) = channel.snapshot else { return Ok(None); };
+
+    while let Some(initial_sync) = initial_sync::Entity::find()
+        .filter(initial_sync::Column::Snapshot.eq(snapshot))
+        .filter(initial_sync::Column::Id.gt(channel.snapshot_index.max(minimum)))
+        .order_by(initial_sync::Column::Id, sea_orm::Order::Asc)
+        .one(trans)
+        .await?
+    {
+        let id = SyncDataId::InitialSync(initial_sync.id);
+        match decode_patch(id, &initial_sync.payload) {
+            Ok(payload) => return Ok(Some(SyncData { id, payload })),
+            Err(e) => {
+                log::warn!("{}: Dropping {e}", ctx.log_context);
+                // A corrupted patch can not be sent to anyone, so it is dropped
+                // for every channel instead of wedging the sync on it.
+                initial_sync::Entity::delete_by_id(initial_sync.id)
+                    .exec(trans)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Next patch of the global log not yet acked by `channel`, after `minimum`.
+async fn next_global(
+    trans: &DatabaseTransaction,
+    ctx: SqliteSyncCtx,
+    channel: &channel::Model,
+    minimum: i32,
+) -> DatabaseResult<Option<SyncData>> {
+    while let Some(sync) = entity::entity::sync::Entity::find()
+        .filter(entity::entity::sync::Column::Id.gt(channel.sync_index))
+        .filter(entity::entity::sync::Column::Id.gt(minimum))
+        .order_by(entity::entity::sync::Column::Id, sea_orm::Order::Asc)
+        .one(trans)
+        .await?
+    {
+        let id = SyncDataId::Global(sync.id);
+        match decode_patch(id, &sync.payload) {
+            Ok(payload) => return Ok(Some(SyncData { id, payload })),
+            Err(e) => {
+                log::warn!("{}: Dropping {e}", ctx.log_context);
+                // Same as in `next_initial_sync`, every channel would wedge on
+                // it.
+                entity::entity::sync::Entity::delete_by_id(sync.id)
+                    .exec(trans)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Forwards `payload` without merging it, unless the log already holds it
+/// for the other channels.
+async fn relay(
+    trans: &DatabaseTransaction,
+    id: SyncDataId,
+    payload: Patch,
+) -> DatabaseResult<Option<SyncData>> {
+    let forwarding = entity::entity::sync::Entity::find()
+        .filter(entity::entity::sync::Column::Payload.eq(bincode::serialize(&payload).unwrap()))
+        .count(trans)
+        .await?;
+    if forwarding > 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(SyncData { id, payload }))
+}
+
+/// Reads a patch stored in the `sync` or `initial_sync` table under `id`.
+pub(crate) fn decode_patch(id: SyncDataId, payload: &[u8]) -> DatabaseResult<Patch> {
+    bincode::deserialize(payload).map_err(|e| DatabaseError::CorruptedPatch(id, e))
+}
+
+/// Records the peer of `channel` as the one that delivered the text of the
+/// message `patch` creates or edits, see [`Database::delivered_by`].
+///
+/// [`Database::delivered_by`]: super::Database::delivered_by
+async fn record_delivery(
+    trans: &DatabaseTransaction,
+    channel: i32,
+    patch: &Patch,
+) -> DatabaseResult<()> {
+    let uuid = match patch {
+        Patch::NewTextMessage(message) => message.id,
+        Patch::NewAttachmentMessage(message) => message.id,
+        Patch::NewReplyMessage(message) => message.id,
+        Patch::NewTypedAttachmentMessage(message) => message.id,
+        Patch::MessageEdit(edit) => edit.id,
+        _ => return Ok(()),
+    };
+    let Some(channel) = channel::Entity::find_by_id(channel).one(trans).await? else { return Ok(()); };
+    let uuid = SplitUuid::from(uuid).to_filter::<message::Column>();
+    let message = message::Entity::find()
+        .filter(uuid.0)
+        .filter(uuid.1)
+        .filter(uuid.2)
+        .filter(uuid.3)
+        .one(trans)
+        .await?;
+    let Some(message) = message else { return Ok(()); };
+
+    message_delivery::Entity::insert(message_delivery::ActiveModel {
+        message: ActiveValue::Set(message.id),
+        peer: ActiveValue::Set(channel.peer),
+    })
+    .on_conflict(
+        OnConflict::column(message_delivery::Column::Message)
+            .update_column(message_delivery::Column::Peer)
+            .to_owned(),
+    )
+    .exec_without_returning(trans)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the snapshots, and their patches, that no channel is draining.
+pub(crate) async fn remove_unused_snapshots(trans: &DatabaseTransaction) -> DatabaseResult<()> {
+    trans
+        .execute_unprepared(
+            "DELETE FROM snapshot WHERE id NOT IN \
+            (SELECT snapshot FROM channel WHERE snapshot IS NOT NULL);",
+        )
+        .await?;
+
+    Ok(())
 }
 
-async fn remove_old_patches(trans: &DatabaseTransaction) -> DatabaseResult<()> {
+pub(crate) async fn remove_old_patches(trans: &DatabaseTransaction) -> DatabaseResult<()> {
     let done_sync = channel::Entity::find()
         .order_by(channel::Column::SyncIndex, Order::Asc)
         .one(trans)
@@ -1,10 +1,30 @@
 use super::{error::DatabaseResult, DbSync};
-use entity::{crdt::Author, patch::Patch};
+use crate::pipe_sync::{SyncCounters, SyncLogContext};
+use entity::{
+    crdt::Author,
+    patch::{attachment::BlobHash, Patch},
+};
 use futures_util::{future::LocalBoxFuture, FutureExt};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::{BitAnd, BitOr},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use uuid::Uuid;
 
+/// Skew, in milliseconds, above which the peer's clock is warned about.
+pub const CLOCK_SKEW_WARNING: i64 = 30_000;
+
+/// Milliseconds the peer is reported as typing after its last
+/// [`Ephemeral::Typing`].
+pub const TYPING_TIMEOUT: i64 = 5_000;
+
+/// Initial sync patches sent between two patches of the global log by
+/// default, see [`PatchSync::with_interleave`].
+pub const DEFAULT_INTERLEAVE: u32 = 16;
+
 pub trait SyncDataSource {
     type Ctx: Copy;
 
@@ -13,6 +33,15 @@ pub trait SyncDataSource {
         ctx: Self::Ctx,
         minimum: (i32, i32),
     ) -> LocalBoxFuture<DatabaseResult<Option<SyncData>>>;
+    /// Same as [`SyncDataSource::next`], but patches of the global log come
+    /// before what remains of the initial sync.
+    fn next_live(
+        &mut self,
+        ctx: Self::Ctx,
+        minimum: (i32, i32),
+    ) -> LocalBoxFuture<DatabaseResult<Option<SyncData>>> {
+        self.next(ctx, minimum)
+    }
     fn ack(&mut self, ctx: Self::Ctx, id: SyncDataId) -> LocalBoxFuture<DatabaseResult<()>>;
     fn merge(
         &mut self,
@@ -20,6 +49,14 @@ pub trait SyncDataSource {
         data: SyncData,
     ) -> LocalBoxFuture<DatabaseResult<Option<SyncData>>>;
     fn save(&mut self, ctx: Self::Ctx, data: SyncData) -> LocalBoxFuture<DatabaseResult<()>>;
+    /// Payloads of the channel's conversation whose chunks are still
+    /// arriving, each with the index of the first chunk missing.
+    fn partial_payloads(
+        &mut self,
+        _ctx: Self::Ctx,
+    ) -> LocalBoxFuture<DatabaseResult<Vec<(BlobHash, i32)>>> {
+        async { Ok(Vec::new()) }.boxed_local()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -45,6 +82,36 @@ impl SyncData {
             Patch::MessageStatus(status) => Some(status.conversation),
             Patch::Attachment(attachment) => Some(attachment.conversation),
             Patch::NewAttachmentMessage(attachment) => Some(attachment.conversation),
+            Patch::MemberRemoval(removal) => Some(removal.conversation),
+            Patch::Receipt(receipt) => Some(receipt.conversation),
+            Patch::MessageTombstone(tombstone) => Some(tombstone.conversation),
+            Patch::MessageEdit(edit) => Some(edit.conversation),
+            Patch::AttachmentChunk(chunk) => Some(chunk.conversation),
+            Patch::KeySupersede(supersede) => Some(supersede.conversation),
+            Patch::NewReplyMessage(message) => Some(message.conversation),
+            Patch::NewTypedAttachmentMessage(attachment) => Some(attachment.conversation),
+            Patch::HistoryClear(clear) => Some(clear.conversation),
+        }
+    }
+
+    fn conversation_mut(&mut self) -> Option<&mut Uuid> {
+        match &mut self.payload {
+            Patch::Contact(_) => None,
+            Patch::Conversation(conversation) => Some(&mut conversation.id),
+            Patch::Member(member) => Some(&mut member.conversation),
+            Patch::NewTextMessage(message) => Some(&mut message.conversation),
+            Patch::MessageStatus(status) => Some(&mut status.conversation),
+            Patch::Attachment(attachment) => Some(&mut attachment.conversation),
+            Patch::NewAttachmentMessage(attachment) => Some(&mut attachment.conversation),
+            Patch::MemberRemoval(removal) => Some(&mut removal.conversation),
+            Patch::Receipt(receipt) => Some(&mut receipt.conversation),
+            Patch::MessageTombstone(tombstone) => Some(&mut tombstone.conversation),
+            Patch::MessageEdit(edit) => Some(&mut edit.conversation),
+            Patch::AttachmentChunk(chunk) => Some(&mut chunk.conversation),
+            Patch::KeySupersede(supersede) => Some(&mut supersede.conversation),
+            Patch::NewReplyMessage(message) => Some(&mut message.conversation),
+            Patch::NewTypedAttachmentMessage(attachment) => Some(&mut attachment.conversation),
+            Patch::HistoryClear(clear) => Some(&mut clear.conversation),
         }
     }
 
@@ -57,6 +124,37 @@ impl SyncData {
             Patch::MessageStatus(message) => message.crdt.author,
             Patch::Attachment(attachment) => attachment.crdt.0,
             Patch::NewAttachmentMessage(attachment) => attachment.crdt.writable.author,
+            Patch::MemberRemoval(removal) => removal.crdt.author,
+            Patch::Receipt(receipt) => receipt.crdt.author,
+            Patch::MessageTombstone(tombstone) => tombstone.crdt.author,
+            Patch::MessageEdit(edit) => edit.crdt.author,
+            Patch::AttachmentChunk(chunk) => chunk.crdt.0,
+            Patch::KeySupersede(supersede) => supersede.crdt.0,
+            Patch::NewReplyMessage(message) => message.crdt.writable.author,
+            Patch::NewTypedAttachmentMessage(attachment) => attachment.crdt.writable.author,
+            Patch::HistoryClear(clear) => clear.author,
+        }
+    }
+
+    /// Name of the kind of patch, for logs.
+    pub fn kind(&self) -> &'static str {
+        match &self.payload {
+            Patch::Contact(_) => "Contact",
+            Patch::Conversation(_) => "Conversation",
+            Patch::Member(_) => "Member",
+            Patch::NewTextMessage(_) => "NewTextMessage",
+            Patch::MessageStatus(_) => "MessageStatus",
+            Patch::Attachment(_) => "Attachment",
+            Patch::NewAttachmentMessage(_) => "NewAttachmentMessage",
+            Patch::MemberRemoval(_) => "MemberRemoval",
+            Patch::Receipt(_) => "Receipt",
+            Patch::MessageTombstone(_) => "MessageTombstone",
+            Patch::MessageEdit(_) => "MessageEdit",
+            Patch::AttachmentChunk(_) => "AttachmentChunk",
+            Patch::KeySupersede(_) => "KeySupersede",
+            Patch::NewReplyMessage(_) => "NewReplyMessage",
+            Patch::NewTypedAttachmentMessage(_) => "NewTypedAttachmentMessage",
+            Patch::HistoryClear(_) => "HistoryClear",
         }
     }
 }
@@ -85,12 +183,112 @@ impl Default for SyncDataId {
     }
 }
 
+/// Optional parts of the sync protocol, told to the peer in the handshake,
+/// see [`PatchSync::with_handshake`]. Each end only uses what both support,
+/// bits it does not know are ignored.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Capabilities(u32);
+impl Capabilities {
+    /// Patches are sent as [`PatchSyncMessage::Interned`].
+    pub const INTERNING: Capabilities = Capabilities(1);
+    /// Partial payloads are reported with [`PatchSyncMessage::Resume`].
+    pub const RESUME: Capabilities = Capabilities(1 << 1);
+    /// [`PatchSyncMessage::Ping`] is answered.
+    pub const PING: Capabilities = Capabilities(1 << 2);
+    /// Deflated frames are inflated, see
+    /// [`PipeSync::set_compression`](crate::pipe_sync::PipeSync::set_compression).
+    pub const DEFLATE: Capabilities = Capabilities(1 << 3);
+    /// Patches are encoded with 64 bit authors, see [`Author`]. Peers that
+    /// encode them otherwise would misread every patch, so patches are only
+    /// exchanged with peers that have it.
+    pub const WIDE_AUTHOR: Capabilities = Capabilities(1 << 4);
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, rhs: Capabilities) -> Capabilities {
+        Capabilities(self.0 | rhs.0)
+    }
+}
+impl BitAnd for Capabilities {
+    type Output = Capabilities;
+
+    fn bitand(self, rhs: Capabilities) -> Capabilities {
+        Capabilities(self.0 & rhs.0)
+    }
+}
+
+/// Conversations written as a handle on the wire instead of their uuid, see
+/// [`entity::uuid::interned`]. A channel only interns its own conversation.
+#[derive(Clone, Copy, Debug)]
+pub struct InternTable {
+    conversation: Uuid,
+}
+impl InternTable {
+    pub fn new(conversation: Uuid) -> Self {
+        InternTable { conversation }
+    }
+
+    /// `data` as sent, the interned conversation replaced by its handle.
+    pub fn intern(&self, mut data: SyncData) -> SyncData {
+        if let Some(conversation) = data.conversation_mut() {
+            if *conversation == self.conversation {
+                *conversation = Uuid::nil();
+            }
+        }
+        data
+    }
+
+    /// `data` as received, the handle replaced by the interned conversation.
+    pub fn expand(&self, mut data: SyncData) -> SyncData {
+        if let Some(conversation) = data.conversation_mut() {
+            if conversation.is_nil() {
+                *conversation = self.conversation;
+            }
+        }
+        data
+    }
+}
+
 pub struct PatchSync<S: SyncDataSource> {
     author: Author,
     conversation: Uuid,
     ctx: S::Ctx,
     tx: VecDeque<PatchSyncMessage>,
     minimum: (i32, i32),
+    /// See [`PatchSync::with_interleave`].
+    interleave: Option<u32>,
+    /// Initial sync patches sent since the last patch of the global log.
+    initial_streak: u32,
+    clock: fn() -> i64,
+    peer_clock_skew: Option<i64>,
+    /// Whether the handshake is still to be sent, see
+    /// [`PatchSync::with_handshake`].
+    handshake: bool,
+    /// Whether patches wait for the peer's handshake, see
+    /// [`Capabilities::WIDE_AUTHOR`].
+    versioned: bool,
+    /// Capabilities told to the peer in the handshake.
+    offered: Capabilities,
+    /// Capabilities the peer told in its handshake, `None` before it did.
+    peer_capabilities: Option<Capabilities>,
+    interns: InternTable,
+    /// Local time of the last [`Ephemeral::Typing`] from the peer.
+    peer_typing: Option<i64>,
+    /// Whether the last patch returned by `tx` holds attachment data, which
+    /// does not compress.
+    tx_binary: bool,
+    /// Index of the first chunk the peer misses of each payload it reported,
+    /// see [`PatchSyncMessage::Resume`]. Chunks before it are acked unsent.
+    peer_payloads: HashMap<BlobHash, i32>,
+    /// See [`PatchSync::with_own_device`].
+    own_device: bool,
+    counters: Arc<SyncCounters>,
+    log_context: SyncLogContext,
 }
 impl<S: SyncDataSource> PatchSync<S> {
     pub fn new(ctx: S::Ctx, author: Author, conversation: Uuid) -> Self {
@@ -100,6 +298,104 @@ impl<S: SyncDataSource> PatchSync<S> {
             ctx,
             tx: Default::default(),
             minimum: (0, 0),
+            interleave: None,
+            initial_streak: 0,
+            clock: system_clock,
+            peer_clock_skew: None,
+            handshake: false,
+            versioned: false,
+            offered: Capabilities::INTERNING
+                | Capabilities::RESUME
+                | Capabilities::PING
+                | Capabilities::WIDE_AUTHOR,
+            peer_capabilities: None,
+            interns: InternTable::new(conversation),
+            peer_typing: None,
+            tx_binary: false,
+            peer_payloads: Default::default(),
+            own_device: false,
+            counters: Default::default(),
+            log_context: Default::default(),
+        }
+    }
+
+    /// Prefixed to what is logged about this sync.
+    pub fn with_log_context(self, log_context: SyncLogContext) -> Self {
+        PatchSync {
+            log_context,
+            ..self
+        }
+    }
+
+    /// The peer is another device of the local user, which shares its author.
+    /// Patches of that author are then sent instead of skipped as the peer's
+    /// own. Those the peer itself sent are echoed back once, and acked there
+    /// without being merged again.
+    pub fn with_own_device(self, own_device: bool) -> Self {
+        PatchSync { own_device, ..self }
+    }
+
+    /// Replaces the source of the current time, in milliseconds since the
+    /// unix epoch.
+    pub fn with_clock(self, clock: fn() -> i64) -> Self {
+        PatchSync { clock, ..self }
+    }
+
+    /// After `interleave` patches of the initial sync, a pending patch of the
+    /// global log is sent before the next one, so that live messages are not
+    /// stuck behind a long initial sync. `None` drains the initial sync first.
+    pub fn with_interleave(self, interleave: Option<u32>) -> Self {
+        PatchSync { interleave, ..self }
+    }
+
+    /// Starts with a handshake, sent before any patch, in which each end
+    /// tells its [`Capabilities`] and its clock, from which
+    /// [`DbSync::peer_clock_skew`] is estimated. Patches are only exchanged
+    /// once the peer told [`Capabilities::WIDE_AUTHOR`] in its own.
+    pub fn with_handshake(self) -> Self {
+        PatchSync {
+            handshake: true,
+            versioned: true,
+            ..self
+        }
+    }
+
+    /// Whether patches are exchanged with the peer, see
+    /// [`PatchSync::with_handshake`].
+    fn exchanges_patches(&self) -> bool {
+        !self.versioned || self.capabilities().contains(Capabilities::WIDE_AUTHOR)
+    }
+
+    /// Called once the peer told its capabilities in the handshake. The
+    /// first time, it is told where to resume the payloads it was sending.
+    async fn peer_hello(
+        &mut self,
+        database: &mut S,
+        capabilities: Capabilities,
+    ) -> DatabaseResult<()> {
+        if self.peer_capabilities.replace(capabilities).is_some() {
+            return Ok(());
+        }
+        if !self.capabilities().contains(Capabilities::RESUME) {
+            return Ok(());
+        }
+
+        let payloads = database.partial_payloads(self.ctx).await?;
+        if !payloads.is_empty() {
+            self.tx.push_back(PatchSyncMessage::Resume(payloads));
+        }
+
+        Ok(())
+    }
+
+    /// Whether `data` is a chunk the peer reported to already hold.
+    fn held_by_peer(&self, data: &SyncData) -> bool {
+        match &data.payload {
+            Patch::AttachmentChunk(chunk) => self
+                .peer_payloads
+                .get(&chunk.hash)
+                .is_some_and(|missing| chunk.index < *missing),
+            _ => false,
         }
     }
 }
@@ -112,12 +408,29 @@ impl<S: SyncDataSource> DbSync for PatchSync<S> {
         database: &'a mut Self::Database,
     ) -> LocalBoxFuture<'a, DatabaseResult<Option<Self::Message>>> {
         async move {
+            if std::mem::take(&mut self.handshake) {
+                return Ok(Some(PatchSyncMessage::Hello {
+                    clock: (self.clock)(),
+                    capabilities: self.offered,
+                }));
+            }
+
             loop {
                 if let Some(next) = self.tx.pop_front() {
                     return Ok(Some(next));
                 }
+                if !self.exchanges_patches() {
+                    return Ok(None);
+                }
 
-                let Some(next) = database.next(self.ctx, self.minimum).await? else {
+                let live = self
+                    .interleave
+                    .is_some_and(|interleave| self.initial_streak >= interleave);
+                let next = match live {
+                    true => database.next_live(self.ctx, self.minimum).await?,
+                    false => database.next(self.ctx, self.minimum).await?,
+                };
+                let Some(next) = next else {
                     return Ok(None);
                 };
 
@@ -126,14 +439,28 @@ impl<S: SyncDataSource> DbSync for PatchSync<S> {
                     .map(|conversation| conversation != self.conversation)
                     .unwrap_or(false);
 
-                if next.author() == self.author || skip_by_conversation {
+                let peers_own = !self.own_device && next.author() == self.author;
+                if peers_own || skip_by_conversation || self.held_by_peer(&next) {
                     database.ack(self.ctx, next.id).await?;
                     continue;
                 }
 
+                self.tx_binary = matches!(next.payload, Patch::AttachmentChunk(_));
                 match next.id {
-                    SyncDataId::Global(id) => self.minimum.1 = id,
-                    SyncDataId::InitialSync(id) => self.minimum.0 = id,
+                    SyncDataId::Global(id) => {
+                        self.minimum.1 = id;
+                        self.initial_streak = 0;
+                    }
+                    SyncDataId::InitialSync(id) => {
+                        self.minimum.0 = id;
+                        self.initial_streak += 1;
+                    }
+                }
+
+                SyncCounters::add(&self.counters.patches_sent, 1);
+                if self.capabilities().contains(Capabilities::INTERNING) {
+                    let next = bincode::serialize(&self.interns.intern(next))?;
+                    break Ok(Some(PatchSyncMessage::Interned(next)));
                 }
 
                 break Ok(Some(PatchSyncMessage::Data(next)));
@@ -148,6 +475,47 @@ impl<S: SyncDataSource> DbSync for PatchSync<S> {
         message: Self::Message,
     ) -> LocalBoxFuture<'a, DatabaseResult<()>> {
         async move {
+            let patch = matches!(
+                message,
+                PatchSyncMessage::Data(_) | PatchSyncMessage::Interned(_)
+            );
+            if patch && !self.exchanges_patches() {
+                // Left unacked, for the peer to send again once it is able
+                // to tell how it encodes patches.
+                match self.peer_capabilities {
+                    None => log::warn!(
+                        "{}: Refusing a patch sent before the handshake, the peer may predate it",
+                        self.log_context
+                    ),
+                    Some(_) => log::warn!(
+                        "{}: Refusing a patch from a peer without wide authors",
+                        self.log_context
+                    ),
+                }
+                return Ok(());
+            }
+
+            let message = match message {
+                PatchSyncMessage::Interned(data) => {
+                    match bincode::deserialize(&data) {
+                        Ok(data) => PatchSyncMessage::Data(self.interns.expand(data)),
+                        Err(e) => {
+                            // Acked when at least the id is readable, so the
+                            // peer moves on instead of resending it forever.
+                            log::warn!(
+                                "{}: Skipping malformed patch from peer: {e}",
+                                self.log_context
+                            );
+                            if let Ok(id) = bincode::deserialize(&data) {
+                                self.tx.push_back(PatchSyncMessage::Ack(id));
+                            }
+                            return Ok(());
+                        }
+                    }
+                }
+                message => message,
+            };
+
             match message {
                 PatchSyncMessage::Data(data) => {
                     let id = data.id;
@@ -157,26 +525,167 @@ impl<S: SyncDataSource> DbSync for PatchSync<S> {
                         .unwrap_or(true);
 
                     if valid_conversation {
-                        if let Some(data) = database.merge(self.ctx, data).await? {
-                            database.save(self.ctx, data).await?;
+                        SyncCounters::add(&self.counters.patches_received, 1);
+                        match database.merge(self.ctx, data).await? {
+                            Some(data) => database.save(self.ctx, data).await?,
+                            None => SyncCounters::add(&self.counters.conflicts, 1),
                         }
                     }
 
                     self.tx.push_back(PatchSyncMessage::Ack(id));
                 }
                 PatchSyncMessage::Ack(id) => database.ack(self.ctx, id).await?,
+                PatchSyncMessage::Interned(_) => unreachable!(),
+                PatchSyncMessage::Hello {
+                    clock: sent,
+                    capabilities,
+                } => {
+                    self.tx.push_back(PatchSyncMessage::HelloReply {
+                        sent,
+                        clock: (self.clock)(),
+                        capabilities: self.offered,
+                    });
+                    self.peer_hello(database, capabilities).await?;
+                }
+                PatchSyncMessage::HelloReply {
+                    sent,
+                    clock,
+                    capabilities,
+                } => {
+                    self.peer_hello(database, capabilities).await?;
+                    let round_trip = (self.clock)() - sent;
+                    let skew = clock - (sent + round_trip / 2);
+                    if skew.abs() > CLOCK_SKEW_WARNING {
+                        log::warn!(
+                            "{}: Peer clock is off by {skew}ms, messages may look out of order",
+                            self.log_context
+                        );
+                    }
+
+                    self.peer_clock_skew = Some(skew);
+                }
+                PatchSyncMessage::Ephemeral(Ephemeral::Typing {
+                    conversation,
+                    author,
+                }) => {
+                    if conversation == self.conversation && author == self.author {
+                        self.peer_typing = Some((self.clock)());
+                    }
+                }
+                PatchSyncMessage::Resume(payloads) => self.peer_payloads.extend(payloads),
+                PatchSyncMessage::Ping => self.tx.push_back(PatchSyncMessage::Pong),
+                PatchSyncMessage::Pong => {}
             }
 
             Ok(())
         }
         .boxed_local()
     }
+
+    fn peer_clock_skew(&self) -> Option<i64> {
+        self.peer_clock_skew
+    }
+
+    fn send_typing(&mut self, author: Author) {
+        self.tx
+            .push_back(PatchSyncMessage::Ephemeral(Ephemeral::Typing {
+                conversation: self.conversation,
+                author,
+            }));
+    }
+
+    fn peer_typing(&self) -> bool {
+        self.peer_typing
+            .map(|since| (self.clock)() - since < TYPING_TIMEOUT)
+            .unwrap_or(false)
+    }
+
+    fn compressible(&self, message: &PatchSyncMessage) -> bool {
+        match message {
+            PatchSyncMessage::Data(_) | PatchSyncMessage::Interned(_) => !self.tx_binary,
+            _ => true,
+        }
+    }
+
+    fn ping(&mut self) -> bool {
+        let answers = self.capabilities().contains(Capabilities::PING);
+        if answers {
+            self.tx.push_back(PatchSyncMessage::Ping);
+        }
+
+        answers
+    }
+
+    fn offer(&mut self, capabilities: Capabilities) {
+        self.offered = self.offered | capabilities;
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.peer_capabilities
+            .map(|peer| peer & self.offered)
+            .unwrap_or_default()
+    }
+
+    fn handshaking(&self) -> bool {
+        self.versioned && self.peer_capabilities.is_none()
+    }
+
+    fn set_counters(&mut self, counters: Arc<SyncCounters>) {
+        self.counters = counters;
+    }
+}
+
+pub(crate) fn system_clock() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
 }
 
+/// Messages exchanged over a channel.
+///
+/// Icechat 0.1 only knew [`PatchSyncMessage::Data`] and
+/// [`PatchSyncMessage::Ack`], with 32 bit authors in patches. Since 0.2 each
+/// end opens with [`PatchSyncMessage::Hello`], which a 0.1 peer fails to
+/// decode, and patches are only exchanged with peers that answer it with
+/// [`Capabilities::WIDE_AUTHOR`]. Both ends of a channel need 0.2 to sync.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PatchSyncMessage {
     Data(SyncData),
     Ack(SyncDataId),
+    /// Handshake, see [`PatchSync::with_handshake`]. The clock is the current
+    /// time of the sender, in milliseconds since the unix epoch.
+    Hello {
+        clock: i64,
+        capabilities: Capabilities,
+    },
+    /// Answer to [`PatchSyncMessage::Hello`], echoing its clock along with
+    /// the current time and the capabilities of the replier.
+    HelloReply {
+        sent: i64,
+        clock: i64,
+        capabilities: Capabilities,
+    },
+    /// [`SyncData`] serialized with the channel's conversation interned, see
+    /// [`InternTable`]. Only sent to peers with [`Capabilities::INTERNING`].
+    Interned(Vec<u8>),
+    Ephemeral(Ephemeral),
+    /// Payloads the sender holds part of, each with the index of the first
+    /// chunk it misses, so that a transfer cut by a disconnect resumes there.
+    /// Only sent to peers with [`Capabilities::RESUME`].
+    Resume(Vec<(BlobHash, i32)>),
+    /// Asks for a [`PatchSyncMessage::Pong`], to tell whether the peer is
+    /// still there. Only sent to peers with [`Capabilities::PING`].
+    Ping,
+    Pong,
+}
+
+/// Notification that only matters while the channel is up. It is kept in
+/// memory by [`PatchSync`], never merged nor stored in the sync log, and
+/// dropped when it is not about the channel's conversation and peer.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Ephemeral {
+    Typing { conversation: Uuid, author: Author },
 }
 impl From<SyncData> for PatchSyncMessage {
     fn from(value: SyncData) -> Self {
@@ -188,10 +697,16 @@ impl From<SyncData> for PatchSyncMessage {
 pub mod tests {
     use super::*;
     use entity::{
-        crdt::{sequence::CrdtWritableSequence, writable::CrdtWritable, CrdtAddOnly},
+        crdt::{
+            sequence::{CrdtPosition, CrdtWritableSequence},
+            writable::CrdtWritable,
+            CrdtAddOnly,
+        },
         patch::{
-            Attachment, Contact, Conversation, Key, Member, MessageStatus, NewAttachmentMessage,
-            NewTextMessage,
+            Attachment, AttachmentChunk, Contact, Conversation, HistoryClear, Key, KeySupersede,
+            Member, MemberRemoval, MessageEdit, MessageStatus, MessageTombstone,
+            NewAttachmentMessage, NewReplyMessage, NewTextMessage, NewTypedAttachmentMessage,
+            Receipt,
         },
     };
     use rstest::*;
@@ -224,6 +739,19 @@ pub mod tests {
         initial_patches: Vec<SyncData>,
         minimum_ack: i32,
         merged: HashSet<SyncDataId>,
+        partial: Vec<(BlobHash, i32)>,
+    }
+    impl SourceMock {
+        fn next_initial(&self, minimum: (i32, i32)) -> Option<SyncData> {
+            self.initial_patches.get(minimum.0 as usize).cloned()
+        }
+
+        fn next_global(&self, minimum: (i32, i32)) -> Option<SyncData> {
+            self.patches
+                .iter()
+                .find(|patch| patch.id.global() > self.minimum_ack && patch.id.global() > minimum.1)
+                .cloned()
+        }
     }
     impl SyncDataSource for SourceMock {
         type Ctx = ();
@@ -233,21 +761,21 @@ pub mod tests {
             _ctx: Self::Ctx,
             minimum: (i32, i32),
         ) -> LocalBoxFuture<DatabaseResult<Option<SyncData>>> {
-            async move {
-                let initial_patch = self.initial_patches.get(minimum.0 as usize).cloned();
-                if let Some(initial_patch) = initial_patch {
-                    return Ok(Some(initial_patch));
-                }
+            let next = self
+                .next_initial(minimum)
+                .or_else(|| self.next_global(minimum));
+            async move { Ok(next) }.boxed_local()
+        }
 
-                Ok(self
-                    .patches
-                    .iter()
-                    .find(|patch| {
-                        patch.id.global() > self.minimum_ack && patch.id.global() > minimum.1
-                    })
-                    .cloned())
-            }
-            .boxed_local()
+        fn next_live(
+            &mut self,
+            _ctx: Self::Ctx,
+            minimum: (i32, i32),
+        ) -> LocalBoxFuture<DatabaseResult<Option<SyncData>>> {
+            let next = self
+                .next_global(minimum)
+                .or_else(|| self.next_initial(minimum));
+            async move { Ok(next) }.boxed_local()
         }
 
         fn ack(&mut self, _ctx: Self::Ctx, id: SyncDataId) -> LocalBoxFuture<DatabaseResult<()>> {
@@ -280,6 +808,14 @@ pub mod tests {
             }
             .boxed_local()
         }
+
+        fn partial_payloads(
+            &mut self,
+            _ctx: Self::Ctx,
+        ) -> LocalBoxFuture<DatabaseResult<Vec<(BlobHash, i32)>>> {
+            let partial = self.partial.clone();
+            async move { Ok(partial) }.boxed_local()
+        }
     }
 
     #[rstest]
@@ -290,6 +826,15 @@ pub mod tests {
     #[case(a_message_status_patch(), Some(SAME_CONVERSATION))]
     #[case(an_attachment_patch(), Some(SAME_CONVERSATION))]
     #[case(an_attachment_message_patch(), Some(SAME_CONVERSATION))]
+    #[case(a_member_removal_patch(), Some(SAME_CONVERSATION))]
+    #[case(a_receipt_patch(), Some(SAME_CONVERSATION))]
+    #[case(a_message_tombstone_patch(), Some(SAME_CONVERSATION))]
+    #[case(a_message_edit_patch(), Some(SAME_CONVERSATION))]
+    #[case(an_attachment_chunk_patch(), Some(SAME_CONVERSATION))]
+    #[case(a_key_supersede_patch(), Some(SAME_CONVERSATION))]
+    #[case(a_reply_message_patch(), Some(SAME_CONVERSATION))]
+    #[case(a_typed_attachment_message_patch(), Some(SAME_CONVERSATION))]
+    #[case(a_history_clear_patch(), Some(SAME_CONVERSATION))]
     fn given_a_sync_data_the_conversation_is_inferred_from_the_patch(
         #[case] patch: Patch,
         #[case] conversation: Option<Uuid>,
@@ -310,6 +855,15 @@ pub mod tests {
     #[case(a_message_status_patch(), USER)]
     #[case(an_attachment_patch(), USER)]
     #[case(an_attachment_message_patch(), USER)]
+    #[case(a_member_removal_patch(), USER)]
+    #[case(a_receipt_patch(), USER)]
+    #[case(a_message_tombstone_patch(), USER)]
+    #[case(a_message_edit_patch(), USER)]
+    #[case(an_attachment_chunk_patch(), USER)]
+    #[case(a_key_supersede_patch(), USER)]
+    #[case(a_reply_message_patch(), USER)]
+    #[case(a_typed_attachment_message_patch(), USER)]
+    #[case(a_history_clear_patch(), USER)]
     fn given_a_sync_data_the_author_is_inferred_from_the_patch(
         #[case] patch: Patch,
         #[case] author: Author,
@@ -322,6 +876,35 @@ pub mod tests {
         assert_eq!(sync_data.author(), author);
     }
 
+    #[rstest]
+    #[case(a_contact_patch(), "Contact")]
+    #[case(a_conversation_patch(), "Conversation")]
+    #[case(a_member_patch(), "Member")]
+    #[case(a_text_message_patch(), "NewTextMessage")]
+    #[case(a_message_status_patch(), "MessageStatus")]
+    #[case(an_attachment_patch(), "Attachment")]
+    #[case(an_attachment_message_patch(), "NewAttachmentMessage")]
+    #[case(a_member_removal_patch(), "MemberRemoval")]
+    #[case(a_receipt_patch(), "Receipt")]
+    #[case(a_message_tombstone_patch(), "MessageTombstone")]
+    #[case(a_message_edit_patch(), "MessageEdit")]
+    #[case(an_attachment_chunk_patch(), "AttachmentChunk")]
+    #[case(a_key_supersede_patch(), "KeySupersede")]
+    #[case(a_reply_message_patch(), "NewReplyMessage")]
+    #[case(a_typed_attachment_message_patch(), "NewTypedAttachmentMessage")]
+    #[case(a_history_clear_patch(), "HistoryClear")]
+    fn given_a_sync_data_the_kind_is_named_after_the_patch(
+        #[case] patch: Patch,
+        #[case] kind: &str,
+    ) {
+        let sync_data = SyncData {
+            payload: patch,
+            ..Default::default()
+        };
+
+        assert_eq!(sync_data.kind(), kind);
+    }
+
     fn a_contact_patch() -> Patch {
         Patch::Contact(Contact {
             crdt: CrdtWritable {
@@ -354,6 +937,7 @@ pub mod tests {
             from: Default::default(),
             conversation: SAME_CONVERSATION,
             text: Default::default(),
+            created_at: Default::default(),
             crdt: CrdtWritableSequence {
                 writable: CrdtWritable {
                     author: USER,
@@ -379,7 +963,7 @@ pub mod tests {
         Attachment {
             id: Default::default(),
             conversation: SAME_CONVERSATION,
-            payload: Default::default(),
+            hash: Default::default(),
             crdt: CrdtAddOnly(USER),
         }
         .into()
@@ -391,6 +975,115 @@ pub mod tests {
             conversation: SAME_CONVERSATION,
             filename: Default::default(),
             attachment: Default::default(),
+            created_at: Default::default(),
+            crdt: CrdtWritableSequence {
+                writable: CrdtWritable {
+                    author: USER,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        }
+        .into()
+    }
+    fn a_member_removal_patch() -> Patch {
+        MemberRemoval {
+            key: Default::default(),
+            conversation: SAME_CONVERSATION,
+            removed: true,
+            crdt: CrdtWritable {
+                author: USER,
+                ..Default::default()
+            },
+        }
+        .into()
+    }
+    fn a_receipt_patch() -> Patch {
+        Receipt {
+            message: Default::default(),
+            conversation: SAME_CONVERSATION,
+            member: Default::default(),
+            status: Default::default(),
+            crdt: CrdtWritable {
+                author: USER,
+                ..Default::default()
+            },
+        }
+        .into()
+    }
+    fn a_message_tombstone_patch() -> Patch {
+        MessageTombstone {
+            id: Default::default(),
+            conversation: SAME_CONVERSATION,
+            crdt: CrdtWritable {
+                author: USER,
+                ..Default::default()
+            },
+        }
+        .into()
+    }
+    fn a_message_edit_patch() -> Patch {
+        MessageEdit {
+            id: Default::default(),
+            conversation: SAME_CONVERSATION,
+            text: "edited".to_string(),
+            crdt: CrdtWritable {
+                author: USER,
+                ..Default::default()
+            },
+        }
+        .into()
+    }
+    fn an_attachment_chunk_patch() -> Patch {
+        AttachmentChunk {
+            hash: Default::default(),
+            conversation: SAME_CONVERSATION,
+            index: 1,
+            data: vec![1, 2, 3],
+            total: 3,
+            crdt: CrdtAddOnly(USER),
+        }
+        .into()
+    }
+    fn a_key_supersede_patch() -> Patch {
+        KeySupersede {
+            old: Default::default(),
+            new: Default::default(),
+            conversation: SAME_CONVERSATION,
+            signature: vec![4; 64],
+            crdt: CrdtAddOnly(USER),
+        }
+        .into()
+    }
+    fn a_reply_message_patch() -> Patch {
+        NewReplyMessage {
+            id: Default::default(),
+            from: Default::default(),
+            conversation: SAME_CONVERSATION,
+            text: Default::default(),
+            reply_to: Uuid::from_u128(5),
+            created_at: Default::default(),
+            crdt: CrdtWritableSequence {
+                writable: CrdtWritable {
+                    author: USER,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        }
+        .into()
+    }
+    fn a_typed_attachment_message_patch() -> Patch {
+        NewTypedAttachmentMessage {
+            id: Default::default(),
+            from: Default::default(),
+            conversation: SAME_CONVERSATION,
+            filename: Default::default(),
+            attachment: Default::default(),
+            mime: Some("image/png".to_string()),
+            size: 3,
+            thumbnail: None,
+            created_at: Default::default(),
             crdt: CrdtWritableSequence {
                 writable: CrdtWritable {
                     author: USER,
@@ -401,6 +1094,18 @@ pub mod tests {
         }
         .into()
     }
+    fn a_history_clear_patch() -> Patch {
+        HistoryClear {
+            conversation: SAME_CONVERSATION,
+            before: CrdtPosition {
+                sequence: 3,
+                author: PEER,
+                id: Uuid::nil(),
+            },
+            author: USER,
+        }
+        .into()
+    }
 
     mod given_a_patch_sync {
         use super::*;
@@ -428,6 +1133,7 @@ pub mod tests {
 
             let tx = sync.tx(&mut source).await.unwrap();
             assert_eq!(tx, Some(PatchSyncMessage::Data(source.patches[0].clone())));
+            assert_eq!(SyncCounters::get(&sync.counters.patches_sent), 1);
         }
 
         #[rstest]
@@ -467,6 +1173,424 @@ pub mod tests {
             assert_eq!(tx, None);
         }
 
+        #[rstest]
+        #[tokio::test]
+        async fn and_it_interleaves_then_global_patches_are_sent_between_initial_ones(
+            given: Given,
+        ) {
+            let (mut source, sync, ..) = given;
+            let mut sync = sync.with_interleave(Some(2));
+            source.initial_patches = (1..=5)
+                .map(|id| SyncData {
+                    id: SyncDataId::InitialSync(id),
+                    payload: USER_PATCH,
+                })
+                .collect();
+            source.patches = [37, 38]
+                .map(|id| SyncData {
+                    id: id.into(),
+                    payload: USER_PATCH,
+                })
+                .to_vec();
+
+            let mut sent = vec![];
+            while let Some(PatchSyncMessage::Data(data)) = sync.tx(&mut source).await.unwrap() {
+                sent.push(data.id);
+            }
+
+            assert_eq!(
+                sent,
+                [
+                    SyncDataId::InitialSync(1),
+                    SyncDataId::InitialSync(2),
+                    37.into(),
+                    SyncDataId::InitialSync(3),
+                    SyncDataId::InitialSync(4),
+                    38.into(),
+                    SyncDataId::InitialSync(5),
+                ]
+            );
+        }
+
+        mod when_clocks_are_exchanged_with_a_skewed_peer {
+            use super::*;
+
+            fn local_clock() -> i64 {
+                1_000_000
+            }
+
+            fn skewed_clock() -> i64 {
+                1_000_000 + 45_000
+            }
+
+            type Given = (PatchSync<SourceMock>, PatchSync<SourceMock>);
+            async fn given() -> Given {
+                let mut source = SourceMock::default();
+                let mut local = PatchSync::new((), PEER, SAME_CONVERSATION)
+                    .with_clock(local_clock)
+                    .with_handshake();
+                let mut peer = PatchSync::new((), USER, SAME_CONVERSATION).with_clock(skewed_clock);
+
+                let hello = local.tx(&mut source).await.unwrap().unwrap();
+                peer.rx(&mut source, hello).await.unwrap();
+                let reply = peer.tx(&mut source).await.unwrap().unwrap();
+                local.rx(&mut source, reply).await.unwrap();
+
+                (local, peer)
+            }
+
+            #[tokio::test]
+            async fn then_the_skew_is_estimated() {
+                let (local, ..) = given().await;
+
+                let skew = local.peer_clock_skew().unwrap();
+                assert!((skew - 45_000).abs() < 100);
+                assert!(skew > CLOCK_SKEW_WARNING);
+            }
+
+            #[tokio::test]
+            async fn then_the_peer_did_not_estimate_it() {
+                let (_, peer, ..) = given().await;
+
+                assert_eq!(peer.peer_clock_skew(), None);
+            }
+        }
+
+        mod when_patches_are_sent_after_the_handshake {
+            use super::*;
+
+            type Given = (Vec<SyncData>, Vec<PatchSyncMessage>, SourceMock);
+            async fn given() -> Given {
+                let (mut source, local) = super::given();
+                let mut peer_source = SourceMock::default();
+                let mut local = local.with_handshake();
+                let mut peer = PatchSync::new((), USER, SAME_CONVERSATION);
+
+                let hello = local.tx(&mut source).await.unwrap().unwrap();
+                peer.rx(&mut peer_source, hello).await.unwrap();
+                let reply = peer.tx(&mut peer_source).await.unwrap().unwrap();
+                local.rx(&mut source, reply).await.unwrap();
+
+                let patches = [
+                    a_conversation_patch(),
+                    a_member_patch(),
+                    a_text_message_patch(),
+                    a_message_status_patch(),
+                    an_attachment_message_patch(),
+                    a_receipt_patch(),
+                    a_message_tombstone_patch(),
+                    a_message_edit_patch(),
+                    an_attachment_chunk_patch(),
+                    a_key_supersede_patch(),
+                    a_reply_message_patch(),
+                ];
+                source.patches = patches
+                    .into_iter()
+                    .zip(1..)
+                    .map(|(payload, id)| SyncData {
+                        id: id.into(),
+                        payload,
+                    })
+                    .collect();
+
+                let mut sent = Vec::new();
+                while let Some(message) = local.tx(&mut source).await.unwrap() {
+                    peer.rx(&mut peer_source, message.clone()).await.unwrap();
+                    sent.push(message);
+                }
+
+                (source.patches, sent, peer_source)
+            }
+
+            #[tokio::test]
+            async fn then_the_conversation_is_interned() {
+                let (_, sent, ..) = given().await;
+
+                assert!(sent
+                    .iter()
+                    .all(|message| matches!(message, PatchSyncMessage::Interned(_))));
+            }
+
+            #[tokio::test]
+            async fn then_the_peer_reconstructs_the_same_data() {
+                let (patches, _, peer_source, ..) = given().await;
+
+                assert_eq!(peer_source.patches, patches);
+            }
+
+            #[tokio::test]
+            async fn then_fewer_bytes_are_sent() {
+                let (patches, sent, ..) = given().await;
+                let size = |message: &PatchSyncMessage| bincode::serialize(message).unwrap().len();
+
+                let interned = sent.iter().map(size).sum::<usize>();
+                let plain = patches
+                    .into_iter()
+                    .map(|data| size(&PatchSyncMessage::Data(data)))
+                    .sum::<usize>();
+
+                assert!(interned < plain, "{interned} >= {plain}");
+            }
+        }
+
+        mod when_the_peer_offers_less_in_the_handshake {
+            use super::*;
+
+            type Given = (PatchSync<SourceMock>, Vec<PatchSyncMessage>);
+            async fn given() -> Given {
+                let (mut source, local) = super::given();
+                let mut peer_source = SourceMock::default();
+                let mut local = local.with_handshake();
+                local.offer(Capabilities::DEFLATE);
+                let mut peer = PatchSync::new((), USER, SAME_CONVERSATION);
+
+                let hello = local.tx(&mut source).await.unwrap().unwrap();
+                let PatchSyncMessage::Hello { clock, .. } = hello else { panic!() };
+                let reply = PatchSyncMessage::HelloReply {
+                    sent: clock,
+                    clock,
+                    capabilities: Capabilities::PING | Capabilities::WIDE_AUTHOR,
+                };
+                local.rx(&mut source, reply).await.unwrap();
+
+                source.patches = vec![SyncData {
+                    id: 1.into(),
+                    payload: a_text_message_patch(),
+                }];
+                let mut sent = Vec::new();
+                while let Some(message) = local.tx(&mut source).await.unwrap() {
+                    peer.rx(&mut peer_source, message.clone()).await.unwrap();
+                    sent.push(message);
+                }
+
+                (local, sent)
+            }
+
+            #[tokio::test]
+            async fn then_only_what_both_support_is_used() {
+                let (local, ..) = given().await;
+
+                assert_eq!(
+                    local.capabilities(),
+                    Capabilities::PING | Capabilities::WIDE_AUTHOR
+                );
+                assert!(!local.capabilities().contains(Capabilities::DEFLATE));
+            }
+
+            #[tokio::test]
+            async fn then_patches_are_not_interned() {
+                let (_, sent) = given().await;
+
+                assert!(matches!(sent[..], [PatchSyncMessage::Data(_)]));
+            }
+        }
+
+        mod when_the_peer_lacks_wide_authors {
+            use super::*;
+
+            type Given = (SourceMock, Vec<PatchSyncMessage>);
+            async fn given() -> Given {
+                let (mut source, local) = super::given();
+                let mut local = local.with_handshake();
+                source.patches = vec![SyncData {
+                    id: 1.into(),
+                    payload: a_text_message_patch(),
+                }];
+
+                local.tx(&mut source).await.unwrap();
+                let hello = PatchSyncMessage::Hello {
+                    clock: 0,
+                    capabilities: Capabilities::PING,
+                };
+                local.rx(&mut source, hello).await.unwrap();
+                let data = SyncData {
+                    id: 37.into(),
+                    payload: PEER_PATCH,
+                };
+                local
+                    .rx(&mut source, PatchSyncMessage::Data(data))
+                    .await
+                    .unwrap();
+                let mut sent = Vec::new();
+                while let Some(message) = local.tx(&mut source).await.unwrap() {
+                    sent.push(message);
+                }
+
+                (source, sent)
+            }
+
+            #[tokio::test]
+            async fn then_no_patch_is_sent() {
+                let (_, sent) = given().await;
+
+                assert!(matches!(sent[..], [PatchSyncMessage::HelloReply { .. }]));
+            }
+
+            #[tokio::test]
+            async fn then_its_patches_are_neither_merged_nor_acked() {
+                let (source, sent) = given().await;
+
+                assert!(source.merged.is_empty());
+                assert!(!sent
+                    .iter()
+                    .any(|message| matches!(message, PatchSyncMessage::Ack(_))));
+            }
+        }
+
+        mod when_the_peer_predates_the_handshake {
+            use super::*;
+
+            #[tokio::test]
+            async fn then_its_patches_are_neither_merged_nor_acked() {
+                let (mut source, local) = super::given();
+                let mut local = local.with_handshake();
+                let data = SyncData {
+                    id: 37.into(),
+                    payload: PEER_PATCH,
+                };
+
+                local
+                    .rx(&mut source, PatchSyncMessage::Data(data))
+                    .await
+                    .unwrap();
+                let mut sent = Vec::new();
+                while let Some(message) = local.tx(&mut source).await.unwrap() {
+                    sent.push(message);
+                }
+
+                assert!(source.merged.is_empty());
+                assert!(matches!(sent[..], [PatchSyncMessage::Hello { .. }]));
+            }
+        }
+
+        mod when_the_handshake_is_exchanged_while_receiving_a_payload {
+            use super::*;
+
+            type Given = Vec<PatchSyncMessage>;
+            async fn given() -> Given {
+                let mut source = SourceMock::default();
+                let mut peer_source = SourceMock {
+                    partial: vec![([1; 32], 2)],
+                    ..Default::default()
+                };
+                let mut local = PatchSync::new((), PEER, SAME_CONVERSATION).with_handshake();
+                let mut peer = PatchSync::new((), USER, SAME_CONVERSATION).with_handshake();
+
+                let hello = local.tx(&mut source).await.unwrap().unwrap();
+                let peer_hello = peer.tx(&mut peer_source).await.unwrap().unwrap();
+                peer.rx(&mut peer_source, hello).await.unwrap();
+                local.rx(&mut source, peer_hello).await.unwrap();
+                let reply = local.tx(&mut source).await.unwrap().unwrap();
+                peer.rx(&mut peer_source, reply).await.unwrap();
+
+                let mut sent = Vec::new();
+                while let Some(message) = peer.tx(&mut peer_source).await.unwrap() {
+                    sent.push(message);
+                }
+
+                sent
+            }
+
+            #[tokio::test]
+            async fn then_the_peer_is_told_where_to_resume_once() {
+                let sent = given().await;
+
+                let resumes = sent
+                    .iter()
+                    .filter(|message| matches!(message, PatchSyncMessage::Resume(_)))
+                    .collect::<Vec<_>>();
+                assert_eq!(resumes, vec![&PatchSyncMessage::Resume(vec![([1; 32], 2)])]);
+            }
+        }
+
+        mod when_the_peer_resumes_a_payload {
+            use super::*;
+
+            fn a_chunk(id: i32, hash: u8, index: i32) -> SyncData {
+                SyncData {
+                    id: id.into(),
+                    payload: AttachmentChunk {
+                        hash: [hash; 32],
+                        conversation: SAME_CONVERSATION,
+                        index,
+                        data: vec![hash],
+                        total: 4,
+                        crdt: CrdtAddOnly(USER),
+                    }
+                    .into(),
+                }
+            }
+
+            type Given = (SourceMock, Vec<(u8, i32)>);
+            async fn given() -> Given {
+                let (mut source, mut sync) = super::given();
+                source.patches = vec![
+                    a_chunk(1, 1, 0),
+                    a_chunk(2, 1, 1),
+                    a_chunk(3, 1, 2),
+                    a_chunk(4, 1, 3),
+                    a_chunk(5, 2, 0),
+                ];
+
+                sync.rx(&mut source, PatchSyncMessage::Resume(vec![([1; 32], 2)]))
+                    .await
+                    .unwrap();
+                let mut sent = Vec::new();
+                while let Some(PatchSyncMessage::Data(data)) = sync.tx(&mut source).await.unwrap() {
+                    let Patch::AttachmentChunk(chunk) = data.payload else { panic!() };
+                    sent.push((chunk.hash[0], chunk.index));
+                }
+
+                (source, sent)
+            }
+
+            #[tokio::test]
+            async fn then_only_the_missing_chunks_are_sent() {
+                let (_, sent) = given().await;
+
+                assert_eq!(sent, vec![(1, 2), (1, 3), (2, 0)]);
+            }
+
+            #[tokio::test]
+            async fn then_the_chunks_held_by_the_peer_are_marked_as_handled() {
+                let (source, ..) = given().await;
+
+                assert_eq!(source.minimum_ack, 2);
+            }
+        }
+
+        #[rstest]
+        #[tokio::test]
+        async fn when_it_receives_a_ping_it_answers(given: Given) {
+            let (mut source, mut sync, ..) = given;
+
+            sync.rx(&mut source, PatchSyncMessage::Ping).await.unwrap();
+
+            let tx = sync.tx(&mut source).await.unwrap();
+            assert_eq!(tx, Some(PatchSyncMessage::Pong));
+        }
+
+        #[rstest]
+        #[tokio::test]
+        async fn when_asked_to_ping_it_only_pings_peers_that_answer_pings(given: Given) {
+            let (mut source, mut sync, ..) = given;
+
+            assert!(!sync.ping());
+            assert_eq!(sync.tx(&mut source).await.unwrap(), None);
+
+            let hello = PatchSyncMessage::Hello {
+                clock: 0,
+                capabilities: Capabilities::PING,
+            };
+            sync.rx(&mut source, hello).await.unwrap();
+            sync.tx(&mut source).await.unwrap();
+            assert!(sync.ping());
+            assert_eq!(
+                sync.tx(&mut source).await.unwrap(),
+                Some(PatchSyncMessage::Ping)
+            );
+        }
+
         mod when_it_receives_a_patch {
             use super::*;
 
@@ -507,6 +1631,78 @@ pub mod tests {
 
                 assert_eq!(source.patches, vec![data]);
             }
+
+            #[tokio::test]
+            async fn then_it_is_counted_as_received() {
+                let (_, sync, ..) = given().await;
+
+                assert_eq!(SyncCounters::get(&sync.counters.patches_received), 1);
+                assert_eq!(SyncCounters::get(&sync.counters.conflicts), 0);
+            }
+        }
+
+        mod when_it_receives_a_patch_with_invalid_text {
+            use super::*;
+
+            fn interned(data: &SyncData) -> Vec<u8> {
+                let data = InternTable::new(SAME_CONVERSATION).intern(data.clone());
+                bincode::serialize(&data).unwrap()
+            }
+
+            type Given = (SourceMock, PatchSync<SourceMock>, SyncData, SyncData);
+            async fn given() -> Given {
+                let (mut source, mut sync) = super::given();
+                let Patch::NewTextMessage(mut message) = a_text_message_patch() else { unreachable!() };
+                message.text = "XXXX".to_string();
+                let invalid = SyncData {
+                    id: 37.into(),
+                    payload: message.into(),
+                };
+                let valid = SyncData {
+                    id: 38.into(),
+                    payload: a_text_message_patch(),
+                };
+
+                let mut corrupted = interned(&invalid);
+                let at = corrupted
+                    .windows(4)
+                    .position(|text| text == b"XXXX")
+                    .unwrap();
+                corrupted[at..at + 4].copy_from_slice(&[0xff, 0xfe, 0xfd, 0xfc]);
+
+                for message in [corrupted, interned(&valid)] {
+                    sync.rx(&mut source, PatchSyncMessage::Interned(message))
+                        .await
+                        .unwrap();
+                }
+
+                (source, sync, invalid, valid)
+            }
+
+            #[tokio::test]
+            async fn then_only_the_valid_patch_is_merged() {
+                let (source, _, _, valid, ..) = given().await;
+
+                assert_eq!(
+                    source.merged.into_iter().collect::<Vec<_>>(),
+                    vec![valid.id]
+                );
+                assert_eq!(source.patches, vec![valid]);
+            }
+
+            #[tokio::test]
+            async fn then_both_are_acknowledged() {
+                let (mut source, mut sync, invalid, valid, ..) = given().await;
+
+                let mut acks = Vec::new();
+                while let Some(message) = sync.tx(&mut source).await.unwrap() {
+                    if let PatchSyncMessage::Ack(id) = message {
+                        acks.push(id);
+                    }
+                }
+
+                assert_eq!(acks, vec![invalid.id, valid.id]);
+            }
         }
 
         mod when_it_receives_a_repeated_patch {
@@ -542,6 +1738,13 @@ pub mod tests {
 
                 assert_eq!(source.patches, vec![]);
             }
+
+            #[tokio::test]
+            async fn then_it_is_counted_as_a_conflict() {
+                let (_, sync, ..) = given().await;
+
+                assert_eq!(SyncCounters::get(&sync.counters.conflicts), 1);
+            }
         }
 
         #[rstest]
@@ -600,6 +1803,92 @@ pub mod tests {
             }
         }
 
+        mod when_the_peer_reports_typing {
+            use super::*;
+
+            fn clock() -> i64 {
+                1_000_000
+            }
+
+            fn later() -> i64 {
+                1_000_000 + TYPING_TIMEOUT
+            }
+
+            fn typing(conversation: Uuid, author: Author) -> PatchSyncMessage {
+                PatchSyncMessage::Ephemeral(Ephemeral::Typing {
+                    conversation,
+                    author,
+                })
+            }
+
+            async fn given_received(message: PatchSyncMessage) -> Given {
+                let (mut source, sync, ..) = super::given();
+                let mut sync = sync.with_clock(clock);
+
+                sync.rx(&mut source, message).await.unwrap();
+
+                (source, sync)
+            }
+
+            #[tokio::test]
+            async fn then_the_peer_is_typing() {
+                let (_, sync) = given_received(typing(SAME_CONVERSATION, PEER)).await;
+
+                assert!(sync.peer_typing());
+            }
+
+            #[tokio::test]
+            async fn then_nothing_is_merged_nor_acknowledged() {
+                let (mut source, mut sync) = given_received(typing(SAME_CONVERSATION, PEER)).await;
+
+                let tx = sync.tx(&mut source).await.unwrap();
+
+                assert_eq!(tx, None);
+                assert!(source.merged.is_empty());
+                assert!(source.patches.is_empty());
+            }
+
+            #[tokio::test]
+            async fn then_it_stops_after_the_timeout() {
+                let (_, sync) = given_received(typing(SAME_CONVERSATION, PEER)).await;
+
+                let sync = sync.with_clock(later);
+
+                assert!(!sync.peer_typing());
+            }
+
+            #[tokio::test]
+            async fn then_a_report_for_another_conversation_is_dropped() {
+                let (_, sync) = given_received(typing(OTHER_CONVERSATION, PEER)).await;
+
+                assert!(!sync.peer_typing());
+            }
+
+            #[tokio::test]
+            async fn then_a_report_for_another_author_is_dropped() {
+                let (_, sync) = given_received(typing(SAME_CONVERSATION, USER)).await;
+
+                assert!(!sync.peer_typing());
+            }
+        }
+
+        #[rstest]
+        #[tokio::test]
+        async fn when_the_user_types_the_peer_is_told(given: Given) {
+            let (mut source, mut sync, ..) = given;
+
+            sync.send_typing(USER);
+            let tx = sync.tx(&mut source).await.unwrap();
+
+            assert_eq!(
+                tx,
+                Some(PatchSyncMessage::Ephemeral(Ephemeral::Typing {
+                    conversation: SAME_CONVERSATION,
+                    author: USER,
+                }))
+            );
+        }
+
         #[rstest]
         #[tokio::test]
         async fn when_it_receives_a_message_with_correct_conversation_it_is_merged(given: Given) {
@@ -680,6 +1969,25 @@ pub mod tests {
             }
         }
 
+        mod when_the_peer_is_another_device_of_the_user {
+            use super::*;
+
+            #[tokio::test]
+            async fn then_patches_of_the_shared_author_are_sent() {
+                let (mut source, sync) = super::given();
+                let mut sync = sync.with_own_device(true);
+                let data = SyncData {
+                    id: 37.into(),
+                    payload: PEER_PATCH,
+                };
+                source.patches.push(data.clone());
+
+                let tx = sync.tx(&mut source).await.unwrap();
+
+                assert_eq!(tx, Some(PatchSyncMessage::Data(data)));
+            }
+        }
+
         mod when_next_patch_specifies_a_different_conversation_than_the_channels_conversation {
             use super::*;
 
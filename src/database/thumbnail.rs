@@ -0,0 +1,60 @@
+//! Previews of the images sent with
+//! [`Database::send_file_stream`](super::Database::send_file_stream).
+
+use image::codecs::jpeg::JpegEncoder;
+
+/// Largest side of a thumbnail, in pixels.
+const SIDE: u32 = 128;
+const QUALITY: u8 = 70;
+
+/// Images longer than this are not decoded for a preview.
+pub(crate) const MAX_SOURCE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Whether files of type `mime` may have a preview.
+pub(crate) fn is_image(mime: Option<&str>) -> bool {
+    matches!(mime, Some(mime) if mime.starts_with("image/"))
+}
+
+/// JPEG preview of the image `payload`, `None` when it does not decode.
+pub(crate) fn generate(payload: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(payload).ok()?;
+    let thumbnail = image.thumbnail(SIDE, SIDE).into_rgb8();
+
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, QUALITY)
+        .encode_image(&thumbnail)
+        .ok()?;
+
+    Some(jpeg)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use image::{ImageOutputFormat, RgbImage};
+    use std::io::Cursor;
+
+    pub fn a_png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        RgbImage::new(width, height)
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+
+        png.into_inner()
+    }
+
+    #[test]
+    fn images_are_downscaled_keeping_the_aspect() {
+        let thumbnail = generate(&a_png(300, 150)).unwrap();
+
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (128, 64));
+    }
+
+    #[test]
+    fn other_files_have_no_preview() {
+        assert!(!is_image(Some("application/pdf")));
+        assert!(!is_image(None));
+        assert_eq!(generate(b"%PDF-"), None);
+    }
+}
@@ -1,8 +1,14 @@
 use byteorder::{BigEndian, ByteOrder};
 use futures_util::{future::LocalBoxFuture, FutureExt};
-use icepipe::pipe_stream::{Control, PipeStream, StreamError, WaitThen};
+use icepipe::pipe_stream::{Control, PipeStream, StreamError, StreamResult, WaitThen};
+use std::io;
 
-const MAX_LEN: usize = 4096;
+/// Default size of the fragments, fine for a WebRTC data channel.
+pub const DEFAULT_MAX_LEN: usize = 4096;
+/// Default limit on the length a peer may announce for a packet. A packet
+/// holds a single patch, attachments being synced in chunks of
+/// [`ATTACHMENT_CHUNK_BYTES`](crate::database::ATTACHMENT_CHUNK_BYTES).
+pub const DEFAULT_MAX_PACKET_LEN: usize = 4 * 1024 * 1024;
 
 pub struct Fragmentable<P>
 where
@@ -11,6 +17,8 @@ where
 {
     underlying: P,
     rx_buf: Vec<u8>,
+    max_len: usize,
+    max_packet_len: usize,
 }
 impl<P> Fragmentable<P>
 where
@@ -18,18 +26,55 @@ where
     P::Error: Into<StreamError>,
 {
     pub fn new(underlying: P) -> Self {
+        Self::with_max_len(underlying, DEFAULT_MAX_LEN)
+    }
+
+    /// Fragments sent packets in pieces of at most `max_len` bytes. Received
+    /// packets are reassembled regardless of the size the peer fragments them.
+    pub fn with_max_len(underlying: P, max_len: usize) -> Self {
+        Self::with_limits(underlying, max_len, DEFAULT_MAX_PACKET_LEN)
+    }
+
+    /// Same as [`Fragmentable::with_max_len`], also failing with an error
+    /// when the peer announces a packet longer than `max_packet_len`, instead
+    /// of buffering it.
+    pub fn with_limits(underlying: P, max_len: usize, max_packet_len: usize) -> Self {
+        assert!(max_len > 0);
+
         Self {
             underlying,
             rx_buf: Default::default(),
+            max_len,
+            max_packet_len,
         }
     }
 
-    async fn send_packet(&mut self, mut packet: &[u8]) -> Result<(), P::Error> {
+    async fn send_packet(&mut self, mut packet: &[u8]) -> StreamResult<()> {
         while !packet.is_empty() {
-            let n = MAX_LEN.min(packet.len());
+            let n = self.max_len.min(packet.len());
             let send = &packet[..n];
             packet = &packet[n..];
-            self.underlying.send(send).await?;
+            self.underlying.send(send).await.map_err(Into::into)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_packet_len(&self) -> StreamResult<()> {
+        if self.rx_buf.len() < 4 {
+            return Ok(());
+        }
+
+        let len = self.next_packet_len();
+        if len > self.max_packet_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Peer announced a packet of {len} bytes, the limit is {}",
+                    self.max_packet_len
+                ),
+            )
+            .into());
         }
 
         Ok(())
@@ -59,7 +104,7 @@ where
     P: PipeStream,
     P::Error: Into<StreamError>,
 {
-    fn send<'a>(&'a mut self, data: &'a [u8]) -> LocalBoxFuture<'a, Result<(), P::Error>> {
+    fn send<'a>(&'a mut self, data: &'a [u8]) -> LocalBoxFuture<'a, StreamResult<()>> {
         async move {
             let mut packet = vec![0; 4];
             BigEndian::write_u32(&mut packet, data.len() as u32);
@@ -76,8 +121,13 @@ where
     P: PipeStream,
     P::Error: Into<StreamError>,
 {
-    fn close(&mut self) -> LocalBoxFuture<'_, Result<(), P::Error>> {
-        Control::close(&mut self.underlying)
+    fn close(&mut self) -> LocalBoxFuture<'_, StreamResult<()>> {
+        async move {
+            Control::close(&mut self.underlying)
+                .await
+                .map_err(Into::into)
+        }
+        .boxed_local()
     }
 
     fn rx_closed(&self) -> bool {
@@ -91,15 +141,19 @@ where
 {
     type Value = Option<P::Value>;
     type Output = Option<Vec<u8>>;
-    type Error = P::Error;
+    type Error = StreamError;
 
-    fn wait(&mut self) -> LocalBoxFuture<'_, Result<Self::Value, P::Error>> {
+    fn wait(&mut self) -> LocalBoxFuture<'_, StreamResult<Self::Value>> {
         async move {
             if self.read_ready() {
                 return Ok(None);
             }
 
-            Ok(Some(WaitThen::wait(&mut self.underlying).await?))
+            Ok(Some(
+                WaitThen::wait(&mut self.underlying)
+                    .await
+                    .map_err(Into::into)?,
+            ))
         }
         .boxed_local()
     }
@@ -107,14 +161,15 @@ where
     fn then<'a>(
         &'a mut self,
         value: &'a mut Self::Value,
-    ) -> LocalBoxFuture<'_, Result<Self::Output, P::Error>> {
+    ) -> LocalBoxFuture<'a, StreamResult<Self::Output>> {
         async move {
             let value = value.take();
             if let Some(mut value) = value {
-                if let Some(data) = self.underlying.then(&mut value).await? {
+                if let Some(data) = self.underlying.then(&mut value).await.map_err(Into::into)? {
                     self.rx_buf.extend(data);
                 }
             }
+            self.check_packet_len()?;
 
             if self.read_ready() {
                 return Ok(Some(self.consume()));
@@ -215,6 +270,56 @@ pub mod tests {
         assert_eq!(data_back, data);
     }
 
+    #[rstest]
+    #[tokio::test]
+    async fn send_fragments_with_a_custom_max_len() {
+        let stream = ArcStream::default();
+        let mut fragmentable = Fragmentable::with_max_len(stream.clone(), 1000);
+
+        let data = (0..6000).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+        fragmentable.send(&data).await.unwrap();
+
+        {
+            let mut buffer = stream.0.lock().unwrap();
+            // The 4 bytes of the length prefix and the 6000 of data, in
+            // pieces of 1000 bytes, the last one holding the final 4 bytes.
+            assert_eq!(buffer.len(), 7);
+            assert!(buffer.iter().take(6).all(|fragment| fragment.len() == 1000));
+            assert_eq!(buffer[6], data[5996..]);
+
+            let total = buffer.drain(..).flatten().collect::<Vec<_>>();
+            buffer.push_back(total);
+        }
+
+        let mut receiver = Fragmentable::new(stream.clone());
+        let data_back = loop {
+            let mut value = receiver.wait().await.unwrap();
+            if let Some(data_back) = receiver.then(&mut value).await.unwrap() {
+                break data_back;
+            }
+        };
+        assert_eq!(data_back, data);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn announcing_a_packet_over_the_limit_fails() {
+        let stream = ArcStream(Arc::new(Mutex::new(
+            [vec![0xFF, 0xFF, 0xFF, 0xFF, 1, 2, 3]]
+                .into_iter()
+                .collect(),
+        )));
+        let mut fragmentable = Fragmentable::with_limits(stream.clone(), DEFAULT_MAX_LEN, 1024);
+
+        let mut value = fragmentable.wait().await.unwrap();
+        let r = fragmentable.then(&mut value).await;
+
+        assert!(matches!(
+            r,
+            Err(StreamError::Io(e)) if e.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+
     #[rstest]
     #[tokio::test]
     async fn receiving_two_packets_in_one_recv() {
@@ -0,0 +1,228 @@
+use crate::channel::{BadEd25519CertStr, Ed25519Cert};
+use std::{fmt, str::FromStr};
+use uuid::Uuid;
+
+const PREFIX: &str = "icechat";
+const VERSION: &str = "1";
+
+/// Invitation to join `conversation` by opening a channel to `peer`.
+///
+/// Written as `icechat1:` followed by the base32 of the conversation, the peer
+/// cert and a CRC-32 of both, so that a truncated or mistyped invite is
+/// rejected instead of naming some other conversation or peer. The digit in
+/// the prefix is the version of the encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Invite {
+    pub conversation: Uuid,
+    pub peer: Ed25519Cert,
+}
+impl Invite {
+    fn payload(&self) -> Vec<u8> {
+        let mut payload = [self.conversation.as_bytes(), &self.peer.0[..]].concat();
+        let checksum = crc32fast::hash(&payload);
+        payload.extend(checksum.to_le_bytes());
+        payload
+    }
+}
+impl fmt::Display for Invite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{PREFIX}{VERSION}:{}", base32::encode(&self.payload()))
+    }
+}
+impl FromStr for Invite {
+    type Err = BadInvite;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let (version, payload) = s
+            .strip_prefix(PREFIX)
+            .and_then(|s| s.split_once(':'))
+            .ok_or(BadInvite::MissingPrefix)?;
+        if version != VERSION {
+            return Err(BadInvite::UnknownVersion(version.to_string()));
+        }
+
+        let payload = base32::decode(payload).ok_or(BadInvite::Encoding)?;
+        if payload.len() != 16 + 32 + 4 {
+            return Err(BadInvite::Encoding);
+        }
+
+        let invite = Invite {
+            conversation: Uuid::from_bytes(payload[..16].try_into().unwrap()),
+            peer: Ed25519Cert(payload[16..][..32].try_into().unwrap()),
+        };
+        if invite.payload() != payload {
+            return Err(BadInvite::Checksum);
+        }
+        if !invite.peer.is_valid() {
+            return Err(BadInvite::Peer(BadEd25519CertStr));
+        }
+
+        Ok(invite)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BadInvite {
+    #[error("Not an icechat invite")]
+    MissingPrefix,
+    #[error("Invite of unknown version {0}")]
+    UnknownVersion(String),
+    #[error("Invite is truncated or badly encoded")]
+    Encoding,
+    #[error("Invite is corrupted, its checksum does not match")]
+    Checksum,
+    #[error("Bad peer in invite: {0}")]
+    Peer(#[from] BadEd25519CertStr),
+}
+
+/// Base32 of RFC 4648, lowercase and without padding.
+mod base32 {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+    pub fn encode(data: &[u8]) -> String {
+        let mut r = String::new();
+        let (mut buffer, mut bits) = (0u32, 0);
+        for byte in data {
+            buffer = buffer << 8 | *byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                r.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+            }
+        }
+        if bits > 0 {
+            r.push(ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+        }
+        r
+    }
+
+    pub fn decode(s: &str) -> Option<Vec<u8>> {
+        let mut r = Vec::new();
+        let (mut buffer, mut bits) = (0u32, 0);
+        for c in s.bytes() {
+            let value = ALPHABET.iter().position(|a| *a == c)? as u32;
+            buffer = buffer << 5 | value;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                r.push((buffer >> bits) as u8);
+            }
+        }
+        // Leftover bits must be the zero padding written by `encode`.
+        (buffer & ((1 << bits) - 1) == 0).then_some(r)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::channel::Ed25519Seed;
+
+    fn an_invite() -> Invite {
+        Invite {
+            conversation: Uuid::new_v4(),
+            peer: Ed25519Seed::generate().public_key(),
+        }
+    }
+
+    #[test]
+    fn given_an_invite_then_it_round_trips_through_a_string() {
+        let invite = an_invite();
+
+        let parsed = invite.to_string().parse::<Invite>().unwrap();
+
+        assert_eq!(parsed, invite);
+    }
+
+    #[test]
+    fn given_an_invite_with_surrounding_whitespace_then_it_is_parsed() {
+        let invite = an_invite();
+
+        let parsed = format!(" {invite}\n").parse::<Invite>().unwrap();
+
+        assert_eq!(parsed, invite);
+    }
+
+    #[test]
+    fn given_an_invite_in_uppercase_then_it_is_parsed() {
+        let invite = an_invite();
+
+        let parsed = invite.to_string().to_uppercase().parse::<Invite>().unwrap();
+
+        assert_eq!(parsed, invite);
+    }
+
+    #[test]
+    fn given_no_prefix_then_it_is_rejected() {
+        let invite = an_invite().to_string().replace("icechat1:", "");
+
+        let r = invite.parse::<Invite>();
+
+        assert!(matches!(r, Err(BadInvite::MissingPrefix)));
+    }
+
+    #[test]
+    fn given_an_unknown_version_then_it_is_rejected() {
+        let invite = an_invite().to_string().replace("icechat1:", "icechat2:");
+
+        let r = invite.parse::<Invite>();
+
+        assert!(matches!(r, Err(BadInvite::UnknownVersion(v)) if v == "2"));
+    }
+
+    #[test]
+    fn given_a_truncated_invite_then_it_is_rejected() {
+        let mut invite = an_invite().to_string();
+        invite.truncate(invite.len() - 2);
+
+        let r = invite.parse::<Invite>();
+
+        assert!(matches!(r, Err(BadInvite::Encoding)));
+    }
+
+    #[test]
+    fn given_an_invite_that_is_not_base32_then_it_is_rejected() {
+        let mut invite = an_invite().to_string();
+        invite.replace_range(20..21, "1");
+
+        let r = invite.parse::<Invite>();
+
+        assert!(matches!(r, Err(BadInvite::Encoding)));
+    }
+
+    #[test]
+    fn given_a_mistyped_invite_then_the_checksum_rejects_it() {
+        let mut invite = an_invite().to_string();
+        let typo = match &invite[20..21] {
+            "a" => "b",
+            _ => "a",
+        };
+        invite.replace_range(20..21, typo);
+
+        let r = invite.parse::<Invite>();
+
+        assert!(matches!(r, Err(BadInvite::Checksum)));
+    }
+
+    #[test]
+    fn given_a_peer_off_the_curve_then_it_is_rejected() {
+        let invite = Invite {
+            conversation: Uuid::new_v4(),
+            peer: Ed25519Cert([2; 32]),
+        };
+
+        let r = invite.to_string().parse::<Invite>();
+
+        assert!(matches!(r, Err(BadInvite::Peer(_))));
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        for len in 0..12 {
+            let data = (0..len).map(|x| x * 21 + 5).collect::<Vec<u8>>();
+
+            assert_eq!(base32::decode(&base32::encode(&data)).unwrap(), data);
+        }
+    }
+}
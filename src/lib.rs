@@ -1,10 +1,15 @@
 pub mod channel;
 pub mod channel_pipe;
+pub mod client;
 pub mod database;
 pub mod fragmentable;
+pub mod invite;
 pub mod notification;
 pub mod pipe_sync;
 pub mod poll_runtime;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transport;
 
 pub type SqliteChannel =
     channel::Channel<crate::database::sync::PatchSync<sea_orm::DatabaseTransaction>>;
@@ -1,18 +1,91 @@
-use crate::database::Message;
-use notify_rust::Notification;
+use crate::database::{Content, Conversation, Message};
+
+const PREVIEW_CHARS: usize = 128;
+
+/// What a notification is about, rendered by [`NotificationManager::show`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Title of the conversation, `None` when it is a direct conversation
+    /// between two members.
+    pub conversation_title: Option<String>,
+    pub sender_name: String,
+    /// Beginning of the text, or the filename of an attachment.
+    pub preview: String,
+    pub kind: NotificationKind,
+}
+impl Notification {
+    /// Notification of `message` arriving in `conversation`.
+    pub fn from_message(message: &Message, conversation: &Conversation) -> Notification {
+        let conversation_title = match conversation.members.len() {
+            0..=2 => None,
+            members => Some(
+                conversation
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| format!("{members} members")),
+            ),
+        };
+        let kind = match message.content {
+            Content::Attachment(..) => NotificationKind::Attachment,
+            _ => NotificationKind::Text,
+        };
 
-pub struct NotificationManager;
-impl NotificationManager {
-    pub fn show(message: Message) {
         let text = message.text();
-        let text = match text.len() {
-            0..=128 => text.to_string(),
-            _ => format!("{}...", text.chars().take(128).collect::<String>()),
+        let preview = match text.chars().nth(PREVIEW_CHARS) {
+            None => text.to_string(),
+            Some(_) => format!(
+                "{}...",
+                text.chars().take(PREVIEW_CHARS).collect::<String>()
+            ),
         };
 
-        let r = Notification::new()
-            .summary(&format!("Message from {from}", from = message.from.name))
-            .body(&text)
+        Notification {
+            conversation_title,
+            sender_name: message.from.display_name().to_string(),
+            preview,
+            kind,
+        }
+    }
+
+    fn summary(&self) -> String {
+        match &self.conversation_title {
+            Some(title) => format!("{} in {title}", self.sender_name),
+            None => format!("Message from {}", self.sender_name),
+        }
+    }
+
+    fn body(&self) -> String {
+        match self.kind {
+            NotificationKind::Text => self.preview.clone(),
+            NotificationKind::Attachment => format!("Sent a file: {}", self.preview),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    Text,
+    Attachment,
+}
+
+#[derive(Default)]
+pub struct NotificationManager {
+    do_not_disturb: bool,
+}
+impl NotificationManager {
+    /// While set, [`NotificationManager::show`] shows nothing.
+    pub fn set_do_not_disturb(&mut self, do_not_disturb: bool) {
+        self.do_not_disturb = do_not_disturb;
+    }
+
+    pub fn show(&self, notification: Notification) {
+        if self.do_not_disturb {
+            return;
+        }
+
+        let r = notify_rust::Notification::new()
+            .summary(&notification.summary())
+            .body(&notification.body())
             .icon("icechat")
             .appname("icechat")
             .show();
@@ -23,3 +96,70 @@ impl NotificationManager {
         }
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::database::Contact;
+
+    fn conversation(members: usize, title: Option<&str>) -> Conversation {
+        Conversation {
+            uuid: Default::default(),
+            title: title.map(str::to_string),
+            crdt: Default::default(),
+            members: vec![Contact::default(); members],
+        }
+    }
+
+    fn message(content: Content) -> Message {
+        let mut message = Message::default();
+        message.from.name = "Alice".to_string();
+        message.content = content;
+        message
+    }
+
+    #[test]
+    fn direct_messages_have_no_conversation_title() {
+        let message = message(Content::Text("Hi".to_string()));
+
+        let notification = Notification::from_message(&message, &conversation(2, Some("Chat")));
+
+        assert_eq!(notification.conversation_title, None);
+        assert_eq!(notification.summary(), "Message from Alice");
+        assert_eq!(notification.body(), "Hi");
+    }
+
+    #[test]
+    fn group_messages_name_the_conversation() {
+        let message = message(Content::Text("Hi".to_string()));
+
+        let titled = Notification::from_message(&message, &conversation(3, Some("Friends")));
+        let untitled = Notification::from_message(&message, &conversation(3, None));
+
+        assert_eq!(titled.summary(), "Alice in Friends");
+        assert_eq!(untitled.summary(), "Alice in 3 members");
+    }
+
+    #[test]
+    fn attachments_show_the_filename() {
+        let message = message(Content::Attachment(
+            "photo.png".to_string(),
+            1,
+            Default::default(),
+        ));
+
+        let notification = Notification::from_message(&message, &conversation(2, None));
+
+        assert_eq!(notification.kind, NotificationKind::Attachment);
+        assert_eq!(notification.body(), "Sent a file: photo.png");
+    }
+
+    #[test]
+    fn long_texts_are_cut() {
+        let message = message(Content::Text("é".repeat(200)));
+
+        let notification = Notification::from_message(&message, &conversation(2, None));
+
+        assert_eq!(notification.preview, format!("{}...", "é".repeat(128)));
+    }
+}
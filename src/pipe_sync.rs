@@ -1,6 +1,34 @@
-use crate::database::{error::DatabaseError, DbSync};
+use crate::{
+    channel::Ed25519Cert,
+    database::{error::DatabaseError, sync::Capabilities, DbSync},
+};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use futures_util::future::{select, Either};
 use icepipe::pipe_stream::{PipeStream, StreamError};
-use std::io;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    pin::pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+// A message is sent as its bincode, whose first byte is the index of a
+// variant for the enums used as messages, or deflated after this tag, which
+// no such index reaches. Only peers with `Capabilities::DEFLATE` are sent
+// deflated messages.
+const FRAME_DEFLATE: u8 = 0xf1;
+
+/// Shorter messages are not worth compressing.
+const MIN_COMPRESSED_BYTES: usize = 128;
+
+/// Messages inflating to more than this are rejected.
+const MAX_INFLATED_BYTES: u64 = 16 * 1024 * 1024;
 
 pub struct PipeSync<S: DbSync, P>
 where
@@ -10,6 +38,18 @@ where
     sync: S,
     pipe: P,
     pending: Option<PipeSyncPending>,
+    traffic: PipeSyncTraffic,
+    counters: Arc<SyncCounters>,
+    log_context: SyncLogContext,
+    rate_limit: Option<RateLimit>,
+    compression: bool,
+    keepalive: Option<Keepalive>,
+    /// When the sync started.
+    started: Instant,
+    /// When anything was last received from the peer.
+    heard: Instant,
+    /// When the peer was pinged, if it has not been heard since.
+    pinged: Option<Instant>,
 }
 impl<S: DbSync, P> PipeSync<S, P>
 where
@@ -21,7 +61,83 @@ where
             sync,
             pipe,
             pending: None,
+            traffic: Default::default(),
+            counters: Default::default(),
+            log_context: Default::default(),
+            rate_limit: None,
+            compression: false,
+            keepalive: None,
+            started: Instant::now(),
+            heard: Instant::now(),
+            pinged: None,
+        }
+    }
+
+    /// Compresses the messages that [`DbSync::compressible`] allows, once the
+    /// peer told in the handshake that it inflates them. Set before the sync
+    /// starts, so that the handshake offers [`Capabilities::DEFLATE`].
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+        if compression {
+            self.sync.offer(Capabilities::DEFLATE);
+        }
+    }
+
+    /// Counts what goes through the pipe into `counters`, along with what
+    /// the sync counts through [`DbSync::set_counters`].
+    pub fn set_counters(&mut self, counters: Arc<SyncCounters>) {
+        self.sync.set_counters(counters.clone());
+        self.counters = counters;
+    }
+
+    /// Prefixed to what is logged about this sync.
+    pub fn set_log_context(&mut self, log_context: SyncLogContext) {
+        self.log_context = log_context;
+    }
+
+    /// Caps the bytes sent per second, `None` sends as fast as the pipe
+    /// drains. Up to one second worth of bytes may be sent in a burst.
+    pub fn set_rate_limit(&mut self, bytes_per_second: Option<u32>) {
+        self.rate_limit = bytes_per_second.map(RateLimit::new);
+    }
+
+    /// Pings the peer when it goes quiet, failing the sync when it does not
+    /// answer in time, so that a peer that vanished without the transport
+    /// noticing is given up. A peer that did not complete the handshake
+    /// within the timeout is given up as well, see [`DbSync::handshaking`].
+    /// `None` waits for the peer forever.
+    pub fn set_keepalive(&mut self, keepalive: Option<Keepalive>) {
+        self.keepalive = keepalive;
+    }
+
+    /// When the peer is due a ping, or is given up if already pinged.
+    fn keepalive_deadline(&self) -> Option<Instant> {
+        let keepalive = self.keepalive?;
+
+        Some(match self.pinged {
+            Some(pinged) => pinged + keepalive.timeout,
+            None if self.sync.handshaking() => self.started + keepalive.timeout,
+            None => self.heard + keepalive.interval,
+        })
+    }
+
+    fn check_keepalive(&mut self) -> PipeSyncResult<()> {
+        let now = Instant::now();
+        match self.keepalive_deadline() {
+            Some(deadline) if now >= deadline => {}
+            _ => return Ok(()),
+        }
+        if self.pinged.is_some() || self.sync.handshaking() {
+            return Err(PipeSyncError::PeerTimeout);
         }
+
+        // Peers that do not answer pings are only given up by the transport.
+        match self.sync.ping() {
+            true => self.pinged = Some(now),
+            false => self.heard = now,
+        }
+
+        Ok(())
     }
 
     pub async fn pre_wait(&mut self, database: &mut S::Database) -> PipeSyncResult<()> {
@@ -29,15 +145,29 @@ where
             match &mut self.pending {
                 Some(PipeSyncPending::Rx(message)) => {
                     let message = std::mem::take(message);
-                    let message = bincode::deserialize(&message)?;
-                    self.sync.rx(database, message).await?;
                     self.pending = None;
+                    match decode_frame(&message) {
+                        Ok(message) => match bincode::deserialize(&message) {
+                            Ok(message) => self.sync.rx(database, message).await?,
+                            Err(e) => log::warn!(
+                                "{}: Skipping malformed message from peer: {e}",
+                                self.log_context
+                            ),
+                        },
+                        Err(e) => log::warn!(
+                            "{}: Skipping malformed message from peer: {e}",
+                            self.log_context
+                        ),
+                    }
                     continue;
                 }
                 Some(PipeSyncPending::Tx(_)) => {}
                 None => {
                     if let Some(message) = self.sync.tx(database).await? {
-                        let message = bincode::serialize(&message)?;
+                        let compress = self.compression
+                            && self.sync.capabilities().contains(Capabilities::DEFLATE)
+                            && self.sync.compressible(&message);
+                        let message = encode_frame(bincode::serialize(&message)?, compress)?;
                         self.pending = Some(PipeSyncPending::Tx(message));
                     }
                 }
@@ -49,14 +179,36 @@ where
     }
 
     pub async fn wait(&mut self) -> PipeSyncResult<PipeSyncValue<P>> {
+        // The message stays pending while delayed, so that it is not lost if
+        // this future is dropped.
+        if let (Some(PipeSyncPending::Tx(_)), Some(rate_limit)) =
+            (&self.pending, &mut self.rate_limit)
+        {
+            let delay = rate_limit.delay();
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+
         match self.pending.take() {
             Some(PipeSyncPending::Rx(_)) => {
                 unreachable!()
             }
             Some(PipeSyncPending::Tx(message)) => Ok(PipeSyncValue::Tx(message)),
-            None => Ok(PipeSyncValue::Rx(
-                self.pipe.wait().await.map_err(Into::into)?,
-            )),
+            None => {
+                let Some(deadline) = self.keepalive_deadline() else {
+                    return Ok(PipeSyncValue::Rx(
+                        self.pipe.wait().await.map_err(Into::into)?,
+                    ));
+                };
+
+                let rx = pin!(self.pipe.wait());
+                let keepalive = pin!(tokio::time::sleep_until(deadline));
+                match select(rx, keepalive).await {
+                    Either::Left((value, _)) => Ok(PipeSyncValue::Rx(value.map_err(Into::into)?)),
+                    Either::Right(_) => Ok(PipeSyncValue::Keepalive),
+                }
+            }
         }
     }
 
@@ -64,18 +216,41 @@ where
         assert!(self.pending.is_none());
         match value {
             PipeSyncValue::Rx(mut value) => {
+                self.heard = Instant::now();
+                self.pinged = None;
                 if let Some(message) = self.pipe.then(&mut value).await.map_err(Into::into)? {
+                    self.traffic.received += message.len() as u64;
+                    SyncCounters::add(&self.counters.bytes_received, message.len());
                     self.pending = Some(PipeSyncPending::Rx(message));
                 }
             }
             PipeSyncValue::Tx(message) => {
                 self.pipe.send(&message).await.map_err(Into::into)?;
+                self.traffic.sent += message.len() as u64;
+                SyncCounters::add(&self.counters.bytes_sent, message.len());
+                if let Some(rate_limit) = &mut self.rate_limit {
+                    rate_limit.consume(message.len());
+                }
+                self.check_keepalive()?;
             }
+            PipeSyncValue::Keepalive => self.check_keepalive()?,
         }
 
         Ok(())
     }
 
+    pub fn sync(&self) -> &S {
+        &self.sync
+    }
+
+    pub fn sync_mut(&mut self) -> &mut S {
+        &mut self.sync
+    }
+
+    pub fn traffic(&self) -> PipeSyncTraffic {
+        self.traffic
+    }
+
     pub fn rx_closed(&self) -> bool {
         self.pipe.rx_closed()
     }
@@ -86,11 +261,151 @@ where
     }
 }
 
+/// `message` as sent to the peer, deflated when `compress` and it pays off.
+fn encode_frame(message: Vec<u8>, compress: bool) -> io::Result<Vec<u8>> {
+    if !compress || message.len() < MIN_COMPRESSED_BYTES {
+        return Ok(message);
+    }
+
+    let mut encoder = DeflateEncoder::new(vec![FRAME_DEFLATE], Compression::fast());
+    encoder.write_all(&message)?;
+    let frame = encoder.finish()?;
+
+    Ok(match frame.len() < message.len() {
+        true => frame,
+        false => message,
+    })
+}
+
+fn decode_frame(frame: &[u8]) -> io::Result<Vec<u8>> {
+    match frame {
+        [FRAME_DEFLATE, deflated @ ..] => {
+            let mut message = Vec::new();
+            DeflateDecoder::new(deflated)
+                .take(MAX_INFLATED_BYTES + 1)
+                .read_to_end(&mut message)?;
+            if message.len() as u64 > MAX_INFLATED_BYTES {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "compressed message is too large",
+                ));
+            }
+            Ok(message)
+        }
+        message => Ok(message.to_vec()),
+    }
+}
+
 pub enum PipeSyncPending {
     Rx(Vec<u8>),
     Tx(Vec<u8>),
 }
 
+/// Token bucket holding up to one second worth of bytes. Sending may take more
+/// bytes than there are, the next message then waits until the debt is paid.
+struct RateLimit {
+    bytes_per_second: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+impl RateLimit {
+    fn new(bytes_per_second: u32) -> RateLimit {
+        let bytes_per_second = bytes_per_second.max(1) as f64;
+
+        RateLimit {
+            bytes_per_second,
+            tokens: bytes_per_second,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_second).min(self.bytes_per_second);
+        self.refilled = now;
+    }
+
+    /// How long until the next message may be sent.
+    fn delay(&mut self) -> Duration {
+        self.refill();
+        Duration::from_secs_f64((-self.tokens).max(0.0) / self.bytes_per_second)
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        self.refill();
+        self.tokens -= bytes as f64;
+    }
+}
+
+/// See [`PipeSync::set_keepalive`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long the peer may stay quiet before it is pinged.
+    pub interval: Duration,
+    /// How long the peer has to answer the ping, any message counting as an
+    /// answer.
+    pub timeout: Duration,
+}
+impl Default for Keepalive {
+    fn default() -> Self {
+        Keepalive {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Bytes of sync messages moved through the pipe since it was connected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipeSyncTraffic {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// Which channel a sync runs over, prefixed to its log lines so that those of
+/// several channels can be told apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncLogContext {
+    pub peer: Ed25519Cert,
+    pub conversation: Uuid,
+    /// Row id of the channel in the database.
+    pub channel: i32,
+}
+impl fmt::Display for SyncLogContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peer={} conversation={} channel={}",
+            self.peer.hex(),
+            self.conversation,
+            self.channel
+        )
+    }
+}
+
+/// Running totals of a channel, kept across its connections and shared so
+/// that they can be read while it syncs.
+#[derive(Debug, Default)]
+pub struct SyncCounters {
+    pub bytes_sent: AtomicU64,
+    pub bytes_received: AtomicU64,
+    pub patches_sent: AtomicU64,
+    pub patches_received: AtomicU64,
+    /// Patches received that the merge discarded, because what was already
+    /// stored wins over them.
+    pub conflicts: AtomicU64,
+}
+impl SyncCounters {
+    pub fn add(counter: &AtomicU64, n: usize) {
+        counter.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+}
+
 pub enum PipeSyncValue<P>
 where
     P: PipeStream,
@@ -98,6 +413,9 @@ where
 {
     Tx(Vec<u8>),
     Rx(P::Value),
+    /// The peer is due a ping, or did not answer it, see
+    /// [`PipeSync::set_keepalive`].
+    Keepalive,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -108,6 +426,8 @@ pub enum PipeSyncError {
     DatabaseError(#[from] DatabaseError),
     #[error(transparent)]
     StreamError(StreamError),
+    #[error("Peer did not answer the keepalive")]
+    PeerTimeout,
 }
 impl From<StreamError> for PipeSyncError {
     fn from(value: StreamError) -> Self {
@@ -130,6 +450,7 @@ pub mod tests {
     use crate::{
         channel_pipe::ChannelPipe,
         database::{error::DatabaseResult, DbSync},
+        poll_runtime::LocalRuntime,
     };
     use futures_util::{future::LocalBoxFuture, FutureExt};
     use std::time::Duration;
@@ -186,8 +507,22 @@ pub mod tests {
         }
     }
 
-    #[tokio::test]
-    async fn sync_test() -> PipeSyncResult<()> {
+    type Synced = (
+        Vec<i32>,
+        Vec<i32>,
+        PipeSyncTraffic,
+        PipeSyncTraffic,
+        Arc<SyncCounters>,
+    );
+
+    async fn sync_alice_and_bob() -> PipeSyncResult<Synced> {
+        sync_alice_and_bob_compressing(true, true).await
+    }
+
+    async fn sync_alice_and_bob_compressing(
+        compress_a: bool,
+        compress_b: bool,
+    ) -> PipeSyncResult<Synced> {
         let mut alice = vec![0, 2, 4];
         let mut bob = vec![1, 3, 5];
         let sync_a = CountSync::new(&alice, true);
@@ -197,6 +532,10 @@ pub mod tests {
 
         let mut sync_a = PipeSync::new(sync_a, pipe_a);
         let mut sync_b = PipeSync::new(sync_b, pipe_b);
+        sync_a.set_compression(compress_a);
+        sync_b.set_compression(compress_b);
+        let counters = Arc::new(SyncCounters::default());
+        sync_a.set_counters(counters.clone());
 
         loop {
             sync_a.pre_wait(&mut alice).await?;
@@ -214,9 +553,341 @@ pub mod tests {
             }
         }
 
+        Ok((alice, bob, sync_a.traffic(), sync_b.traffic(), counters))
+    }
+
+    #[tokio::test]
+    async fn sync_test() -> PipeSyncResult<()> {
+        let (alice, bob, ..) = sync_alice_and_bob().await?;
+
+        assert_eq!(alice, [0, 2, 4, 1, 3, 5]);
+        assert_eq!(bob, [1, 3, 5, 0, 2, 4]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sync_test_on_a_local_runtime() -> PipeSyncResult<()> {
+        let runtime = LocalRuntime::new()?;
+
+        let (alice, bob, ..) = runtime.block_on(async {
+            tokio::task::spawn_local(sync_alice_and_bob())
+                .await
+                .unwrap()
+        })?;
+
+        assert_eq!(alice, [0, 2, 4, 1, 3, 5]);
+        assert_eq!(bob, [1, 3, 5, 0, 2, 4]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sync_test_counts_traffic() -> PipeSyncResult<()> {
+        let (_, _, alice, bob, _) = sync_alice_and_bob().await?;
+
+        assert!(alice.sent > 0);
+        assert_eq!(alice.sent, bob.received);
+        assert_eq!(bob.sent, alice.received);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sync_test_counts_bytes_into_the_shared_counters() -> PipeSyncResult<()> {
+        let (_, _, alice, _, counters) = sync_alice_and_bob().await?;
+
+        assert_eq!(SyncCounters::get(&counters.bytes_sent), alice.sent);
+        assert_eq!(SyncCounters::get(&counters.bytes_received), alice.received);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sync_test_with_a_peer_that_does_not_compress() -> PipeSyncResult<()> {
+        let (alice, bob, ..) = sync_alice_and_bob_compressing(true, false).await?;
+
         assert_eq!(alice, [0, 2, 4, 1, 3, 5]);
         assert_eq!(bob, [1, 3, 5, 0, 2, 4]);
 
         Ok(())
     }
+
+    #[test]
+    fn log_context_names_the_peer_conversation_and_channel() {
+        let context = SyncLogContext {
+            peer: Ed25519Cert([1; 32]),
+            conversation: Uuid::from_u128(2),
+            channel: 3,
+        };
+
+        assert_eq!(
+            context.to_string(),
+            format!(
+                "peer={} conversation=00000000-0000-0000-0000-000000000002 channel=3",
+                "01".repeat(32)
+            )
+        );
+    }
+
+    #[test]
+    fn compressed_messages_decompress_to_the_same_message() {
+        use crate::database::sync::{PatchSyncMessage, SyncData};
+        use entity::patch::{Contact, Patch};
+
+        let message = PatchSyncMessage::Data(SyncData {
+            id: 37.into(),
+            payload: Patch::Contact(Contact {
+                name: "la".repeat(200),
+                ..Default::default()
+            }),
+        });
+        let serialized = bincode::serialize(&message).unwrap();
+
+        let frame = encode_frame(serialized.clone(), true).unwrap();
+        assert_eq!(frame[0], FRAME_DEFLATE);
+        assert!(frame.len() < serialized.len());
+
+        let decoded = decode_frame(&frame).unwrap();
+        let decoded: PatchSyncMessage = bincode::deserialize(&decoded).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    /// Sends one long message, telling the peer supports `DEFLATE` when
+    /// `peer_deflates`, as if the handshake was already exchanged.
+    struct HandshakenSync {
+        peer_deflates: bool,
+        offered: Capabilities,
+        sent: bool,
+    }
+    impl DbSync for HandshakenSync {
+        type Database = Vec<Vec<u8>>;
+        type Message = Vec<u8>;
+
+        fn tx<'a>(
+            &'a mut self,
+            _database: &'a mut Self::Database,
+        ) -> LocalBoxFuture<'a, DatabaseResult<Option<Vec<u8>>>> {
+            let message = (!std::mem::replace(&mut self.sent, true)).then(|| vec![7; 1000]);
+            async move { Ok(message) }.boxed_local()
+        }
+
+        fn rx<'a>(
+            &'a mut self,
+            database: &'a mut Self::Database,
+            message: Vec<u8>,
+        ) -> LocalBoxFuture<'a, DatabaseResult<()>> {
+            database.push(message);
+            async move { Ok(()) }.boxed_local()
+        }
+
+        fn offer(&mut self, capabilities: Capabilities) {
+            self.offered = self.offered | capabilities;
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            match self.peer_deflates {
+                true => self.offered,
+                false => Default::default(),
+            }
+        }
+    }
+
+    /// Bytes alice sent to bob for one long message, and what bob received.
+    async fn send_compressing(peer_deflates: bool) -> PipeSyncResult<(u64, Vec<Vec<u8>>)> {
+        let (pipe_a, pipe_b) = ChannelPipe::channel();
+        let handshaken = |sent| HandshakenSync {
+            peer_deflates,
+            offered: Default::default(),
+            sent,
+        };
+        let mut alice = PipeSync::new(handshaken(false), pipe_a);
+        let mut bob = PipeSync::new(handshaken(true), pipe_b);
+        alice.set_compression(true);
+        bob.set_compression(true);
+
+        let mut received = Vec::new();
+        while received.is_empty() {
+            alice.pre_wait(&mut Vec::new()).await?;
+            let value = alice.wait().await?;
+            alice.then(value).await?;
+            let value = bob.wait().await?;
+            bob.then(value).await?;
+            bob.pre_wait(&mut received).await?;
+        }
+
+        Ok((alice.traffic().sent, received))
+    }
+
+    #[tokio::test]
+    async fn messages_are_compressed_for_peers_that_deflate() -> PipeSyncResult<()> {
+        let (sent, received) = send_compressing(true).await?;
+
+        assert!(sent < 1000, "{sent}");
+        assert_eq!(received, [vec![7; 1000]]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn messages_are_not_compressed_for_peers_that_do_not_deflate() -> PipeSyncResult<()> {
+        let (sent, received) = send_compressing(false).await?;
+
+        assert!(sent > 1000, "{sent}");
+        assert_eq!(received, [vec![7; 1000]]);
+
+        Ok(())
+    }
+
+    /// Always has another chunk to send when `flood`.
+    struct FloodSync {
+        flood: bool,
+    }
+    impl DbSync for FloodSync {
+        type Database = ();
+        type Message = Vec<u8>;
+
+        fn tx<'a>(
+            &'a mut self,
+            _database: &'a mut Self::Database,
+        ) -> LocalBoxFuture<'a, DatabaseResult<Option<Vec<u8>>>> {
+            let chunk = self.flood.then(|| vec![0; 92]);
+            async move { Ok(chunk) }.boxed_local()
+        }
+
+        fn rx<'a>(
+            &'a mut self,
+            _database: &'a mut Self::Database,
+            _message: Vec<u8>,
+        ) -> LocalBoxFuture<'a, DatabaseResult<()>> {
+            async move { Ok(()) }.boxed_local()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_caps_the_bytes_sent() -> PipeSyncResult<()> {
+        let (pipe_a, pipe_b) = ChannelPipe::channel();
+        let mut alice = PipeSync::new(FloodSync { flood: true }, pipe_a);
+        let mut bob = PipeSync::new(FloodSync { flood: false }, pipe_b);
+        alice.set_rate_limit(Some(1000));
+
+        let window = tokio::time::sleep(Duration::from_secs(10));
+        tokio::pin!(window);
+        loop {
+            alice.pre_wait(&mut ()).await?;
+            bob.pre_wait(&mut ()).await?;
+            tokio::select! {
+                biased;
+                value = bob.wait() => bob.then(value?).await?,
+                value = alice.wait() => alice.then(value?).await?,
+                _ = &mut window => break,
+            }
+        }
+
+        // A burst of one second, then the rate, each chunk being 100 bytes
+        // once serialized.
+        let sent = alice.traffic().sent;
+        assert!((10_000..=11_100).contains(&sent), "{sent}");
+        assert_eq!(bob.traffic().received, sent);
+
+        Ok(())
+    }
+
+    /// Pings when asked to, answering pings when `answers`.
+    struct PingSync {
+        answers: bool,
+        handshaking: bool,
+        queue: Vec<bool>,
+    }
+    impl DbSync for PingSync {
+        type Database = ();
+        /// `true` for a ping, `false` for a pong.
+        type Message = bool;
+
+        fn tx<'a>(
+            &'a mut self,
+            _database: &'a mut Self::Database,
+        ) -> LocalBoxFuture<'a, DatabaseResult<Option<bool>>> {
+            let message = self.queue.pop();
+            async move { Ok(message) }.boxed_local()
+        }
+
+        fn rx<'a>(
+            &'a mut self,
+            _database: &'a mut Self::Database,
+            ping: bool,
+        ) -> LocalBoxFuture<'a, DatabaseResult<()>> {
+            if ping && self.answers {
+                self.queue.push(false);
+            }
+            async move { Ok(()) }.boxed_local()
+        }
+
+        fn ping(&mut self) -> bool {
+            self.queue.push(true);
+            true
+        }
+
+        fn handshaking(&self) -> bool {
+            self.handshaking
+        }
+    }
+
+    /// Runs alice and, unless it `vanished`, bob, for a minute. Alice pings
+    /// after 10 seconds of silence and waits 5 seconds for an answer, or for
+    /// bob's handshake if it is still `handshaking`.
+    async fn keep_alive(vanished: bool, handshaking: bool) -> PipeSyncResult<()> {
+        let (pipe_a, pipe_b) = ChannelPipe::channel();
+        let ping_sync = |handshaking| PingSync {
+            answers: true,
+            handshaking,
+            queue: vec![],
+        };
+        let mut alice = PipeSync::new(ping_sync(handshaking), pipe_a);
+        let mut bob = PipeSync::new(ping_sync(false), pipe_b);
+        alice.set_keepalive(Some(Keepalive {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+        }));
+
+        let window = tokio::time::sleep(Duration::from_secs(60));
+        tokio::pin!(window);
+        loop {
+            alice.pre_wait(&mut ()).await?;
+            bob.pre_wait(&mut ()).await?;
+            tokio::select! {
+                biased;
+                value = bob.wait(), if !vanished => bob.then(value?).await?,
+                value = alice.wait() => alice.then(value?).await?,
+                _ = &mut window => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keepalive_keeps_a_peer_that_answers() -> PipeSyncResult<()> {
+        keep_alive(false, false).await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keepalive_gives_up_a_peer_that_vanished() {
+        let start = Instant::now();
+
+        let r = keep_alive(true, false).await;
+
+        assert!(matches!(r, Err(PipeSyncError::PeerTimeout)));
+        assert_eq!(start.elapsed().as_secs(), 15);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keepalive_gives_up_a_peer_that_never_completes_the_handshake() {
+        let start = Instant::now();
+
+        let r = keep_alive(false, true).await;
+
+        assert!(matches!(r, Err(PipeSyncError::PeerTimeout)));
+        assert_eq!(start.elapsed().as_secs(), 5);
+    }
 }
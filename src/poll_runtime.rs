@@ -1,8 +1,72 @@
 use std::{
     future::Future,
+    io,
+    sync::Arc,
+    thread::JoinHandle,
     time::{Duration, Instant},
 };
-use tokio::runtime::Handle;
+use tokio::{
+    runtime::{Builder, Runtime},
+    sync::oneshot,
+    task::LocalSet,
+};
+
+/// Single threaded runtime to drive the sync. Sync futures are `!Send`, they
+/// borrow the database transaction and are boxed as `LocalBoxFuture`, so they
+/// must be polled, and spawned with `spawn_local`, on the thread that owns the
+/// database.
+///
+/// Between calls to [`LocalRuntime::block_on`] a background thread keeps
+/// driving the timers, the IO and the `Send` tasks, such as the ones of the
+/// WebRTC connections, so those do not stall while the owner is busy.
+pub struct LocalRuntime {
+    runtime: Arc<Runtime>,
+    local: LocalSet,
+    driver: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+}
+impl LocalRuntime {
+    pub fn new() -> io::Result<Self> {
+        let runtime = Arc::new(Builder::new_current_thread().enable_all().build()?);
+
+        let (stop, stopped) = oneshot::channel();
+        let driven = runtime.clone();
+        let driver = std::thread::Builder::new()
+            .name("icechat-runtime".to_string())
+            .spawn(move || {
+                let _ = driven.block_on(stopped);
+            })?;
+
+        Ok(Self {
+            runtime,
+            local: LocalSet::new(),
+            driver: Some((stop, driver)),
+        })
+    }
+
+    /// Runs `future` to completion, also driving tasks spawned with
+    /// `spawn_local`.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.local.block_on(&self.runtime, future)
+    }
+
+    /// Spawns a `Send` task, driven even while nothing is blocking on the
+    /// runtime.
+    pub fn spawn<F>(&self, future: F) -> tokio::task::JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.runtime.spawn(future)
+    }
+}
+impl Drop for LocalRuntime {
+    fn drop(&mut self) {
+        if let Some((stop, driver)) = self.driver.take() {
+            let _ = stop.send(());
+            let _ = driver.join();
+        }
+    }
+}
 
 pub struct PollRuntime {
     last_run: Instant,
@@ -19,7 +83,7 @@ impl PollRuntime {
         Self { last_run }
     }
 
-    pub fn poll<F: Future>(&mut self, runtime: Handle, future: F) -> Option<F::Output> {
+    pub fn poll<F: Future>(&mut self, runtime: &LocalRuntime, future: F) -> Option<F::Output> {
         let now = Instant::now();
         if now
             .checked_duration_since(self.last_run)
@@ -33,3 +97,37 @@ impl PollRuntime {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timers_fire_while_nothing_blocks_on_the_runtime() {
+        let runtime = LocalRuntime::new().unwrap();
+        let (fired, on_fire) = std::sync::mpsc::channel();
+
+        runtime.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            fired.send(()).unwrap();
+        });
+
+        on_fire.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn local_tasks_run_while_the_driver_holds_the_runtime() {
+        let runtime = LocalRuntime::new().unwrap();
+
+        let r = runtime.block_on(async {
+            tokio::task::spawn_local(async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                42
+            })
+            .await
+            .unwrap()
+        });
+
+        assert_eq!(r, 42);
+    }
+}
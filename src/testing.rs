@@ -0,0 +1,193 @@
+//! Helpers to sync real databases with each other in tests, without a
+//! network. Built for the tests of this crate, and for other crates with the
+//! `testing` feature.
+
+use crate::{
+    channel_pipe::ChannelPipe,
+    database::{error::DatabaseError, sync::PatchSync, ChannelData, Database},
+    pipe_sync::{PipeSync, PipeSyncResult},
+};
+use futures_util::FutureExt;
+use sea_orm::DatabaseTransaction;
+
+/// Unread messages each end of a pipe holds. Both ends are driven from one
+/// task, so sending must never wait for the other end to read.
+const PIPE_CAPACITY: usize = 1 << 20;
+
+type PipedSync = PipeSync<PatchSync<DatabaseTransaction>, ChannelPipe>;
+
+/// Syncs `a` and `b` over every pair of channels they have to each other,
+/// until neither end of any pair has anything left to send. Channels with no
+/// matching channel on the other side are left alone.
+pub async fn sync_until_idle(a: &Database, b: &Database) -> PipeSyncResult<()> {
+    let mut pairs = Vec::new();
+    for (to_b, to_a) in channel_pairs(a, b).await? {
+        let (pipe_a, pipe_b) = ChannelPipe::with_capacity(PIPE_CAPACITY);
+        let mut sync_a = PipeSync::new(a.start_sync(to_b), pipe_a);
+        let mut sync_b = PipeSync::new(b.start_sync(to_a), pipe_b);
+        sync_a.set_compression(true);
+        sync_b.set_compression(true);
+        pairs.push((sync_a, sync_b));
+    }
+
+    loop {
+        let mut idle = true;
+        for (sync_a, sync_b) in pairs.iter_mut() {
+            idle &= !step(sync_a, a).await?;
+            idle &= !step(sync_b, b).await?;
+        }
+
+        if idle {
+            return Ok(());
+        }
+    }
+}
+
+/// Channels of `a` to `b`, each with the channel of `b` to `a` on the same
+/// conversation.
+async fn channel_pairs(
+    a: &Database,
+    b: &Database,
+) -> PipeSyncResult<Vec<(ChannelData, ChannelData)>> {
+    let mut pairs = Vec::new();
+    for conversation in a.list_conversation().await? {
+        let to_b = a
+            .list_channels(&conversation)
+            .await?
+            .into_iter()
+            .find(|channel| channel.peer_cert == *b.cert());
+        let Some(to_b) = to_b else { continue; };
+
+        let to_a = b
+            .list_channels(&conversation)
+            .await?
+            .into_iter()
+            .find(|channel| channel.peer_cert == *a.cert());
+        let Some(to_a) = to_a else { continue; };
+
+        pairs.push((to_b, to_a));
+    }
+
+    Ok(pairs)
+}
+
+/// Saves what `sync` received and handles the next message, if one is ready,
+/// returning whether it made progress.
+async fn step(sync: &mut PipedSync, database: &Database) -> PipeSyncResult<bool> {
+    let mut trans = database.begin().await?;
+    sync.pre_wait(&mut trans).await?;
+    trans.commit().await.map_err(DatabaseError::from)?;
+
+    // Waiting for the pipe is cancel safe, nothing is lost when not ready.
+    let Some(value) = sync.wait().now_or_never() else { return Ok(false); };
+    sync.then(value?).await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Content, Conversation, Message, ATTACHMENT_CHUNK_BYTES};
+
+    fn texts(messages: Vec<Message>) -> Vec<String> {
+        messages
+            .into_iter()
+            .map(|message| message.text().to_string())
+            .collect()
+    }
+
+    mod given_two_peers_in_a_conversation {
+        use super::*;
+
+        type Given = (Database, Conversation, Database, Conversation);
+        async fn given() -> Given {
+            let alice = Database::connect(":memory:").await.unwrap();
+            let bob = Database::connect(":memory:").await.unwrap();
+            let conversation = alice.create_conversation(None).await.unwrap();
+            let joined = bob.join_conversation(conversation.uuid).await.unwrap();
+            alice
+                .create_channel(conversation.clone(), *bob.cert())
+                .await
+                .unwrap();
+            bob.create_channel(joined.clone(), *alice.cert())
+                .await
+                .unwrap();
+
+            (alice, conversation, bob, joined)
+        }
+
+        #[tokio::test]
+        async fn then_messages_reach_both_ends() {
+            let (alice, conversation, bob, joined) = given().await;
+            alice
+                .send_message(conversation.clone(), "hi bob".to_string(), None)
+                .await
+                .unwrap();
+            bob.send_message(joined.clone(), "hi alice".to_string(), None)
+                .await
+                .unwrap();
+
+            sync_until_idle(&alice, &bob).await.unwrap();
+
+            assert_eq!(
+                texts(bob.new_messages(Some(&joined)).await.unwrap()),
+                ["hi bob"]
+            );
+            assert_eq!(
+                texts(alice.new_messages(Some(&conversation)).await.unwrap()),
+                ["hi alice"]
+            );
+        }
+
+        #[tokio::test]
+        async fn then_the_title_reaches_the_other_end() {
+            let (alice, mut conversation, bob, joined) = given().await;
+            conversation.title = Some("Plans".to_string());
+            alice.save_conversation(conversation).await.unwrap();
+
+            sync_until_idle(&alice, &bob).await.unwrap();
+
+            let synced = bob.list_conversation().await.unwrap();
+            let synced = synced.iter().find(|c| c.uuid == joined.uuid).unwrap();
+            assert_eq!(synced.title.as_deref(), Some("Plans"));
+        }
+
+        #[tokio::test]
+        async fn then_an_attachment_of_several_chunks_reaches_the_other_end() {
+            let (alice, conversation, bob, joined) = given().await;
+            let payload = (0..ATTACHMENT_CHUNK_BYTES * 3 + 10)
+                .map(|i| i as u8)
+                .collect::<Vec<_>>();
+            alice
+                .send_file(conversation, "big".to_string(), payload.clone())
+                .await
+                .unwrap();
+
+            sync_until_idle(&alice, &bob).await.unwrap();
+
+            let message = joined.get_message(&bob, 0).await.unwrap();
+            let Content::Attachment(_, attachment, _) = message.unwrap().content else { panic!() };
+            let fetched = bob
+                .fetch_file_payload(attachment)
+                .await
+                .unwrap()
+                .map(|payload| payload.into_bytes().unwrap());
+            assert_eq!(fetched, Some(payload));
+        }
+
+        #[tokio::test]
+        async fn then_nothing_is_left_to_sync() {
+            let (alice, conversation, bob, _) = given().await;
+            alice
+                .send_message(conversation, "hi bob".to_string(), None)
+                .await
+                .unwrap();
+
+            sync_until_idle(&alice, &bob).await.unwrap();
+
+            assert_eq!(alice.sync_backlog().await.unwrap(), 0);
+            assert_eq!(bob.sync_backlog().await.unwrap(), 0);
+        }
+    }
+}
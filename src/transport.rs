@@ -0,0 +1,236 @@
+//! How a [`Channel`](crate::channel::Channel) reaches its peer.
+//!
+//! The sync runs over any [`PipeStream`] that hands packets over whole. A
+//! [`Transport`] opens one such pipe for each connection of a channel,
+//! [`IcepipeTransport`] through WebRTC, which is the default, and
+//! [`TcpTransport`] over a plain socket.
+
+use crate::{
+    channel::ConnectConfig,
+    fragmentable::{Fragmentable, DEFAULT_MAX_LEN},
+};
+use futures_util::{future::LocalBoxFuture, FutureExt};
+use icepipe::{
+    agreement::Ed25519PairAndPeer,
+    async_pipe_stream::AsyncPipeStream,
+    connect::Connection,
+    pipe_stream::{PipeStream, StreamError, StreamResult},
+};
+use std::{net::SocketAddr, rc::Rc};
+use tokio::net::{TcpListener, TcpStream};
+
+pub trait Transport {
+    /// Hands over packets whole, see [`Fragmentable`].
+    type Pipe: PipeStream<Error = StreamError> + 'static;
+
+    /// Opens a pipe to the peer on `channel`, the name both ends of a channel
+    /// derive for it. `auth` proves the local key and checks the peer's. The
+    /// pipe fails when the peer announces a packet longer than
+    /// `max_packet_len`, see [`Fragmentable::with_limits`].
+    fn connect(
+        &self,
+        channel: String,
+        auth: Ed25519PairAndPeer,
+        max_packet_len: usize,
+    ) -> LocalBoxFuture<'static, StreamResult<Self::Pipe>>;
+}
+
+/// Meets the peer through the signaling server, then connects over WebRTC.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IcepipeTransport {
+    pub config: ConnectConfig,
+}
+impl Transport for IcepipeTransport {
+    type Pipe = Fragmentable<Connection>;
+
+    fn connect(
+        &self,
+        channel: String,
+        auth: Ed25519PairAndPeer,
+        max_packet_len: usize,
+    ) -> LocalBoxFuture<'static, StreamResult<Self::Pipe>> {
+        let ConnectConfig { signaling, ice } = self.config.clone();
+
+        async move {
+            let connection = icepipe::ConnectOptions {
+                channel,
+                signaling,
+                ice,
+            }
+            .connect(auth)
+            .await
+            .map_err(StreamError::from)?;
+
+            Ok(Fragmentable::with_limits(
+                connection,
+                DEFAULT_MAX_LEN,
+                max_packet_len,
+            ))
+        }
+        .boxed_local()
+    }
+}
+
+/// A plain TCP socket, one end listening and the other connecting, for tests
+/// and local networks. Neither authenticated nor encrypted, the peer is
+/// whoever is at the other end.
+#[derive(Debug, Clone)]
+pub enum TcpTransport {
+    /// Accepts a peer on the listener for each connection.
+    Listen(Rc<TcpListener>),
+    /// Connects to the peer listening on the address.
+    Connect(SocketAddr),
+}
+impl Transport for TcpTransport {
+    type Pipe = Fragmentable<AsyncPipeStream>;
+
+    fn connect(
+        &self,
+        _channel: String,
+        _auth: Ed25519PairAndPeer,
+        max_packet_len: usize,
+    ) -> LocalBoxFuture<'static, StreamResult<Self::Pipe>> {
+        let transport = self.clone();
+
+        async move {
+            let stream = match transport {
+                TcpTransport::Listen(listener) => listener.accept().await?.0,
+                TcpTransport::Connect(address) => TcpStream::connect(address).await?,
+            };
+            stream.set_nodelay(true)?;
+            let (read, write) = stream.into_split();
+
+            Ok(Fragmentable::with_limits(
+                AsyncPipeStream::new(read, write),
+                DEFAULT_MAX_LEN,
+                max_packet_len,
+            ))
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel::{Channel, ChannelStateLabel, OfflineReason},
+        database::{sync::PatchSync, Database},
+    };
+    use sea_orm::DatabaseTransaction;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    type TcpChannel = Channel<PatchSync<DatabaseTransaction>, TcpTransport>;
+
+    async fn pre_wait(channel: &mut TcpChannel, database: &Database) {
+        if channel.state() == ChannelStateLabel::Offline {
+            channel.connect(database.start_sync(channel.channel().clone()));
+        }
+        let mut trans = database.begin().await.unwrap();
+        channel.pre_wait(&mut trans).await;
+        trans.commit().await.unwrap();
+    }
+
+    mod given_two_peers_over_tcp {
+        use super::*;
+
+        type Given = (Database, TcpChannel, Database, TcpChannel);
+        async fn given() -> Given {
+            let [alice, bob] = [Database::connect(":memory:"), Database::connect(":memory:")];
+            let (alice, bob) = (alice.await.unwrap(), bob.await.unwrap());
+            let conversation = alice.create_conversation(None).await.unwrap();
+            let bobs_conversation = bob
+                .create_conversation_with_id(conversation.uuid, None)
+                .await
+                .unwrap();
+            alice
+                .create_channel(conversation.clone(), *bob.cert())
+                .await
+                .unwrap();
+            bob.create_channel(bobs_conversation.clone(), *alice.cert())
+                .await
+                .unwrap();
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let alices = alice.list_channels(&conversation).await.unwrap().remove(0);
+            let alices = alice.channel_over(alices, TcpTransport::Listen(Rc::new(listener)));
+            let bobs = bob
+                .list_channels(&bobs_conversation)
+                .await
+                .unwrap()
+                .remove(0);
+            let bobs = bob.channel_over(bobs, TcpTransport::Connect(address));
+
+            (alice, alices, bob, bobs)
+        }
+
+        #[tokio::test]
+        async fn then_a_message_sent_by_one_reaches_the_other() {
+            let (alice, mut alices, bob, mut bobs) = given().await;
+            let conversation = alice.list_conversation().await.unwrap().remove(0);
+            alice
+                .send_message(conversation.clone(), "over tcp".to_string(), None)
+                .await
+                .unwrap();
+
+            let received = async {
+                loop {
+                    pre_wait(&mut alices, &alice).await;
+                    pre_wait(&mut bobs, &bob).await;
+                    tokio::select! {
+                        value = alices.wait() => alices.then(value).await,
+                        value = bobs.wait() => bobs.then(value).await,
+                    }
+
+                    let received = bob.new_messages(Some(&conversation)).await.unwrap();
+                    if let Some(message) = received.into_iter().next() {
+                        break message;
+                    }
+                }
+            };
+            let received = tokio::time::timeout(Duration::from_secs(10), received)
+                .await
+                .unwrap();
+
+            assert_eq!(received.text(), "over tcp");
+            assert_eq!(alices.state(), ChannelStateLabel::Connected);
+            assert_eq!(bobs.state(), ChannelStateLabel::Connected);
+        }
+
+        #[tokio::test]
+        async fn then_a_packet_over_the_limit_of_the_receiver_drops_the_connection() {
+            let (alice, mut alices, bob, mut bobs) = given().await;
+            bobs.set_max_packet_len(1024);
+            let conversation = alice.list_conversation().await.unwrap().remove(0);
+            // Random enough not to deflate under the limit.
+            let text = (0..128).map(|_| Uuid::new_v4().to_string()).collect();
+            alice
+                .send_message(conversation.clone(), text, None)
+                .await
+                .unwrap();
+
+            let dropped = async {
+                loop {
+                    pre_wait(&mut alices, &alice).await;
+                    pre_wait(&mut bobs, &bob).await;
+                    tokio::select! {
+                        value = alices.wait() => alices.then(value).await,
+                        value = bobs.wait() => bobs.then(value).await,
+                    }
+
+                    if let Some(reason) = bobs.offline_reason() {
+                        break reason;
+                    }
+                }
+            };
+            let reason = tokio::time::timeout(Duration::from_secs(10), dropped)
+                .await
+                .unwrap();
+
+            assert_eq!(reason, OfflineReason::ConnectionLost);
+            assert!(bob.new_messages(None).await.unwrap().is_empty());
+        }
+    }
+}